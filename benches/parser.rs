@@ -0,0 +1,115 @@
+//! Per-frame cost of decoding, over the frames of the bundled sample game.
+//!
+//! `cargo bench --bench parser`
+
+use bytes::Bytes;
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use majsoul_max_rs::{
+    base::BaseMessage,
+    dump::read_frames,
+    lq::ActionPrototype,
+    parser::{wtf_decode, LiqiMessage, Parser},
+};
+use prost::Message;
+use prost_reflect::DescriptorPool;
+use serde_json::Value as JsonValue;
+use std::hint::black_box;
+
+/// Frames the client received in `simulate fake-game`, as dumped by a binary raw file sink.
+const DUMP: &[u8] = include_bytes!("../samples/fake_game.dump");
+
+struct Frame {
+    /// of responses, whose requests are not dumped
+    method: Option<String>,
+    buf: Bytes,
+}
+
+fn parser() -> Parser {
+    let pool = DescriptorPool::decode(include_bytes!("../liqi_config/liqi.desc").as_slice())
+        .expect("liqi.desc");
+    let proto_json: JsonValue =
+        serde_json::from_str(include_str!("../liqi_config/liqi.json")).expect("liqi.json");
+    Parser::new(Box::leak(Box::new(proto_json)), Box::leak(Box::new(pool)))
+}
+
+fn frames() -> Vec<Frame> {
+    read_frames(DUMP)
+        .into_iter()
+        .map(|frame| Frame {
+            method: frame.method,
+            buf: Bytes::from(frame.buf.expect("broken sample dump")),
+        })
+        .collect()
+}
+
+fn parse(parser: &mut Parser, frame: &Frame) -> anyhow::Result<LiqiMessage> {
+    if let (Some(method), [3, a, b, ..]) = (&frame.method, frame.buf.as_ref()) {
+        parser.expect_response(u16::from_le_bytes([*a, *b]), method)?;
+    }
+    parser.parse(frame.buf.clone())
+}
+
+/// The xored `data` of every action in the game.
+fn actions(frames: &[Frame]) -> Vec<Vec<u8>> {
+    frames
+        .iter()
+        .filter(|frame| frame.buf[0] == 1)
+        .filter_map(|frame| BaseMessage::decode(&frame.buf[1..]).ok())
+        .filter(|msg| msg.method_name == ".lq.ActionPrototype")
+        .filter_map(|msg| ActionPrototype::decode(msg.data.as_slice()).ok())
+        .map(|action| action.data)
+        .collect()
+}
+
+fn bench(c: &mut Criterion) {
+    let frames = frames();
+    let bytes = frames.iter().map(|f| f.buf.len() as u64).sum();
+
+    let mut group = c.benchmark_group("game");
+    group.throughput(Throughput::Bytes(bytes));
+    // one parser for the whole run, as the helper keeps, so descriptors are cached
+    let mut parser = parser();
+    group.bench_function("parse", |b| {
+        b.iter(|| {
+            for frame in &frames {
+                black_box(parse(&mut parser, frame).expect("undecodable frame"));
+            }
+        })
+    });
+    group.bench_function("base_message", |b| {
+        b.iter(|| {
+            for frame in &frames {
+                let header = if frame.buf[0] == 1 { 1 } else { 3 };
+                black_box(BaseMessage::decode(&frame.buf[header..]).expect("no base message"));
+            }
+        })
+    });
+    group.finish();
+
+    let actions = actions(&frames);
+    let mut group = c.benchmark_group("actions");
+    group.throughput(Throughput::Bytes(
+        actions.iter().map(|a| a.len() as u64).sum(),
+    ));
+    group.bench_function("wtf_decode", |b| {
+        let mut buf = actions.clone();
+        b.iter(|| {
+            for action in buf.iter_mut() {
+                wtf_decode(black_box(action));
+            }
+        })
+    });
+    group.finish();
+
+    // a GameRestore snapshot or a replayed record is one long buffer
+    let mut snapshot = actions.concat().repeat(64);
+    let mut group = c.benchmark_group("snapshot");
+    group.throughput(Throughput::Bytes(snapshot.len() as u64));
+    group.bench_function("wtf_decode", |b| {
+        b.iter(|| wtf_decode(black_box(&mut snapshot)))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench);
+criterion_main!(benches);
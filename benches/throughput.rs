@@ -0,0 +1,314 @@
+//! End-to-end latency of the whole proxy: a [`MockServer`] streams the notify
+//! frames of the bundled sample game through a running proxy to a websocket
+//! client, the proxy feeding them to a `raw` http sink served here.
+//!
+//! `cargo bench --bench throughput -- --rate 2000 --frames 20000 --max-p99-ms 50`
+//!
+//! Prints the percentiles of server to client (forwarding) and server to sink
+//! (the helper pipeline) latency, and fails when the sink p99 is over
+//! `--max-p99-ms`.
+
+use anyhow::{anyhow, bail, Context, Result};
+use bytes::Bytes;
+use http_body_util::{BodyExt, Full};
+use hudsucker::{
+    futures::StreamExt,
+    hyper::{body::Incoming, server::conn::http1, service::service_fn, Request, Response},
+    hyper_util::rt::TokioIo,
+    tokio_tungstenite::tungstenite::Message,
+};
+use majsoul_max_rs::mock::{self, connect_through, MockOptions, MockServer, Recording};
+use serde_json::json;
+use std::{
+    collections::{HashMap, VecDeque},
+    convert::Infallible,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    process::{Child, Command, ExitCode, Stdio},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::mpsc::UnboundedReceiver,
+    time::{sleep, timeout},
+};
+
+/// How long the frames still on their way are waited for once all are sent.
+const GRACE: Duration = Duration::from_secs(5);
+
+struct Args {
+    /// frames per second sent by the server
+    rate: u64,
+    frames: usize,
+    max_p99_ms: Option<f64>,
+}
+
+impl Args {
+    fn parse() -> Result<Self> {
+        let mut args = Args {
+            rate: 1000,
+            frames: 10_000,
+            max_p99_ms: None,
+        };
+        let mut argv = std::env::args().skip(1);
+        while let Some(arg) = argv.next() {
+            let mut value = || argv.next().ok_or(anyhow!("{} needs a value", arg));
+            match arg.as_str() {
+                "--rate" => args.rate = value()?.parse()?,
+                "--frames" => args.frames = value()?.parse()?,
+                "--max-p99-ms" => args.max_p99_ms = Some(value()?.parse()?),
+                // e.g. `--bench` from cargo
+                _ => (),
+            }
+        }
+        Ok(args)
+    }
+}
+
+/// When each frame was sent, waiting for the client and the sink.
+struct Sent {
+    /// of the server, taken as the frames are looked up
+    rx: UnboundedReceiver<mock::Sent>,
+    /// in order, the websocket keeps it
+    client: Vec<Instant>,
+    /// by content, the same frame recurring as the game is repeated
+    sink: HashMap<Bytes, VecDeque<Instant>>,
+}
+
+impl Sent {
+    fn new(rx: UnboundedReceiver<mock::Sent>) -> Self {
+        Sent {
+            rx,
+            client: vec![],
+            sink: HashMap::new(),
+        }
+    }
+
+    /// Take in the frames the server sent so far.
+    fn update(&mut self) -> &mut Self {
+        while let Ok(sent) = self.rx.try_recv() {
+            self.client.push(sent.at);
+            self.sink.entry(sent.frame).or_default().push_back(sent.at);
+        }
+        self
+    }
+}
+
+#[derive(Default)]
+struct Received {
+    client: Vec<Duration>,
+    sink: Vec<Duration>,
+}
+
+type Shared<T> = Arc<Mutex<T>>;
+
+/// The proxy under test, killed when dropped.
+struct Proxy(Child);
+
+impl Drop for Proxy {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+/// A free port of localhost, for the proxy to listen on.
+async fn free_port() -> Result<SocketAddr> {
+    Ok(TcpListener::bind("127.0.0.1:0").await?.local_addr()?)
+}
+
+/// A config folder of the proxy at `proxy` sending every frame to `sink`.
+fn config_dir(proxy: SocketAddr, sink: SocketAddr) -> Result<PathBuf> {
+    let source = Path::new(env!("CARGO_MANIFEST_DIR")).join("liqi_config");
+    let dir = std::env::temp_dir().join(format!("majsoul_throughput_{}", std::process::id()));
+    std::fs::create_dir_all(&dir)?;
+    for file in ["liqi.desc", "liqi.json"] {
+        std::fs::copy(source.join(file), dir.join(file))?;
+    }
+    let mut settings: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(source.join("settings.json"))?)?;
+    settings["proxyAddr"] = json!(proxy.to_string());
+    settings["adminAddr"] = json!("");
+    settings["autoUpdate"] = json!(0);
+    settings["modSwitch"] = json!(0);
+    settings["logLevel"] = json!("error");
+    settings["sinks"] = json!([{
+        "name": "bench",
+        "type": "http",
+        "url": format!("http://{}/", sink),
+        "format": "raw",
+    }]);
+    std::fs::write(dir.join("settings.json"), settings.to_string())?;
+    Ok(dir)
+}
+
+async fn start_proxy(dir: &Path, addr: SocketAddr) -> Result<Proxy> {
+    let child = Command::new(env!("CARGO_BIN_EXE_majsoul_max_rs"))
+        .arg("-c")
+        .arg(dir)
+        .stdout(Stdio::null())
+        .stderr(Stdio::inherit())
+        .spawn()
+        .context("Failed to start the proxy")?;
+    let proxy = Proxy(child);
+    let started = Instant::now();
+    while TcpStream::connect(addr).await.is_err() {
+        if started.elapsed() > Duration::from_secs(20) {
+            bail!("The proxy did not listen on {}", addr);
+        }
+        sleep(Duration::from_millis(50)).await;
+    }
+    Ok(proxy)
+}
+
+/// Receive the sink's posts, each the raw frame.
+async fn serve_sink(listener: TcpListener, sent: Shared<Sent>, received: Shared<Received>) {
+    while let Ok((stream, _)) = listener.accept().await {
+        let (sent, received) = (sent.clone(), received.clone());
+        tokio::spawn(async move {
+            let service = service_fn(move |req: Request<Incoming>| {
+                let (sent, received) = (sent.clone(), received.clone());
+                async move {
+                    if let Ok(body) = req.into_body().collect().await {
+                        let at = Instant::now();
+                        let frame = body.to_bytes();
+                        let sent_at = sent
+                            .lock()
+                            .ok()
+                            .and_then(|mut s| s.update().sink.get_mut(&frame)?.pop_front());
+                        if let (Some(sent_at), Ok(mut received)) = (sent_at, received.lock()) {
+                            received.sink.push(at - sent_at);
+                        }
+                    }
+                    Ok::<_, Infallible>(Response::new(Full::new(Bytes::new())))
+                }
+            });
+            let _ = http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .await;
+        });
+    }
+}
+
+/// Open a websocket to `game` through the proxy and time the frames it gets.
+async fn run_client(
+    proxy: SocketAddr,
+    game: &str,
+    args: &Args,
+    sent: Shared<Sent>,
+    received: Shared<Received>,
+) -> Result<()> {
+    let mut ws = connect_through(proxy, game).await?;
+    let mut got = 0;
+    while got < args.frames {
+        let Ok(Some(message)) = timeout(GRACE, ws.next()).await else {
+            break;
+        };
+        if let Message::Binary(_) = message? {
+            let at = Instant::now();
+            let sent_at = sent
+                .lock()
+                .ok()
+                .and_then(|mut s| s.update().client.get(got).copied());
+            if let (Some(sent_at), Ok(mut received)) = (sent_at, received.lock()) {
+                received.client.push(at - sent_at);
+            }
+            got += 1;
+        }
+    }
+    ws.close(None).await?;
+    Ok(())
+}
+
+fn percentile(sorted: &[Duration], p: f64) -> f64 {
+    let i = ((sorted.len() as f64 * p).ceil() as usize).clamp(1, sorted.len()) - 1;
+    sorted[i].as_secs_f64() * 1000.0
+}
+
+/// Print the percentiles of `latencies`, returning the p99 in ms.
+fn report(label: &str, mut latencies: Vec<Duration>, sent: usize) -> Option<f64> {
+    if latencies.is_empty() {
+        println!("{:<8} 0/{} received", label, sent);
+        return None;
+    }
+    latencies.sort();
+    let p99 = percentile(&latencies, 0.99);
+    println!(
+        "{:<8} {}/{} received, ms p50 {:.2} p90 {:.2} p99 {:.2} p99.9 {:.2} max {:.2}",
+        label,
+        latencies.len(),
+        sent,
+        percentile(&latencies, 0.5),
+        percentile(&latencies, 0.9),
+        p99,
+        percentile(&latencies, 0.999),
+        percentile(&latencies, 1.0),
+    );
+    Some(p99)
+}
+
+async fn bench(args: Args) -> Result<bool> {
+    let (game, sent) = MockServer::start(
+        Recording::sample(),
+        MockOptions {
+            rate: args.rate,
+            frames: Some(args.frames),
+            ..Default::default()
+        },
+    )
+    .await?;
+    let sink = TcpListener::bind("127.0.0.1:0").await?;
+    let proxy_addr = free_port().await?;
+    let dir = config_dir(proxy_addr, sink.local_addr()?)?;
+    let proxy = start_proxy(&dir, proxy_addr).await?;
+
+    let sent = Arc::new(Mutex::new(Sent::new(sent)));
+    let received: Shared<Received> = Default::default();
+    tokio::spawn(serve_sink(sink, sent.clone(), received.clone()));
+    let started = Instant::now();
+    run_client(proxy_addr, &game.url(), &args, sent, received.clone()).await?;
+    // the sink lags behind the client
+    let settled = Instant::now();
+    while received.lock().map_or(0, |r| r.sink.len()) < args.frames && settled.elapsed() < GRACE {
+        sleep(Duration::from_millis(50)).await;
+    }
+    let elapsed = started.elapsed();
+    drop(proxy);
+    let _ = std::fs::remove_dir_all(&dir);
+
+    let received = std::mem::take(&mut *received.lock().map_err(|_| anyhow!("poisoned"))?);
+    println!(
+        "{} frames at {}/s in {:.2}s",
+        args.frames,
+        args.rate,
+        elapsed.as_secs_f64()
+    );
+    report("client", received.client, args.frames);
+    let p99 = report("sink", received.sink, args.frames);
+    Ok(match (args.max_p99_ms, p99) {
+        (Some(max), Some(p99)) if p99 > max => {
+            println!("sink p99 {:.2} ms is over the target of {} ms", p99, max);
+            false
+        }
+        (Some(_), None) => false,
+        _ => true,
+    })
+}
+
+fn main() -> ExitCode {
+    let result = Args::parse().and_then(|args| {
+        tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()?
+            .block_on(bench(args))
+    });
+    match result {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(e) => {
+            eprintln!("{:?}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
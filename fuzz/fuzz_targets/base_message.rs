@@ -0,0 +1,13 @@
+//! The method name and data of a frame, after its type and id.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use majsoul_max_rs::{base::BaseMessage, lq::ActionPrototype};
+use prost::Message;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(msg) = BaseMessage::decode(data) {
+        let _ = ActionPrototype::decode(msg.data.as_slice());
+    }
+});
@@ -0,0 +1,30 @@
+//! The data of an action of a known type, before the xor and base64.
+
+#![no_main]
+
+use base64::prelude::*;
+use libfuzzer_sys::fuzz_target;
+use majsoul_max_rs::parser::{decode_action, wtf_decode};
+use once_cell::sync::Lazy;
+use prost_reflect::DescriptorPool;
+
+static POOL: Lazy<DescriptorPool> = Lazy::new(|| {
+    DescriptorPool::decode(include_bytes!("../../liqi_config/liqi.desc").as_slice())
+        .expect("liqi.desc")
+});
+
+/// e.g. `ActionDiscardTile`
+static ACTIONS: Lazy<Vec<String>> = Lazy::new(|| {
+    POOL.all_messages()
+        .filter(|m| m.package_name() == "lq" && m.name().starts_with("Action"))
+        .map(|m| m.name().to_string())
+        .collect()
+});
+
+fuzz_target!(|input: (u8, &[u8])| {
+    let (action, data) = input;
+    let name = &ACTIONS[action as usize % ACTIONS.len()];
+    let mut data = data.to_vec();
+    wtf_decode(&mut data);
+    let _ = decode_action(name, &BASE64_STANDARD.encode(data), &POOL);
+});
@@ -0,0 +1,22 @@
+//! A frame as the mod sees it: a byte whose low bit sets `from_client`, then
+//! the frame. Without `lqc.lqbin` the modder has no sheets, which is enough
+//! to reach the envelope and id checks of every message type.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use majsoul_max_rs::modder::Modder;
+use once_cell::sync::Lazy;
+use tokio::runtime::{Builder, Runtime};
+
+static RUNTIME: Lazy<Runtime> =
+    Lazy::new(|| Builder::new_current_thread().build().expect("runtime"));
+
+static MODDER: Lazy<Modder> = Lazy::new(Modder::default);
+
+fuzz_target!(|data: &[u8]| {
+    let Some((flags, frame)) = data.split_first() else {
+        return;
+    };
+    let _ = RUNTIME.block_on(MODDER.modify(frame.to_vec(), flags & 1 == 1));
+});
@@ -0,0 +1,38 @@
+//! Frames of a connection, as the proxy hands them to the parser: a byte
+//! whose low bit sets `lazy`, then frames of `[u16 LE length][frame]`, e.g.
+//! from the frames of `samples/fake_game.dump` to seed the corpus.
+
+#![no_main]
+
+use bytes::Bytes;
+use libfuzzer_sys::fuzz_target;
+use majsoul_max_rs::parser::Parser;
+use once_cell::sync::Lazy;
+use prost_reflect::DescriptorPool;
+use serde_json::Value as JsonValue;
+
+static POOL: Lazy<DescriptorPool> = Lazy::new(|| {
+    DescriptorPool::decode(include_bytes!("../../liqi_config/liqi.desc").as_slice())
+        .expect("liqi.desc")
+});
+
+static PROTO_JSON: Lazy<JsonValue> = Lazy::new(|| {
+    serde_json::from_str(include_str!("../../liqi_config/liqi.json")).expect("liqi.json")
+});
+
+// one parser for all of them, so responses can find their requests
+fuzz_target!(|data: &[u8]| {
+    let Some((flags, mut rest)) = data.split_first() else {
+        return;
+    };
+    let mut parser = Parser::new(&PROTO_JSON, &POOL);
+    parser.lazy = flags & 1 == 1;
+    while let [a, b, tail @ ..] = rest {
+        let len = (u16::from_le_bytes([*a, *b]) as usize).min(tail.len());
+        let (frame, tail) = tail.split_at(len);
+        rest = tail;
+        if let Ok(msg) = parser.parse(Bytes::copy_from_slice(frame)) {
+            let _ = msg.json();
+        }
+    }
+});
@@ -0,0 +1,12 @@
+//! Dumps given to `parse`: base64 lines, binary or zstd-compressed.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use majsoul_max_rs::dump::{compressed_frame_count, read_frames_from};
+
+fuzz_target!(|input: (u16, &[u8])| {
+    let (from, bytes) = input;
+    let _ = read_frames_from(bytes, from as usize);
+    let _ = compressed_frame_count(&mut std::io::Cursor::new(bytes));
+});
@@ -0,0 +1,250 @@
+use crate::{parser::ParseError, sink::SinkHealth};
+use chrono::{DateTime, Local};
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+/// Decoded messages and log lines kept for the dashboard.
+const KEPT: usize = 200;
+
+/// What the proxy is doing right now, for the `--tui` dashboard.
+pub static ACTIVITY: Lazy<Activity> = Lazy::new(Activity::default);
+
+#[derive(Debug, Clone)]
+pub struct Decoded {
+    pub time: DateTime<Local>,
+    pub direction: char,
+    pub method: String,
+    pub spectated: bool,
+}
+
+/// Depth of the queue between the proxy and the helper.
+#[derive(Debug, Clone, Serialize)]
+pub struct QueueStats {
+    pub depth: usize,
+    pub peak: usize,
+    pub capacity: usize,
+    pub dropped: u64,
+}
+
+/// A way a sink failed to take a message.
+#[derive(Debug, Clone, Copy)]
+pub enum SinkFailure {
+    /// its format could not encode the event
+    Encode,
+    /// its queue was full
+    QueueFull,
+    /// sending to the target failed
+    Send,
+}
+
+/// Failures since start by kind, so a spike, e.g. after a client update, shows
+/// up in the status.
+#[derive(Debug, Clone, Serialize)]
+pub struct ErrorStats {
+    /// frames by [`ParseError`] kind, errors of no kind as `other`
+    pub parse: BTreeMap<&'static str, u64>,
+    pub sink_encode: u64,
+    pub sink_queue_full: u64,
+    pub sink_send: u64,
+    /// panics caught instead of taking down the proxy
+    pub panics: u64,
+    /// websockets closed as the game server went silent
+    pub upstream_stalls: u64,
+}
+
+#[derive(Default)]
+pub struct Activity {
+    /// open game websockets
+    sessions: AtomicUsize,
+    /// frames received by the helper
+    frames: AtomicU64,
+    /// frames waiting for the helper when it took the last one
+    queued: AtomicUsize,
+    peak_queued: AtomicUsize,
+    queue_capacity: AtomicUsize,
+    /// frames dropped as the helper queue was full
+    dropped_frames: AtomicU64,
+    /// by the index of the kind in [`ParseError::ALL`], then the other errors
+    parse_errors: [AtomicU64; ParseError::ALL.len() + 1],
+    /// by [`SinkFailure`]
+    sink_failures: [AtomicU64; 3],
+    /// panics caught, each closing its session or skipping its message
+    panics: AtomicU64,
+    /// websockets closed by the [`crate::watchdog`]
+    upstream_stalls: AtomicU64,
+    decoded: Mutex<VecDeque<Decoded>>,
+    logs: Mutex<VecDeque<String>>,
+    /// shared with the running sinks, replaced when they restart
+    sinks: Mutex<Vec<Arc<Mutex<SinkHealth>>>>,
+}
+
+fn push<T>(queue: &Mutex<VecDeque<T>>, item: T) {
+    if let Ok(mut queue) = queue.lock() {
+        if queue.len() == KEPT {
+            queue.pop_front();
+        }
+        queue.push_back(item);
+    }
+}
+
+impl Activity {
+    /// Counts the websocket as open until the guard is dropped.
+    pub fn session(&self) -> SessionGuard<'_> {
+        self.sessions.fetch_add(1, Ordering::Relaxed);
+        SessionGuard(self)
+    }
+
+    pub fn sessions(&self) -> usize {
+        self.sessions.load(Ordering::Relaxed)
+    }
+
+    pub fn frame(&self) {
+        self.frames.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.frames.load(Ordering::Relaxed)
+    }
+
+    pub fn set_queue_capacity(&self, capacity: usize) {
+        self.queue_capacity.store(capacity, Ordering::Relaxed);
+    }
+
+    pub fn queued(&self, depth: usize) {
+        self.queued.store(depth, Ordering::Relaxed);
+        self.peak_queued.fetch_max(depth, Ordering::Relaxed);
+    }
+
+    pub fn frame_dropped(&self) {
+        self.dropped_frames.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn queue(&self) -> QueueStats {
+        QueueStats {
+            depth: self.queued.load(Ordering::Relaxed),
+            peak: self.peak_queued.load(Ordering::Relaxed),
+            capacity: self.queue_capacity.load(Ordering::Relaxed),
+            dropped: self.dropped_frames.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn parse_failed(&self, e: &anyhow::Error) {
+        let i = ParseError::of(e)
+            .and_then(|kind| ParseError::ALL.iter().position(|k| *k == kind))
+            .unwrap_or(ParseError::ALL.len());
+        self.parse_errors[i].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn sink_failed(&self, failure: SinkFailure) {
+        self.sink_failures[failure as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn panicked(&self) {
+        self.panics.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn stalled(&self) {
+        self.upstream_stalls.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn errors(&self) -> ErrorStats {
+        let names = ParseError::ALL.iter().map(|k| k.name()).chain(["other"]);
+        let sink =
+            |failure: SinkFailure| self.sink_failures[failure as usize].load(Ordering::Relaxed);
+        ErrorStats {
+            parse: names
+                .zip(&self.parse_errors)
+                .map(|(name, count)| (name, count.load(Ordering::Relaxed)))
+                .collect(),
+            sink_encode: sink(SinkFailure::Encode),
+            sink_queue_full: sink(SinkFailure::QueueFull),
+            sink_send: sink(SinkFailure::Send),
+            panics: self.panics.load(Ordering::Relaxed),
+            upstream_stalls: self.upstream_stalls.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn decoded(&self, direction: char, method: &str, spectated: bool) {
+        push(
+            &self.decoded,
+            Decoded {
+                time: Local::now(),
+                direction,
+                method: method.to_string(),
+                spectated,
+            },
+        );
+    }
+
+    /// The last `n` decoded messages, newest first.
+    pub fn recent(&self, n: usize) -> Vec<Decoded> {
+        self.decoded
+            .lock()
+            .map(|q| q.iter().rev().take(n).cloned().collect())
+            .unwrap_or_default()
+    }
+
+    pub fn log(&self, line: String) {
+        push(&self.logs, line);
+    }
+
+    /// The last `n` log lines, oldest first.
+    pub fn logs(&self, n: usize) -> Vec<String> {
+        self.logs
+            .lock()
+            .map(|q| q.iter().skip(q.len().saturating_sub(n)).cloned().collect())
+            .unwrap_or_default()
+    }
+
+    pub fn set_sinks(&self, sinks: Vec<Arc<Mutex<SinkHealth>>>) {
+        if let Ok(mut current) = self.sinks.lock() {
+            *current = sinks;
+        }
+    }
+
+    pub fn sinks(&self) -> Vec<SinkHealth> {
+        self.sinks
+            .lock()
+            .map(|sinks| {
+                sinks
+                    .iter()
+                    .map(|h| h.lock().map(|h| h.clone()).unwrap_or_default())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+pub struct SessionGuard<'a>(&'a Activity);
+
+impl Drop for SessionGuard<'_> {
+    fn drop(&mut self) {
+        self.0.sessions.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Log output collected for the dashboard instead of the console.
+#[derive(Clone, Copy, Default)]
+pub struct LogWriter;
+
+impl std::io::Write for LogWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        for line in String::from_utf8_lossy(buf).lines() {
+            if !line.trim().is_empty() {
+                ACTIVITY.log(line.to_string());
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,133 @@
+use crate::{record::Record, SETTINGS};
+use anyhow::Result;
+use serde_json::{json, Value as JsonValue};
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+};
+
+/// Account fields dropped from a shared record, they only serve to recognize the player.
+const PROFILE_FIELDS: [&str; 6] = [
+    "avatar_id",
+    "avatar_frame",
+    "character",
+    "title",
+    "verified",
+    "views",
+];
+
+/// Replaces identities by pseudonyms derived from a secret salt, so the same
+/// player gets the same pseudonym in every record anonymized on this machine.
+pub struct Anonymizer {
+    salt: String,
+}
+
+impl Anonymizer {
+    /// Use the salt in `anonymize_salt` of the config dir, created on first use.
+    pub fn new() -> Result<Self> {
+        let path = SETTINGS.config_dir().join("anonymize_salt");
+        let salt = match std::fs::read_to_string(&path) {
+            Ok(salt) if !salt.trim().is_empty() => salt.trim().to_string(),
+            _ => {
+                let salt = format!(
+                    "{:016x}{:016x}",
+                    RandomState::new().build_hasher().finish(),
+                    RandomState::new().build_hasher().finish()
+                );
+                std::fs::write(&path, &salt)?;
+                salt
+            }
+        };
+        Ok(Anonymizer { salt })
+    }
+
+    /// FNV-1a of the salt and `value`, stable across builds unlike `DefaultHasher`.
+    fn hash(&self, value: &str) -> u64 {
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        for b in self.salt.bytes().chain([0]).chain(value.bytes()) {
+            hash ^= b as u64;
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+        hash
+    }
+
+    fn account_id(&self, id: u64) -> u64 {
+        match id {
+            // AI
+            0 => 0,
+            id => self.hash(&id.to_string()) % 90_000_000 + 10_000_000,
+        }
+    }
+
+    fn nickname(&self, id: u64, nickname: &str) -> String {
+        match id {
+            0 if nickname.is_empty() => String::new(),
+            0 => format!("玩家{:06x}", self.hash(nickname) & 0xff_ffff),
+            id => format!("玩家{:06x}", self.hash(&id.to_string()) & 0xff_ffff),
+        }
+    }
+
+    /// A made up uuid of the same layout, keeping the date prefix.
+    fn uuid(&self, uuid: &str) -> String {
+        let hex = format!(
+            "{:016x}{:016x}",
+            self.hash(uuid),
+            self.hash(&format!("{}#", uuid))
+        );
+        let date = uuid.split('-').next().filter(|d| d.len() == 6);
+        format!(
+            "{}-{}-{}-{}-{}-{}",
+            date.unwrap_or("000000"),
+            &hex[0..8],
+            &hex[8..12],
+            &hex[12..16],
+            &hex[16..20],
+            &hex[20..32]
+        )
+    }
+
+    /// Rewrite account ids, nicknames and the uuid, drop profile details and
+    /// anything that is not a game action, such as chat.
+    pub fn anonymize(&self, record: &mut Record) {
+        record.actions.retain(|a| a.name.starts_with("Record"));
+        if let Some(uuid) = record.head["uuid"].as_str() {
+            record.head["uuid"] = json!(self.uuid(uuid));
+        }
+        // a friend room number can be looked up
+        if let Some(meta) = record.head["config"]["meta"].as_object_mut() {
+            meta.remove("room_id");
+        }
+        for account in record.head["accounts"].as_array_mut().into_iter().flatten() {
+            if let Some(account) = account.as_object_mut() {
+                for field in PROFILE_FIELDS {
+                    account.remove(field);
+                }
+            }
+        }
+        self.rewrite(&mut record.head);
+        for action in record.actions.iter_mut() {
+            self.rewrite(&mut action.data);
+        }
+    }
+
+    /// Replace every `account_id` and `nickname` field, wherever it is nested.
+    fn rewrite(&self, value: &mut JsonValue) {
+        match value {
+            JsonValue::Object(map) => {
+                let id = map.get("account_id").and_then(|id| id.as_u64());
+                if let Some(id) = id {
+                    map.insert("account_id".to_string(), json!(self.account_id(id)));
+                }
+                if let Some(nickname) = map.get("nickname").and_then(|n| n.as_str()) {
+                    let nickname = self.nickname(id.unwrap_or_default(), nickname);
+                    map.insert("nickname".to_string(), json!(nickname));
+                }
+                for (_, v) in map.iter_mut() {
+                    self.rewrite(v);
+                }
+            }
+            JsonValue::Array(items) => items.iter_mut().for_each(|v| self.rewrite(v)),
+            _ => (),
+        }
+    }
+}
@@ -0,0 +1,238 @@
+use crate::{
+    activity::ACTIVITY, contest::CONTEST_STATE, flight, memory, notes::NOTES, safety,
+    session::SESSION, shanten, state::GAME_STATE, SETTINGS,
+};
+use anyhow::{anyhow, Result};
+use bytes::Bytes;
+use http_body_util::{BodyExt, Full, LengthLimitError, Limited};
+use hudsucker::{
+    hyper::{
+        body::Incoming, header, header::HeaderValue, server::conn::http1, service::service_fn,
+        Method, Request, Response, StatusCode,
+    },
+    hyper_util::rt::TokioIo,
+};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value as JsonValue};
+use std::{convert::Infallible, net::SocketAddr, time::Instant};
+use tokio::net::TcpListener;
+use tracing::{info, warn};
+
+static STARTED: Lazy<Instant> = Lazy::new(Instant::now);
+
+/// Longest note accepted by `POST /opponents/{account_id}/note`.
+const NOTE_LIMIT: usize = 4 << 10;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiConfig {
+    /// origins of the web pages, e.g. stream widgets, that may read the API,
+    /// like `http://localhost:8080`; other pages are refused
+    #[serde(default)]
+    pub allowed_origins: Vec<String>,
+}
+
+/// Serve the admin API on `addr` until the listener fails.
+pub async fn serve(addr: SocketAddr) -> Result<()> {
+    Lazy::force(&STARTED);
+    let listener = TcpListener::bind(addr).await?;
+    info!("Admin API listening on http://{}", addr);
+    loop {
+        let (stream, _) = listener.accept().await?;
+        tokio::spawn(async move {
+            if let Err(e) = http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service_fn(handle))
+                .await
+            {
+                warn!("Admin API connection error: {}", e);
+            }
+        });
+    }
+}
+
+async fn handle(req: Request<Incoming>) -> Result<Response<Full<Bytes>>, Infallible> {
+    let origin = allowed_origin(&req);
+    let mut res = answer(req, origin.is_some()).await;
+    if let Some(origin) = origin {
+        let headers = res.headers_mut();
+        headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+        headers.insert(header::VARY, HeaderValue::from_static("origin"));
+    }
+    Ok(res)
+}
+
+async fn answer(req: Request<Incoming>, allowed: bool) -> Response<Full<Bytes>> {
+    if from_web_page(&req) {
+        if req.method() == Method::POST {
+            return respond(
+                StatusCode::FORBIDDEN,
+                json!({"error": "writes from web pages are not allowed"}),
+            );
+        }
+        if !allowed {
+            return respond(
+                StatusCode::FORBIDDEN,
+                json!({"error": "origin not in api.allowedOrigins"}),
+            );
+        }
+    }
+    if req.method() == Method::POST && req.uri().path() == "/debug/dump" {
+        return match flight::dump().await {
+            Ok(dir) => respond(StatusCode::OK, json!({ "dir": dir })),
+            Err(e) => respond(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({"error": e.to_string()}),
+            ),
+        };
+    }
+    if req.method() == Method::POST {
+        return match set_note(req).await {
+            Ok(Some(body)) => respond(StatusCode::OK, body),
+            Ok(None) => respond(StatusCode::NOT_FOUND, json!({"error": "not found"})),
+            Err(e) if e.is::<LengthLimitError>() => respond(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                json!({"error": format!("notes are limited to {} bytes", NOTE_LIMIT)}),
+            ),
+            Err(e) => respond(StatusCode::BAD_REQUEST, json!({"error": e.to_string()})),
+        };
+    }
+    if req.method() != Method::GET {
+        return respond(StatusCode::METHOD_NOT_ALLOWED, JsonValue::Null);
+    }
+    match route(req.uri().path()).await {
+        Some(body) => respond(StatusCode::OK, body),
+        None => respond(StatusCode::NOT_FOUND, json!({"error": "not found"})),
+    }
+}
+
+/// The `Origin` of `req` when it is in `api.allowedOrigins`.
+fn allowed_origin(req: &Request<Incoming>) -> Option<HeaderValue> {
+    let origin = req.headers().get(header::ORIGIN)?;
+    let text = origin.to_str().ok()?.trim_end_matches('/');
+    SETTINGS
+        .api
+        .allowed_origins
+        .iter()
+        .any(|allowed| allowed.trim_end_matches('/') == text)
+        .then(|| origin.clone())
+}
+
+/// Whether `req` was sent by a web page, which may only read the API, and
+/// only from `api.allowedOrigins`: browsers send `Origin` with every POST and
+/// cross-origin GET, or at least `Sec-Fetch-Site`, curl and scripts don't.
+fn from_web_page(req: &Request<Incoming>) -> bool {
+    let headers = req.headers();
+    headers.contains_key(header::ORIGIN)
+        || headers
+            .get("sec-fetch-site")
+            .is_some_and(|site| site != "none")
+}
+
+/// `POST /opponents/{account_id}/note` with the note as body.
+async fn set_note(req: Request<Incoming>) -> Result<Option<JsonValue>> {
+    let Some(id) = req
+        .uri()
+        .path()
+        .strip_prefix("/opponents/")
+        .and_then(|rest| rest.strip_suffix("/note"))
+    else {
+        return Ok(None);
+    };
+    let account_id: u32 = id.parse()?;
+    let body = Limited::new(req.into_body(), NOTE_LIMIT)
+        .collect()
+        .await
+        .map_err(|e| match e.downcast::<LengthLimitError>() {
+            Ok(e) => anyhow!(*e),
+            Err(e) => anyhow!(e),
+        })?
+        .to_bytes();
+    let note = String::from_utf8(body.to_vec())?.trim().to_string();
+    let mut notes = NOTES.write().await;
+    notes.set_note(account_id, note)?;
+    Ok(Some(json!(notes.get(account_id))))
+}
+
+async fn route(path: &str) -> Option<JsonValue> {
+    if let Some(id) = path.strip_prefix("/opponents/") {
+        let account_id: u32 = id.trim_end_matches('/').parse().ok()?;
+        return Some(json!(NOTES.read().await.get(account_id)));
+    }
+    match path.trim_end_matches('/') {
+        "/contest" => return Some(json!(CONTEST_STATE.read().await.current)),
+        "/session" => return Some(json!(*SESSION.read().await)),
+        "/status" => return Some(status().await),
+        _ => (),
+    }
+    let state = GAME_STATE.read().await;
+    let players = &state.players;
+    Some(match path.trim_end_matches('/') {
+        "/state" => json!(*state),
+        "/state/round" => json!({
+            "round_wind": state.round_wind,
+            "ju": state.ju,
+            "honba": state.honba,
+            "riichi_sticks": state.riichi_sticks,
+            "pending_riichi": state.pending_riichi,
+            "riichi": players.iter().filter(|p| p.riichi).map(|p| p.seat).collect::<Vec<_>>(),
+            "left_tiles": state.left_tiles,
+            "dora_indicators": state.dora_indicators,
+            "ura_indicators": state.ura_indicators,
+            "current": state.current,
+            "result": state.result,
+        }),
+        "/state/hand" => match state.own() {
+            Some(own) => json!({"seat": own.seat, "hand": own.hand, "melds": own.melds}),
+            None => JsonValue::Null,
+        },
+        "/state/players" => json!(players),
+        "/state/discards" => json!(players.iter().map(|p| &p.discards).collect::<Vec<_>>()),
+        "/state/melds" => json!(players.iter().map(|p| &p.melds).collect::<Vec<_>>()),
+        "/state/scores" => json!(players.iter().map(|p| p.score).collect::<Vec<_>>()),
+        "/state/doras" => {
+            let (doras, uras) = state.doras();
+            json!({
+                "indicators": state.dora_indicators,
+                "doras": doras,
+                "ura_indicators": state.ura_indicators,
+                "uras": uras,
+            })
+        }
+        "/state/clocks" => json!(state.clocks),
+        "/state/analysis" => json!(shanten::analyze(&state)),
+        "/state/safety" => json!(safety::analyze(&state)),
+        _ => return None,
+    })
+}
+
+/// What `status` prints: the instance, its traffic, sinks and the game.
+async fn status() -> JsonValue {
+    let state = GAME_STATE.read().await;
+    json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "pid": std::process::id(),
+        "uptime": STARTED.elapsed().as_secs(),
+        "proxy_addr": SETTINGS.proxy_addr,
+        "sessions": ACTIVITY.sessions(),
+        "frames": ACTIVITY.frames(),
+        "queue": ACTIVITY.queue(),
+        "memory": memory::stats(),
+        "errors": ACTIVITY.errors(),
+        "sinks": ACTIVITY.sinks(),
+        "in_game": !state.players.is_empty(),
+        "game": state.game,
+        "session": *SESSION.read().await,
+    })
+}
+
+fn respond(status: StatusCode, body: JsonValue) -> Response<Full<Bytes>> {
+    let mut res = Response::new(Full::new(Bytes::from(body.to_string())));
+    *res.status_mut() = status;
+    let headers = res.headers_mut();
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/json"),
+    );
+    res
+}
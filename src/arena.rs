@@ -0,0 +1,238 @@
+//! Record files parsed into a per-thread bump arena, only used by `stats` of
+//! record files: `record convert` and `record export` keep building
+//! `serde_json::Value` trees, their converters take owned action data.
+
+use anyhow::Result;
+use bumpalo::{collections::Vec as BumpVec, Bump};
+use serde::{
+    de::{DeserializeSeed, MapAccess, SeqAccess, Visitor},
+    ser::{SerializeMap, SerializeSeq},
+    Deserializer, Serialize, Serializer,
+};
+use serde_json::Number;
+use std::{cell::RefCell, fmt, ops::Index};
+
+/// A JSON tree allocated in a [`Bump`], freed all at once when it is reset.
+#[derive(Debug, Clone, Copy)]
+pub enum ArenaValue<'b> {
+    Null,
+    Bool(bool),
+    Number(&'b Number),
+    String(&'b str),
+    Array(&'b [ArenaValue<'b>]),
+    Object(&'b [(&'b str, ArenaValue<'b>)]),
+}
+
+static NULL: ArenaValue<'static> = ArenaValue::Null;
+
+impl<'b> ArenaValue<'b> {
+    /// Parse `json` into `bump`.
+    pub fn parse(bump: &'b Bump, json: &[u8]) -> Result<Self> {
+        let mut de = serde_json::Deserializer::from_slice(json);
+        let value = Seed(bump).deserialize(&mut de)?;
+        de.end()?;
+        Ok(value)
+    }
+
+    pub fn as_str(&self) -> Option<&'b str> {
+        match self {
+            ArenaValue::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            ArenaValue::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            ArenaValue::Number(n) => n.as_u64(),
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            ArenaValue::Number(n) => n.as_i64(),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&'b [ArenaValue<'b>]> {
+        match self {
+            ArenaValue::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+}
+
+/// Null for a missing key, like `serde_json::Value`.
+impl<'b> Index<&str> for ArenaValue<'b> {
+    type Output = ArenaValue<'b>;
+
+    fn index(&self, key: &str) -> &Self::Output {
+        match self {
+            ArenaValue::Object(fields) => fields
+                .iter()
+                .find(|(k, _)| *k == key)
+                .map_or(&NULL, |(_, v)| v),
+            _ => &NULL,
+        }
+    }
+}
+
+impl<'b> Index<usize> for ArenaValue<'b> {
+    type Output = ArenaValue<'b>;
+
+    fn index(&self, i: usize) -> &Self::Output {
+        self.as_array()
+            .and_then(|items| items.get(i))
+            .unwrap_or(&NULL)
+    }
+}
+
+impl Serialize for ArenaValue<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            ArenaValue::Null => serializer.serialize_unit(),
+            ArenaValue::Bool(b) => serializer.serialize_bool(*b),
+            ArenaValue::Number(n) => n.serialize(serializer),
+            ArenaValue::String(s) => serializer.serialize_str(s),
+            ArenaValue::Array(items) => {
+                let mut seq = serializer.serialize_seq(Some(items.len()))?;
+                for item in items.iter() {
+                    seq.serialize_element(item)?;
+                }
+                seq.end()
+            }
+            ArenaValue::Object(fields) => {
+                let mut map = serializer.serialize_map(Some(fields.len()))?;
+                for (k, v) in fields.iter() {
+                    map.serialize_entry(k, v)?;
+                }
+                map.end()
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Seed<'b>(&'b Bump);
+
+impl<'de, 'b> DeserializeSeed<'de> for Seed<'b> {
+    type Value = ArenaValue<'b>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de, 'b> Visitor<'de> for Seed<'b> {
+    type Value = ArenaValue<'b>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("any JSON value")
+    }
+
+    fn visit_unit<E>(self) -> Result<Self::Value, E> {
+        Ok(ArenaValue::Null)
+    }
+
+    fn visit_none<E>(self) -> Result<Self::Value, E> {
+        Ok(ArenaValue::Null)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        self.deserialize(deserializer)
+    }
+
+    fn visit_bool<E>(self, b: bool) -> Result<Self::Value, E> {
+        Ok(ArenaValue::Bool(b))
+    }
+
+    fn visit_i64<E>(self, n: i64) -> Result<Self::Value, E> {
+        Ok(ArenaValue::Number(self.0.alloc(n.into())))
+    }
+
+    fn visit_u64<E>(self, n: u64) -> Result<Self::Value, E> {
+        Ok(ArenaValue::Number(self.0.alloc(n.into())))
+    }
+
+    fn visit_f64<E>(self, n: f64) -> Result<Self::Value, E> {
+        Ok(Number::from_f64(n).map_or(ArenaValue::Null, |n| ArenaValue::Number(self.0.alloc(n))))
+    }
+
+    fn visit_str<E>(self, s: &str) -> Result<Self::Value, E> {
+        Ok(ArenaValue::String(self.0.alloc_str(s)))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut items = BumpVec::with_capacity_in(seq.size_hint().unwrap_or_default(), self.0);
+        while let Some(item) = seq.next_element_seed(self)? {
+            items.push(item);
+        }
+        Ok(ArenaValue::Array(items.into_bump_slice()))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut fields = BumpVec::with_capacity_in(map.size_hint().unwrap_or_default(), self.0);
+        while let Some(key) = map.next_key_seed(Key(self.0))? {
+            fields.push((key, map.next_value_seed(self)?));
+        }
+        Ok(ArenaValue::Object(fields.into_bump_slice()))
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Key<'b>(&'b Bump);
+
+impl<'de, 'b> DeserializeSeed<'de> for Key<'b> {
+    type Value = &'b str;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_str(self)
+    }
+}
+
+impl<'de, 'b> Visitor<'de> for Key<'b> {
+    type Value = &'b str;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a key")
+    }
+
+    fn visit_str<E>(self, s: &str) -> Result<Self::Value, E> {
+        Ok(self.0.alloc_str(s))
+    }
+}
+
+thread_local! {
+    /// Arena of the messages parsed on this thread, reset after each.
+    static ARENA: RefCell<Bump> = RefCell::new(Bump::new());
+}
+
+/// Capacity above which the arena is not kept, e.g. after a huge record.
+const ARENA_MAX: usize = 16 << 20;
+
+/// Call `f` with `json` parsed into the arena of this thread, a new one when
+/// it is already in use, reset once `f` returns.
+pub fn with_parsed<T>(json: &[u8], f: impl FnOnce(&ArenaValue) -> T) -> Result<T> {
+    ARENA.with(|arena| {
+        let mut fresh = Bump::new();
+        let mut kept = arena.try_borrow_mut();
+        let bump = match kept.as_deref_mut() {
+            Ok(bump) => bump,
+            Err(_) => &mut fresh,
+        };
+        let out = ArenaValue::parse(bump, json).map(|value| f(&value));
+        match bump.allocated_bytes() > ARENA_MAX {
+            true => *bump = Bump::new(),
+            false => bump.reset(),
+        }
+        out
+    })
+}
@@ -0,0 +1,100 @@
+//! What the account did through the proxy: every request the client sent,
+//! its arguments with secrets masked, and the status of the response, a JSON
+//! line each in the `auditLog` file.
+
+use crate::{
+    parser::{LiqiMessage, MessageType},
+    SETTINGS,
+};
+use chrono::Local;
+use once_cell::sync::Lazy;
+use serde_json::{json, Value as JsonValue};
+use std::{fs::File, io::Write, net::SocketAddr, sync::Mutex};
+use tracing::error;
+
+/// Arguments written as `***`, also any ending in `_token` or `_password`.
+const SECRETS: &[&str] = &["password", "token", "access_token", "code", "secret"];
+
+static LOG: Lazy<Option<Mutex<File>>> = Lazy::new(|| {
+    let path = SETTINGS.audit_log.as_ref()?;
+    let opened = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path);
+    match opened {
+        Ok(file) => Some(Mutex::new(file)),
+        Err(e) => {
+            error!("Failed to open audit log {}: {}", path.display(), e);
+            None
+        }
+    }
+});
+
+/// Whether `method` is audited, a request and its response, not a notify.
+pub fn covers(method: &str) -> bool {
+    SETTINGS.audit_log.is_some() && method.split('.').count() == 4
+}
+
+/// Write the secrets in `value` as `***`, telling whether there were any.
+pub fn mask(value: &mut JsonValue) -> bool {
+    match value {
+        JsonValue::Object(map) => {
+            let mut masked = false;
+            for (key, value) in map.iter_mut() {
+                // e.g. `gen_access_token`, a flag
+                let secret = !value.is_boolean()
+                    && (SECRETS.contains(&key.as_str())
+                        || key.ends_with("_token")
+                        || key.ends_with("_password"));
+                masked |= match secret {
+                    true => {
+                        *value = json!("***");
+                        true
+                    }
+                    false => mask(value),
+                };
+            }
+            masked
+        }
+        JsonValue::Array(values) => values.iter_mut().fold(false, |masked, v| mask(v) | masked),
+        _ => false,
+    }
+}
+
+/// Write the request or response `msg` of the websocket of `session`.
+pub fn record(session: SocketAddr, msg: &LiqiMessage) {
+    let Some(log) = LOG.as_ref() else {
+        return;
+    };
+    let mut line = json!({
+        "time": Local::now().to_rfc3339(),
+        "session": session,
+        "type": msg.msg_type.name(),
+        "id": msg.id,
+        "method": msg.method_name.as_ref(),
+    });
+    match msg.msg_type {
+        MessageType::Request => {
+            let mut args = msg.data.clone();
+            mask(&mut args);
+            line["args"] = args;
+        }
+        MessageType::Response => {
+            let code = msg.data["error"]["code"].as_u64().unwrap_or_default();
+            line["status"] = match code {
+                0 => json!("ok"),
+                _ => json!("error"),
+            };
+            if code != 0 {
+                line["code"] = json!(code);
+            }
+        }
+        MessageType::Notify => return,
+    }
+    let mut bytes = line.to_string().into_bytes();
+    bytes.push(b'\n');
+    let written = log.lock().map(|mut file| file.write_all(&bytes));
+    if let Ok(Err(e)) = written {
+        error!("Failed to write audit log: {}", e);
+    }
+}
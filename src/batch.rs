@@ -0,0 +1,73 @@
+use anyhow::Result;
+use std::{
+    io::{IsTerminal, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread,
+};
+use tracing::warn;
+
+/// Run `job` on every file with one worker per core, returning the results in
+/// the order of `files`.
+///
+/// Progress is drawn on stderr when it is a terminal, failures are logged per
+/// file once all are done.
+pub fn run<T, F>(label: &str, files: &[PathBuf], job: F) -> Vec<Option<T>>
+where
+    T: Send,
+    F: Fn(&Path) -> Result<T> + Sync,
+{
+    let workers = thread::available_parallelism()
+        .map_or(1, |n| n.get())
+        .min(files.len().max(1));
+    let next = AtomicUsize::new(0);
+    let done = AtomicUsize::new(0);
+    let results: Mutex<Vec<Option<Result<T>>>> =
+        Mutex::new((0..files.len()).map(|_| None).collect());
+    let progress = std::io::stderr().is_terminal() && files.len() > 1;
+    thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                let Some(file) = files.get(i) else {
+                    break;
+                };
+                let result = job(file);
+                if let Ok(mut results) = results.lock() {
+                    results[i] = Some(result);
+                }
+                let n = done.fetch_add(1, Ordering::Relaxed) + 1;
+                if progress {
+                    let mut stderr = std::io::stderr().lock();
+                    let _ = write!(stderr, "\r{} {}/{}", label, n, files.len());
+                    let _ = stderr.flush();
+                }
+            });
+        }
+    });
+    if progress {
+        eprintln!();
+    }
+    let results = results.into_inner().unwrap_or_default();
+    let mut failed = 0;
+    let outputs = files
+        .iter()
+        .zip(results)
+        .map(|(file, result)| match result {
+            Some(Ok(output)) => Some(output),
+            Some(Err(e)) => {
+                failed += 1;
+                warn!("Failed on {}: {:?}", file.display(), e);
+                None
+            }
+            None => None,
+        })
+        .collect();
+    if failed > 0 {
+        warn!("{} of {} files failed", failed, files.len());
+    }
+    outputs
+}
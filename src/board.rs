@@ -0,0 +1,153 @@
+use crate::{
+    state::{GameState, MeldKind, RoundResult, Wind},
+    tile::{index34, notate, Notation},
+};
+use std::fmt::Write;
+
+pub(crate) fn wind_char(wind: Wind) -> char {
+    match wind {
+        Wind::East => '东',
+        Wind::South => '南',
+        Wind::West => '西',
+        Wind::North => '北',
+    }
+}
+
+/// Tiles in `notation`, sorted when `sort` is set.
+///
+/// mpsz is written compactly, e.g. `123m055p77z`.
+pub fn compact<S: AsRef<str>>(tiles: &[S], sort: bool, notation: Notation) -> String {
+    let mut tiles: Vec<&str> = tiles.iter().map(|t| t.as_ref()).collect();
+    if sort {
+        // red fives next to the normal ones
+        tiles.sort_by_key(|t| (index34(t).unwrap_or(usize::MAX), !t.starts_with('0')));
+    }
+    match notation {
+        Notation::Mpsz => {}
+        Notation::Unicode => return tiles.iter().map(|t| notate(t, notation)).collect(),
+        Notation::Tile136 => {
+            let codes: Vec<String> = tiles.iter().map(|t| notate(t, notation)).collect();
+            return codes.join(",");
+        }
+    }
+    let mut out = String::new();
+    let mut suit = None;
+    for tile in tiles {
+        let mut chars = tile.chars();
+        let (Some(num), Some(s)) = (chars.next(), chars.next()) else {
+            continue;
+        };
+        if suit.is_some_and(|prev| prev != s) {
+            out.push(suit.unwrap_or_default());
+        }
+        out.push(num);
+        suit = Some(s);
+    }
+    if let Some(s) = suit {
+        out.push(s);
+    }
+    out
+}
+
+/// Draw the tracked table as text, one block per seat.
+pub fn render(state: &GameState, notation: Notation) -> String {
+    let compact = |tiles: &[String], sort| compact(tiles, sort, notation);
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "{}{}局 {}本场 供托{} 余{} 宝牌指示: {}",
+        wind_char(state.round_wind),
+        state.ju + 1,
+        state.honba,
+        state.riichi_sticks,
+        state.left_tiles,
+        compact(&state.dora_indicators, false),
+    );
+    if !state.ura_indicators.is_empty() {
+        let _ = writeln!(out, "里宝牌指示: {}", compact(&state.ura_indicators, false));
+    }
+    for player in &state.players {
+        let mut flags = vec![];
+        if state.seat == Some(player.seat) {
+            flags.push("自家".to_string());
+        }
+        if player.riichi {
+            flags.push("立直".to_string());
+        }
+        if player.nukidora > 0 {
+            flags.push(format!("拔北{}", player.nukidora));
+        }
+        if state.current == Some(player.seat) {
+            flags.push("←".to_string());
+        }
+        let _ = writeln!(
+            out,
+            "[{}] 座位{} {:>6} {}",
+            wind_char(player.wind),
+            player.seat,
+            player.score,
+            flags.join(" ")
+        );
+        let hand = if player.hand.is_empty() {
+            format!("?×{}", player.hand_count)
+        } else {
+            compact(&player.hand, true)
+        };
+        let melds: Vec<String> = player
+            .melds
+            .iter()
+            .map(|m| {
+                let kind = match m.kind {
+                    MeldKind::Chi => "吃",
+                    MeldKind::Pon => "碰",
+                    MeldKind::Daiminkan => "明杠",
+                    MeldKind::Ankan => "暗杠",
+                    MeldKind::Kakan => "加杠",
+                };
+                format!("{}{}", kind, compact(&m.tiles, true))
+            })
+            .collect();
+        let line = format!("  手牌: {} {}", hand, melds.join(" "));
+        let _ = writeln!(out, "{}", line.trim_end());
+        // `*` tsumogiri, `r` riichi declaration, `^` called away
+        let river: Vec<String> = player
+            .discards
+            .iter()
+            .map(|d| {
+                let mut s = notate(&d.tile, notation);
+                if d.tsumogiri {
+                    s.push('*');
+                }
+                if d.riichi {
+                    s.push('r');
+                }
+                if d.called {
+                    s.push('^');
+                }
+                s
+            })
+            .collect();
+        let line = format!("  牌河: {}", river.join(" "));
+        let _ = writeln!(out, "{}", line.trim_end());
+    }
+    if let Some(result) = &state.result {
+        let line = match result {
+            RoundResult::Hule { wins, deltas } => {
+                let wins: Vec<String> = wins
+                    .iter()
+                    .map(|w| {
+                        let how = if w.zimo { "自摸" } else { "荣和" };
+                        format!("座位{}{} {}番{}符 {}点", w.seat, how, w.han, w.fu, w.points)
+                    })
+                    .collect();
+                format!("和了: {} {:?}", wins.join(", "), deltas)
+            }
+            RoundResult::NoTile { tenpai, deltas } => {
+                format!("流局: 听牌{:?} {:?}", tenpai, deltas)
+            }
+            RoundResult::LiuJu { kind } => format!("途中流局: {}", kind),
+        };
+        let _ = writeln!(out, "{}", line);
+    }
+    out
+}
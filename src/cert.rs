@@ -0,0 +1,163 @@
+use crate::{tr, SETTINGS};
+use anyhow::{ensure, Result};
+use hudsucker::{
+    certificate_authority::RcgenAuthority,
+    rcgen::{
+        BasicConstraints, CertificateParams, DistinguishedName, DnType, DnValue, IsCa, KeyPair,
+        KeyUsagePurpose,
+    },
+};
+use std::{io::Write, path::Path};
+use tracing::info;
+
+pub const KEY_FILE: &str = "hudsucker.key";
+pub const CERT_FILE: &str = "hudsucker.cer";
+
+/// Shared by every copy of the program, `gen-cert` makes one of its own.
+const BUILTIN_KEY: &str = include_str!("./ca/hudsucker.key");
+const BUILTIN_CERT: &str = include_str!("./ca/hudsucker.cer");
+
+/// Key and certificate written by `gen-cert` to the config folder, if any.
+fn own_ca() -> Option<(String, String)> {
+    let dir = SETTINGS.config_dir();
+    let key = std::fs::read_to_string(dir.join(KEY_FILE)).ok()?;
+    let cert = std::fs::read_to_string(dir.join(CERT_FILE)).ok()?;
+    Some((key, cert))
+}
+
+/// PEM of the CA certificate the proxy uses, and whether it is one of `gen-cert`.
+pub fn current_cert() -> (String, bool) {
+    match own_ca() {
+        Some((_, cert)) => (cert, true),
+        None => (BUILTIN_CERT.to_string(), false),
+    }
+}
+
+/// Common name of a PEM certificate, as shown by certificate stores.
+pub fn common_name(pem: &str) -> Option<String> {
+    let params = CertificateParams::from_ca_cert_pem(pem).ok()?;
+    match params.distinguished_name.get(&DnType::CommonName)? {
+        DnValue::Utf8String(name) => Some(name.clone()),
+        DnValue::PrintableString(name) => Some(name.as_str().to_string()),
+        _ => None,
+    }
+}
+
+/// The CA the proxy signs its certificates with: the one written by `gen-cert`
+/// to the config folder, else the built-in one.
+pub fn load_ca() -> Result<RcgenAuthority> {
+    let (key, cert) = match own_ca() {
+        Some(ca) => {
+            let path = SETTINGS.config_dir().join(CERT_FILE);
+            info!(
+                "{}",
+                tr!(
+                    "使用配置目录中的CA证书 {}",
+                    "設定フォルダのCA証明書 {} を使用します",
+                    "Using the CA {} of the config folder",
+                    path.display()
+                )
+            );
+            ca
+        }
+        None => (BUILTIN_KEY.to_string(), BUILTIN_CERT.to_string()),
+    };
+    let key_pair = KeyPair::from_pem(&key)?;
+    let ca_cert = CertificateParams::from_ca_cert_pem(&cert)?.self_signed(&key_pair)?;
+    Ok(RcgenAuthority::new(key_pair, ca_cert, 1_000))
+}
+
+/// Write a new CA key and certificate to `dir`, keeping existing ones unless `force`.
+pub fn generate(dir: &Path, force: bool) -> Result<()> {
+    let (key_path, cert_path) = (dir.join(KEY_FILE), dir.join(CERT_FILE));
+    ensure!(
+        force || !(key_path.exists() || cert_path.exists()),
+        "{}",
+        tr!(
+            "{} 已有CA证书，用--force替换",
+            "{} には既にCA証明書があります。--force で置き換えます",
+            "{} already has a CA, use --force to replace it",
+            dir.display()
+        )
+    );
+    let key_pair = KeyPair::generate()?;
+    let mut params = CertificateParams::default();
+    let mut name = DistinguishedName::new();
+    name.push(DnType::CommonName, "MajsoulMax-rs CA");
+    name.push(DnType::OrganizationName, "MajsoulMax-rs");
+    params.distinguished_name = name;
+    params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    params.key_usages = vec![
+        KeyUsagePurpose::KeyCertSign,
+        KeyUsagePurpose::CrlSign,
+        KeyUsagePurpose::DigitalSignature,
+    ];
+    let cert = params.self_signed(&key_pair)?;
+    std::fs::create_dir_all(dir)?;
+    write_private(&key_path, key_pair.serialize_pem().as_bytes())?;
+    std::fs::write(&cert_path, cert.pem())?;
+    info!(
+        "{}",
+        tr!(
+            "新的CA证书已写入 {}，请将其导入系统或浏览器的受信任根证书",
+            "新しいCA証明書を {} に書き込みました。システムまたはブラウザの信頼されたルート証明書にインポートしてください",
+            "New CA written to {}, import it into the trusted roots of the system or browser",
+            cert_path.display()
+        )
+    );
+    Ok(())
+}
+
+/// Write the CA key readable by the current user only, other accounts
+/// holding it could intercept every TLS connection of this user.
+fn write_private(path: &Path, contents: &[u8]) -> Result<()> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+        options.mode(0o600);
+        // `mode` only applies to new files, not a key replaced by --force
+        if path.exists() {
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+        }
+    }
+    options.open(path)?.write_all(contents)?;
+    Ok(())
+}
+
+/// Add the certificate at `path` to the trusted roots of the current user.
+///
+/// Linux has no such store, the commands to run as root are in the error.
+pub fn install(path: &Path) -> Result<()> {
+    let mut cmd = if cfg!(windows) {
+        let mut cmd = std::process::Command::new("certutil");
+        cmd.args(["-user", "-addstore", "Root"]).arg(path);
+        cmd
+    } else if cfg!(target_os = "macos") {
+        let home = std::env::var("HOME")?;
+        let mut cmd = std::process::Command::new("security");
+        cmd.args(["add-trusted-cert", "-r", "trustRoot", "-k"])
+            .arg(Path::new(&home).join("Library/Keychains/login.keychain-db"))
+            .arg(path);
+        cmd
+    } else {
+        anyhow::bail!(tr!(
+            "请以root运行`cp {} /usr/local/share/ca-certificates/majsoul_max_rs.crt && update-ca-certificates`，或导入浏览器的证书设置",
+            "root で `cp {} /usr/local/share/ca-certificates/majsoul_max_rs.crt && update-ca-certificates` を実行するか、ブラウザの証明書設定にインポートしてください",
+            "Run `cp {} /usr/local/share/ca-certificates/majsoul_max_rs.crt && update-ca-certificates` as root, or import it in the certificate settings of the browser",
+            path.display()
+        ));
+    };
+    let status = cmd.status()?;
+    ensure!(status.success(), "{:?} exited with {}", cmd, status);
+    info!(
+        "{}",
+        tr!(
+            "CA证书已导入受信任根证书",
+            "CA証明書を信頼されたルート証明書にインポートしました",
+            "CA imported into the trusted roots"
+        )
+    );
+    Ok(())
+}
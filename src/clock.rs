@@ -0,0 +1,85 @@
+use crate::{parser::u32_field, state::GameState};
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use std::time::Instant;
+
+/// Thinking time of one player over the game, measured between live actions.
+///
+/// The gaps include animations and network delay, so other players' figures are estimates.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Clock {
+    pub turns: u32,
+    pub total_ms: u64,
+    pub last_ms: u64,
+    pub max_ms: u64,
+    /// time spent beyond the base time of each turn
+    pub bank_used_ms: u64,
+    /// time bank left, as sent by the server for own seat
+    pub bank_ms: Option<u64>,
+    /// base time of the next own turn
+    pub base_ms: Option<u64>,
+}
+
+/// Data of `.helper.TurnTime`
+#[derive(Serialize, Debug, Clone)]
+pub struct TurnTime {
+    pub seat: u32,
+    pub elapsed_ms: u64,
+    pub clock: Clock,
+}
+
+/// Charge the time since the previous live action to the player acting now.
+pub fn tick(state: &mut GameState, name: &str, data: &JsonValue, now: Instant) -> Option<TurnTime> {
+    let last = state.last_action_at.replace(now);
+    if state.clocks.len() < state.players.len() {
+        state.clocks.resize(state.players.len(), Clock::default());
+    }
+    update_own_bank(state, data);
+    if !matches!(
+        name,
+        "DiscardTile" | "ChiPengGang" | "AnGangAddGang" | "BaBei"
+    ) {
+        return None;
+    }
+    // the first action after a restart or a new round has nothing to compare with
+    let elapsed = now.checked_duration_since(last?)?.as_millis() as u64;
+    let seat = u32_field(data, "seat").ok()?;
+    let rules = state.game.as_ref().map(|g| (g.time_fixed, g.time_add));
+    let own = state.seat == Some(seat);
+    let clock = state.clocks.get_mut(seat as usize)?;
+    clock.turns += 1;
+    clock.total_ms += elapsed;
+    clock.last_ms = elapsed;
+    clock.max_ms = clock.max_ms.max(elapsed);
+    let base = clock
+        .base_ms
+        .filter(|_| own)
+        .or(rules.map(|(fixed, _)| fixed as u64 * 1000));
+    if let Some(base) = base {
+        clock.bank_used_ms += elapsed.saturating_sub(base);
+    }
+    if !own {
+        if let Some((_, add)) = rules {
+            clock.bank_ms = Some((add as u64 * 1000).saturating_sub(clock.bank_used_ms));
+        }
+    }
+    Some(TurnTime {
+        seat,
+        elapsed_ms: elapsed,
+        clock: clock.clone(),
+    })
+}
+
+/// Own draws and callable discards carry the timers of the own decision.
+fn update_own_bank(state: &mut GameState, data: &JsonValue) {
+    let Some(seat) = state.seat else {
+        return;
+    };
+    let operation = std::iter::once(&data["operation"])
+        .chain(data["operations"].as_array().into_iter().flatten())
+        .find(|op| op["seat"].as_u64().unwrap_or_default() as u32 == seat && op.is_object());
+    if let (Some(op), Some(clock)) = (operation, state.clocks.get_mut(seat as usize)) {
+        clock.bank_ms = Some(op["time_add"].as_u64().unwrap_or_default());
+        clock.base_ms = Some(op["time_fixed"].as_u64().unwrap_or_default());
+    }
+}
@@ -0,0 +1,588 @@
+use crate::{
+    filter::Filter,
+    settings::{merge, Settings},
+    sink::SinkTarget,
+    tr,
+    wizard::reachable,
+    ARG,
+};
+use anyhow::{bail, Result};
+use clap::Subcommand;
+use prost_reflect::DescriptorPool;
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    fmt::Write,
+    path::{Path, PathBuf},
+};
+
+#[derive(Subcommand, Debug)]
+pub enum ConfigCommand {
+    /// Validate the settings files without starting anything: syntax, types,
+    /// unknown keys, sink filters, sink URLs and the liqi files, each problem
+    /// with its file, line and column
+    Check,
+}
+
+pub async fn run(command: &ConfigCommand) -> Result<()> {
+    match command {
+        ConfigCommand::Check => check().await,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Seg {
+    Key(String),
+    Index(usize),
+}
+
+/// e.g. `sinks[0].format`
+fn display(path: &[Seg]) -> String {
+    let mut out = String::new();
+    for seg in path {
+        match seg {
+            Seg::Key(key) if out.is_empty() => out.push_str(key),
+            Seg::Key(key) => {
+                let _ = write!(out, ".{}", key);
+            }
+            Seg::Index(i) => {
+                let _ = write!(out, "[{}]", i);
+            }
+        }
+    }
+    out
+}
+
+/// A settings file with the byte offset of every key and array item in it.
+struct Source {
+    path: PathBuf,
+    text: String,
+    offsets: HashMap<Vec<Seg>, usize>,
+}
+
+impl Source {
+    /// `path:line:column` of `offset`, columns counted in characters.
+    fn position(&self, offset: usize) -> String {
+        let before = &self.text[..offset.min(self.text.len())];
+        let line = before.matches('\n').count() + 1;
+        let start = before.rfind('\n').map_or(0, |i| i + 1);
+        let column = before[start..].chars().count() + 1;
+        format!("{}:{}:{}", self.path.display(), line, column)
+    }
+}
+
+/// Records where each value of a JSON document starts, the document is
+/// known to be valid.
+struct JsonScanner<'a> {
+    text: &'a [u8],
+    pos: usize,
+    offsets: HashMap<Vec<Seg>, usize>,
+}
+
+impl JsonScanner<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.text.get(self.pos).copied()
+    }
+
+    fn whitespace(&mut self) {
+        while self.peek().is_some_and(|b| b.is_ascii_whitespace()) {
+            self.pos += 1;
+        }
+    }
+
+    fn string(&mut self) -> String {
+        let start = self.pos;
+        self.pos += 1;
+        while let Some(b) = self.peek() {
+            self.pos += if b == b'\\' { 2 } else { 1 };
+            if b == b'"' {
+                break;
+            }
+        }
+        serde_json::from_slice(&self.text[start..self.pos.min(self.text.len())]).unwrap_or_default()
+    }
+
+    fn value(&mut self, path: &mut Vec<Seg>) {
+        self.whitespace();
+        match self.peek() {
+            Some(b'{') => {
+                self.pos += 1;
+                loop {
+                    self.whitespace();
+                    match self.peek() {
+                        Some(b'"') => {
+                            let at = self.pos;
+                            path.push(Seg::Key(self.string()));
+                            self.offsets.insert(path.clone(), at);
+                            self.whitespace();
+                            if self.peek() == Some(b':') {
+                                self.pos += 1;
+                            }
+                            self.value(path);
+                            path.pop();
+                            self.whitespace();
+                            if self.peek() == Some(b',') {
+                                self.pos += 1;
+                            }
+                        }
+                        Some(b'}') => {
+                            self.pos += 1;
+                            return;
+                        }
+                        _ => return,
+                    }
+                }
+            }
+            Some(b'[') => {
+                self.pos += 1;
+                for i in 0.. {
+                    self.whitespace();
+                    match self.peek() {
+                        Some(b']') => {
+                            self.pos += 1;
+                            return;
+                        }
+                        None => return,
+                        Some(_) => {
+                            let at = self.pos;
+                            path.push(Seg::Index(i));
+                            self.offsets.insert(path.clone(), at);
+                            self.value(path);
+                            path.pop();
+                            self.whitespace();
+                            match self.peek() {
+                                Some(b',') => self.pos += 1,
+                                // never stuck on what is not a value
+                                _ if self.pos == at => return,
+                                _ => {}
+                            }
+                        }
+                    }
+                }
+            }
+            Some(b'"') => {
+                self.string();
+            }
+            Some(_) => {
+                while self
+                    .peek()
+                    .is_some_and(|b| !matches!(b, b',' | b'}' | b']') && !b.is_ascii_whitespace())
+                {
+                    self.pos += 1;
+                }
+            }
+            None => {}
+        }
+    }
+}
+
+fn toml_table(table: &toml_edit::Table, path: &mut Vec<Seg>, out: &mut HashMap<Vec<Seg>, usize>) {
+    for (key, item) in table.iter() {
+        path.push(Seg::Key(key.to_string()));
+        if let Some(start) = table.key(key).and_then(|k| k.span()).map(|span| span.start) {
+            out.insert(path.clone(), start);
+        }
+        match item {
+            toml_edit::Item::Table(table) => toml_table(table, path, out),
+            toml_edit::Item::Value(value) => toml_value(value, path, out),
+            toml_edit::Item::ArrayOfTables(tables) => {
+                for (i, table) in tables.iter().enumerate() {
+                    path.push(Seg::Index(i));
+                    if let Some(span) = table.span() {
+                        out.insert(path.clone(), span.start);
+                    }
+                    toml_table(table, path, out);
+                    path.pop();
+                }
+            }
+            toml_edit::Item::None => {}
+        }
+        path.pop();
+    }
+}
+
+fn toml_value(value: &toml_edit::Value, path: &mut Vec<Seg>, out: &mut HashMap<Vec<Seg>, usize>) {
+    match value {
+        toml_edit::Value::InlineTable(table) => {
+            for (key, value) in table.iter() {
+                path.push(Seg::Key(key.to_string()));
+                if let Some(start) = table.key(key).and_then(|k| k.span()).map(|span| span.start) {
+                    out.insert(path.clone(), start);
+                }
+                toml_value(value, path, out);
+                path.pop();
+            }
+        }
+        toml_edit::Value::Array(array) => {
+            for (i, value) in array.iter().enumerate() {
+                path.push(Seg::Index(i));
+                if let Some(span) = value.span() {
+                    out.insert(path.clone(), span.start);
+                }
+                toml_value(value, path, out);
+                path.pop();
+            }
+        }
+        _ => {}
+    }
+}
+
+/// The file parsed for its positions, or where its syntax error is.
+fn read_source(path: PathBuf, text: String) -> Result<Source, (String, String)> {
+    let mut offsets = HashMap::new();
+    let toml = path.extension().is_some_and(|ext| ext == "toml");
+    if toml {
+        match toml_edit::ImDocument::parse(text.as_str()) {
+            Ok(doc) => toml_table(doc.as_table(), &mut vec![], &mut offsets),
+            Err(e) => {
+                let source = Source {
+                    path,
+                    text: text.clone(),
+                    offsets,
+                };
+                let at = e.span().map_or(0, |span| span.start);
+                return Err((source.position(at), e.message().to_string()));
+            }
+        }
+    } else {
+        if let Err(e) = serde_json::from_str::<Value>(&text) {
+            let at = format!("{}:{}:{}", path.display(), e.line(), e.column());
+            return Err((at, e.to_string()));
+        }
+        let mut scanner = JsonScanner {
+            text: text.as_bytes(),
+            pos: 0,
+            offsets: HashMap::new(),
+        };
+        scanner.value(&mut vec![]);
+        offsets = scanner.offsets;
+    }
+    Ok(Source {
+        path,
+        text,
+        offsets,
+    })
+}
+
+#[derive(PartialEq, Eq)]
+enum Level {
+    Error,
+    Warning,
+}
+
+struct Problem {
+    level: Level,
+    /// `file:line:column`, or where the value came from
+    at: String,
+    message: String,
+}
+
+/// Whether the bytes of a file can be used.
+type Valid = fn(&[u8]) -> bool;
+
+struct Checker {
+    sources: Vec<Source>,
+    problems: Vec<Problem>,
+}
+
+impl Checker {
+    /// Position of `path` in the last layer setting it, else of its nearest
+    /// parent, trying the `--profile` entry of each layer first.
+    fn locate(&self, path: &[Seg]) -> String {
+        let candidates = |path: &[Seg]| {
+            let mut list = vec![];
+            if let Some(profile) = &ARG.profile {
+                let mut under = vec![Seg::Key("profiles".to_string()), Seg::Key(profile.clone())];
+                under.extend_from_slice(path);
+                list.push(under);
+            }
+            list.push(path.to_vec());
+            list
+        };
+        for len in (1..=path.len()).rev() {
+            for source in self.sources.iter().rev() {
+                for candidate in candidates(&path[..len]) {
+                    if let Some(offset) = source.offsets.get(&candidate) {
+                        return source.position(*offset);
+                    }
+                }
+            }
+        }
+        match self.sources.last() {
+            Some(source) if !path.is_empty() => tr!(
+                "{}（来自环境变量或命令行）",
+                "{}（環境変数またはコマンドライン由来）",
+                "{} (from the environment or command line)",
+                source.path.display()
+            ),
+            Some(source) => source.path.display().to_string(),
+            None => tr!(
+                "环境变量或命令行",
+                "環境変数またはコマンドライン",
+                "environment or command line"
+            ),
+        }
+    }
+
+    fn report(&mut self, level: Level, path: &[Seg], message: String) {
+        let at = self.locate(path);
+        self.problems.push(Problem { level, at, message });
+    }
+
+    /// `value` as settings, reporting where it fails to deserialize.
+    fn typed(&mut self, value: &Value, prefix: &[Seg]) -> Option<Settings> {
+        match serde_path_to_error::deserialize::<_, Settings>(value.clone()) {
+            Ok(settings) => Some(settings),
+            Err(e) => {
+                let mut path = prefix.to_vec();
+                for seg in e.path().iter() {
+                    match seg {
+                        serde_path_to_error::Segment::Seq { index } => {
+                            path.push(Seg::Index(*index))
+                        }
+                        serde_path_to_error::Segment::Map { key } => {
+                            path.push(Seg::Key(key.clone()))
+                        }
+                        serde_path_to_error::Segment::Enum { variant } => {
+                            path.push(Seg::Key(variant.clone()))
+                        }
+                        serde_path_to_error::Segment::Unknown => {}
+                    }
+                }
+                let message = match path.is_empty() {
+                    true => e.inner().to_string(),
+                    false => format!("{}: {}", display(&path), e.inner()),
+                };
+                self.report(Level::Error, &path, message);
+                None
+            }
+        }
+    }
+
+    /// Keys of `input` the settings dropped when deserialized into `known`.
+    fn unknown(&mut self, input: &Value, known: &Value, path: &mut Vec<Seg>) {
+        match (input, known) {
+            (Value::Object(input), Value::Object(known)) => {
+                for (key, value) in input {
+                    // not a setting, checked on its own
+                    if path.is_empty() && key == "profiles" {
+                        continue;
+                    }
+                    path.push(Seg::Key(key.clone()));
+                    match known.get(key) {
+                        Some(known) => self.unknown(value, known, path),
+                        None => {
+                            let message = tr!(
+                                "未知的键 {}",
+                                "不明なキー {}",
+                                "Unknown key {}",
+                                display(path)
+                            );
+                            self.report(Level::Warning, path, message);
+                        }
+                    }
+                    path.pop();
+                }
+            }
+            (Value::Array(input), Value::Array(known)) => {
+                for (i, (value, known)) in input.iter().zip(known).enumerate() {
+                    path.push(Seg::Index(i));
+                    self.unknown(value, known, path);
+                    path.pop();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Types, unknown keys and filters of `value`, whose keys are under `prefix`.
+    fn settings(&mut self, value: &Value, prefix: Vec<Seg>) -> Option<Settings> {
+        let settings = self.typed(value, &prefix)?;
+        let known = serde_json::to_value(&settings).unwrap_or_default();
+        self.unknown(value, &known, &mut prefix.clone());
+        for (i, sink) in settings.sinks.iter().enumerate() {
+            let Some(filter) = &sink.filter else {
+                continue;
+            };
+            if let Err(e) = Filter::parse(filter) {
+                let mut path = prefix.clone();
+                path.extend([
+                    Seg::Key("sinks".to_string()),
+                    Seg::Index(i),
+                    Seg::Key("filter".to_string()),
+                ]);
+                let message = tr!(
+                    "{} 无效: {:#}",
+                    "{} が無効です: {:#}",
+                    "Invalid {}: {:#}",
+                    display(&path),
+                    e
+                );
+                self.report(Level::Error, &path, message);
+            }
+        }
+        Some(settings)
+    }
+
+    async fn urls(&mut self, settings: &Settings) {
+        let mut urls = vec![];
+        if settings.sinks.is_empty() && settings.helper_on() {
+            urls.push((
+                vec![Seg::Key("apiUrl".to_string())],
+                settings.api_url.clone(),
+            ));
+        }
+        for (i, sink) in settings.sinks.iter().enumerate() {
+            if let SinkTarget::Http { url } = &sink.target {
+                let path = vec![
+                    Seg::Key("sinks".to_string()),
+                    Seg::Index(i),
+                    Seg::Key("url".to_string()),
+                ];
+                urls.push((path, url.clone()));
+            }
+        }
+        for (path, url) in urls {
+            if !reachable(&url).await {
+                let message = tr!(
+                    "无法访问 {}",
+                    "{} にアクセスできません",
+                    "Cannot reach {}",
+                    url
+                );
+                self.report(Level::Warning, &path, message);
+            }
+        }
+    }
+
+    /// The liqi files the proxy cannot start without, and lqc.lqbin for the mod.
+    fn liqi_files(&mut self, merged: &Value, dir: &Path, settings: &Settings) {
+        let path = vec![Seg::Key("liqiDir".to_string())];
+        let liqi_dir = match merged["liqiDir"].as_str() {
+            Some(sub) => dir.join(sub),
+            None => dir.to_path_buf(),
+        };
+        let mut files: Vec<(&str, Valid)> = vec![
+            ("liqi.desc", |bytes| DescriptorPool::decode(bytes).is_ok()),
+            ("liqi.json", |bytes| {
+                serde_json::from_slice::<Value>(bytes).is_ok()
+            }),
+        ];
+        if settings.mod_on() {
+            files.push(("lqc.lqbin", |_| true));
+        }
+        for (name, valid) in files {
+            let file = match name {
+                "lqc.lqbin" => dir.join(name),
+                _ => liqi_dir.join(name),
+            };
+            let message = match std::fs::read(&file) {
+                Ok(bytes) if valid(&bytes) => continue,
+                Ok(_) => tr!(
+                    "无法解析 {}",
+                    "{} を解析できません",
+                    "Cannot parse {}",
+                    file.display()
+                ),
+                Err(_) => tr!("缺少 {}", "{} がありません", "Missing {}", file.display()),
+            };
+            self.report(Level::Error, &path, message);
+        }
+    }
+}
+
+/// Check every settings layer and print the problems like a compiler,
+/// failing when any is an error.
+async fn check() -> Result<()> {
+    let dir = Settings::find_dir();
+    let mut checker = Checker {
+        sources: vec![],
+        problems: vec![],
+    };
+    let mut syntax_ok = true;
+    for path in Settings::files(&dir).into_iter().filter(|p| p.is_file()) {
+        let text = match std::fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) => {
+                checker.problems.push(Problem {
+                    level: Level::Error,
+                    at: path.display().to_string(),
+                    message: e.to_string(),
+                });
+                syntax_ok = false;
+                continue;
+            }
+        };
+        match read_source(path, text) {
+            Ok(source) => checker.sources.push(source),
+            Err((at, message)) => {
+                checker.problems.push(Problem {
+                    level: Level::Error,
+                    at,
+                    message,
+                });
+                syntax_ok = false;
+            }
+        }
+    }
+    if syntax_ok {
+        match Settings::merged(&dir) {
+            Ok((merged, _)) => {
+                if let Some(settings) = checker.settings(&merged, vec![]) {
+                    checker.urls(&settings).await;
+                    checker.liqi_files(&merged, &dir, &settings);
+                }
+                // every profile, not only the selected one
+                let profiles = merged["profiles"].as_object().cloned().unwrap_or_default();
+                for (name, profile) in profiles {
+                    let mut applied = merged.clone();
+                    merge(&mut applied, profile.clone());
+                    let prefix = vec![Seg::Key("profiles".to_string()), Seg::Key(name)];
+                    // only the keys of the profile itself are reported
+                    if let Some(settings) = checker.typed(&applied, &prefix) {
+                        let known = serde_json::to_value(&settings).unwrap_or_default();
+                        checker.unknown(&profile, &known, &mut prefix.clone());
+                    }
+                }
+            }
+            Err(e) => checker.problems.push(Problem {
+                level: Level::Error,
+                at: dir.display().to_string(),
+                message: format!("{:#}", e),
+            }),
+        }
+    }
+
+    let errors = checker
+        .problems
+        .iter()
+        .filter(|p| p.level == Level::Error)
+        .count();
+    for problem in &checker.problems {
+        let level = match problem.level {
+            Level::Error => tr!("错误", "エラー", "error"),
+            Level::Warning => tr!("警告", "警告", "warning"),
+        };
+        println!("{}: {}: {}", problem.at, level, problem.message);
+    }
+    let warnings = checker.problems.len() - errors;
+    if errors > 0 {
+        bail!(tr!(
+            "配置有{}个错误，{}个警告",
+            "設定にエラー {} 件、警告 {} 件があります",
+            "{} errors and {} warnings in the settings",
+            errors,
+            warnings
+        ));
+    }
+    println!(
+        "{}",
+        tr!(
+            "配置有效，{}个警告",
+            "設定は有効です。警告 {} 件",
+            "Settings are valid, {} warnings",
+            warnings
+        )
+    );
+    Ok(())
+}
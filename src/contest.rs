@@ -0,0 +1,214 @@
+use crate::sink::Event;
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use serde_json::{json, Value as JsonValue};
+use std::collections::BTreeMap;
+use tokio::sync::RwLock;
+
+/// The contest (custom tournament lobby) the client is in, fed by the helper worker.
+pub static CONTEST_STATE: Lazy<RwLock<ContestState>> =
+    Lazy::new(|| RwLock::new(ContestState::default()));
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ContestPlayer {
+    pub account_id: u32,
+    pub nickname: String,
+    /// only known once a game ended
+    pub total_point: Option<i32>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ContestGame {
+    pub uuid: String,
+    pub players: Vec<ContestPlayer>,
+    pub finished: bool,
+}
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct Contest {
+    /// internal id used by the protocol
+    pub unique_id: u32,
+    /// id shown to players
+    pub contest_id: u32,
+    pub name: String,
+    pub state: u32,
+    pub rank_rule: u32,
+    /// `GameMode` with the detailed rules
+    pub rules: JsonValue,
+    /// own standing, `CustomizedContestPlayerReport`
+    pub report: JsonValue,
+    pub online: Option<u32>,
+    /// every player seen in the contest's games
+    pub players: BTreeMap<u32, String>,
+    /// games started or ended while in the contest
+    pub games: Vec<ContestGame>,
+}
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct ContestState {
+    pub current: Option<Contest>,
+}
+
+impl ContestState {
+    /// Update with one message, returning the `.helper.Contest*` events it produced.
+    pub fn feed(&mut self, event: &Event) -> Vec<Event> {
+        let data = &event.msg.data;
+        let mut derived = vec![];
+        match event.msg.method_name.as_ref() {
+            ".lq.Lobby.enterCustomizedContest" => {
+                let detail = &data["detail_info"];
+                let contest = Contest {
+                    unique_id: u32_of(&detail["unique_id"]),
+                    contest_id: u32_of(&detail["contest_id"]),
+                    name: detail["contest_name"]
+                        .as_str()
+                        .unwrap_or_default()
+                        .to_string(),
+                    state: u32_of(&detail["state"]),
+                    rank_rule: u32_of(&detail["rank_rule"]),
+                    rules: detail["game_mode"].clone(),
+                    report: data["player_report"].clone(),
+                    ..Default::default()
+                };
+                derived.push(Event::derived(".helper.ContestEnter", json!(contest)));
+                self.current = Some(contest);
+            }
+            ".lq.Lobby.leaveCustomizedContest" => self.current = None,
+            ".lq.Lobby.fetchCustomizedContestOnlineInfo" => {
+                if let Some(contest) = self.current.as_mut() {
+                    contest.online = data["online_player"].as_u64().map(|n| n as u32);
+                }
+            }
+            ".lq.NotifyCustomContestState" => {
+                let Some(contest) = self.contest_mut(data) else {
+                    return derived;
+                };
+                contest.state = u32_of(&data["state"]);
+                derived.push(Event::derived(
+                    ".helper.ContestState",
+                    json!({
+                        "unique_id": contest.unique_id,
+                        "contest_id": contest.contest_id,
+                        "state": contest.state,
+                    }),
+                ));
+            }
+            ".lq.NotifyCustomContestSystemMsg" => {
+                let Some(contest) = self.contest_mut(data) else {
+                    return derived;
+                };
+                let uuid = data["uuid"].as_str().unwrap_or_default().to_string();
+                for (key, method, finished) in [
+                    ("game_start", ".helper.ContestGameStart", false),
+                    ("game_end", ".helper.ContestGameEnd", true),
+                ] {
+                    let players = players_of(&data[key]["players"]);
+                    if players.is_empty() {
+                        continue;
+                    }
+                    for p in &players {
+                        contest.players.insert(p.account_id, p.nickname.clone());
+                    }
+                    let game = ContestGame {
+                        uuid: uuid.clone(),
+                        players,
+                        finished,
+                    };
+                    derived.push(Event::derived(
+                        method,
+                        json!({
+                            "unique_id": contest.unique_id,
+                            "contest_id": contest.contest_id,
+                            "game": game,
+                        }),
+                    ));
+                    match contest.games.iter_mut().find(|g| g.uuid == uuid) {
+                        Some(g) => *g = game,
+                        None => contest.games.push(game),
+                    }
+                }
+            }
+            ".lq.Lobby.fetchCustomizedContestGameRecords" => {
+                let Some(contest) = self.current.as_mut() else {
+                    return derived;
+                };
+                let records: Vec<JsonValue> = data["record_list"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .map(|r| record_summary(r, contest))
+                    .collect();
+                derived.push(Event::derived(
+                    ".helper.ContestRecords",
+                    json!({
+                        "unique_id": contest.unique_id,
+                        "contest_id": contest.contest_id,
+                        "next_index": data["next_index"],
+                        "records": records,
+                    }),
+                ));
+            }
+            _ => {}
+        }
+        derived
+    }
+
+    /// The current contest if a notify is about it.
+    fn contest_mut(&mut self, data: &JsonValue) -> Option<&mut Contest> {
+        let unique_id = u32_of(&data["unique_id"]);
+        self.current.as_mut().filter(|c| c.unique_id == unique_id)
+    }
+}
+
+fn u32_of(v: &JsonValue) -> u32 {
+    v.as_u64().unwrap_or_default() as u32
+}
+
+fn players_of(players: &JsonValue) -> Vec<ContestPlayer> {
+    players
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|p| ContestPlayer {
+            account_id: u32_of(&p["account_id"]),
+            nickname: p["nickname"].as_str().unwrap_or_default().to_string(),
+            total_point: p["total_point"].as_i64().map(|t| t as i32),
+        })
+        .collect()
+}
+
+/// uuid, time and per seat results of a `RecordGame`.
+fn record_summary(record: &JsonValue, contest: &mut Contest) -> JsonValue {
+    let accounts = record["accounts"].as_array().cloned().unwrap_or_default();
+    let results = record["result"]["players"]
+        .as_array()
+        .cloned()
+        .unwrap_or_default();
+    let players: Vec<JsonValue> = results
+        .iter()
+        .map(|r| {
+            let seat = r["seat"].as_u64();
+            let account = accounts.iter().find(|a| a["seat"].as_u64() == seat);
+            let account_id = account
+                .map(|a| u32_of(&a["account_id"]))
+                .unwrap_or_default();
+            let nickname = account.and_then(|a| a["nickname"].as_str()).unwrap_or("AI");
+            if account_id != 0 {
+                contest.players.insert(account_id, nickname.to_string());
+            }
+            json!({
+                "seat": seat,
+                "account_id": account_id,
+                "nickname": nickname,
+                "total_point": r["total_point"],
+                "part_point_1": r["part_point_1"],
+            })
+        })
+        .collect();
+    json!({
+        "uuid": record["uuid"],
+        "start_time": record["start_time"],
+        "end_time": record["end_time"],
+        "players": players,
+    })
+}
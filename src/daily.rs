@@ -0,0 +1,306 @@
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, FixedOffset, Local, NaiveDate, Utc};
+use chrono_tz::Tz;
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::{
+    collections::BTreeMap,
+    fmt,
+    fs::{File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+/// Index of the files written in a day folder.
+pub const DAY_INDEX: &str = "day.json";
+
+/// Timezone the calendar days are counted in: an IANA name such as
+/// `Europe/Berlin`, following its daylight saving time, or a fixed offset from
+/// UTC such as `+09:00` or `UTC`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Timezone {
+    Fixed(FixedOffset),
+    Named(Tz),
+}
+
+impl Timezone {
+    /// The offset in effect at `at`.
+    pub fn offset_at(&self, at: DateTime<Utc>) -> DateTime<FixedOffset> {
+        match self {
+            Timezone::Fixed(offset) => at.with_timezone(offset),
+            Timezone::Named(tz) => at.with_timezone(tz).fixed_offset(),
+        }
+    }
+}
+
+impl FromStr for Timezone {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || {
+            anyhow!(
+                "Invalid timezone {}, expected e.g. Asia/Tokyo, +08:00 or UTC",
+                s
+            )
+        };
+        if let Ok(tz) = s.trim().parse::<Tz>() {
+            return Ok(Timezone::Named(tz));
+        }
+        let offset = s.trim().trim_start_matches("UTC").trim_start_matches("GMT");
+        if offset.is_empty() || offset == "Z" {
+            return Ok(Timezone::Fixed(
+                FixedOffset::east_opt(0).ok_or_else(invalid)?,
+            ));
+        }
+        let (sign, rest) = match offset.split_at(1) {
+            ("+", rest) => (1, rest),
+            ("-", rest) => (-1, rest),
+            _ => return Err(invalid()),
+        };
+        let (hours, minutes) = rest.split_once(':').unwrap_or((rest, "0"));
+        let hours: i32 = hours.parse().map_err(|_| invalid())?;
+        let minutes: i32 = minutes.parse().map_err(|_| invalid())?;
+        if hours > 14 || minutes >= 60 {
+            return Err(invalid());
+        }
+        FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60))
+            .map(Timezone::Fixed)
+            .ok_or_else(invalid)
+    }
+}
+
+impl fmt::Display for Timezone {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Timezone::Fixed(offset) => write!(f, "{}", offset),
+            Timezone::Named(tz) => write!(f, "{}", tz.name()),
+        }
+    }
+}
+
+impl Serialize for Timezone {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Timezone {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+static TIMEZONE: OnceCell<Timezone> = OnceCell::new();
+
+/// The `timezone` setting, once the settings are loaded.
+pub fn set(timezone: Option<Timezone>) {
+    if let Some(timezone) = timezone {
+        let _ = TIMEZONE.set(timezone);
+    }
+}
+
+/// Now in the `timezone` setting, else in the system timezone.
+pub fn now() -> DateTime<FixedOffset> {
+    match TIMEZONE.get() {
+        Some(timezone) => timezone.offset_at(Utc::now()),
+        None => Local::now().fixed_offset(),
+    }
+}
+
+pub fn today() -> NaiveDate {
+    now().date_naive()
+}
+
+/// `<base>/<YYYY-MM-DD>`, the folder of `day`.
+pub fn day_dir(base: &Path, day: NaiveDate) -> PathBuf {
+    base.join(day.format("%Y-%m-%d").to_string())
+}
+
+/// The day of a day folder, `None` for other folders.
+fn parse_day(dir: &Path) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(dir.file_name()?.to_str()?, "%Y-%m-%d").ok()
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DayEntry {
+    /// `log` or `sink`
+    pub kind: String,
+    /// when it was first written that day
+    pub opened: DateTime<FixedOffset>,
+}
+
+fn read_index(dir: &Path) -> BTreeMap<String, DayEntry> {
+    std::fs::read(dir.join(DAY_INDEX))
+        .ok()
+        .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+        .unwrap_or_default()
+}
+
+fn write_index(dir: &Path, entries: &BTreeMap<String, DayEntry>) -> Result<()> {
+    if entries.is_empty() {
+        let _ = std::fs::remove_file(dir.join(DAY_INDEX));
+        return Ok(());
+    }
+    std::fs::write(dir.join(DAY_INDEX), serde_json::to_vec_pretty(entries)?)?;
+    Ok(())
+}
+
+/// List `file` of the day folder `dir` in its index, once.
+pub fn index(dir: &Path, file: &str, kind: &str) -> Result<()> {
+    let mut entries = read_index(dir);
+    if entries.contains_key(file) {
+        return Ok(());
+    }
+    entries.insert(
+        file.to_string(),
+        DayEntry {
+            kind: kind.to_string(),
+            opened: now(),
+        },
+    );
+    write_index(dir, &entries)
+}
+
+/// A file name kept in a folder per day under `base`, e.g. `sinks/2024-06-01/game.jsonl`.
+pub struct DailyPath {
+    base: PathBuf,
+    file: String,
+    kind: &'static str,
+    day: Option<NaiveDate>,
+}
+
+impl DailyPath {
+    /// `path` split into the base folder and the file name of every day.
+    pub fn new(path: &Path, kind: &'static str) -> Self {
+        DailyPath {
+            base: path.parent().map(Path::to_path_buf).unwrap_or_default(),
+            file: path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned(),
+            kind,
+            day: None,
+        }
+    }
+
+    /// The path of today when the day changed since the last call, created and indexed.
+    pub fn roll(&mut self) -> Result<Option<PathBuf>> {
+        let today = today();
+        if self.day == Some(today) {
+            return Ok(None);
+        }
+        let dir = day_dir(&self.base, today);
+        std::fs::create_dir_all(&dir)?;
+        index(&dir, &self.file, self.kind)?;
+        self.day = Some(today);
+        Ok(Some(dir.join(&self.file)))
+    }
+
+    /// Remove the file from all but the `keep` latest day folders, with the
+    /// folders left empty.
+    pub fn prune(&self, keep: usize) -> Result<()> {
+        let mut days: Vec<(NaiveDate, PathBuf)> = std::fs::read_dir(&self.base)?
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|dir| dir.join(&self.file).exists())
+            .filter_map(|dir| Some((parse_day(&dir)?, dir)))
+            .collect();
+        days.sort();
+        for (_, dir) in days.iter().rev().skip(keep) {
+            std::fs::remove_file(dir.join(&self.file))?;
+            let mut entries = read_index(dir);
+            entries.remove(&self.file);
+            write_index(dir, &entries)?;
+            // kept when other files are left
+            let _ = std::fs::remove_dir(dir);
+        }
+        Ok(())
+    }
+}
+
+/// Log file of the day in `<dir>/<YYYY-MM-DD>/`, switched at midnight of the
+/// `timezone` setting, for the `daily` log rotation.
+pub struct DailyLog {
+    path: DailyPath,
+    keep: usize,
+    file: Option<File>,
+}
+
+impl DailyLog {
+    pub fn new(dir: &Path, file: &str, keep: usize) -> Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        let mut log = DailyLog {
+            path: DailyPath::new(&dir.join(file), "log"),
+            keep,
+            file: None,
+        };
+        log.roll()?;
+        Ok(log)
+    }
+
+    fn roll(&mut self) -> std::io::Result<()> {
+        let next = self.path.roll().map_err(std::io::Error::other)?;
+        if let Some(path) = next {
+            self.file = Some(OpenOptions::new().create(true).append(true).open(path)?);
+            if self.keep > 0 {
+                self.path.prune(self.keep).map_err(std::io::Error::other)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Write for DailyLog {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.roll()?;
+        match self.file.as_mut() {
+            Some(file) => file.write(buf),
+            None => Ok(buf.len()),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self.file.as_mut() {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn fixed_offsets() {
+        let tz: Timezone = "+09:00".parse().unwrap();
+        assert_eq!(
+            tz,
+            Timezone::Fixed(FixedOffset::east_opt(9 * 3600).unwrap())
+        );
+        let tz: Timezone = "UTC-3:30".parse().unwrap();
+        assert_eq!(tz.to_string(), "-03:30");
+        assert!("+15:00".parse::<Timezone>().is_err());
+        assert!("Mars/Olympus".parse::<Timezone>().is_err());
+    }
+
+    #[test]
+    fn named_zones_follow_daylight_saving_time() {
+        let tz: Timezone = "Europe/Berlin".parse().unwrap();
+        assert_eq!(tz.to_string(), "Europe/Berlin");
+        // 23:30 UTC is already the next day in Berlin, by one hour in winter
+        // and two in summer
+        let winter = Utc.with_ymd_and_hms(2024, 1, 15, 23, 30, 0).unwrap();
+        let summer = Utc.with_ymd_and_hms(2024, 7, 15, 22, 30, 0).unwrap();
+        assert_eq!(tz.offset_at(winter).offset().local_minus_utc(), 3600);
+        assert_eq!(tz.offset_at(summer).offset().local_minus_utc(), 7200);
+        assert_eq!(
+            tz.offset_at(summer).date_naive(),
+            NaiveDate::from_ymd_opt(2024, 7, 16).unwrap()
+        );
+    }
+}
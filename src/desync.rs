@@ -0,0 +1,183 @@
+use crate::{
+    format::{restore_actions, strip_action},
+    parser::{str_list, u32_field},
+    state::{GameState, PlayerState, Wind},
+};
+use anyhow::Result;
+use serde::Serialize;
+use serde_json::{json, Value as JsonValue};
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Difference {
+    /// JSON path such as `players.2.discards.5`
+    pub path: String,
+    pub local: JsonValue,
+    pub server: JsonValue,
+}
+
+/// Data of `.helper.Desync`, emitted when a syncGame disagrees with the tracked state.
+#[derive(Serialize, Debug, Clone)]
+pub struct Desync {
+    /// last action step applied locally
+    pub step: u32,
+    /// the server state was rebuilt from the snapshot, only table totals are compared
+    pub from_snapshot: bool,
+    pub differences: Vec<Difference>,
+}
+
+/// Compare the tracked state with the `game_restore` of a syncGame response,
+/// replayed up to the step the tracker has seen.
+pub fn check(local: &GameState, sync: &JsonValue) -> Result<Option<Desync>> {
+    if local.players.is_empty() {
+        return Ok(None);
+    }
+    let restore = &sync["game_restore"];
+    let steps: Vec<u32> = restore["actions"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|a| a["step"].as_u64().unwrap_or_default() as u32)
+        .collect();
+    let actions = restore_actions(sync)?;
+    let starts_round = actions
+        .first()
+        .is_some_and(|(name, _)| name == "ActionNewRound");
+    let mut server = GameState {
+        seat: local.seat,
+        spectated: local.spectated,
+        ..Default::default()
+    };
+    let from_snapshot = !starts_round;
+    if from_snapshot {
+        // a snapshot without actions is the current table, partial hands can't be replayed
+        if !actions.is_empty() || restore["snapshot"].is_null() {
+            return Ok(None);
+        }
+        apply_snapshot(&mut server, &restore["snapshot"])?;
+    }
+    let mut replayed = false;
+    for ((name, data), step) in actions.iter().zip(&steps) {
+        if *step > local.step {
+            break;
+        }
+        server.feed(strip_action(name), data)?;
+        replayed = true;
+    }
+    if starts_round && !replayed {
+        // the tracker is still in an earlier hand
+        return Ok(None);
+    }
+    let mut differences = vec![];
+    diff(
+        "",
+        &comparable(local, from_snapshot),
+        &comparable(&server, from_snapshot),
+        &mut differences,
+    );
+    Ok((!differences.is_empty()).then_some(Desync {
+        step: local.step,
+        from_snapshot,
+        differences,
+    }))
+}
+
+/// Round totals, scores and the own hand of a snapshot.
+fn apply_snapshot(state: &mut GameState, snapshot: &JsonValue) -> Result<()> {
+    let ju = u32_field(snapshot, "ju")?;
+    let players = snapshot["players"].as_array().cloned().unwrap_or_default();
+    let count = players.len() as u32;
+    state.round_wind = Wind::from_index(u32_field(snapshot, "chang")?);
+    state.ju = ju;
+    state.honba = u32_field(snapshot, "ben")?;
+    state.riichi_sticks = u32_field(snapshot, "liqibang")?;
+    state.left_tiles = u32_field(snapshot, "left_tile_count")?;
+    state.dora_indicators = str_list(snapshot, "doras")
+        .into_iter()
+        .map(String::from)
+        .collect();
+    let hand: Vec<String> = str_list(snapshot, "hands")
+        .into_iter()
+        .map(String::from)
+        .collect();
+    state.players = players
+        .iter()
+        .enumerate()
+        .map(|(seat, p)| {
+            let seat = seat as u32;
+            PlayerState {
+                seat,
+                wind: Wind::from_index((seat + count - ju % count) % count),
+                score: p["score"].as_i64().unwrap_or_default() as i32,
+                hand: if state.seat == Some(seat) {
+                    hand.clone()
+                } else {
+                    vec![]
+                },
+                hand_count: p["tilenum"].as_u64().unwrap_or_default() as usize,
+                ..Default::default()
+            }
+        })
+        .collect();
+    Ok(())
+}
+
+/// The parts of a state both sides can know, hands sorted.
+fn comparable(state: &GameState, totals_only: bool) -> JsonValue {
+    let players: Vec<JsonValue> = state
+        .players
+        .iter()
+        .map(|p| {
+            let mut hand = p.hand.clone();
+            hand.sort();
+            let mut player = json!({
+                "score": p.score,
+                "hand": hand,
+                "hand_count": p.hand_count,
+            });
+            if !totals_only {
+                player["discards"] = json!(p.discards.iter().map(|d| &d.tile).collect::<Vec<_>>());
+                player["melds"] = json!(p.melds);
+                player["riichi"] = json!(p.riichi);
+                player["nukidora"] = json!(p.nukidora);
+            }
+            player
+        })
+        .collect();
+    json!({
+        "round_wind": state.round_wind,
+        "ju": state.ju,
+        "honba": state.honba,
+        "riichi_sticks": state.riichi_sticks,
+        "left_tiles": state.left_tiles,
+        "dora_indicators": state.dora_indicators,
+        "players": players,
+    })
+}
+
+fn diff(path: &str, local: &JsonValue, server: &JsonValue, out: &mut Vec<Difference>) {
+    let child = |key: &str| {
+        if path.is_empty() {
+            key.to_string()
+        } else {
+            format!("{}.{}", path, key)
+        }
+    };
+    match (local, server) {
+        (JsonValue::Object(l), JsonValue::Object(s)) => {
+            for (key, lv) in l {
+                diff(&child(key), lv, s.get(key).unwrap_or(&JsonValue::Null), out);
+            }
+        }
+        (JsonValue::Array(l), JsonValue::Array(s)) if l.len() == s.len() => {
+            for (i, (lv, sv)) in l.iter().zip(s).enumerate() {
+                diff(&child(&i.to_string()), lv, sv, out);
+            }
+        }
+        _ if local != server => out.push(Difference {
+            path: path.to_string(),
+            local: local.clone(),
+            server: server.clone(),
+        }),
+        _ => {}
+    }
+}
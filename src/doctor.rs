@@ -0,0 +1,526 @@
+use crate::{
+    cert::{self, CERT_FILE},
+    settings::{get_proto_prefix, get_version},
+    sink::SinkTarget,
+    tr, SETTINGS,
+};
+use anyhow::{bail, Result};
+use std::{
+    net::{SocketAddr, TcpListener},
+    process::Command,
+    str::FromStr,
+    time::Duration,
+};
+
+/// Helper endpoints are local, anything slower than this counts as down.
+const REACH_TIMEOUT: Duration = Duration::from_secs(3);
+
+#[derive(PartialEq, Eq)]
+enum Outcome {
+    Pass,
+    /// may be fine, e.g. a setup the check cannot see
+    Warn,
+    Fail,
+}
+
+struct Check {
+    name: String,
+    outcome: Outcome,
+    detail: String,
+    fix: Option<String>,
+}
+
+impl Check {
+    fn pass(name: impl Into<String>, detail: impl Into<String>) -> Self {
+        Check {
+            name: name.into(),
+            outcome: Outcome::Pass,
+            detail: detail.into(),
+            fix: None,
+        }
+    }
+
+    fn warn(name: impl Into<String>, detail: impl Into<String>, fix: impl Into<String>) -> Self {
+        Check {
+            name: name.into(),
+            outcome: Outcome::Warn,
+            detail: detail.into(),
+            fix: Some(fix.into()),
+        }
+    }
+
+    fn fail(name: impl Into<String>, detail: impl Into<String>, fix: impl Into<String>) -> Self {
+        Check {
+            name: name.into(),
+            outcome: Outcome::Fail,
+            detail: detail.into(),
+            fix: Some(fix.into()),
+        }
+    }
+
+    fn print(&self) {
+        let tag = match self.outcome {
+            Outcome::Pass => tr!("通过", "OK", "PASS"),
+            Outcome::Warn => tr!("警告", "警告", "WARN"),
+            Outcome::Fail => tr!("失败", "失敗", "FAIL"),
+        };
+        println!(
+            "[{}] {}{}{}",
+            tag,
+            self.name,
+            tr!("：", "：", ": "),
+            self.detail
+        );
+        if let Some(fix) = &self.fix {
+            println!("       {}{}", tr!("建议：", "対処：", "Fix: "), fix);
+        }
+    }
+}
+
+fn check_ca() -> Vec<Check> {
+    let (pem, own) = cert::current_cert();
+    let path = SETTINGS.config_dir().join(CERT_FILE);
+    let ca = tr!("CA证书", "CA証明書", "CA");
+    let generated = match own {
+        true => Check::pass(
+            ca,
+            tr!(
+                "使用自己生成的 {}",
+                "生成した {} を使用",
+                "Using {} of your own",
+                path.display()
+            ),
+        ),
+        false => Check::warn(
+            ca,
+            tr!(
+                "使用内置的公用CA证书，其私钥随程序公开",
+                "内蔵の共用CA証明書を使用中、その秘密鍵は公開されています",
+                "Using the built-in CA, whose private key ships with the program"
+            ),
+            tr!(
+                "运行`gen-cert`生成自己的CA证书并导入受信任根证书",
+                "`gen-cert` で専用のCA証明書を生成し、信頼されたルート証明書にインポートしてください",
+                "Run `gen-cert` for a CA of your own and import it into the trusted roots"
+            ),
+        ),
+    };
+    let name = cert::common_name(&pem).unwrap_or_default();
+    let trust = tr!("证书信任", "証明書の信頼", "CA trust");
+    let trusted = match is_trusted(&pem, &name) {
+        Some(true) => Check::pass(
+            trust,
+            tr!(
+                "{} 已在受信任根证书中",
+                "{} は信頼されたルート証明書にあります",
+                "{} is a trusted root",
+                name
+            ),
+        ),
+        Some(false) => Check::fail(
+            trust,
+            tr!(
+                "{} 不在受信任根证书中，游戏会拒绝代理的连接",
+                "{} は信頼されたルート証明書になく、ゲームはプロキシへの接続を拒否します",
+                "{} is not a trusted root, the game refuses the connections of the proxy",
+                name
+            ),
+            match own {
+                true => tr!(
+                    "将 {} 导入系统或浏览器的受信任根证书，或启动游戏时加`--ignore-certificate-errors`",
+                    "{} をシステムまたはブラウザの信頼されたルート証明書にインポートするか、ゲームを`--ignore-certificate-errors`付きで起動してください",
+                    "Import {} into the trusted roots of the system or browser, or start the game with `--ignore-certificate-errors`",
+                    path.display()
+                ),
+                false => tr!(
+                    "运行`gen-cert`并导入生成的证书，或启动游戏时加`--ignore-certificate-errors`",
+                    "`gen-cert` を実行して生成した証明書をインポートするか、ゲームを`--ignore-certificate-errors`付きで起動してください",
+                    "Run `gen-cert` and import the certificate, or start the game with `--ignore-certificate-errors`"
+                ),
+            },
+        ),
+        None => Check::warn(
+            trust,
+            tr!(
+                "无法读取系统的受信任根证书",
+                "システムの信頼されたルート証明書を読み取れません",
+                "Cannot read the trusted roots of the system"
+            ),
+            tr!(
+                "确认CA证书已导入，或启动游戏时加`--ignore-certificate-errors`",
+                "CA証明書がインポート済みか確認するか、ゲームを`--ignore-certificate-errors`付きで起動してください",
+                "Make sure the CA is imported, or start the game with `--ignore-certificate-errors`"
+            ),
+        ),
+    };
+    vec![generated, trusted]
+}
+
+/// Whether the system trusts the certificate, `None` when the store cannot be read.
+fn is_trusted(pem: &str, name: &str) -> Option<bool> {
+    if cfg!(windows) {
+        // the user store first, then the machine store
+        let found = ["-user", ""].iter().any(|store| {
+            let mut cmd = Command::new("certutil");
+            if !store.is_empty() {
+                cmd.arg(store);
+            }
+            cmd.args(["-verifystore", "Root", name])
+                .output()
+                .is_ok_and(|out| out.status.success())
+        });
+        Some(found)
+    } else if cfg!(target_os = "macos") {
+        let out = Command::new("security")
+            .args(["find-certificate", "-c", name])
+            .output()
+            .ok()?;
+        Some(out.status.success())
+    } else {
+        const BUNDLES: [&str; 3] = [
+            "/etc/ssl/certs/ca-certificates.crt",
+            "/etc/pki/tls/certs/ca-bundle.crt",
+            "/etc/ssl/cert.pem",
+        ];
+        let body: String = pem
+            .lines()
+            .filter(|line| !line.starts_with("-----"))
+            .flat_map(str::split_whitespace)
+            .collect();
+        let bundles: Vec<String> = BUNDLES
+            .iter()
+            .filter_map(|path| std::fs::read_to_string(path).ok())
+            .collect();
+        if bundles.is_empty() {
+            return None;
+        }
+        Some(bundles.iter().any(|bundle| {
+            let bundle: String = bundle.split_whitespace().collect();
+            bundle.contains(&body)
+        }))
+    }
+}
+
+fn check_port(name: String, key: &str, addr: &str) -> Check {
+    let Ok(addr) = SocketAddr::from_str(addr) else {
+        return Check::fail(
+            name,
+            tr!(
+                "{}不是有效的地址：{}",
+                "{} は有効なアドレスではありません：{}",
+                "{} is not a valid address: {}",
+                key,
+                addr
+            ),
+            tr!(
+                "在settings.json中将{}设为如`127.0.0.1:23410`的地址",
+                "settings.json の {} を `127.0.0.1:23410` のようなアドレスにしてください",
+                "Set {} in settings.json to an address such as `127.0.0.1:23410`",
+                key
+            ),
+        );
+    };
+    match TcpListener::bind(addr) {
+        Ok(_) => Check::pass(
+            name,
+            tr!(
+                "{} 可以监听",
+                "{} で待ち受けできます",
+                "{} can be listened on",
+                addr
+            ),
+        ),
+        Err(e) => Check::fail(
+            name,
+            tr!(
+                "无法监听 {}：{}",
+                "{} で待ち受けできません：{}",
+                "Cannot listen on {}: {}",
+                addr,
+                e
+            ),
+            tr!(
+                "关闭占用该端口的程序（可能是已在运行的本程序），或修改settings.json中的{}",
+                "ポートを使っているプログラム（既に起動している本プログラムかもしれません）を終了するか、settings.json の {} を変更してください",
+                "Close the program on that port, maybe this one already running, or change {} in settings.json",
+                key
+            ),
+        ),
+    }
+}
+
+/// `host:port` of the HTTPS proxy the system sends traffic to, if any.
+fn system_proxy() -> Option<String> {
+    if cfg!(windows) {
+        let out = Command::new("reg")
+            .args([
+                "query",
+                r"HKCU\Software\Microsoft\Windows\CurrentVersion\Internet Settings",
+            ])
+            .output()
+            .ok()?;
+        let text = String::from_utf8_lossy(&out.stdout);
+        let value = |key: &str| {
+            text.lines()
+                .find(|line| line.split_whitespace().next() == Some(key))
+                .and_then(|line| line.split_whitespace().nth(2))
+                .map(str::to_string)
+        };
+        if value("ProxyEnable").as_deref() != Some("0x1") {
+            return None;
+        }
+        // either `host:port` or per scheme as `http=host:port;https=host:port`
+        let server = value("ProxyServer")?;
+        match server.split(';').find_map(|s| s.strip_prefix("https=")) {
+            Some(https) => Some(https.to_string()),
+            None => Some(server),
+        }
+    } else if cfg!(target_os = "macos") {
+        let out = Command::new("scutil").arg("--proxy").output().ok()?;
+        let text = String::from_utf8_lossy(&out.stdout);
+        let value = |key: &str| {
+            text.lines()
+                .filter_map(|line| line.split_once(" : "))
+                .find(|(k, _)| k.trim() == key)
+                .map(|(_, v)| v.trim().to_string())
+        };
+        if value("HTTPSEnable").as_deref() != Some("1") {
+            return None;
+        }
+        Some(format!("{}:{}", value("HTTPSProxy")?, value("HTTPSPort")?))
+    } else {
+        let url = ["https_proxy", "HTTPS_PROXY", "all_proxy", "ALL_PROXY"]
+            .iter()
+            .find_map(|key| std::env::var(key).ok().filter(|v| !v.is_empty()))?;
+        let host = url.split_once("://").map_or(url.as_str(), |(_, rest)| rest);
+        Some(host.trim_end_matches('/').to_string())
+    }
+}
+
+fn check_system_proxy() -> Check {
+    let name = tr!("系统代理", "システムプロキシ", "System proxy");
+    let ours = SETTINGS.proxy_addr.as_str();
+    let fix = tr!(
+        "将系统的HTTPS代理设为 {}",
+        "システムの HTTPS プロキシを {} に設定してください",
+        "Set the HTTPS proxy of the system to {}",
+        ours
+    );
+    let Some(proxy) = system_proxy() else {
+        return Check::warn(
+            name,
+            tr!(
+                "未设置系统代理",
+                "システムプロキシが未設定です",
+                "No system proxy is set"
+            ),
+            tr!(
+                "{}；使用Proxifier或浏览器的`--proxy-server`参数时可忽略",
+                "{}。Proxifier やブラウザの `--proxy-server` を使う場合は無視してください",
+                "{}; ignore this with Proxifier or the `--proxy-server` option of a browser",
+                fix
+            ),
+        );
+    };
+    let port = |addr: &str| addr.rsplit_once(':').map(|(_, port)| port.to_string());
+    let host = proxy.rsplit_once(':').map_or("", |(host, _)| host);
+    let local = ["127.0.0.1", "localhost", "[::1]"].contains(&host)
+        || ours.starts_with(&format!("{}:", host));
+    match local && port(&proxy) == port(ours) {
+        true => Check::pass(
+            name,
+            tr!(
+                "指向本程序 {}",
+                "本プログラム {} を指しています",
+                "Points to this program at {}",
+                proxy
+            ),
+        ),
+        false => Check::fail(
+            name,
+            tr!(
+                "指向 {} 而不是本程序",
+                "本プログラムではなく {} を指しています",
+                "Points to {} instead of this program",
+                proxy
+            ),
+            fix,
+        ),
+    }
+}
+
+async fn check_descriptor() -> Check {
+    let name = tr!("liqi版本", "liqi バージョン", "liqi version");
+    let local = SETTINGS.liqi_version();
+    let live = match get_version().await {
+        Ok(version) => get_proto_prefix(&version)
+            .await
+            .map(|prefix| (version, prefix)),
+        Err(e) => Err(e),
+    };
+    match live {
+        Ok((_, prefix)) if prefix == local => Check::pass(
+            name,
+            tr!(
+                "与服务器一致（{}）",
+                "サーバーと一致しています（{}）",
+                "Same as the server ({})",
+                local
+            ),
+        ),
+        Ok((version, prefix)) => Check::fail(
+            name,
+            tr!(
+                "本地为 {}，服务器（{}）为 {}，新消息无法解析",
+                "ローカルは {}、サーバー（{}）は {} で、新しいメッセージを解析できません",
+                "{} here but {} on the server ({}), new messages cannot be parsed",
+                local,
+                version,
+                prefix
+            ),
+            tr!(
+                "将settings.json中的autoUpdate设为1后启动程序自动更新，或从AutoLiqi的发布页下载liqi文件到配置目录",
+                "settings.json の autoUpdate を 1 にして起動すると自動更新されます。または AutoLiqi のリリースから liqi ファイルを設定フォルダにダウンロードしてください",
+                "Set autoUpdate in settings.json to 1 and start the program to update, or download the liqi files from the AutoLiqi releases into the config folder"
+            ),
+        ),
+        Err(e) => Check::fail(
+            name,
+            tr!(
+                "无法获取服务器版本：{:#}",
+                "サーバーのバージョンを取得できません：{:#}",
+                "Cannot get the version of the server: {:#}",
+                e
+            ),
+            tr!(
+                "检查网络能否访问{}",
+                "{} にアクセスできるかネットワークを確認してください",
+                "Check that {} can be reached",
+                SETTINGS.region.resource_url()
+            ),
+        ),
+    }
+}
+
+async fn check_sinks() -> Vec<Check> {
+    const NAME: &str = "helper";
+    if !SETTINGS.helper_on() {
+        return vec![Check::pass(
+            NAME,
+            tr!(
+                "helper已关闭，跳过",
+                "helper はオフのためスキップ",
+                "Helper is off, skipped"
+            ),
+        )];
+    }
+    let urls = match SETTINGS.sinks.is_empty() {
+        true => vec![("helper".to_string(), SETTINGS.api_url.clone())],
+        false => SETTINGS
+            .sinks
+            .iter()
+            .enumerate()
+            .filter_map(|(i, sink)| match &sink.target {
+                SinkTarget::Http { url } => Some(match sink.name.is_empty() {
+                    true => (format!("sink{}", i), url.clone()),
+                    false => (sink.name.clone(), url.clone()),
+                }),
+                _ => None,
+            })
+            .collect(),
+    };
+    if urls.is_empty() {
+        return vec![Check::pass(
+            NAME,
+            tr!(
+                "没有http输出目标，跳过",
+                "http の出力先がないためスキップ",
+                "No http sinks, skipped"
+            ),
+        )];
+    }
+    let client = match reqwest::Client::builder()
+        .danger_accept_invalid_certs(true)
+        .timeout(REACH_TIMEOUT)
+        .build()
+    {
+        Ok(client) => client,
+        Err(e) => {
+            return vec![Check::fail(
+                NAME,
+                format!("{:#}", e),
+                tr!("重试一次", "もう一度試してください", "Try again"),
+            )]
+        }
+    };
+    let mut checks = vec![];
+    for (name, url) in urls {
+        // any answer means something listens there, helpers reject a bare GET
+        checks.push(match client.get(&url).send().await {
+            Ok(_) => Check::pass(
+                NAME,
+                tr!(
+                    "{} 可以访问 {}",
+                    "{} は {} にアクセスできます",
+                    "{} can reach {}",
+                    name,
+                    url
+                ),
+            ),
+            Err(e) => Check::fail(
+                NAME,
+                tr!(
+                    "{} 无法访问 {}：{}",
+                    "{} は {} にアクセスできません：{}",
+                    "{} cannot reach {}: {}",
+                    name,
+                    url,
+                    e
+                ),
+                tr!(
+                    "先启动mahjong-helper（如`mahjong-helper -majsoul`），或修正settings.json中的地址",
+                    "先に mahjong-helper を起動する（`mahjong-helper -majsoul` など）か、settings.json のアドレスを修正してください",
+                    "Start mahjong-helper first, e.g. `mahjong-helper -majsoul`, or fix the address in settings.json"
+                ),
+            ),
+        });
+    }
+    checks
+}
+
+/// Check the setup problems most support requests come down to and print
+/// each result with a suggested fix, failing when any check failed.
+pub async fn run() -> Result<()> {
+    let mut checks = check_ca();
+    checks.push(check_port(
+        tr!("代理端口", "プロキシのポート", "Proxy port"),
+        "proxyAddr",
+        &SETTINGS.proxy_addr,
+    ));
+    if !SETTINGS.admin_addr.is_empty() {
+        checks.push(check_port(
+            tr!("管理端口", "管理ポート", "Admin port"),
+            "adminAddr",
+            &SETTINGS.admin_addr,
+        ));
+    }
+    checks.push(check_system_proxy());
+    checks.push(check_descriptor().await);
+    checks.extend(check_sinks().await);
+    for check in &checks {
+        check.print();
+    }
+    let failed = checks.iter().filter(|c| c.outcome == Outcome::Fail).count();
+    if failed > 0 {
+        bail!("{} of {} checks failed", failed, checks.len());
+    }
+    println!(
+        "{}",
+        tr!(
+            "全部检查通过",
+            "すべてのチェックに合格しました",
+            "All checks passed"
+        )
+    );
+    Ok(())
+}
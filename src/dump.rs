@@ -0,0 +1,500 @@
+use crate::parser::{LiqiMessage, Parser};
+use anyhow::{anyhow, bail, ensure, Result};
+use base64::prelude::*;
+use bytes::Bytes;
+use serde_json::json;
+use std::{
+    fmt,
+    io::{Read, Seek, SeekFrom, Write},
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tracing::{info, warn};
+
+/// Start of a binary frame dump, followed by frames of `[u64 LE unix millis]
+/// [u16 LE length][method][u32 LE length][frame]`. The method lets responses be
+/// decoded without their request, which the helper never sees.
+pub const DUMP_MAGIC: &[u8; 8] = b"MJFRAME1";
+
+/// Uncompressed frames per zstd chunk of a compressed dump.
+pub const CHUNK_SIZE: usize = 64 * 1024;
+const ZSTD_MAGIC: u32 = 0xFD2F_B528;
+/// Skippable frame before every chunk with `[u64 LE index of its first
+/// frame][u32 LE frames in it]`, ignored by `zstd -d`.
+const INDEX_MAGIC: u32 = 0x184D_2A5E;
+const INDEX_LEN: usize = 12;
+/// Decompressed size above which a chunk is taken for broken: a chunk is
+/// flushed at [`CHUNK_SIZE`], so only a frame this big could exceed it.
+const CHUNK_MAX: u64 = 64 << 20;
+
+#[derive(clap::Args, Debug)]
+pub struct ParseArgs {
+    /// frames as base64 lines, as written by a `raw` file sink, or a binary dump
+    /// of a file sink with `binary` or `compress` set
+    input: PathBuf,
+    /// start at this frame, counted from 0; compressed dumps skip the chunks
+    /// before it without decompressing them
+    #[clap(long, default_value_t = 0)]
+    from: usize,
+    /// print the JSON of each frame on several lines
+    #[clap(long)]
+    pretty: bool,
+}
+
+/// One frame of a dump, in the binary format.
+pub fn encode_frame(method: &str, frame: &[u8]) -> Vec<u8> {
+    encode_frame_at(now_millis(), method, frame)
+}
+
+/// Milliseconds since the unix epoch, as frames are timed in dumps.
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// [`encode_frame`] of a frame captured at `millis`.
+pub fn encode_frame_at(millis: u64, method: &str, frame: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(method.len() + frame.len() + 14);
+    out.extend_from_slice(&millis.to_le_bytes());
+    out.extend_from_slice(&(method.len() as u16).to_le_bytes());
+    out.extend_from_slice(method.as_bytes());
+    out.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+    out.extend_from_slice(frame);
+    out
+}
+
+/// Where a frame starts in a dump.
+#[derive(Debug, Clone, Copy)]
+pub struct Position {
+    pub offset: usize,
+    /// 1 for the first line of a base64 dump
+    pub line: Option<usize>,
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "line {} (offset {})", line, self.offset),
+            None => write!(f, "offset {}", self.offset),
+        }
+    }
+}
+
+pub struct DumpFrame {
+    pub position: Position,
+    /// only kept by binary dumps
+    pub time_ms: Option<u64>,
+    /// only kept by binary dumps
+    pub method: Option<String>,
+    pub buf: Result<Vec<u8>>,
+}
+
+/// Split a dump into frames, telling the binary format by its magic.
+pub fn read_frames(bytes: &[u8]) -> Vec<DumpFrame> {
+    read_frames_from(bytes, 0)
+}
+
+/// [`read_frames`] from the frame `from` on.
+pub fn read_frames_from(bytes: &[u8], from: usize) -> Vec<DumpFrame> {
+    if is_compressed(bytes) {
+        return zstd_frames(bytes, from);
+    }
+    let frames = match bytes.strip_prefix(DUMP_MAGIC.as_slice()) {
+        Some(rest) => binary_frames(rest, DUMP_MAGIC.len()),
+        None => base64_frames(bytes),
+    };
+    frames.into_iter().skip(from).collect()
+}
+
+fn magic(bytes: &[u8]) -> Option<u32> {
+    Some(u32::from_le_bytes(bytes.get(..4)?.try_into().ok()?))
+}
+
+pub fn is_compressed(bytes: &[u8]) -> bool {
+    matches!(magic(bytes), Some(ZSTD_MAGIC | INDEX_MAGIC))
+}
+
+/// A chunk of a compressed dump: its index, then the zstd frame of `raw`,
+/// binary frames as in an uncompressed dump. The first chunk of a file holds
+/// [`DUMP_MAGIC`], so `zstd -d` turns a compressed dump into a plain one.
+pub fn compress_chunk(first: u64, frames: u32, raw: &[u8]) -> Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(raw.len() / 4 + 8 + INDEX_LEN);
+    out.extend_from_slice(&INDEX_MAGIC.to_le_bytes());
+    out.extend_from_slice(&(INDEX_LEN as u32).to_le_bytes());
+    out.extend_from_slice(&first.to_le_bytes());
+    out.extend_from_slice(&frames.to_le_bytes());
+    out.extend(zstd::bulk::compress(raw, 3)?);
+    Ok(out)
+}
+
+/// One zstd or index frame of a compressed dump, `None` at its end.
+enum Chunk<'a> {
+    Index { first: u64, frames: u32 },
+    Data(&'a [u8]),
+}
+
+fn next_chunk<'a>(rest: &mut &'a [u8]) -> Result<Option<Chunk<'a>>> {
+    match magic(rest) {
+        None if rest.is_empty() => Ok(None),
+        Some(INDEX_MAGIC) => {
+            let head = take(rest, 8 + INDEX_LEN)?;
+            Ok(Some(Chunk::Index {
+                first: u64::from_le_bytes(head[8..16].try_into()?),
+                frames: u32::from_le_bytes(head[16..20].try_into()?),
+            }))
+        }
+        Some(ZSTD_MAGIC) => {
+            let len = zstd::zstd_safe::find_frame_compressed_size(rest).map_err(|code| {
+                anyhow!(
+                    "Broken zstd chunk: {}",
+                    zstd::zstd_safe::get_error_name(code)
+                )
+            })?;
+            Ok(Some(Chunk::Data(take(rest, len)?)))
+        }
+        _ => Err(anyhow!("Not a compressed dump chunk")),
+    }
+}
+
+/// Frames already in a compressed dump, to go on counting when appending to it.
+/// Only the index frames are read, the chunks are skipped by their headers.
+pub fn compressed_frame_count<R: Read + Seek>(input: &mut R) -> Result<u64> {
+    let len = input.seek(SeekFrom::End(0))?;
+    let mut pos = input.seek(SeekFrom::Start(0))?;
+    let mut count = 0;
+    while pos < len {
+        let mut magic = [0; 4];
+        input.read_exact(&mut magic)?;
+        match u32::from_le_bytes(magic) {
+            INDEX_MAGIC => {
+                let mut index = [0; 4 + INDEX_LEN];
+                input.read_exact(&mut index)?;
+                let first = u64::from_le_bytes(index[4..12].try_into()?);
+                let frames = u32::from_le_bytes(index[12..16].try_into()?);
+                count = first.saturating_add(frames as u64);
+            }
+            ZSTD_MAGIC => skip_zstd_frame(input)?,
+            _ => bail!("Not a compressed dump chunk"),
+        }
+        pos = input.stream_position()?;
+    }
+    ensure!(pos == len, "Broken zstd chunk: truncated");
+    Ok(count)
+}
+
+/// Seek past a zstd frame after its magic, by its header and the sizes of
+/// its blocks.
+fn skip_zstd_frame<R: Read + Seek>(input: &mut R) -> Result<()> {
+    let mut descriptor = [0; 1];
+    input.read_exact(&mut descriptor)?;
+    let descriptor = descriptor[0];
+    let single_segment = descriptor & 0x20 != 0;
+    let window = if single_segment { 0 } else { 1 };
+    let dict = [0, 1, 2, 4][(descriptor & 3) as usize];
+    let content_size = match descriptor >> 6 {
+        0 if single_segment => 1,
+        0 => 0,
+        1 => 2,
+        2 => 4,
+        _ => 8,
+    };
+    input.seek(SeekFrom::Current(window + dict + content_size))?;
+    loop {
+        let mut block = [0; 4];
+        input.read_exact(&mut block[..3])?;
+        let block = u32::from_le_bytes(block);
+        let size = match (block >> 1) & 3 {
+            // raw or compressed
+            0 | 2 => block >> 3,
+            // one byte repeated
+            1 => 1,
+            _ => bail!("Broken zstd chunk: reserved block type"),
+        };
+        input.seek(SeekFrom::Current(size as i64))?;
+        if block & 1 == 1 {
+            break;
+        }
+    }
+    if descriptor & 4 != 0 {
+        // checksum
+        input.seek(SeekFrom::Current(4))?;
+    }
+    Ok(())
+}
+
+fn zstd_frames(bytes: &[u8], from: usize) -> Vec<DumpFrame> {
+    let mut frames = vec![];
+    let mut rest = bytes;
+    // of the next chunk, counted from the previous ones when it has no index
+    let mut index: Option<(u64, u32)> = None;
+    let mut next = 0;
+    loop {
+        let position = Position {
+            offset: bytes.len() - rest.len(),
+            line: None,
+        };
+        let broken = |e: anyhow::Error| DumpFrame {
+            position,
+            time_ms: None,
+            method: None,
+            buf: Err(e),
+        };
+        let data = match next_chunk(&mut rest) {
+            Ok(None) => break,
+            Ok(Some(Chunk::Index { first, frames })) => {
+                index = Some((first, frames));
+                continue;
+            }
+            Ok(Some(Chunk::Data(data))) => data,
+            Err(e) => {
+                // nothing after a broken chunk can be found again
+                frames.push(broken(e));
+                break;
+            }
+        };
+        let first = match index.take() {
+            Some((first, count)) if first.saturating_add(count as u64) <= from as u64 => {
+                next = first.saturating_add(count as u64);
+                continue;
+            }
+            Some((first, _)) => first,
+            None => next,
+        };
+        let raw = match decompress_chunk(data) {
+            Ok(raw) => raw,
+            Err(e) => {
+                frames.push(broken(e));
+                continue;
+            }
+        };
+        let raw = raw.strip_prefix(DUMP_MAGIC.as_slice()).unwrap_or(&raw);
+        let chunk = binary_frames(raw, 0);
+        next = first.saturating_add(chunk.len() as u64);
+        let skip = (from as u64).saturating_sub(first) as usize;
+        frames.extend(chunk.into_iter().skip(skip).map(|mut frame| {
+            // offsets are of the chunk, frames inside it are only known decompressed
+            frame.position = position;
+            frame
+        }));
+    }
+    frames
+}
+
+/// The frames of a chunk, refusing to inflate a broken one without bound.
+fn decompress_chunk(data: &[u8]) -> Result<Vec<u8>> {
+    let mut raw = vec![];
+    zstd::stream::read::Decoder::new(data)?
+        .take(CHUNK_MAX + 1)
+        .read_to_end(&mut raw)?;
+    ensure!(
+        raw.len() as u64 <= CHUNK_MAX,
+        "Chunk over {} MiB decompressed",
+        CHUNK_MAX >> 20
+    );
+    Ok(raw)
+}
+
+/// `len` bytes at the start of `rest`, moving past them.
+fn take<'a>(rest: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+    ensure!(
+        rest.len() >= len,
+        "Truncated frame, {} of {} bytes present",
+        rest.len(),
+        len
+    );
+    let (head, tail) = rest.split_at(len);
+    *rest = tail;
+    Ok(head)
+}
+
+fn binary_frame(rest: &mut &[u8]) -> Result<(u64, String, Vec<u8>)> {
+    let time_ms = u64::from_le_bytes(take(rest, 8)?.try_into()?);
+    let len = u16::from_le_bytes(take(rest, 2)?.try_into()?) as usize;
+    let method = String::from_utf8(take(rest, len)?.to_vec())?;
+    let len = u32::from_le_bytes(take(rest, 4)?.try_into()?) as usize;
+    Ok((time_ms, method, take(rest, len)?.to_vec()))
+}
+
+fn binary_frames(mut rest: &[u8], offset: usize) -> Vec<DumpFrame> {
+    let mut frames = vec![];
+    let end = offset + rest.len();
+    while !rest.is_empty() {
+        let position = Position {
+            offset: end - rest.len(),
+            line: None,
+        };
+        match binary_frame(&mut rest) {
+            Ok((time_ms, method, buf)) => frames.push(DumpFrame {
+                position,
+                time_ms: Some(time_ms),
+                method: Some(method).filter(|m| !m.is_empty()),
+                buf: Ok(buf),
+            }),
+            Err(e) => {
+                // nothing after a broken frame can be found again
+                frames.push(DumpFrame {
+                    position,
+                    time_ms: None,
+                    method: None,
+                    buf: Err(e),
+                });
+                break;
+            }
+        }
+    }
+    frames
+}
+
+fn base64_frames(bytes: &[u8]) -> Vec<DumpFrame> {
+    let mut frames = vec![];
+    let mut offset = 0;
+    for (i, line) in bytes.split(|b| *b == b'\n').enumerate() {
+        let position = Position {
+            offset,
+            line: Some(i + 1),
+        };
+        offset += line.len() + 1;
+        let line = line.trim_ascii();
+        if line.is_empty() || line.starts_with(b"#") {
+            continue;
+        }
+        frames.push(DumpFrame {
+            position,
+            time_ms: None,
+            method: None,
+            buf: BASE64_STANDARD
+                .decode(line)
+                .map_err(|e| anyhow!("Not base64: {}", e)),
+        });
+    }
+    frames
+}
+
+fn response_id(buf: &[u8]) -> Option<u16> {
+    match buf {
+        [0x03, a, b, ..] => Some(u16::from_le_bytes([*a, *b])),
+        _ => None,
+    }
+}
+
+/// Decode a frame of a dump, a response as of the method kept for it.
+pub fn decode_frame(parser: &mut Parser, frame: &DumpFrame) -> Result<LiqiMessage> {
+    let buf = frame.buf.as_ref().map_err(|e| anyhow!("{}", e))?;
+    if let (Some(method), Some(id)) = (&frame.method, response_id(buf)) {
+        parser.expect_response(id, method)?;
+    }
+    parser.parse(Bytes::copy_from_slice(buf))
+}
+
+/// Decode every frame of a dump as the helper would and print it as JSON,
+/// failures go to stderr with their position.
+pub fn run(args: &ParseArgs) -> Result<()> {
+    let bytes = std::fs::read(&args.input)?;
+    let frames = read_frames_from(&bytes, args.from);
+    ensure!(!frames.is_empty(), "No frames in {}", args.input.display());
+    let mut parser = Parser::default();
+    let mut stdout = std::io::stdout().lock();
+    let mut failed = 0;
+    for frame in &frames {
+        let msg = match decode_frame(&mut parser, frame) {
+            Ok(msg) => msg,
+            Err(e) => {
+                failed += 1;
+                warn!("{}: {:#}", frame.position, e);
+                continue;
+            }
+        };
+        let out = json!({
+            "offset": frame.position.offset,
+            "line": frame.position.line,
+            "time": frame.time_ms,
+            "type": msg.msg_type.name(),
+            "id": msg.id,
+            "method": msg.method_name.as_ref(),
+            "data": msg.data,
+        });
+        let text = match args.pretty {
+            true => serde_json::to_string_pretty(&out)?,
+            false => serde_json::to_string(&out)?,
+        };
+        writeln!(stdout, "{}", text)?;
+    }
+    info!(
+        "{} of {} frames decoded",
+        frames.len() - failed,
+        frames.len()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn index(first: u64, frames: u32) -> Vec<u8> {
+        let mut out = INDEX_MAGIC.to_le_bytes().to_vec();
+        out.extend_from_slice(&(INDEX_LEN as u32).to_le_bytes());
+        out.extend_from_slice(&first.to_le_bytes());
+        out.extend_from_slice(&frames.to_le_bytes());
+        out
+    }
+
+    #[test]
+    fn compressed_dumps_round_trip() {
+        let mut raw = DUMP_MAGIC.to_vec();
+        raw.extend(encode_frame_at(1, ".lq.Lobby.heatbeat", b"\x02\x01\x00"));
+        raw.extend(encode_frame_at(2, "", b"\x01"));
+        let mut dump = compress_chunk(0, 2, &raw).unwrap();
+        let second = encode_frame_at(3, "", b"\x01\x02");
+        dump.extend(compress_chunk(2, 1, &second).unwrap());
+        assert_eq!(compressed_frame_count(&mut Cursor::new(&dump)).unwrap(), 3);
+        let frames = read_frames(&dump);
+        assert_eq!(frames.len(), 3);
+        assert_eq!(frames[0].method.as_deref(), Some(".lq.Lobby.heatbeat"));
+        assert_eq!(frames[2].buf.as_ref().unwrap(), b"\x01\x02");
+        let from = read_frames_from(&dump, 2);
+        assert_eq!(from.len(), 1);
+        assert_eq!(from[0].time_ms, Some(3));
+    }
+
+    #[test]
+    fn truncated_compressed_dumps_are_errors() {
+        let dump = compress_chunk(0, 1, &encode_frame_at(1, "", b"\x01")).unwrap();
+        let cut = &dump[..dump.len() - 2];
+        assert!(compressed_frame_count(&mut Cursor::new(cut)).is_err());
+        assert!(read_frames(cut).last().unwrap().buf.is_err());
+    }
+
+    /// Found by the `read_frames` fuzz target: chunk indexes at the end of
+    /// the u64 range overflowed.
+    #[test]
+    fn overflowing_chunk_index() {
+        let crash = b"\x5e\x2a\x4d\x18\x70\x70\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\
+            \xff\xff\xff\xff\xff\xff\xff\xff\x58\x31\x70\x70\x70\x30\x90\x90\x25\x25\x25\x25\
+            \x25\x00\x00\x00\xe5\xff";
+        let _ = read_frames_from(crash, 0x58);
+        let _ = compressed_frame_count(&mut Cursor::new(crash));
+        let mut dump = index(u64::MAX, u32::MAX);
+        dump.extend(zstd::bulk::compress(b"", 3).unwrap());
+        assert_eq!(
+            compressed_frame_count(&mut Cursor::new(&dump)).unwrap(),
+            u64::MAX
+        );
+        assert!(read_frames_from(&dump, usize::MAX).is_empty());
+    }
+
+    /// Found by the `read_frames` fuzz target: a small chunk inflating to
+    /// gigabytes ran out of memory.
+    #[test]
+    fn oversized_chunks_are_refused() {
+        let bomb = zstd::bulk::compress(&vec![0; CHUNK_MAX as usize + 1], 19).unwrap();
+        assert!(bomb.len() < 64 << 10);
+        let frames = read_frames(&bomb);
+        assert_eq!(frames.len(), 1);
+        let e = frames[0].buf.as_ref().unwrap_err();
+        assert!(e.to_string().contains("MiB"), "{}", e);
+    }
+}
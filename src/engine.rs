@@ -0,0 +1,144 @@
+use crate::{
+    format::{game_actions, EventFormat, FormatKind},
+    sink::{Event, Payload},
+    SETTINGS,
+};
+use anyhow::{anyhow, Result};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value as JsonValue};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tracing::{info, warn};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct EngineConfig {
+    /// receives a JSON array of the mjai events since the last query
+    pub url: String,
+    #[serde(default = "default_timeout")]
+    pub timeout_ms: u64,
+}
+
+fn default_timeout() -> u64 {
+    3000
+}
+
+/// Asks an mjai engine such as Mortal or Akagi for a move at every own decision.
+///
+/// Queries run in their own task, answers come back as `.helper.EngineAction` events.
+pub struct Engine {
+    mjai: Box<dyn EventFormat>,
+    pending: Vec<JsonValue>,
+    tx: UnboundedSender<Vec<JsonValue>>,
+}
+
+impl Engine {
+    pub fn new(config: EngineConfig) -> Result<(Self, UnboundedReceiver<Event>)> {
+        let client = Client::builder()
+            .timeout(Duration::from_millis(config.timeout_ms))
+            .build()?;
+        let (tx, rx) = unbounded_channel();
+        let (hint_tx, hint_rx) = unbounded_channel();
+        info!("Engine bridge started, url: {}", config.url);
+        tokio::spawn(query_worker(client, config.url, rx, hint_tx));
+        let engine = Engine {
+            mjai: FormatKind::Mjai.build(&SETTINGS),
+            pending: vec![],
+            tx,
+        };
+        Ok((engine, hint_rx))
+    }
+
+    /// Convert one event to mjai, querying the engine when it asks the own seat to act.
+    pub fn feed(&mut self, event: &Event) -> Result<()> {
+        if event.spectated {
+            return Ok(());
+        }
+        for payload in self.mjai.encode(event)? {
+            if let Payload::Json(json) = payload {
+                self.pending.push(json);
+            }
+        }
+        if !self.pending.is_empty() && is_decision(event)? {
+            self.tx
+                .send(std::mem::take(&mut self.pending))
+                .map_err(|_| anyhow!("Engine worker stopped"))?;
+        }
+        Ok(())
+    }
+}
+
+/// Own draws and calls that can be answered come with an operation list.
+fn is_decision(event: &Event) -> Result<bool> {
+    if event.msg.method_name.as_ref() != ".lq.ActionPrototype" {
+        return Ok(false);
+    }
+    let has_operations = |op: &JsonValue| {
+        op["operation_list"]
+            .as_array()
+            .is_some_and(|l| !l.is_empty())
+    };
+    Ok(game_actions(event)?.iter().any(|(_, data)| {
+        has_operations(&data["operation"])
+            || data["operations"]
+                .as_array()
+                .is_some_and(|ops| ops.iter().any(has_operations))
+    }))
+}
+
+async fn query_worker(
+    client: Client,
+    url: String,
+    mut rx: UnboundedReceiver<Vec<JsonValue>>,
+    hints: UnboundedSender<Event>,
+) {
+    // events of failed queries are sent again so the engine never misses one
+    let mut backlog: Vec<JsonValue> = vec![];
+    while let Some(batch) = rx.recv().await {
+        extend(&mut backlog, batch);
+        // only the latest decision matters when queries fall behind
+        while let Ok(batch) = rx.try_recv() {
+            extend(&mut backlog, batch);
+        }
+        let start = Instant::now();
+        let action = match query(&client, &url, &backlog).await {
+            Ok(action) => action,
+            Err(e) => {
+                warn!("Engine query failed: {:?}", e);
+                continue;
+            }
+        };
+        let last = backlog.pop().unwrap_or_default();
+        backlog.clear();
+        let data = json!({
+            "action": action,
+            "latency_ms": start.elapsed().as_millis() as u64,
+            "event": last,
+        });
+        if hints
+            .send(Event::derived(".helper.EngineAction", data))
+            .is_err()
+        {
+            break;
+        }
+    }
+}
+
+fn extend(backlog: &mut Vec<JsonValue>, batch: Vec<JsonValue>) {
+    // a new game makes older events useless
+    if let Some(start) = batch.iter().rposition(|e| e["type"] == "start_game") {
+        backlog.clear();
+        backlog.extend(batch.into_iter().skip(start));
+    } else {
+        backlog.extend(batch);
+    }
+}
+
+async fn query(client: &Client, url: &str, events: &[JsonValue]) -> Result<JsonValue> {
+    let res = client.post(url).json(events).send().await?;
+    if !res.status().is_success() {
+        return Err(anyhow!("Engine returned {}", res.status()));
+    }
+    Ok(res.json().await?)
+}
@@ -0,0 +1,233 @@
+use crate::{
+    parser::{i32_list, str_field, str_list, u32_field, u32_list},
+    state::{GameState, RoundResult, Wind},
+};
+use anyhow::{anyhow, Result};
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use serde_json::Value as JsonValue;
+use tokio::sync::broadcast;
+
+/// Typed events of the own live games, see [`subscribe`].
+static BUS: Lazy<broadcast::Sender<GameEvent>> = Lazy::new(|| broadcast::channel(1024).0);
+
+/// Receive the typed events of own games from now on, slow receivers lose the oldest ones.
+///
+/// Spectated games and replays only reach the sinks as `.helper.GameEvent`.
+pub fn subscribe() -> broadcast::Receiver<GameEvent> {
+    BUS.subscribe()
+}
+
+pub fn publish(event: GameEvent) {
+    // no subscribers is fine
+    let _ = BUS.send(event);
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum KanKind {
+    Open,
+    Closed,
+    Added,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct FinalScore {
+    pub seat: u32,
+    pub score: i32,
+    /// final points with oka and uma, ×1000 as majsoul sends them
+    pub total_point: i32,
+}
+
+/// A game action without liqi internals, data of `.helper.GameEvent`.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum GameEvent {
+    RoundStart {
+        round_wind: Wind,
+        ju: u32,
+        honba: u32,
+        riichi_sticks: u32,
+        dora_indicators: Vec<String>,
+        scores: Vec<i32>,
+        /// own starting hand, empty when unknown
+        hand: Vec<String>,
+    },
+    Draw {
+        seat: u32,
+        /// hidden for other players
+        tile: Option<String>,
+    },
+    Discard {
+        seat: u32,
+        tile: String,
+        tsumogiri: bool,
+        riichi: bool,
+    },
+    Chi {
+        seat: u32,
+        from: u32,
+        tiles: Vec<String>,
+    },
+    Pon {
+        seat: u32,
+        from: u32,
+        tiles: Vec<String>,
+    },
+    Kan {
+        seat: u32,
+        kind: KanKind,
+        /// the added or concealed tile, all four for an open kan
+        tiles: Vec<String>,
+        from: Option<u32>,
+    },
+    Nukidora {
+        seat: u32,
+    },
+    Ron {
+        seat: u32,
+        from: Option<u32>,
+        tile: String,
+        han: u32,
+        fu: u32,
+        points: u32,
+    },
+    Tsumo {
+        seat: u32,
+        tile: String,
+        han: u32,
+        fu: u32,
+        points: u32,
+    },
+    ExhaustiveDraw {
+        tenpai: Vec<u32>,
+        deltas: Vec<i32>,
+    },
+    /// see `ActionLiuJu.type`
+    AbortiveDraw {
+        kind: u32,
+    },
+    GameEnd {
+        scores: Vec<FinalScore>,
+    },
+}
+
+/// Typed events of one action, `state` having already been fed with it.
+pub fn from_action(name: &str, data: &JsonValue, state: &GameState) -> Result<Vec<GameEvent>> {
+    Ok(match name {
+        "NewRound" => vec![GameEvent::RoundStart {
+            round_wind: state.round_wind,
+            ju: state.ju,
+            honba: state.honba,
+            riichi_sticks: state.riichi_sticks,
+            dora_indicators: state.dora_indicators.clone(),
+            scores: i32_list(data, "scores"),
+            hand: state.own().map(|p| p.hand.clone()).unwrap_or_default(),
+        }],
+        "DealTile" => vec![GameEvent::Draw {
+            seat: u32_field(data, "seat")?,
+            tile: data["tile"]
+                .as_str()
+                .filter(|t| !t.is_empty())
+                .map(String::from),
+        }],
+        "DiscardTile" => vec![GameEvent::Discard {
+            seat: u32_field(data, "seat")?,
+            tile: str_field(data, "tile")?.to_string(),
+            tsumogiri: data["moqie"].as_bool().unwrap_or_default(),
+            riichi: data["is_liqi"].as_bool().unwrap_or_default()
+                || data["is_wliqi"].as_bool().unwrap_or_default(),
+        }],
+        "ChiPengGang" => {
+            let seat = u32_field(data, "seat")?;
+            let tiles: Vec<String> = str_list(data, "tiles")
+                .into_iter()
+                .map(String::from)
+                .collect();
+            let from = u32_list(data, "froms")
+                .into_iter()
+                .find(|f| *f != seat)
+                .ok_or(anyhow!("No called tile in ChiPengGang"))?;
+            vec![match u32_field(data, "type")? {
+                0 => GameEvent::Chi { seat, from, tiles },
+                1 => GameEvent::Pon { seat, from, tiles },
+                _ => GameEvent::Kan {
+                    seat,
+                    kind: KanKind::Open,
+                    tiles,
+                    from: Some(from),
+                },
+            }]
+        }
+        "AnGangAddGang" => vec![GameEvent::Kan {
+            seat: u32_field(data, "seat")?,
+            kind: if u32_field(data, "type")? == 3 {
+                KanKind::Closed
+            } else {
+                KanKind::Added
+            },
+            tiles: vec![str_field(data, "tiles")?.to_string()],
+            from: None,
+        }],
+        "BaBei" => vec![GameEvent::Nukidora {
+            seat: u32_field(data, "seat")?,
+        }],
+        "Hule" | "NoTile" | "LiuJu" => match &state.result {
+            Some(RoundResult::Hule { wins, .. }) => wins
+                .iter()
+                .map(|w| match w.zimo {
+                    true => GameEvent::Tsumo {
+                        seat: w.seat,
+                        tile: w.tile.clone(),
+                        han: w.han,
+                        fu: w.fu,
+                        points: w.points,
+                    },
+                    false => GameEvent::Ron {
+                        seat: w.seat,
+                        from: w.from,
+                        tile: w.tile.clone(),
+                        han: w.han,
+                        fu: w.fu,
+                        points: w.points,
+                    },
+                })
+                .collect(),
+            Some(RoundResult::NoTile { tenpai, deltas }) => vec![GameEvent::ExhaustiveDraw {
+                tenpai: tenpai.clone(),
+                deltas: deltas.clone(),
+            }],
+            Some(RoundResult::LiuJu { kind }) => vec![GameEvent::AbortiveDraw { kind: *kind }],
+            None => vec![],
+        },
+        _ => vec![],
+    })
+}
+
+/// The `GameEnd` of a `.lq.NotifyGameEndResult`.
+pub fn game_end(data: &JsonValue, state: &GameState) -> GameEvent {
+    GameEvent::GameEnd {
+        scores: final_scores(data, state),
+    }
+}
+
+/// Final scores by the order of the result, scores missing from it taken from `state`.
+pub fn final_scores(data: &JsonValue, state: &GameState) -> Vec<FinalScore> {
+    data["result"]["players"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|p| {
+            let seat = p["seat"].as_u64().unwrap_or_default() as u32;
+            FinalScore {
+                seat,
+                score: p["part_point_1"]
+                    .as_i64()
+                    .map(|s| s as i32)
+                    .or_else(|| state.player(seat).map(|p| p.score))
+                    .unwrap_or_default(),
+                total_point: p["total_point"].as_i64().unwrap_or_default() as i32,
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,318 @@
+use crate::{
+    format::strip_action,
+    record::Record,
+    report::Round,
+    shanten::shanten,
+    state::{GameState, RoundResult},
+    tile::counts34,
+};
+use anyhow::Result;
+use std::{fmt::Write, path::Path};
+
+/// One hand of a record, as seen from `seat`.
+#[derive(Debug, Clone)]
+pub struct HandRow {
+    pub uuid: String,
+    /// 1 for the first hand of the game
+    pub hand: u32,
+    pub round: Round,
+    pub dealer: u32,
+    pub seat: u32,
+    /// `zimo`, `ron`, `draw` or `abort`
+    pub outcome: &'static str,
+    pub winners: Vec<u32>,
+    /// value of the wins, honba and sticks excluded
+    pub points: u32,
+    /// score change of the seat
+    pub delta: i32,
+    pub riichi: bool,
+    /// chi, pon and open kan of the seat
+    pub calls: u32,
+    /// of the hand when the hand ended, -1 for a complete one
+    pub shanten: Option<i32>,
+}
+
+/// One action of a record with the hand it is in.
+#[derive(Debug, Clone)]
+pub struct ActionRow {
+    pub uuid: String,
+    /// 0 for the first action of the game
+    pub seq: u32,
+    /// 0 before the first hand
+    pub hand: u32,
+    pub chang: u32,
+    pub ju: u32,
+    pub ben: u32,
+    /// without the `Record` prefix, e.g. `DiscardTile`
+    pub name: String,
+    pub seat: Option<u32>,
+    pub tile: Option<String>,
+    /// the whole action as JSON
+    pub data: String,
+}
+
+/// Replay `record` and list its hands from `seat`.
+pub fn hand_rows(record: &Record, seat: u32) -> Vec<HandRow> {
+    let uuid = record.head["uuid"].as_str().unwrap_or_default();
+    let mut state = GameState {
+        spectated: true,
+        ..Default::default()
+    };
+    let (mut hand, mut calls) = (0, 0);
+    let mut rows = vec![];
+    for action in &record.actions {
+        let name = strip_action(&action.name);
+        match name {
+            "NewRound" => {
+                hand += 1;
+                calls = 0;
+            }
+            "ChiPengGang" if action.data["seat"].as_u64() == Some(seat as u64) => calls += 1,
+            _ => (),
+        }
+        // the hand as it was when play stopped
+        let last_hand = state
+            .player(seat)
+            .filter(|p| !p.hand.is_empty())
+            .map(|p| shanten(&counts34(&p.hand), p.melds.len()));
+        if state.feed(name, &action.data).is_err() {
+            continue;
+        }
+        let (outcome, winners, points, deltas) = match state.result.as_ref() {
+            Some(RoundResult::Hule { wins, deltas }) if name == "Hule" => (
+                if wins.iter().all(|w| w.zimo) {
+                    "zimo"
+                } else {
+                    "ron"
+                },
+                wins.iter().map(|w| w.seat).collect(),
+                wins.iter().map(|w| w.points).sum(),
+                deltas.as_slice(),
+            ),
+            Some(RoundResult::NoTile { deltas, .. }) if name == "NoTile" => {
+                ("draw", vec![], 0, deltas.as_slice())
+            }
+            Some(RoundResult::LiuJu { .. }) if name == "LiuJu" => ("abort", vec![], 0, &[][..]),
+            _ => continue,
+        };
+        rows.push(HandRow {
+            uuid: uuid.to_string(),
+            hand,
+            round: Round {
+                round_wind: state.round_wind,
+                ju: state.ju,
+                honba: state.honba,
+            },
+            dealer: state.ju,
+            seat,
+            outcome,
+            winners,
+            points,
+            delta: deltas.get(seat as usize).copied().unwrap_or_default(),
+            riichi: state.player(seat).is_some_and(|p| p.riichi),
+            calls,
+            shanten: last_hand,
+        });
+    }
+    rows
+}
+
+/// Every action of `record`, in order.
+pub fn action_rows(record: &Record) -> Vec<ActionRow> {
+    let uuid = record.head["uuid"].as_str().unwrap_or_default();
+    let field = |data: &serde_json::Value, key: &str| data[key].as_u64().unwrap_or_default() as u32;
+    let (mut hand, mut round) = (0, (0, 0, 0));
+    let mut rows = vec![];
+    for (seq, action) in record.actions.iter().enumerate() {
+        let data = &action.data;
+        let name = strip_action(&action.name);
+        if name == "NewRound" {
+            hand += 1;
+            round = (field(data, "chang"), field(data, "ju"), field(data, "ben"));
+        }
+        rows.push(ActionRow {
+            uuid: uuid.to_string(),
+            seq: seq as u32,
+            hand,
+            chang: round.0,
+            ju: round.1,
+            ben: round.2,
+            name: name.to_string(),
+            seat: data["seat"].as_u64().map(|s| s as u32),
+            tile: data["tile"]
+                .as_str()
+                .filter(|t| !t.is_empty())
+                .map(String::from),
+            data: data.to_string(),
+        });
+    }
+    rows
+}
+
+/// A column of an exported table, `None` where a row has no value.
+pub enum Column {
+    Int(Vec<Option<i64>>),
+    Str(Vec<Option<String>>),
+}
+
+impl Column {
+    fn len(&self) -> usize {
+        match self {
+            Column::Int(values) => values.len(),
+            Column::Str(values) => values.len(),
+        }
+    }
+}
+
+fn ints<T>(rows: &[T], f: impl Fn(&T) -> Option<i64>) -> Column {
+    Column::Int(rows.iter().map(f).collect())
+}
+
+fn strs<T>(rows: &[T], f: impl Fn(&T) -> Option<String>) -> Column {
+    Column::Str(rows.iter().map(f).collect())
+}
+
+/// Rows of one kind by column, written as CSV or Parquet.
+pub struct Table {
+    pub name: &'static str,
+    pub columns: Vec<(&'static str, Column)>,
+}
+
+impl Table {
+    /// Winners separated by spaces, riichi as 0 or 1.
+    pub fn hands(rows: &[HandRow]) -> Self {
+        let winners = |r: &HandRow| {
+            let winners: Vec<String> = r.winners.iter().map(u32::to_string).collect();
+            Some(winners.join(" "))
+        };
+        Table {
+            name: "hands",
+            columns: vec![
+                ("uuid", strs(rows, |r| Some(r.uuid.clone()))),
+                ("hand", ints(rows, |r| Some(r.hand.into()))),
+                ("round", strs(rows, |r| Some(r.round.to_string()))),
+                ("dealer", ints(rows, |r| Some(r.dealer.into()))),
+                ("seat", ints(rows, |r| Some(r.seat.into()))),
+                ("outcome", strs(rows, |r| Some(r.outcome.to_string()))),
+                ("winners", strs(rows, winners)),
+                ("points", ints(rows, |r| Some(r.points.into()))),
+                ("delta", ints(rows, |r| Some(r.delta.into()))),
+                ("riichi", ints(rows, |r| Some(r.riichi.into()))),
+                ("calls", ints(rows, |r| Some(r.calls.into()))),
+                ("shanten", ints(rows, |r| r.shanten.map(i64::from))),
+            ],
+        }
+    }
+
+    pub fn actions(rows: &[ActionRow]) -> Self {
+        Table {
+            name: "actions",
+            columns: vec![
+                ("uuid", strs(rows, |r| Some(r.uuid.clone()))),
+                ("seq", ints(rows, |r| Some(r.seq.into()))),
+                ("hand", ints(rows, |r| Some(r.hand.into()))),
+                ("chang", ints(rows, |r| Some(r.chang.into()))),
+                ("ju", ints(rows, |r| Some(r.ju.into()))),
+                ("ben", ints(rows, |r| Some(r.ben.into()))),
+                ("name", strs(rows, |r| Some(r.name.clone()))),
+                ("seat", ints(rows, |r| r.seat.map(i64::from))),
+                ("tile", strs(rows, |r| r.tile.clone())),
+                ("data", strs(rows, |r| Some(r.data.clone()))),
+            ],
+        }
+    }
+
+    pub fn rows(&self) -> usize {
+        self.columns.first().map_or(0, |(_, c)| c.len())
+    }
+
+    /// CSV with a header line, missing values left empty.
+    pub fn to_csv(&self) -> String {
+        let header: Vec<&str> = self.columns.iter().map(|(name, _)| *name).collect();
+        // the BOM makes Excel read it as UTF-8
+        let mut out = format!("\u{feff}{}", header.join(","));
+        out.push('\n');
+        for i in 0..self.rows() {
+            let fields: Vec<String> = self
+                .columns
+                .iter()
+                .map(|(_, column)| match column {
+                    Column::Int(values) => values[i].map(|v| v.to_string()).unwrap_or_default(),
+                    Column::Str(values) => csv_field(values[i].as_deref().unwrap_or_default()),
+                })
+                .collect();
+            let _ = writeln!(out, "{}", fields.join(","));
+        }
+        out
+    }
+
+    /// Write a snappy compressed Parquet file of a single row group.
+    #[cfg(feature = "parquet")]
+    pub fn write_parquet(&self, path: &Path) -> Result<()> {
+        use parquet::{
+            basic::Compression,
+            column::writer::ColumnWriter,
+            data_type::ByteArray,
+            file::{properties::WriterProperties, writer::SerializedFileWriter},
+            schema::parser::parse_message_type,
+        };
+        use std::sync::Arc;
+
+        let fields: Vec<String> = self
+            .columns
+            .iter()
+            .map(|(name, column)| match column {
+                Column::Int(_) => format!("OPTIONAL INT64 {};", name),
+                Column::Str(_) => format!("OPTIONAL BYTE_ARRAY {} (UTF8);", name),
+            })
+            .collect();
+        let schema =
+            parse_message_type(&format!("message {} {{ {} }}", self.name, fields.join(" ")))?;
+        let props = WriterProperties::builder()
+            .set_compression(Compression::SNAPPY)
+            .build();
+        let file = std::fs::File::create(path)?;
+        let mut writer = SerializedFileWriter::new(file, Arc::new(schema), Arc::new(props))?;
+        let mut row_group = writer.next_row_group()?;
+        for (_, column) in &self.columns {
+            let Some(mut out) = row_group.next_column()? else {
+                break;
+            };
+            match (out.untyped(), column) {
+                (ColumnWriter::Int64ColumnWriter(w), Column::Int(values)) => {
+                    let levels: Vec<i16> = values.iter().map(|v| v.is_some().into()).collect();
+                    let values: Vec<i64> = values.iter().flatten().copied().collect();
+                    w.write_batch(&values, Some(&levels), None)?;
+                }
+                (ColumnWriter::ByteArrayColumnWriter(w), Column::Str(values)) => {
+                    let levels: Vec<i16> = values.iter().map(|v| v.is_some().into()).collect();
+                    let values: Vec<ByteArray> = values
+                        .iter()
+                        .flatten()
+                        .map(|v| ByteArray::from(v.as_str()))
+                        .collect();
+                    w.write_batch(&values, Some(&levels), None)?;
+                }
+                _ => anyhow::bail!("Column type does not match the schema"),
+            }
+            out.close()?;
+        }
+        row_group.close()?;
+        writer.close()?;
+        Ok(())
+    }
+
+    #[cfg(not(feature = "parquet"))]
+    pub fn write_parquet(&self, _path: &Path) -> Result<()> {
+        anyhow::bail!("Parquet support is not compiled in")
+    }
+}
+
+/// Quote a field containing a separator, a quote or a line break.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
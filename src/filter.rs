@@ -0,0 +1,460 @@
+//! Filter expressions deciding which events a sink receives, e.g.
+//! `method =~ "^\.lq\.ActionPrototype$" && data.name != "ActionMJStart"`.
+//!
+//! Operands are literals (`"str"`, `1.5`, `true`, `false`, `null`) or paths:
+//! `method`, `type` (`notify`/`response`, requests of the client never reach sinks), `spectated`
+//! and `data.<field>...` into the decoded message, array elements as `data.tiles.0`.
+//! Operators are `== != < <= > >= =~ !~`, `&& || !` and parentheses,
+//! a bare operand is true unless it is missing, `null`, `false`, `0` or `""`.
+
+use crate::sink::Event;
+use anyhow::{anyhow, bail, Result};
+use regex::Regex;
+use serde_json::{json, Value as JsonValue};
+use std::borrow::Cow;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Str(String),
+    Num(f64),
+    Op(&'static str),
+    LParen,
+    RParen,
+}
+
+#[derive(Debug)]
+enum Operand {
+    Path(Vec<String>),
+    Literal(JsonValue),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CmpOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug)]
+enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Cmp(Operand, CmpOp, Operand),
+    Match(Operand, Regex, bool),
+    Truthy(Operand),
+}
+
+/// A compiled filter expression.
+#[derive(Debug)]
+pub struct Filter {
+    expr: Expr,
+}
+
+impl Filter {
+    pub fn parse(src: &str) -> Result<Self> {
+        let tokens = tokenize(src)?;
+        let mut parser = FilterParser { tokens, pos: 0 };
+        let expr = parser.or()?;
+        if parser.pos < parser.tokens.len() {
+            bail!("Unexpected token {:?}", parser.tokens[parser.pos].1);
+        }
+        Ok(Filter { expr })
+    }
+
+    pub fn matches(&self, event: &Event) -> bool {
+        eval(&self.expr, event)
+    }
+
+    /// The paths the filter reads, e.g. `["data", "tiles", "0"]` of `data.tiles.0`.
+    pub fn paths(&self) -> Vec<&[String]> {
+        fn operands<'a>(expr: &'a Expr, out: &mut Vec<&'a Operand>) {
+            match expr {
+                Expr::And(a, b) | Expr::Or(a, b) => {
+                    operands(a, out);
+                    operands(b, out);
+                }
+                Expr::Not(e) => operands(e, out),
+                Expr::Cmp(a, _, b) => out.extend([a, b]),
+                Expr::Match(o, _, _) | Expr::Truthy(o) => out.push(o),
+            }
+        }
+        let mut out = vec![];
+        operands(&self.expr, &mut out);
+        out.into_iter()
+            .filter_map(|o| match o {
+                Operand::Path(path) => Some(path.as_slice()),
+                Operand::Literal(_) => None,
+            })
+            .collect()
+    }
+}
+
+fn tokenize(src: &str) -> Result<Vec<(usize, Token)>> {
+    const OPS: [&str; 12] = [
+        "&&", "||", "==", "!=", "=~", "!~", "<=", ">=", "<", ">", "!", ".",
+    ];
+    let chars: Vec<char> = src.chars().collect();
+    let mut tokens = vec![];
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let start = i;
+        if c.is_whitespace() {
+            i += 1;
+        } else if c == '(' || c == ')' {
+            tokens.push((
+                start,
+                if c == '(' {
+                    Token::LParen
+                } else {
+                    Token::RParen
+                },
+            ));
+            i += 1;
+        } else if c == '"' {
+            let mut s = String::new();
+            i += 1;
+            loop {
+                match chars.get(i) {
+                    None => bail!("Unterminated string at column {}", start + 1),
+                    Some('"') => break,
+                    // only quotes and backslashes are escaped, `\.` stays for regexes
+                    Some('\\') if matches!(chars.get(i + 1), Some('"') | Some('\\')) => {
+                        s.push(chars[i + 1]);
+                        i += 2;
+                    }
+                    Some(c) => {
+                        s.push(*c);
+                        i += 1;
+                    }
+                }
+            }
+            i += 1;
+            tokens.push((start, Token::Str(s)));
+        } else if c.is_ascii_digit()
+            || (c == '-' && chars.get(i + 1).is_some_and(|c| c.is_ascii_digit()))
+        {
+            // an index of a path, e.g. the `0` of `data.hands.0.1`, ends at the next `.`
+            let index = matches!(tokens.last(), Some((_, Token::Op("."))));
+            i += 1;
+            while i < chars.len()
+                && (chars[i].is_ascii_digit()
+                    || (!index
+                        && chars[i] == '.'
+                        && chars.get(i + 1).is_some_and(|c| c.is_ascii_digit())))
+            {
+                i += 1;
+            }
+            let text: String = chars[start..i].iter().collect();
+            let num = text
+                .parse()
+                .map_err(|_| anyhow!("Invalid number {} at column {}", text, start + 1))?;
+            tokens.push((start, Token::Num(num)));
+        } else if c.is_alphanumeric() || c == '_' {
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            tokens.push((start, Token::Ident(chars[start..i].iter().collect())));
+        } else {
+            let rest: String = chars[i..(i + 2).min(chars.len())].iter().collect();
+            let op = OPS.iter().find(|op| rest.starts_with(*op)).ok_or(anyhow!(
+                "Unexpected character {:?} at column {}",
+                c,
+                start + 1
+            ))?;
+            i += op.len();
+            tokens.push((start, Token::Op(op)));
+        }
+    }
+    Ok(tokens)
+}
+
+struct FilterParser {
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+}
+
+impl FilterParser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(_, t)| t)
+    }
+
+    fn next(&mut self) -> Result<Token> {
+        let token = self
+            .tokens
+            .get(self.pos)
+            .map(|(_, t)| t.clone())
+            .ok_or(anyhow!("Unexpected end of filter"))?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn column(&self) -> usize {
+        self.tokens
+            .get(self.pos.saturating_sub(1))
+            .map(|(c, _)| c + 1)
+            .unwrap_or_default()
+    }
+
+    fn or(&mut self) -> Result<Expr> {
+        let mut lhs = self.and()?;
+        while self.peek() == Some(&Token::Op("||")) {
+            self.pos += 1;
+            lhs = Expr::Or(Box::new(lhs), Box::new(self.and()?));
+        }
+        Ok(lhs)
+    }
+
+    fn and(&mut self) -> Result<Expr> {
+        let mut lhs = self.unary()?;
+        while self.peek() == Some(&Token::Op("&&")) {
+            self.pos += 1;
+            lhs = Expr::And(Box::new(lhs), Box::new(self.unary()?));
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Expr> {
+        match self.peek() {
+            Some(Token::Op("!")) => {
+                self.pos += 1;
+                Ok(Expr::Not(Box::new(self.unary()?)))
+            }
+            Some(Token::LParen) => {
+                self.pos += 1;
+                let expr = self.or()?;
+                match self.next()? {
+                    Token::RParen => Ok(expr),
+                    t => bail!("Expected ) at column {}, found {:?}", self.column(), t),
+                }
+            }
+            _ => self.comparison(),
+        }
+    }
+
+    fn comparison(&mut self) -> Result<Expr> {
+        let lhs = self.operand()?;
+        let op = match self.peek() {
+            Some(Token::Op(op)) if !matches!(*op, "&&" | "||" | "!" | ".") => *op,
+            _ => return Ok(Expr::Truthy(lhs)),
+        };
+        self.pos += 1;
+        if op == "=~" || op == "!~" {
+            let Token::Str(pattern) = self.next()? else {
+                bail!("Expected regex string at column {}", self.column());
+            };
+            let regex = Regex::new(&pattern)
+                .map_err(|e| anyhow!("Invalid regex at column {}: {}", self.column(), e))?;
+            return Ok(Expr::Match(lhs, regex, op == "=~"));
+        }
+        let op = match op {
+            "==" => CmpOp::Eq,
+            "!=" => CmpOp::Ne,
+            "<" => CmpOp::Lt,
+            "<=" => CmpOp::Le,
+            ">" => CmpOp::Gt,
+            _ => CmpOp::Ge,
+        };
+        Ok(Expr::Cmp(lhs, op, self.operand()?))
+    }
+
+    fn operand(&mut self) -> Result<Operand> {
+        match self.next()? {
+            Token::Str(s) => Ok(Operand::Literal(json!(s))),
+            Token::Num(n) => Ok(Operand::Literal(json!(n))),
+            Token::Ident(id) if id == "true" => Ok(Operand::Literal(json!(true))),
+            Token::Ident(id) if id == "false" => Ok(Operand::Literal(json!(false))),
+            Token::Ident(id) if id == "null" => Ok(Operand::Literal(JsonValue::Null)),
+            Token::Ident(id) => {
+                let mut path = vec![id];
+                while self.peek() == Some(&Token::Op(".")) {
+                    self.pos += 1;
+                    match self.next()? {
+                        Token::Ident(seg) => path.push(seg),
+                        Token::Num(n) if n >= 0.0 && n.fract() == 0.0 => path.push(n.to_string()),
+                        t => bail!("Invalid path segment {:?} at column {}", t, self.column()),
+                    }
+                }
+                if !matches!(path[0].as_str(), "method" | "type" | "spectated" | "data") {
+                    bail!("Unknown field {} at column {}", path[0], self.column());
+                }
+                Ok(Operand::Path(path))
+            }
+            t => bail!("Unexpected {:?} at column {}", t, self.column()),
+        }
+    }
+}
+
+/// The value of an operand, borrowed from the filter or the event where it can be.
+fn resolve<'a>(operand: &'a Operand, event: &'a Event) -> Cow<'a, JsonValue> {
+    const NULL: &JsonValue = &JsonValue::Null;
+    let path = match operand {
+        Operand::Literal(v) => return Cow::Borrowed(v),
+        Operand::Path(path) => path,
+    };
+    let msg = &event.msg;
+    let root = match path[0].as_str() {
+        "method" => return Cow::Owned(json!(msg.method_name.as_ref())),
+        "type" => return Cow::Owned(json!(msg.msg_type.name())),
+        "spectated" => return Cow::Owned(json!(event.spectated)),
+        _ => &msg.data,
+    };
+    let mut value = root;
+    for seg in &path[1..] {
+        value = match value {
+            JsonValue::Array(a) => match seg.parse::<usize>().ok().and_then(|i| a.get(i)) {
+                Some(v) => v,
+                None => return Cow::Borrowed(NULL),
+            },
+            JsonValue::Object(o) => match o.get(seg) {
+                Some(v) => v,
+                None => return Cow::Borrowed(NULL),
+            },
+            _ => return Cow::Borrowed(NULL),
+        };
+    }
+    Cow::Borrowed(value)
+}
+
+fn truthy(value: &JsonValue) -> bool {
+    match value {
+        JsonValue::Null => false,
+        JsonValue::Bool(b) => *b,
+        JsonValue::Number(n) => n.as_f64() != Some(0.0),
+        JsonValue::String(s) => !s.is_empty(),
+        _ => true,
+    }
+}
+
+fn eval(expr: &Expr, event: &Event) -> bool {
+    match expr {
+        Expr::And(a, b) => eval(a, event) && eval(b, event),
+        Expr::Or(a, b) => eval(a, event) || eval(b, event),
+        Expr::Not(e) => !eval(e, event),
+        Expr::Truthy(o) => truthy(&resolve(o, event)),
+        Expr::Match(o, regex, positive) => {
+            let value = resolve(o, event);
+            let text = match value.as_ref() {
+                JsonValue::String(s) => Cow::Borrowed(s.as_str()),
+                JsonValue::Null => return !positive,
+                v => Cow::Owned(v.to_string()),
+            };
+            regex.is_match(&text) == *positive
+        }
+        Expr::Cmp(a, op, b) => {
+            let (a, b) = (resolve(a, event), resolve(b, event));
+            match (op, a.as_f64(), b.as_f64()) {
+                (CmpOp::Eq, Some(x), Some(y)) => x == y,
+                (CmpOp::Ne, Some(x), Some(y)) => x != y,
+                (CmpOp::Eq, _, _) => a == b,
+                (CmpOp::Ne, _, _) => a != b,
+                (op, Some(x), Some(y)) => match op {
+                    CmpOp::Lt => x < y,
+                    CmpOp::Le => x <= y,
+                    CmpOp::Gt => x > y,
+                    _ => x >= y,
+                },
+                (op, _, _) => match (a.as_str(), b.as_str()) {
+                    (Some(x), Some(y)) => match op {
+                        CmpOp::Lt => x < y,
+                        CmpOp::Le => x <= y,
+                        CmpOp::Gt => x > y,
+                        _ => x >= y,
+                    },
+                    _ => false,
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event() -> Event {
+        Event::derived(
+            ".lq.ActionPrototype",
+            json!({"name": "ActionDiscardTile", "tiles": ["1m", "5z"], "score": -1.5}),
+        )
+    }
+
+    fn check(src: &str) -> bool {
+        Filter::parse(src).unwrap().matches(&event())
+    }
+
+    #[test]
+    fn tokenizes_paths_numbers_and_strings() {
+        let tokens: Vec<Token> = tokenize(r#"data.tiles.0 -1.5 2 "a\"b\\c\.""#)
+            .unwrap()
+            .into_iter()
+            .map(|(_, t)| t)
+            .collect();
+        assert_eq!(
+            tokens,
+            [
+                Token::Ident("data".into()),
+                Token::Op("."),
+                Token::Ident("tiles".into()),
+                Token::Op("."),
+                Token::Num(0.0),
+                Token::Num(-1.5),
+                Token::Num(2.0),
+                Token::Str(r#"a"b\c\."#.into()),
+            ]
+        );
+        assert!(tokenize(r#""open"#).is_err());
+        assert!(tokenize("data @ 1").is_err());
+    }
+
+    #[test]
+    fn compares_paths_and_literals() {
+        assert!(check(r#"data.tiles.0 == "1m""#));
+        assert!(check(r#"data.tiles.1 != "1m""#));
+        assert!(check("data.score == -1.5 && data.score < 0"));
+        assert!(check(r#"type == "notify" && !spectated"#));
+        assert!(!check("data.score >= 0"));
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        assert!(check("true || false && false"));
+        assert!(!check("(true || false) && false"));
+        assert!(check("!false && !(false || false)"));
+        assert!(!check("!true || false"));
+    }
+
+    #[test]
+    fn matches_regexes() {
+        assert!(check(r#"method =~ "^\.lq\.ActionPrototype$""#));
+        assert!(check(r#"data.name !~ "MJStart""#));
+        assert!(check(r#"data.score =~ "^-1\.5$""#));
+        // a missing value matches no regex
+        assert!(!check(r#"data.missing =~ """#));
+        assert!(check(r#"data.missing !~ "x""#));
+        assert!(Filter::parse(r#"method =~ "(""#).is_err());
+    }
+
+    #[test]
+    fn missing_paths_are_falsy() {
+        assert!(check("data.tiles"));
+        assert!(!check("data.missing"));
+        assert!(!check("data.tiles.5"));
+        assert!(!check("data.name.deeper"));
+        assert!(check("data.missing == null"));
+        assert!(Filter::parse("unknown.field").is_err());
+    }
+
+    #[test]
+    fn lists_the_paths_read() {
+        let filter =
+            Filter::parse(r#"method == "data" || !(data.tiles.0 =~ "m" && spectated)"#).unwrap();
+        let paths: Vec<String> = filter.paths().iter().map(|p| p.join(".")).collect();
+        assert_eq!(paths, ["method", "data.tiles.0", "spectated"]);
+    }
+}
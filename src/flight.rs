@@ -0,0 +1,243 @@
+//! The last `debugFrames` frames of each session kept in memory, written to
+//! `debug/<time>/` of the config folder with the state of their parsers on
+//! `POST /debug/dump` or `SIGUSR1`, to look into a glitch after the fact:
+//! `parse debug/<time>/<session>.dump` replays what the parser saw. Secrets
+//! in requests and responses are masked as in the audit log.
+
+use crate::{
+    activity::ACTIVITY,
+    audit,
+    base::BaseMessage,
+    dump::{encode_frame_at, now_millis, DUMP_MAGIC},
+    instance,
+    memory::{self, Charge, Consumer},
+    parser::{dyn_to_json, json_to_bytes, Parser},
+    SETTINGS,
+};
+use anyhow::{bail, Result};
+use bytes::Bytes;
+use chrono::Local;
+use once_cell::sync::Lazy;
+use prost::Message;
+use prost_reflect::DynamicMessage;
+use serde_json::{json, Value as JsonValue};
+use std::{
+    collections::VecDeque,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{
+    sync::{
+        broadcast,
+        mpsc::{unbounded_channel, UnboundedSender},
+    },
+    time::timeout,
+};
+use tracing::{error, info};
+
+/// Lines of the dashboard log written along, empty without the dashboard.
+const LOGS: usize = 200;
+
+static REQUESTS: Lazy<broadcast::Sender<Arc<DumpRequest>>> = Lazy::new(|| broadcast::channel(4).0);
+
+struct Recorded {
+    time_ms: u64,
+    /// empty when the frame failed to parse
+    method: Arc<str>,
+    buf: Bytes,
+}
+
+/// The last frames of one session, the oldest dropped past `debugFrames`.
+pub struct Recent {
+    frames: VecDeque<Recorded>,
+    /// why the last frame that failed to parse did
+    last_error: Option<String>,
+    bytes: usize,
+    charge: Charge,
+}
+
+impl Default for Recent {
+    fn default() -> Self {
+        Recent {
+            frames: VecDeque::new(),
+            last_error: None,
+            bytes: 0,
+            charge: Charge::new(Consumer::Debug, 0),
+        }
+    }
+}
+
+impl Recent {
+    /// Keep a frame, parsed as `method` or not at all, none while the memory
+    /// is over the cap.
+    pub fn push(&mut self, buf: Bytes, method: Option<Arc<str>>) {
+        if SETTINGS.debug_frames == 0 {
+            return;
+        }
+        if memory::shed_debug() {
+            self.frames.clear();
+            self.bytes = 0;
+            self.charge.set(0);
+            return;
+        }
+        while self.frames.len() >= SETTINGS.debug_frames {
+            if let Some(old) = self.frames.pop_front() {
+                self.bytes -= old.buf.len();
+            }
+        }
+        self.bytes += buf.len();
+        self.charge.set(self.bytes);
+        self.frames.push_back(Recorded {
+            time_ms: now_millis(),
+            method: method.unwrap_or_else(|| Arc::from("")),
+            buf,
+        });
+    }
+
+    pub fn failed(&mut self, error: String) {
+        self.last_error = Some(error);
+    }
+}
+
+/// Asks every parse task to write its sessions.
+pub struct DumpRequest {
+    dir: PathBuf,
+    done: UnboundedSender<Vec<JsonValue>>,
+}
+
+impl DumpRequest {
+    /// Write the frames of every session of a parse task, replying with their
+    /// parser states.
+    pub fn write<'a>(
+        &self,
+        sessions: impl Iterator<Item = (&'a Option<SocketAddr>, &'a mut Parser, &'a Recent)>,
+    ) {
+        let states = sessions
+            .map(|(session, parser, recent)| {
+                let name = match session {
+                    Some(addr) => format!("session-{}", addr).replace([':', '[', ']'], "_"),
+                    None => "replayed".to_string(),
+                };
+                let file = self.dir.join(format!("{}.dump", name));
+                let written = write_frames(&file, parser, recent);
+                let pending: Vec<JsonValue> = parser
+                    .respond_type
+                    .iter()
+                    .map(|(id, method)| json!({ "id": id, "method": method.as_ref() }))
+                    .collect();
+                json!({
+                    "session": session,
+                    "file": file,
+                    "error": written.err().map(|e| format!("{:#}", e)),
+                    "frames": recent.frames.len(),
+                    "parsed": parser.parsed(),
+                    "pending": pending,
+                    "last_error": recent.last_error,
+                })
+            })
+            .collect();
+        let _ = self.done.send(states);
+    }
+}
+
+fn write_frames(path: &Path, parser: &mut Parser, recent: &Recent) -> Result<()> {
+    let mut out = DUMP_MAGIC.to_vec();
+    for frame in &recent.frames {
+        let buf = masked(parser, frame);
+        out.extend(encode_frame_at(frame.time_ms, &frame.method, &buf));
+    }
+    std::fs::write(path, out)?;
+    Ok(())
+}
+
+/// A request or response with the secrets of its body masked, the body
+/// dropped when it can't be decoded to look for them; notifies as they are.
+fn masked(parser: &mut Parser, frame: &Recorded) -> Bytes {
+    let buf = &frame.buf;
+    if !matches!(buf.first(), Some(2 | 3)) || buf.len() < 3 {
+        return buf.clone();
+    }
+    let Ok(mut envelope) = BaseMessage::decode(&buf[3..]) else {
+        return buf.slice(..3);
+    };
+    let body = parser
+        .body_type(&frame.method, buf[0] == 3)
+        .and_then(|desc| {
+            let mut data = dyn_to_json(DynamicMessage::decode(desc.clone(), &*envelope.data)?)?;
+            match audit::mask(&mut data) {
+                true => Ok(Some(json_to_bytes(desc, &data)?)),
+                false => Ok(None),
+            }
+        });
+    envelope.data = match body {
+        Ok(None) => return buf.clone(),
+        Ok(Some(data)) => data,
+        Err(_) => vec![],
+    };
+    let mut out = buf[..3].to_vec();
+    out.extend(envelope.encode_to_vec());
+    out.into()
+}
+
+/// For the parse tasks to take dump requests.
+pub fn requests() -> broadcast::Receiver<Arc<DumpRequest>> {
+    REQUESTS.subscribe()
+}
+
+/// Write the last frames of every session and the state of their parsers,
+/// returning the folder they are in.
+pub async fn dump() -> Result<PathBuf> {
+    let dir = instance::nested(SETTINGS.config_dir().join("debug"))
+        .join(Local::now().format("%Y%m%d-%H%M%S").to_string());
+    std::fs::create_dir_all(&dir)?;
+    let (done, mut replies) = unbounded_channel();
+    let request = Arc::new(DumpRequest {
+        dir: dir.clone(),
+        done,
+    });
+    let Ok(tasks) = REQUESTS.send(request) else {
+        bail!("The helper is not running");
+    };
+    let mut sessions = vec![];
+    for _ in 0..tasks {
+        match timeout(Duration::from_secs(10), replies.recv()).await {
+            Ok(Some(states)) => sessions.extend(states),
+            _ => bail!("A parse task did not answer"),
+        }
+    }
+    let state = json!({
+        "time": Local::now().to_rfc3339(),
+        "version": env!("CARGO_PKG_VERSION"),
+        "sessions": sessions,
+        "errors": ACTIVITY.errors(),
+        "logs": ACTIVITY.logs(LOGS),
+    });
+    std::fs::write(dir.join("state.json"), serde_json::to_vec_pretty(&state)?)?;
+    Ok(dir)
+}
+
+/// Dump on every `SIGUSR1`, e.g. `kill -USR1 <pid>`.
+#[cfg(unix)]
+pub async fn on_signal() {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut signals = match signal(SignalKind::user_defined1()) {
+        Ok(signals) => signals,
+        Err(e) => {
+            error!("Failed to install SIGUSR1 handler: {}", e);
+            return;
+        }
+    };
+    while signals.recv().await.is_some() {
+        match dump().await {
+            Ok(dir) => info!("Wrote the recent frames to {}", dir.display()),
+            Err(e) => error!("Failed to write debug dump: {:?}", e),
+        }
+    }
+}
+
+#[cfg(not(unix))]
+pub async fn on_signal() {
+    std::future::pending().await
+}
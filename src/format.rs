@@ -0,0 +1,316 @@
+use crate::{
+    mjai::MjaiConverter,
+    parser::{decode_action, decode_wrapper, record_actions, to_json_bytes},
+    players::{GameInfo, Rank},
+    settings::Settings,
+    sink::{Event, Payload},
+    tenhou::TenhouConverter,
+    text::TextFormat,
+    ARBITRARY_MD5, SETTINGS,
+};
+use anyhow::{anyhow, Result};
+use base64::prelude::*;
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value as JsonValue};
+use std::collections::{HashMap, HashSet};
+
+/// Payload format of a sink.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum FormatKind {
+    /// liqi JSON as expected by mahjong-helper
+    #[default]
+    Liqi,
+    /// one mjai event per payload
+    Mjai,
+    /// one tenhou.net/6 document per finished hand
+    Tenhou,
+    /// the original binary frame
+    Raw,
+    /// one line of text per action with Unicode tiles, for the `console` sink
+    Text,
+}
+
+pub trait EventFormat: Send {
+    /// Turn one event into zero or more payloads for the sink.
+    fn encode(&mut self, event: &Event) -> Result<Vec<Payload>>;
+}
+
+impl FormatKind {
+    /// The format of a sink, `liqi` sends the `sendMethod` and `sendAction` of `settings`.
+    pub fn build(self, settings: &Settings) -> Box<dyn EventFormat> {
+        match self {
+            FormatKind::Liqi => Box::new(LiqiFormat::new(settings)),
+            FormatKind::Mjai => Box::<MjaiFormat>::default(),
+            FormatKind::Tenhou => Box::<TenhouFormat>::default(),
+            FormatKind::Raw => Box::new(RawFormat),
+            FormatKind::Text => Box::<TextFormat>::default(),
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct Action {
+    pub name: String,
+    pub data: JsonValue,
+}
+
+pub struct LiqiFormat {
+    methods: HashSet<String>,
+    actions: HashSet<String>,
+}
+
+impl LiqiFormat {
+    pub fn new(settings: &Settings) -> Self {
+        LiqiFormat {
+            methods: settings.send_method.iter().cloned().collect(),
+            actions: settings.send_action.iter().cloned().collect(),
+        }
+    }
+}
+
+impl EventFormat for LiqiFormat {
+    fn encode(&mut self, event: &Event) -> Result<Vec<Payload>> {
+        let parsed = &event.msg;
+        if !self.methods.contains(parsed.method_name.as_ref()) {
+            return Ok(vec![]);
+        }
+        let json_data = match parsed.method_name.as_ref() {
+            ".lq.ActionPrototype" => {
+                let name = parsed
+                    .data
+                    .get("name")
+                    .and_then(|n| n.as_str())
+                    .ok_or(anyhow!("name field invalid"))?;
+                if !self.actions.contains(name) {
+                    return Ok(vec![]);
+                }
+                let data = parsed.data.get("data").ok_or(anyhow!("No data field"))?;
+                if name != "ActionNewRound" {
+                    return Ok(liqi_payloads(data, to_json_bytes(data)?));
+                }
+                let mut data = data.clone();
+                data.as_object_mut()
+                    .ok_or(anyhow!("data field invalid"))?
+                    .insert("md5".to_string(), json!(ARBITRARY_MD5));
+                data
+            }
+            ".lq.FastTest.syncGame" => {
+                let mut actions: Vec<Action> = vec![];
+                for (name, mut value) in restore_actions(&parsed.data)? {
+                    if name == "ActionNewRound" {
+                        value
+                            .as_object_mut()
+                            .ok_or(anyhow!("data is not an object"))?
+                            .insert("md5".to_string(), json!(ARBITRARY_MD5));
+                    }
+                    actions.push(Action { name, data: value });
+                }
+                let mut map = Map::with_capacity(1);
+                map.insert(
+                    "sync_game_actions".to_string(),
+                    serde_json::to_value(actions)?,
+                );
+                JsonValue::Object(map)
+            }
+            _ => return Ok(liqi_payloads(&parsed.data, parsed.json()?)),
+        };
+        Ok(liqi_payloads(&json_data, to_json_bytes(&json_data)?))
+    }
+}
+
+/// `data` already serialized as `serialized`, then its riichi, which is sent
+/// to mahjong-helper separately.
+fn liqi_payloads(data: &JsonValue, serialized: Bytes) -> Vec<Payload> {
+    let mut payloads = vec![Payload::Serialized(serialized)];
+    payloads.extend(data.get("liqi").cloned().map(Payload::Json));
+    payloads
+}
+
+pub struct RawFormat;
+
+impl EventFormat for RawFormat {
+    fn encode(&mut self, event: &Event) -> Result<Vec<Payload>> {
+        if event.raw.is_empty() {
+            // derived events have no frame
+            return Ok(vec![]);
+        }
+        Ok(vec![Payload::Binary(event.raw.clone())])
+    }
+}
+
+#[derive(Default)]
+pub struct MjaiFormat {
+    seating: Seating,
+    converter: MjaiConverter,
+}
+
+impl EventFormat for MjaiFormat {
+    fn encode(&mut self, event: &Event) -> Result<Vec<Payload>> {
+        if let Some(game) = self.seating.observe(event) {
+            self.converter.set_game(&game);
+        }
+        let mut events = vec![];
+        if event.msg.method_name.as_ref() == ".lq.NotifyGameEndResult" {
+            events.extend(self.converter.end_game());
+        }
+        for (name, data) in game_actions(event)? {
+            events.extend(self.converter.feed(strip_action(&name), &data)?);
+        }
+        Ok(events.into_iter().map(Payload::Json).collect())
+    }
+}
+
+#[derive(Default)]
+pub struct TenhouFormat {
+    seating: Seating,
+    converter: TenhouConverter,
+}
+
+impl EventFormat for TenhouFormat {
+    fn encode(&mut self, event: &Event) -> Result<Vec<Payload>> {
+        if let Some(game) = self.seating.observe(event) {
+            self.converter.set_game(&game);
+        }
+        let mut payloads = vec![];
+        for (name, data) in game_actions(event)? {
+            if let Some(entry) = self.converter.feed(strip_action(&name), &data)? {
+                payloads.push(Payload::Json(self.converter.document_with(vec![entry])));
+            }
+        }
+        Ok(payloads)
+    }
+}
+
+/// Own account and seat, learnt from login and authGame responses.
+#[derive(Default)]
+pub struct Seating {
+    account_id: Option<u32>,
+    game_uuid: Option<String>,
+    /// friends' ranks, for players whose game view lacks them
+    friends: HashMap<u32, (Option<Rank>, Option<Rank>)>,
+}
+
+impl Seating {
+    pub fn set_account_id(&mut self, account_id: u32) {
+        self.account_id = Some(account_id);
+    }
+
+    /// Returns own seat and the players when a game is joined.
+    pub fn observe(&mut self, event: &Event) -> Option<GameInfo> {
+        let data = &event.msg.data;
+        match event.msg.method_name.as_ref() {
+            ".lq.Lobby.login" | ".lq.Lobby.oauth2Login" | ".lq.Lobby.emailLogin" => {
+                self.account_id = data["account_id"].as_u64().map(|id| id as u32);
+                None
+            }
+            ".lq.Lobby.fetchFriendList" => {
+                for friend in data["friends"].as_array().into_iter().flatten() {
+                    let base = &friend["base"];
+                    if let Some(id) = base["account_id"].as_u64() {
+                        let ranks = (
+                            Rank::from_level(&base["level"]),
+                            Rank::from_level(&base["level3"]),
+                        );
+                        self.friends.insert(id as u32, ranks);
+                    }
+                }
+                None
+            }
+            ".lq.NotifyRoomGameStart" | ".lq.NotifyMatchGameStart" => {
+                self.game_uuid = data["game_uuid"].as_str().map(String::from);
+                None
+            }
+            ".lq.FastTest.authGame" => {
+                let account_id = self.account_id? as u64;
+                let seat = data["seat_list"]
+                    .as_array()?
+                    .iter()
+                    .position(|id| id.as_u64() == Some(account_id))?;
+                let mut game = GameInfo::from_auth(data, seat as u32, self.game_uuid.take());
+                for player in game.players.iter_mut() {
+                    if let Some((rank, rank3)) = self.friends.get(&player.account_id) {
+                        player.rank = player.rank.take().or_else(|| rank.clone());
+                        player.rank3 = player.rank3.take().or_else(|| rank3.clone());
+                    }
+                }
+                Some(game)
+            }
+            _ => None,
+        }
+    }
+}
+
+pub fn strip_action(name: &str) -> &str {
+    name.strip_prefix("Action")
+        .or_else(|| name.strip_prefix("Record"))
+        .unwrap_or(name)
+}
+
+/// Decoded game actions carried by an event, in order.
+pub fn game_actions(event: &Event) -> Result<Vec<(String, JsonValue)>> {
+    let data = &event.msg.data;
+    match event.msg.method_name.as_ref() {
+        ".lq.ActionPrototype" => {
+            let name = data
+                .get("name")
+                .and_then(|n| n.as_str())
+                .ok_or(anyhow!("name field invalid"))?;
+            let data = data.get("data").ok_or(anyhow!("No data field"))?;
+            Ok(vec![(name.to_string(), data.clone())])
+        }
+        ".lq.FastTest.syncGame" => restore_actions(data),
+        ".lq.NotifyObserveData" => live_actions(std::slice::from_ref(&data["unit"])),
+        ".lq.FastTest.startObserve" => live_actions(
+            data["passed"]["actions"]
+                .as_array()
+                .map(Vec::as_slice)
+                .unwrap_or_default(),
+        ),
+        ".lq.Lobby.fetchGameRecord" => match data["data"].as_str() {
+            Some(records) if !records.is_empty() => record_actions(records, SETTINGS.desc()),
+            // stored remotely at data_url
+            _ => Ok(vec![]),
+        },
+        _ => Ok(vec![]),
+    }
+}
+
+/// Decode `GameLiveUnit`s of a spectated game.
+pub(crate) fn live_actions(units: &[JsonValue]) -> Result<Vec<(String, JsonValue)>> {
+    units
+        .iter()
+        .filter_map(|u| u["action_data"].as_str())
+        .filter(|d| !d.is_empty())
+        .map(|d| decode_wrapper(&BASE64_STANDARD.decode(d)?, SETTINGS.desc()))
+        .collect()
+}
+
+/// Decode the actions of a syncGame response's game_restore.
+pub fn restore_actions(data: &JsonValue) -> Result<Vec<(String, JsonValue)>> {
+    let game_restore = data
+        .get("game_restore")
+        .and_then(|n| n.get("actions"))
+        .and_then(|n| n.as_array())
+        .ok_or(anyhow!("actions field invalid"))?;
+    let mut actions = vec![];
+    for item in game_restore.iter() {
+        let action_name = item
+            .get("name")
+            .and_then(|n| n.as_str())
+            .ok_or(anyhow!("name field invalid"))?;
+        let action_data = item
+            .get("data")
+            .ok_or(anyhow!("No data field"))?
+            .as_str()
+            .unwrap_or_default();
+        let value = if action_data.is_empty() {
+            JsonValue::Object(Map::new())
+        } else {
+            decode_action(action_name, action_data, SETTINGS.desc())?
+        };
+        actions.push((action_name.to_string(), value));
+    }
+    Ok(actions)
+}
@@ -1,174 +1,417 @@
 use crate::{
-    parser::{decode_action, LiqiMessage, Parser},
-    ARBITRARY_MD5, SETTINGS,
+    activity::ACTIVITY,
+    board, clock,
+    contest::CONTEST_STATE,
+    desync,
+    engine::Engine,
+    events,
+    format::{game_actions, strip_action, Seating},
+    hint, image,
+    isolation::{self, frame_head},
+    logging::PIPELINE,
+    notes::NOTES,
+    notify::Notifier,
+    pool::{self, Parsed},
+    record::LiveRecorder,
+    reload,
+    report::{self, GameReport},
+    safety,
+    session::SESSION,
+    shanten::analyze,
+    sink::{Event, Sinks},
+    snapshot::Snapshots,
+    state::{GameState, GAME_STATE},
+    stats::Enricher,
+    store::Store,
+    summary::summarize,
+    tr, watchdog, ARG, SETTINGS,
 };
-use anyhow::{anyhow, Result};
 use bytes::Bytes;
-use once_cell::sync::Lazy;
-use reqwest::Client;
-use serde::Serialize;
-use serde_json::{json, Map, Value as JsonValue};
-use std::future::Future;
-use tokio::{sync::mpsc::Receiver, time::sleep};
-use tracing::{debug, error, info};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{net::SocketAddr, time::Instant};
+use tokio::{
+    sync::mpsc::{error::TrySendError, unbounded_channel, Receiver, Sender},
+    time::sleep,
+};
+use tracing::{debug, debug_span, error, warn, Instrument};
+
+/// A binary websocket frame captured by the proxy.
+pub struct Frame {
+    pub buf: Bytes,
+    /// client end of the websocket, none for replayed frames
+    pub session: Option<SocketAddr>,
+    pub direction: char,
+    /// from the `/ob` websocket of a spectated game
+    pub spectated: bool,
+    /// replayed by `record simulate`, kept out of the stored history
+    pub simulated: bool,
+}
+
+/// What the proxy does with a frame when the helper queue is full.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum Overflow {
+    /// drop the frame, the game traffic is never held up by the helper
+    #[default]
+    Drop,
+    /// wait for room, holding up the websocket until the helper catches up
+    Block,
+}
+
+/// Queue between the proxy and the helper, see [`enqueue`].
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct FrameQueueConfig {
+    #[serde(default = "default_queue_capacity")]
+    pub capacity: usize,
+    #[serde(default)]
+    pub overflow: Overflow,
+    /// tasks parsing frames, 0 for one per CPU core, see [`crate::pool`]
+    #[serde(default)]
+    pub workers: usize,
+}
+
+impl Default for FrameQueueConfig {
+    fn default() -> Self {
+        Self {
+            capacity: default_queue_capacity(),
+            overflow: Overflow::default(),
+            workers: 0,
+        }
+    }
+}
 
-#[derive(Serialize, Debug)]
-struct Action {
-    pub name: String,
-    pub data: JsonValue,
+fn default_queue_capacity() -> usize {
+    1000
 }
 
-pub async fn helper_worker(mut receiver: Receiver<(Bytes, char)>, mut parser: Parser) {
+/// Hand a frame to the helper, which parses it in its own task while the
+/// proxy forwards the original.
+pub async fn enqueue(sender: &Sender<Frame>, frame: Frame, overflow: Overflow) {
+    let sent = match overflow {
+        Overflow::Drop => match sender.try_send(frame) {
+            Err(TrySendError::Full(_)) => {
+                ACTIVITY.frame_dropped();
+                warn!("Helper queue full, frame dropped");
+                return;
+            }
+            sent => sent.map_err(|_| ()),
+        },
+        Overflow::Block => sender.send(frame).await.map_err(|_| ()),
+    };
+    if sent.is_err() {
+        error!("Failed to send message to channel: helper stopped");
+    }
+}
+
+pub async fn helper_worker(receiver: Receiver<Frame>) {
+    let (mut sinks, mut status) = Sinks::from_settings(&SETTINGS);
+    let mut reloads = reload::subscribe();
+    let mut stalls = watchdog::subscribe();
+    let mut seating = Seating::default();
+    let mut snapshots = Snapshots::new(SETTINGS.snapshot_interval_secs);
+    if let Some(account_id) = snapshots.account_id() {
+        seating.set_account_id(account_id);
+    }
+    let (mut engine, mut engine_hints) = match SETTINGS.engine.clone().map(Engine::new) {
+        Some(Ok((engine, hints))) => (Some(engine), hints),
+        Some(Err(e)) => {
+            error!("Failed to start engine bridge: {:?}", e);
+            (None, unbounded_channel().1)
+        }
+        None => (None, unbounded_channel().1),
+    };
+    let (enricher, mut enrichments) = match SETTINGS.stats_api.clone().map(Enricher::new) {
+        Some(Ok((enricher, rx))) => (Some(enricher), rx),
+        Some(Err(e)) => {
+            error!("Failed to start stats lookup: {:?}", e);
+            (None, unbounded_channel().1)
+        }
+        None => (None, unbounded_channel().1),
+    };
+    let notifier = SETTINGS.notifications.clone().and_then(Notifier::new);
+    let mut recorder = SETTINGS.live_record_dir.clone().map(LiveRecorder::new);
+    let mut store = SETTINGS
+        .database
+        .as_ref()
+        .and_then(|path| match Store::open(path) {
+            Ok(store) => Some(store),
+            Err(e) => {
+                error!("Failed to open database: {:?}", e);
+                None
+            }
+        });
+    let mut messages = pool::spawn(receiver, SETTINGS.frame_queue.workers);
     loop {
-        let (buf, direction_char) = match receiver.recv().await {
-            Some((b, c)) => (b, c),
-            None => {
-                error!("Failed to receive message from channel, retrying...");
-                sleep(std::time::Duration::from_secs(1)).await;
+        let Parsed {
+            msg: parsed,
+            raw: buf,
+            spectated,
+            simulated,
+        } = tokio::select! {
+            parsed = messages.recv() => match parsed {
+                Some(parsed) => parsed,
+                None => {
+                    error!("Failed to receive message from channel, retrying...");
+                    sleep(std::time::Duration::from_secs(1)).await;
+                    continue;
+                }
+            },
+            Some(event) = status.recv() => {
+                sinks.dispatch(&event);
                 continue;
             }
-        };
-        let hex = buf
-            .iter()
-            .map(|b| {
-                if *b >= 0x20 && *b <= 0x7e {
-                    format!("{}", *b as char)
-                } else {
-                    format!("{:02x} ", b)
+            Some(event) = engine_hints.recv() => {
+                sinks.dispatch(&event);
+                continue;
+            }
+            Some(event) = enrichments.recv() => {
+                sinks.dispatch(&event);
+                continue;
+            }
+            Ok(stall) = stalls.recv() => {
+                sinks.dispatch(&Event::derived(".helper.UpstreamStall", json!(stall)));
+                continue;
+            }
+            Ok(()) = reloads.changed() => {
+                if let Some(settings) = reloads.borrow_and_update().clone() {
+                    // only the sinks restart, parsers and game state are kept
+                    (sinks, status) = Sinks::from_settings(&settings);
                 }
-            })
-            .collect::<String>();
-        debug!("{} {}", direction_char, hex);
-        let parsed = parser.parse(buf.clone());
-        let parsed = match parsed {
-            Ok(parsed) => parsed,
-            Err(e) => {
-                error!("Failed to parse message: {:?}", e);
                 continue;
             }
         };
-        debug!(
-            "Method: {}, {}, {:?}, {}",
-            direction_char, parsed.id, parsed.msg_type, parsed.method_name
-        );
-        if direction_char == '\u{2191}' {
-            continue;
-        }
-        if let Err(e) = process_message(parsed, &mut parser) {
-            error!("Failed to process message: {:?}", e);
+        let method = parsed.method_name.clone();
+        let raw = buf.clone();
+        // a panic skips the message, the helper goes on with the next
+        let handled = isolation::catch_async(async {
+            // viewed replays are analyzed like spectated games
+            let spectated = spectated || parsed.method_name.as_ref() == ".lq.Lobby.fetchGameRecord";
+            let event = Event {
+                msg: parsed,
+                raw: buf,
+                from_client: false,
+                spectated,
+            };
+            let span =
+                debug_span!(target: PIPELINE, "track", method = event.msg.method_name.as_ref());
+            let mut derived = update_state(&mut seating, &mut snapshots, &event, simulated)
+                .instrument(span)
+                .await;
+            derived.extend(CONTEST_STATE.write().await.feed(&event));
+            let (degraded, seat) = {
+                let state = GAME_STATE.read().await;
+                if let Some(store) = store.as_mut().filter(|_| !simulated) {
+                    store.feed(&event, &state);
+                }
+                if let Some(recorder) = recorder.as_mut().filter(|_| !simulated) {
+                    recorder.feed(&event, &state);
+                }
+                (state.degraded(), state.seat)
+            };
+            if let Some(enricher) = enricher.as_ref() {
+                if event.msg.method_name.as_ref() == ".lq.FastTest.authGame" {
+                    if let Some(game) = GAME_STATE.read().await.game.as_ref() {
+                        enricher.lookup(game);
+                    }
+                }
+            }
+            if let Some(engine) = engine.as_mut().filter(|_| !degraded) {
+                if let Err(e) = engine.feed(&event) {
+                    error!("Failed to feed engine: {:?}", e);
+                }
+            }
+            sinks.dispatch(&event);
+            for event in &derived {
+                sinks.dispatch(event);
+            }
+            if let Some(notifier) = notifier.as_ref() {
+                notifier.feed(&event, seat);
+                for event in &derived {
+                    notifier.feed(event, seat);
+                }
+            }
+        })
+        .await;
+        if let Err(panicked) = handled {
+            ACTIVITY.panicked();
+            error!(
+                "Helper crashed on {}, message skipped; frame of {} bytes: {}\n{}",
+                method,
+                raw.len(),
+                frame_head(&raw),
+                panicked
+            );
         }
     }
 }
 
-fn process_message(mut parsed: LiqiMessage, parser: &mut Parser) -> Result<()> {
-    static CLIENT: Lazy<Client> = Lazy::new(|| {
-        reqwest::ClientBuilder::new()
-            .danger_accept_invalid_certs(true)
-            .build()
-            .expect("Failed to create reqwest client")
-    });
-    if !SETTINGS.is_method(&parsed.method_name) {
-        return Ok(());
+/// Feed the game state, returning the derived events it produced.
+async fn update_state(
+    seating: &mut Seating,
+    snapshots: &mut Snapshots,
+    event: &Event,
+    simulated: bool,
+) -> Vec<Event> {
+    let mut state = GAME_STATE.write().await;
+    let mut derived = vec![];
+    if let Some(game) = seating.observe(event) {
+        derived.extend(NOTES.read().await.encounter(&game));
+        // a new game was joined
+        *state = GameState::default();
+        state.set_seat(game.seat);
+        state.game = Some(game);
+        snapshots.restore(&mut state);
+        if state.degraded() {
+            let modes = state
+                .game
+                .as_ref()
+                .map(|g| g.modes.clone())
+                .unwrap_or_default();
+            warn!(
+                "{}",
+                tr!(
+                    "特殊模式 {:?}，牌桌追踪可能不准确，已停用分析",
+                    "特殊ルール {:?} のため卓の追跡が不正確な可能性があり、分析を停止しました",
+                    "Special modes {:?}, the table may be tracked wrongly, analysis is off",
+                    modes
+                )
+            );
+        }
+    }
+    let degraded = state.degraded();
+    let method = event.msg.method_name.as_ref();
+    if matches!(
+        method,
+        ".lq.FastTest.startObserve" | ".lq.Lobby.fetchGameRecord"
+    ) {
+        // watching someone else's game, there is no own seat
+        *state = GameState {
+            spectated: true,
+            ..Default::default()
+        };
     }
-    let json_data = match parsed.method_name.as_ref() {
-        ".lq.ActionPrototype" => {
-            let name = parsed
-                .data
-                .get("name")
-                .and_then(|n| n.as_str())
-                .ok_or(anyhow!("name field invalid"))?;
-            if !SETTINGS.is_action(name) {
-                return Ok(());
-            }
-            if name == "ActionNewRound" {
-                parsed
-                    .data
-                    .get_mut("data")
-                    .and_then(|d| d.as_object_mut())
-                    .ok_or(anyhow!("data field invalid"))?
-                    .insert("md5".to_string(), json!(ARBITRARY_MD5));
-            }
-            parsed
-                .data
-                .get_mut("data")
-                .ok_or(anyhow!("No data field"))?
-                .take()
-        }
-        ".lq.FastTest.syncGame" => {
-            let game_restore = parsed
-                .data
-                .get("game_restore")
-                .and_then(|n| n.get("actions"))
-                .and_then(|n| n.as_array())
-                .ok_or(anyhow!("actions field invalid"))?;
-            let mut actions: Vec<Action> = vec![];
-            for item in game_restore.iter() {
-                let action_name = item
-                    .get("name")
-                    .and_then(|n| n.as_str())
-                    .ok_or(anyhow!("name field invalid"))?;
-                let action_data = item
-                    .get("data")
-                    .ok_or(anyhow!("No data field"))?
-                    .as_str()
-                    .unwrap_or_default();
-                if action_data.is_empty() {
-                    let action = Action {
-                        name: action_name.to_string(),
-                        data: JsonValue::Object(Map::new()),
-                    };
-                    actions.push(action);
-                } else {
-                    let mut value = decode_action(action_name, action_data, parser.pool)?;
-                    if action_name == "ActionNewRound" {
-                        value
-                            .as_object_mut()
-                            .ok_or(anyhow!("data is not an object"))?
-                            .insert("md5".to_string(), json!(ARBITRARY_MD5));
+    if method == ".lq.NotifyGameEndResult" {
+        let end = events::game_end(&event.msg.data, &state);
+        derived.push(Event::derived(".helper.GameEvent", json!(end)));
+        if !event.spectated && !simulated {
+            snapshots.clear();
+            if let Err(e) = NOTES.write().await.record_game(&state, &event.msg.data) {
+                error!("Failed to save opponent records: {:?}", e);
+            }
+            events::publish(end);
+            let mut session = SESSION.write().await;
+            if let Some(session) = session.record(&event.msg.data, &state, SETTINGS.session_reset) {
+                derived.push(Event::derived(".helper.Session", json!(session)));
+            }
+            if let Some(config) = SETTINGS.report.as_ref() {
+                let report = GameReport::new(&state, &event.msg.data);
+                tokio::spawn(async move {
+                    if let Err(e) = report.write(config).await {
+                        error!("Failed to write game report: {:?}", e);
                     }
-                    let action = Action {
-                        name: action_name.to_string(),
-                        data: value,
-                    };
-                    actions.push(action);
-                }
+                });
             }
-            let mut map = Map::with_capacity(1);
-            map.insert(
-                "sync_game_actions".to_string(),
-                serde_json::to_value(actions)?,
-            );
-            JsonValue::Object(map)
         }
-        _ => parsed.data,
+    }
+    if method == ".lq.FastTest.syncGame" && !degraded {
+        match desync::check(&state, &event.msg.data) {
+            Ok(Some(report)) => {
+                warn!(
+                    "Tracked state diverged from syncGame at step {}: {} differences",
+                    report.step,
+                    report.differences.len()
+                );
+                derived.push(Event::derived(".helper.Desync", json!(report)));
+            }
+            Ok(None) => (),
+            Err(e) => error!("Failed to check syncGame against state: {:?}", e),
+        }
+    }
+    let actions = match game_actions(event) {
+        Ok(actions) => actions,
+        Err(e) => {
+            error!("Failed to decode actions: {:?}", e);
+            return derived;
+        }
     };
-
-    // post data to API, no verification
-    let future = CLIENT.post(&SETTINGS.api_url).json(&json_data).send();
-
-    handle_future(future);
-    info!("已发送至助手");
-
-    if let Some(liqi_data) = json_data.get("liqi") {
-        let res = CLIENT.post(&SETTINGS.api_url).json(liqi_data).send();
-        handle_future(res);
-        info!("已发送立直至助手");
+    let live = matches!(method, ".lq.ActionPrototype" | ".lq.NotifyObserveData");
+    let has_actions = !actions.is_empty();
+    // the dashboard shows the board itself
+    let print_board = ARG.print_board && !ARG.tui && has_actions;
+    if matches!(method, ".lq.ActionPrototype" | ".lq.FastTest.syncGame") {
+        if let Some(step) = event.msg.data["step"].as_u64() {
+            state.step = step as u32;
+        }
     }
-
-    Ok(())
-}
-
-fn handle_future(
-    future: impl Future<Output = Result<reqwest::Response, reqwest::Error>> + Send + 'static,
-) {
-    tokio::spawn(async {
-        match future.await {
-            Ok(_) => {
-                info!("小助手已接收");
+    let now = Instant::now();
+    for (name, data) in actions {
+        let before: Vec<i32> = state.players.iter().map(|p| p.score).collect();
+        if let Err(e) = state.feed(strip_action(&name), &data) {
+            // expected with event modes, the raw event still goes out
+            if degraded {
+                debug!("Skipped {} in special mode: {:?}", name, e);
+            } else {
+                error!("Failed to update game state with {}: {:?}", name, e);
             }
-            Err(e) => {
-                error!("请求失败: {:?}", e);
+            continue;
+        }
+        match events::from_action(strip_action(&name), &data, &state) {
+            Ok(typed) => {
+                for e in typed {
+                    derived.push(Event::derived(".helper.GameEvent", json!(e)));
+                    if method == ".lq.ActionPrototype" {
+                        events::publish(e);
+                    }
+                }
             }
+            Err(e) if degraded => debug!("Skipped game events of {}: {:?}", name, e),
+            Err(e) => error!("Failed to build game events of {}: {:?}", name, e),
+        }
+        if let Err(e) = report::record(&mut state, strip_action(&name), &data) {
+            error!("Failed to tally {}: {:?}", name, e);
         }
-    });
+        if live {
+            if let Some(turn) = clock::tick(&mut state, strip_action(&name), &data, now) {
+                derived.push(Event::derived(".helper.TurnTime", json!(turn)));
+            }
+        }
+        let own_draw = name == "ActionDealTile"
+            && data["seat"].as_u64().map(|s| s as u32) == state.seat
+            && !data["tile"].as_str().unwrap_or_default().is_empty();
+        if degraded {
+            continue;
+        }
+        if live && own_draw {
+            if let Some(analysis) = analyze(&state) {
+                derived.push(Event::derived(".helper.HandAnalysis", json!(analysis)));
+            }
+        }
+        if live && name == "ActionDiscardTile" {
+            let report = safety::analyze(&state);
+            derived.push(Event::derived(".helper.Safety", json!(report)));
+            if let Some(hint) = hint::analyze(&state) {
+                derived.push(Event::derived(".helper.TenpaiHint", json!(hint)));
+            }
+        }
+        if matches!(name.as_str(), "ActionHule" | "ActionNoTile" | "ActionLiuJu") {
+            if let Some(summary) = summarize(&state, &before) {
+                derived.push(Event::derived(".helper.HandSummary", json!(summary)));
+            }
+        }
+    }
+    if has_actions && !event.spectated && !simulated {
+        snapshots.save(&state);
+    }
+    if let Some(config) = SETTINGS.hand_image.as_ref().filter(|_| has_actions) {
+        image::write(&state, config);
+    }
+    if print_board {
+        println!("{}", board::render(&state, SETTINGS.tile_notation));
+    }
+    for e in derived.iter_mut() {
+        e.spectated = event.spectated;
+    }
+    derived
 }
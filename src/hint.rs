@@ -0,0 +1,100 @@
+use crate::{
+    shanten::{shanten, waits},
+    state::{GameState, MeldKind},
+    tile::{counts34, index34, TILES34},
+    yaku::{detect, Context},
+};
+use serde::Serialize;
+
+#[derive(Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Furiten {
+    /// a wait is among the own discards
+    pub discard: bool,
+    /// a wait was passed since the own last discard
+    pub temporary: bool,
+    /// a wait was passed after declaring riichi
+    pub riichi: bool,
+}
+
+impl Furiten {
+    pub fn any(&self) -> bool {
+        self.discard || self.temporary || self.riichi
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct WaitYaku {
+    pub tile: String,
+    /// yaku when winning by ron, riichi included if declared
+    pub ron: Vec<&'static str>,
+    pub tsumo: Vec<&'static str>,
+}
+
+/// Data of `.helper.TenpaiHint`, emitted while the own hand is tenpai.
+#[derive(Serialize, Debug, Clone)]
+pub struct TenpaiHint {
+    pub seat: u32,
+    pub waits: Vec<WaitYaku>,
+    pub furiten: Furiten,
+    /// no wait has a yaku for ron, riichi or tsumo on a closed hand may still help
+    pub yakuless: bool,
+    pub menzen: bool,
+}
+
+/// Waits, furiten and yaku of the own 3n+1 hand, `None` unless tenpai.
+pub fn analyze(state: &GameState) -> Option<TenpaiHint> {
+    let own = state.own()?;
+    if own.hand.len() % 3 != 1 {
+        return None;
+    }
+    let counts = counts34(&own.hand);
+    let melds = own.melds.len();
+    if shanten(&counts, melds) != 0 {
+        return None;
+    }
+    let waits = waits(&counts, melds, state.is_sanma());
+    let is_wait = |tile: &str| index34(tile).is_ok_and(|i| waits.contains(&i));
+    let last_own = own.discards.last().map(|d| d.index);
+    let riichi_at = own.discards.iter().find(|d| d.riichi).map(|d| d.index);
+    let passed_after = |after: Option<usize>| {
+        after.is_some_and(|after| {
+            state
+                .players
+                .iter()
+                .filter(|p| p.seat != own.seat)
+                .flat_map(|p| &p.discards)
+                .any(|d| d.index > after && is_wait(&d.tile))
+        })
+    };
+    let furiten = Furiten {
+        discard: own.discards.iter().any(|d| is_wait(&d.tile)),
+        temporary: passed_after(last_own),
+        riichi: own.riichi && passed_after(riichi_at),
+    };
+    let menzen = own.melds.iter().all(|m| m.kind == MeldKind::Ankan);
+    let ctx = Context {
+        seat_wind: own.wind as usize,
+        round_wind: state.round_wind as usize,
+        riichi: own.riichi,
+        zimo: false,
+    };
+    let waits: Vec<WaitYaku> = waits
+        .iter()
+        .map(|w| {
+            let mut c = counts;
+            c[*w] += 1;
+            WaitYaku {
+                tile: TILES34[*w].to_string(),
+                ron: detect(&c, &own.melds, *w, ctx),
+                tsumo: detect(&c, &own.melds, *w, Context { zimo: true, ..ctx }),
+            }
+        })
+        .collect();
+    Some(TenpaiHint {
+        seat: own.seat,
+        yakuless: waits.iter().all(|w| w.ron.is_empty()),
+        waits,
+        furiten,
+        menzen,
+    })
+}
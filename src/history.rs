@@ -0,0 +1,360 @@
+#[cfg(feature = "arena")]
+use crate::arena::{self, ArenaValue};
+use crate::{
+    batch,
+    events::final_scores,
+    format::strip_action,
+    record::{record_files, Record},
+    selection::{GameFacts, GameFilter},
+    state::GameState,
+    store, tr, SETTINGS,
+};
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use serde_json::Value as JsonValue;
+use std::{fmt::Write, ops::Index, path::PathBuf};
+
+#[derive(clap::Args, Debug)]
+pub struct StatsArgs {
+    /// folders or files of records written by `record fetch` or `record sync`,
+    /// the SQLite database when none are given
+    inputs: Vec<PathBuf>,
+    /// database file, `database` of settings.json by default
+    #[clap(long)]
+    db: Option<PathBuf>,
+    /// player to compute for, the own seat of each stored game by default
+    #[clap(long)]
+    account_id: Option<u32>,
+    #[clap(flatten)]
+    filter: GameFilter,
+    /// print the metrics as JSON
+    #[clap(long)]
+    json: bool,
+}
+
+/// One game of the player the metrics are computed for.
+#[derive(Debug, Clone)]
+pub struct GameSample {
+    pub facts: GameFacts,
+    pub seat: u32,
+    /// 1 for the top, unknown for unfinished games
+    pub rank: Option<u32>,
+    /// prefix stripped
+    pub actions: Vec<(String, JsonValue)>,
+}
+
+impl GameSample {
+    fn from_record(record: &Record, account_id: u32) -> Option<Self> {
+        let seat = record.seat_of(account_id)?;
+        let mut scores = final_scores(&record.head, &GameState::default());
+        scores.sort_by_key(|s| (-s.total_point, s.seat));
+        Some(GameSample {
+            facts: GameFacts::from_head(&record.head),
+            seat,
+            rank: scores
+                .iter()
+                .position(|s| s.seat == seat)
+                .map(|i| i as u32 + 1),
+            actions: record
+                .actions
+                .iter()
+                .map(|a| (strip_action(&a.name).to_string(), a.data.clone()))
+                .collect(),
+        })
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct Metrics {
+    pub games: u32,
+    /// games finished at each rank, the top first
+    pub placements: Vec<u32>,
+    pub avg_rank: f64,
+    pub hands: u32,
+    /// hands with a riichi declaration
+    pub riichi: u32,
+    /// hands with at least one open call
+    pub calls: u32,
+    pub wins: u32,
+    pub deal_ins: u32,
+    /// points paid for all deal-ins, honba included
+    pub deal_in_points: i64,
+    /// hands ended by an exhaustive draw
+    pub draws: u32,
+    pub tenpai_at_draw: u32,
+}
+
+/// Action data as read by [`Metrics`], a `serde_json::Value` or one parsed
+/// into an arena.
+trait Fields: Index<&'static str, Output = Self> + Index<usize, Output = Self> + Sized {
+    fn as_u64(&self) -> Option<u64>;
+    fn as_i64(&self) -> Option<i64>;
+    fn as_bool(&self) -> Option<bool>;
+    /// empty when not an array
+    fn items(&self) -> &[Self];
+}
+
+impl Fields for JsonValue {
+    fn as_u64(&self) -> Option<u64> {
+        JsonValue::as_u64(self)
+    }
+
+    fn as_i64(&self) -> Option<i64> {
+        JsonValue::as_i64(self)
+    }
+
+    fn as_bool(&self) -> Option<bool> {
+        JsonValue::as_bool(self)
+    }
+
+    fn items(&self) -> &[Self] {
+        self.as_array().map(Vec::as_slice).unwrap_or_default()
+    }
+}
+
+#[cfg(feature = "arena")]
+impl<'b> Fields for ArenaValue<'b> {
+    fn as_u64(&self) -> Option<u64> {
+        ArenaValue::as_u64(self)
+    }
+
+    fn as_i64(&self) -> Option<i64> {
+        ArenaValue::as_i64(self)
+    }
+
+    fn as_bool(&self) -> Option<bool> {
+        ArenaValue::as_bool(self)
+    }
+
+    fn items(&self) -> &[Self] {
+        self.as_array().unwrap_or_default()
+    }
+}
+
+/// Where [`Metrics`] is in a game.
+struct Tally {
+    seat: u32,
+    riichi: bool,
+    called: bool,
+    /// the discard or kan a ron is called on
+    last_actor: Option<u32>,
+}
+
+impl Metrics {
+    fn add(&mut self, game: &GameSample) {
+        let mut tally = self.start(game);
+        for (name, data) in &game.actions {
+            self.action(&mut tally, name, data);
+        }
+    }
+
+    /// Count the game itself, its actions go to [`Metrics::action`].
+    fn start(&mut self, game: &GameSample) -> Tally {
+        self.games += 1;
+        if let Some(rank) = game.rank {
+            if self.placements.len() < rank as usize {
+                self.placements.resize(rank as usize, 0);
+            }
+            self.placements[rank as usize - 1] += 1;
+        }
+        Tally {
+            seat: game.seat,
+            riichi: false,
+            called: false,
+            last_actor: None,
+        }
+    }
+
+    /// Count the action `name`, prefix stripped.
+    fn action<V: Fields>(&mut self, tally: &mut Tally, name: &str, data: &V) {
+        let seat = tally.seat;
+        let actor = data["seat"].as_u64().map(|s| s as u32);
+        match name {
+            "NewRound" => {
+                self.hands += 1;
+                (tally.riichi, tally.called) = (false, false);
+            }
+            "DiscardTile" => {
+                let declared = data["is_liqi"].as_bool().unwrap_or_default()
+                    || data["is_wliqi"].as_bool().unwrap_or_default();
+                if actor == Some(seat) && declared && !tally.riichi {
+                    tally.riichi = true;
+                    self.riichi += 1;
+                }
+                tally.last_actor = actor;
+            }
+            "ChiPengGang" if actor == Some(seat) && !tally.called => {
+                tally.called = true;
+                self.calls += 1;
+            }
+            "AnGangAddGang" | "BaBei" => tally.last_actor = actor,
+            "Hule" => {
+                let hules = data["hules"].items();
+                if hules
+                    .iter()
+                    .any(|h| h["seat"].as_u64() == Some(seat as u64))
+                {
+                    self.wins += 1;
+                }
+                let ron = hules
+                    .iter()
+                    .any(|h| !h["zimo"].as_bool().unwrap_or_default());
+                if ron && tally.last_actor == Some(seat) {
+                    self.deal_ins += 1;
+                    let delta = data["delta_scores"][seat as usize].as_i64();
+                    self.deal_in_points -= delta.unwrap_or_default();
+                }
+            }
+            "NoTile" => {
+                self.draws += 1;
+                if data["players"][seat as usize]["tingpai"]
+                    .as_bool()
+                    .unwrap_or_default()
+                {
+                    self.tenpai_at_draw += 1;
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Add the counts of `other`, e.g. of another batch of games.
+    #[cfg(feature = "arena")]
+    fn merge(&mut self, other: &Metrics) {
+        if self.placements.len() < other.placements.len() {
+            self.placements.resize(other.placements.len(), 0);
+        }
+        for (n, m) in self.placements.iter_mut().zip(&other.placements) {
+            *n += m;
+        }
+        self.games += other.games;
+        self.hands += other.hands;
+        self.riichi += other.riichi;
+        self.calls += other.calls;
+        self.wins += other.wins;
+        self.deal_ins += other.deal_ins;
+        self.deal_in_points += other.deal_in_points;
+        self.draws += other.draws;
+        self.tenpai_at_draw += other.tenpai_at_draw;
+    }
+
+    fn finish(&mut self) {
+        let ranked: u32 = self.placements.iter().sum();
+        if ranked > 0 {
+            let sum: u32 = (1..).zip(&self.placements).map(|(rank, n)| rank * n).sum();
+            self.avg_rank = sum as f64 / ranked as f64;
+        }
+    }
+
+    pub fn text(&self) -> String {
+        let rate = |n: u32, of: u32| match of {
+            0 => "-".to_string(),
+            of => format!("{:.1}%", n as f64 * 100.0 / of as f64),
+        };
+        let mut out = String::new();
+        let _ = writeln!(out, "对局数：{}，局数：{}", self.games, self.hands);
+        let ranked: u32 = self.placements.iter().sum();
+        let placements: Vec<String> = (1..)
+            .zip(&self.placements)
+            .map(|(rank, n)| format!("{}位 {}（{}）", rank, n, rate(*n, ranked)))
+            .collect();
+        let _ = writeln!(out, "顺位：{}", placements.join("，"));
+        let _ = writeln!(out, "平均顺位：{:.2}", self.avg_rank);
+        let _ = writeln!(out, "和了率：{}", rate(self.wins, self.hands));
+        let _ = writeln!(out, "立直率：{}", rate(self.riichi, self.hands));
+        let _ = writeln!(out, "副露率：{}", rate(self.calls, self.hands));
+        let _ = writeln!(out, "放铳率：{}", rate(self.deal_ins, self.hands));
+        let avg = match self.deal_ins {
+            0 => "-".to_string(),
+            n => format!("{:.0}", self.deal_in_points as f64 / n as f64),
+        };
+        let _ = writeln!(out, "平均放铳点数：{}", avg);
+        let _ = write!(out, "流局听牌率：{}", rate(self.tenpai_at_draw, self.draws));
+        out
+    }
+}
+
+impl StatsArgs {
+    fn load(&self) -> Result<Vec<GameSample>> {
+        if self.inputs.is_empty() {
+            let path = self
+                .db
+                .as_ref()
+                .or(SETTINGS.database.as_ref())
+                .ok_or(anyhow!("No records or database given"))?;
+            return store::read_games(path, self.account_id);
+        }
+        let account_id = self
+            .account_id
+            .ok_or(anyhow!("--account-id is needed for record files"))?;
+        let files = record_files(&self.inputs)?;
+        let games = batch::run(&tr!("读取", "読み込み", "Reading"), &files, |file| {
+            Ok(GameSample::from_record(&Record::load(file)?, account_id))
+        });
+        Ok(games.into_iter().flatten().flatten().collect())
+    }
+
+    fn metrics(&self) -> Result<Metrics> {
+        #[cfg(feature = "arena")]
+        if !self.inputs.is_empty() {
+            return self.record_metrics();
+        }
+        let mut metrics = Metrics::default();
+        for game in self
+            .load()?
+            .iter()
+            .filter(|g| self.filter.matches(&g.facts))
+        {
+            metrics.add(game);
+        }
+        Ok(metrics)
+    }
+
+    /// Metrics of the record files, each parsed into an arena and counted
+    /// instead of kept as samples.
+    #[cfg(feature = "arena")]
+    fn record_metrics(&self) -> Result<Metrics> {
+        let account_id = self
+            .account_id
+            .ok_or(anyhow!("--account-id is needed for record files"))?;
+        let files = record_files(&self.inputs)?;
+        let partials = batch::run(&tr!("读取", "読み込み", "Reading"), &files, |file| {
+            arena::with_parsed(&std::fs::read(file)?, |record| {
+                // the head is small, the actions are only read
+                let head = Record {
+                    head: serde_json::to_value(record["head"])?,
+                    actions: vec![],
+                };
+                let Some(game) = GameSample::from_record(&head, account_id) else {
+                    return Ok(None);
+                };
+                if !self.filter.matches(&game.facts) {
+                    return Ok(None);
+                }
+                let mut metrics = Metrics::default();
+                let mut tally = metrics.start(&game);
+                for action in record["actions"].items() {
+                    let name = action["name"].as_str().unwrap_or_default();
+                    metrics.action(&mut tally, strip_action(name), &action["data"]);
+                }
+                Ok(Some(metrics))
+            })?
+        });
+        let mut metrics = Metrics::default();
+        for partial in partials.iter().flatten().flatten() {
+            metrics.merge(partial);
+        }
+        Ok(metrics)
+    }
+}
+
+pub fn run(args: &StatsArgs) -> Result<()> {
+    let mut metrics = args.metrics()?;
+    metrics.finish();
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&metrics)?);
+    } else {
+        println!("{}", metrics.text());
+    }
+    Ok(())
+}
@@ -0,0 +1,56 @@
+use once_cell::sync::{Lazy, OnceCell};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lang {
+    #[serde(rename = "zh-CN")]
+    ZhCn,
+    #[serde(rename = "ja")]
+    Ja,
+    #[serde(rename = "en")]
+    En,
+}
+
+static SETTING: OnceCell<Lang> = OnceCell::new();
+static SYSTEM: Lazy<Lang> =
+    Lazy::new(|| sys_locale::get_locale().map_or(Lang::ZhCn, |l| Lang::from_locale(&l)));
+
+impl Lang {
+    /// From a locale such as `ja-JP` or `zh_CN.UTF-8`, Chinese for the others
+    /// as the messages were before.
+    pub fn from_locale(locale: &str) -> Self {
+        let locale = locale.to_lowercase();
+        if locale.starts_with("ja") {
+            Lang::Ja
+        } else if locale.starts_with("en") {
+            Lang::En
+        } else {
+            Lang::ZhCn
+        }
+    }
+}
+
+/// The `language` setting, once the settings are loaded.
+pub fn set(lang: Option<Lang>) {
+    if let Some(lang) = lang {
+        let _ = SETTING.set(lang);
+    }
+}
+
+/// Language of user-facing messages: the setting, else the system locale.
+pub fn lang() -> Lang {
+    SETTING.get().copied().unwrap_or(*SYSTEM)
+}
+
+/// A message in the current language, formatted like `format!`, with the
+/// Chinese, Japanese and English text in this order.
+#[macro_export]
+macro_rules! tr {
+    ($zh:literal, $ja:literal, $en:literal $(, $arg:expr)* $(,)?) => {
+        match $crate::i18n::lang() {
+            $crate::i18n::Lang::ZhCn => format!($zh $(, $arg)*),
+            $crate::i18n::Lang::Ja => format!($ja $(, $arg)*),
+            $crate::i18n::Lang::En => format!($en $(, $arg)*),
+        }
+    };
+}
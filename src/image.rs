@@ -0,0 +1,258 @@
+use crate::{
+    state::{GameState, Wind},
+    tile::index34,
+};
+use anyhow::{anyhow, Result};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tokio::sync::watch;
+use tracing::error;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct HandImageConfig {
+    /// PNG rewritten after every action, e.g. for an OBS image source
+    pub path: PathBuf,
+    /// also draw every player's discards under the hand
+    #[serde(default = "default_true")]
+    pub discards: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+const TILE_W: usize = 24;
+const TILE_H: usize = 36;
+const GAP: usize = 2;
+const MARGIN: usize = 6;
+/// discards per river row, as on the table
+const RIVER_ROW: usize = 6;
+
+type Rgba = [u8; 4];
+
+const BACKGROUND: Rgba = [0, 0, 0, 0];
+const FACE: Rgba = [250, 248, 240, 255];
+/// called away or tsumogiri discards
+const DIM: Rgba = [200, 200, 195, 255];
+const EDGE: Rgba = [90, 90, 90, 255];
+const BLACK: Rgba = [20, 20, 20, 255];
+const RED: Rgba = [210, 30, 30, 255];
+const GREEN: Rgba = [20, 140, 60, 255];
+const BLUE: Rgba = [30, 70, 190, 255];
+
+/// 5×7 glyphs, one byte per row with the leftmost pixel in bit 4.
+const GLYPHS: [(char, [u8; 7]); 20] = [
+    ('0', [0x0e, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0e]),
+    ('1', [0x04, 0x0c, 0x04, 0x04, 0x04, 0x04, 0x0e]),
+    ('2', [0x0e, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1f]),
+    ('3', [0x1f, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0e]),
+    ('4', [0x02, 0x06, 0x0a, 0x12, 0x1f, 0x02, 0x02]),
+    ('5', [0x1f, 0x10, 0x1e, 0x01, 0x01, 0x11, 0x0e]),
+    ('6', [0x06, 0x08, 0x10, 0x1e, 0x11, 0x11, 0x0e]),
+    ('7', [0x1f, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08]),
+    ('8', [0x0e, 0x11, 0x11, 0x0e, 0x11, 0x11, 0x0e]),
+    ('9', [0x0e, 0x11, 0x11, 0x0f, 0x01, 0x02, 0x0c]),
+    ('m', [0x00, 0x00, 0x1a, 0x15, 0x15, 0x11, 0x11]),
+    ('p', [0x00, 0x00, 0x1e, 0x11, 0x1e, 0x10, 0x10]),
+    ('s', [0x00, 0x00, 0x0f, 0x10, 0x0e, 0x01, 0x1e]),
+    ('E', [0x1f, 0x10, 0x10, 0x1e, 0x10, 0x10, 0x1f]),
+    ('S', [0x0f, 0x10, 0x10, 0x0e, 0x01, 0x01, 0x1e]),
+    ('W', [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0a]),
+    ('N', [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11]),
+    ('P', [0x1e, 0x11, 0x11, 0x1e, 0x10, 0x10, 0x10]),
+    ('F', [0x1f, 0x10, 0x10, 0x1e, 0x10, 0x10, 0x10]),
+    ('C', [0x0e, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0e]),
+];
+
+struct Canvas {
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>,
+}
+
+impl Canvas {
+    fn new(width: usize, height: usize) -> Self {
+        let mut pixels = Vec::with_capacity(width * height * 4);
+        for _ in 0..width * height {
+            pixels.extend_from_slice(&BACKGROUND);
+        }
+        Canvas {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    fn fill(&mut self, x: usize, y: usize, w: usize, h: usize, color: Rgba) {
+        for row in y..(y + h).min(self.height) {
+            for col in x..(x + w).min(self.width) {
+                let i = (row * self.width + col) * 4;
+                self.pixels[i..i + 4].copy_from_slice(&color);
+            }
+        }
+    }
+
+    fn glyph(&mut self, c: char, x: usize, y: usize, scale: usize, color: Rgba) {
+        let Some((_, rows)) = GLYPHS.iter().find(|(g, _)| *g == c) else {
+            return;
+        };
+        for (dy, bits) in rows.iter().enumerate() {
+            for dx in 0..5 {
+                if bits & (0x10 >> dx) != 0 {
+                    self.fill(x + dx * scale, y + dy * scale, scale, scale, color);
+                }
+            }
+        }
+    }
+
+    /// A tile face with its number over its suit, honors as a single letter.
+    fn tile(&mut self, tile: &str, x: usize, y: usize, face: Rgba, edge: Rgba) {
+        self.fill(x, y, TILE_W, TILE_H, edge);
+        self.fill(x + 1, y + 1, TILE_W - 2, TILE_H - 2, face);
+        let mut chars = tile.chars();
+        let (Some(num), Some(suit)) = (chars.next(), chars.next()) else {
+            return;
+        };
+        if suit == 'z' {
+            let (letter, color) = match num {
+                '1' => ('E', BLACK),
+                '2' => ('S', BLACK),
+                '3' => ('W', BLACK),
+                '4' => ('N', BLACK),
+                '5' => ('P', BLUE),
+                '6' => ('F', GREEN),
+                _ => ('C', RED),
+            };
+            self.glyph(letter, x + 5, y + 8, 3, color);
+            return;
+        }
+        let (digit, color) = match num {
+            // red five
+            '0' => ('5', RED),
+            d => (d, BLACK),
+        };
+        let suit_color = match suit {
+            'm' => RED,
+            'p' => BLUE,
+            _ => GREEN,
+        };
+        self.glyph(digit, x + 5, y + 3, 3, color);
+        self.glyph(suit, x + 7, y + 26, 2, suit_color);
+    }
+
+    fn png(&self) -> Result<Vec<u8>> {
+        let mut out = vec![];
+        let mut encoder = png::Encoder::new(&mut out, self.width as u32, self.height as u32);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.write_header()?.write_image_data(&self.pixels)?;
+        Ok(out)
+    }
+}
+
+fn wind_letter(wind: Wind) -> char {
+    match wind {
+        Wind::East => 'E',
+        Wind::South => 'S',
+        Wind::West => 'W',
+        Wind::North => 'N',
+    }
+}
+
+/// Draw the own hand and melds, then each player's river, as a PNG.
+pub fn render(state: &GameState, discards: bool) -> Result<Vec<u8>> {
+    let own = state.own().ok_or(anyhow!("Own seat unknown"))?;
+    let step = TILE_W + GAP;
+    let mut hand = own.hand.clone();
+    hand.sort_by_key(|t| (index34(t).unwrap_or(usize::MAX), !t.starts_with('0')));
+    let meld_tiles: usize = own.melds.iter().map(|m| m.tiles.len()).sum();
+    let hand_width = (hand.len() + meld_tiles) * step + own.melds.len() * step / 2;
+    // wind letter, then the river, riichi and calls don't change its width
+    let river_width = step + RIVER_ROW * step;
+    let river_rows: Vec<usize> = state
+        .players
+        .iter()
+        .map(|p| p.discards.len().div_ceil(RIVER_ROW).max(1))
+        .collect();
+    let rivers_height = match discards {
+        true => river_rows.iter().sum::<usize>() * (TILE_H + GAP) + state.players.len() * GAP * 2,
+        false => 0,
+    };
+    let width = hand_width.max(river_width) + MARGIN * 2;
+    let height = TILE_H + MARGIN * 3 + rivers_height;
+    let mut canvas = Canvas::new(width, height);
+    let mut x = MARGIN;
+    for tile in &hand {
+        canvas.tile(tile, x, MARGIN, FACE, EDGE);
+        x += step;
+    }
+    for meld in &own.melds {
+        x += step / 2;
+        for tile in &meld.tiles {
+            canvas.tile(tile, x, MARGIN, DIM, EDGE);
+            x += step;
+        }
+    }
+    if !discards {
+        return canvas.png();
+    }
+    let mut y = TILE_H + MARGIN * 2;
+    for (player, rows) in state.players.iter().zip(river_rows) {
+        let label = if state.seat == Some(player.seat) {
+            RED
+        } else {
+            EDGE
+        };
+        canvas.glyph(wind_letter(player.wind), MARGIN + 4, y + 8, 3, label);
+        for (i, d) in player.discards.iter().enumerate() {
+            let x = MARGIN + step + (i % RIVER_ROW) * step;
+            let y = y + (i / RIVER_ROW) * (TILE_H + GAP);
+            let face = if d.called || d.tsumogiri { DIM } else { FACE };
+            let edge = if d.riichi { RED } else { EDGE };
+            canvas.tile(&d.tile, x, y, face, edge);
+        }
+        y += rows * (TILE_H + GAP) + GAP * 2;
+    }
+    canvas.png()
+}
+
+/// A rendered image and where it goes.
+type Pending = Option<(PathBuf, Vec<u8>)>;
+
+/// The latest image to write and where, taken by a single writer task so two
+/// renames of the temporary file never interleave; images rendered while one
+/// is written only leave the newest.
+static LATEST: Lazy<watch::Sender<Pending>> = Lazy::new(|| {
+    let (tx, mut rx) = watch::channel::<Pending>(None);
+    tokio::spawn(async move {
+        while rx.changed().await.is_ok() {
+            let Some((path, png)) = rx.borrow_and_update().clone() else {
+                continue;
+            };
+            match tokio::task::spawn_blocking(move || replace(&path, &png)).await {
+                Ok(Ok(())) => (),
+                Ok(Err(e)) => error!("Failed to write hand image: {:?}", e),
+                Err(e) => error!("Hand image writer failed: {:?}", e),
+            }
+        }
+    });
+    tx
+});
+
+/// Render and replace `path` without readers ever seeing a partial file.
+pub fn write(state: &GameState, config: &HandImageConfig) {
+    let png = match render(state, config.discards) {
+        Ok(png) => png,
+        Err(_) => return,
+    };
+    LATEST.send_replace(Some((config.path.clone(), png)));
+}
+
+fn replace(path: &Path, bytes: &[u8]) -> Result<()> {
+    let tmp = path.with_extension("png.tmp");
+    std::fs::write(&tmp, bytes)?;
+    std::fs::rename(&tmp, path)?;
+    Ok(())
+}
@@ -0,0 +1,187 @@
+use crate::{tr, ARG, SETTINGS};
+use anyhow::{bail, Result};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::{
+    net::{Ipv4Addr, SocketAddr, TcpStream},
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+/// Name of the instance started without `--name`.
+pub const DEFAULT_NAME: &str = "default";
+
+/// A running instance, as registered for `list`.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Instance {
+    pub name: String,
+    pub pid: u32,
+    pub proxy_addr: String,
+    pub admin_addr: String,
+    pub config_dir: PathBuf,
+    pub started: DateTime<Local>,
+}
+
+impl Instance {
+    /// Whether its proxy still accepts connections, the registration of a
+    /// crashed instance stays behind.
+    fn alive(&self) -> bool {
+        let Ok(mut addr) = SocketAddr::from_str(&self.proxy_addr) else {
+            return false;
+        };
+        if addr.ip().is_unspecified() {
+            addr.set_ip(Ipv4Addr::LOCALHOST.into());
+        }
+        TcpStream::connect_timeout(&addr, Duration::from_millis(300)).is_ok()
+    }
+}
+
+/// `--name`, or the default one.
+pub fn name() -> &'static str {
+    ARG.name.as_deref().unwrap_or(DEFAULT_NAME)
+}
+
+/// `path` of this instance, with `-<name>` after the file stem of a named one,
+/// e.g. `snapshot-alt.json`.
+pub fn suffixed(path: &Path) -> PathBuf {
+    let Some(name) = &ARG.name else {
+        return path.to_path_buf();
+    };
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let mut file = format!("{}-{}", stem, name);
+    if let Some(ext) = path.extension() {
+        file = format!("{}.{}", file, ext.to_string_lossy());
+    }
+    path.with_file_name(file)
+}
+
+/// Folder `dir` of this instance, with a subfolder of the name of a named one,
+/// e.g. `spool/alt/`.
+pub fn nested(dir: PathBuf) -> PathBuf {
+    match &ARG.name {
+        Some(name) => dir.join(name),
+        None => dir,
+    }
+}
+
+/// A name fit for file names, checked before anything is namespaced by it.
+pub fn check_name(name: &str) -> Result<()> {
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        bail!(tr!(
+            "实例名只能包含字母、数字、-和_：{}",
+            "インスタンス名に使えるのは英数字、- と _ だけです：{}",
+            "Instance names may only contain letters, digits, - and _: {}",
+            name
+        ));
+    }
+    Ok(())
+}
+
+/// Shared by all instances of the user whatever their config folder.
+fn registry() -> PathBuf {
+    std::env::temp_dir()
+        .join(env!("CARGO_PKG_NAME"))
+        .join("instances")
+}
+
+/// Registered instances still running, removing the stale registrations.
+fn running() -> Vec<Instance> {
+    let Ok(entries) = std::fs::read_dir(registry()) else {
+        return vec![];
+    };
+    let mut instances = vec![];
+    for path in entries.flatten().map(|e| e.path()) {
+        let instance = std::fs::read(&path)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice::<Instance>(&bytes).ok());
+        match instance {
+            Some(instance) if instance.alive() => instances.push(instance),
+            _ => {
+                let _ = std::fs::remove_file(&path);
+            }
+        }
+    }
+    instances.sort_by(|a, b| a.name.cmp(&b.name));
+    instances
+}
+
+/// Registration of this instance, removed when dropped.
+pub struct Registration(PathBuf);
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+/// Register this instance for `list`, failing when one of the same name runs.
+pub fn register() -> Result<Registration> {
+    let name = name();
+    if let Some(other) = running().into_iter().find(|i| i.name == name) {
+        bail!(tr!(
+            "实例 {} 已在运行（PID {}，代理 {}），请用--name另起一个名字",
+            "インスタンス {} は既に動作中です（PID {}、プロキシ {}）。--name で別の名前を付けてください",
+            "Instance {} is already running (PID {}, proxy {}), pick another with --name",
+            name,
+            other.pid,
+            other.proxy_addr
+        ));
+    }
+    let instance = Instance {
+        name: name.to_string(),
+        pid: std::process::id(),
+        proxy_addr: SETTINGS.proxy_addr.clone(),
+        admin_addr: SETTINGS.admin_addr.clone(),
+        config_dir: SETTINGS.config_dir().to_path_buf(),
+        started: Local::now(),
+    };
+    let dir = registry();
+    std::fs::create_dir_all(&dir)?;
+    let path = dir.join(format!("{}.json", name));
+    std::fs::write(&path, serde_json::to_vec_pretty(&instance)?)?;
+    Ok(Registration(path))
+}
+
+/// Print the running instances.
+pub fn list() -> Result<()> {
+    let instances = running();
+    if instances.is_empty() {
+        println!(
+            "{}",
+            tr!(
+                "没有正在运行的实例",
+                "動作中のインスタンスはありません",
+                "No instance is running"
+            )
+        );
+        return Ok(());
+    }
+    println!(
+        "{:<16} {:>8} {:<22} {:<22} {}",
+        tr!("名称", "名前", "Name"),
+        "PID",
+        tr!("代理", "プロキシ", "Proxy"),
+        tr!("管理接口", "管理 API", "Admin API"),
+        tr!("启动时间", "起動時刻", "Started")
+    );
+    for instance in instances {
+        println!(
+            "{:<16} {:>8} {:<22} {:<22} {}",
+            instance.name,
+            instance.pid,
+            instance.proxy_addr,
+            match instance.admin_addr.is_empty() {
+                true => "-",
+                false => &instance.admin_addr,
+            },
+            instance.started.format("%Y-%m-%d %H:%M:%S")
+        );
+    }
+    Ok(())
+}
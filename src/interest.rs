@@ -0,0 +1,64 @@
+use crate::{audit, filter::Filter, format::FormatKind, settings::Settings, sink::SinkConfig};
+use once_cell::sync::Lazy;
+use std::{
+    collections::HashSet,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        RwLock,
+    },
+};
+
+/// Methods the helper reads itself: the table state and its derived events,
+/// seating, contests, live records, the database and notifications.
+const TRACKED: &[&str] = &[
+    ".lq.ActionPrototype",
+    ".lq.FastTest.authGame",
+    ".lq.FastTest.syncGame",
+    ".lq.FastTest.startObserve",
+    ".lq.NotifyObserveData",
+    ".lq.Lobby.fetchGameRecord",
+    ".lq.NotifyGameEndResult",
+    ".lq.NotifyRoomGameStart",
+    ".lq.NotifyMatchGameStart",
+    ".lq.Lobby.login",
+    ".lq.Lobby.oauth2Login",
+    ".lq.Lobby.emailLogin",
+    ".lq.Lobby.fetchFriendList",
+    ".lq.Lobby.enterCustomizedContest",
+    ".lq.Lobby.leaveCustomizedContest",
+    ".lq.Lobby.fetchCustomizedContestOnlineInfo",
+    ".lq.Lobby.fetchCustomizedContestGameRecords",
+    ".lq.NotifyCustomContestState",
+    ".lq.NotifyCustomContestSystemMsg",
+];
+
+/// A sink filter looks into the data.
+static ALL: AtomicBool = AtomicBool::new(true);
+/// The `sendMethod` methods, when a sink sends them in the liqi format.
+static LIQI: Lazy<RwLock<HashSet<String>>> = Lazy::new(Default::default);
+
+/// What the running sinks read, updated when they restart, e.g. after a
+/// reload of `sendMethod`.
+pub fn set(settings: &Settings, sinks: &[SinkConfig]) {
+    let all = sinks
+        .iter()
+        .filter_map(|s| Filter::parse(s.filter.as_deref()?).ok())
+        .any(|f| f.paths().iter().any(|p| p[0] == "data"));
+    let liqi = match sinks.iter().any(|s| s.format == FormatKind::Liqi) {
+        true => settings.send_method.iter().cloned().collect(),
+        false => HashSet::new(),
+    };
+    ALL.store(all, Ordering::Relaxed);
+    if let Ok(mut methods) = LIQI.write() {
+        *methods = liqi;
+    }
+}
+
+/// Whether messages of `method` are read by anything, the others are only
+/// parsed up to their method name.
+pub fn wanted(method: &str) -> bool {
+    ALL.load(Ordering::Relaxed)
+        || TRACKED.contains(&method)
+        || audit::covers(method)
+        || LIQI.read().is_ok_and(|methods| methods.contains(method))
+}
@@ -0,0 +1,105 @@
+use std::{
+    backtrace::Backtrace,
+    cell::{Cell, RefCell},
+    fmt,
+    future::Future,
+    panic::{self, AssertUnwindSafe},
+    sync::Once,
+    task::Poll,
+};
+
+/// A panic caught by [`catch`], with where it happened.
+#[derive(Debug)]
+pub struct Panicked {
+    pub message: String,
+    pub location: String,
+    pub backtrace: Option<Backtrace>,
+}
+
+impl fmt::Display for Panicked {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "panicked at {}: {}", self.location, self.message)?;
+        if let Some(backtrace) = &self.backtrace {
+            write!(f, "\n{}", backtrace)?;
+        }
+        Ok(())
+    }
+}
+
+thread_local! {
+    /// Inside [`catch`] on this thread, where panics are kept instead of printed.
+    static CATCHING: Cell<bool> = const { Cell::new(false) };
+    static CAUGHT: RefCell<Option<Panicked>> = const { RefCell::new(None) };
+}
+
+static HOOK: Once = Once::new();
+
+/// Keep the panics inside [`catch`] for it to log with its context, the
+/// others still go to the previous hook.
+fn install_hook() {
+    HOOK.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if !CATCHING.get() {
+                return previous(info);
+            }
+            let payload = info.payload();
+            let message = payload
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "Box<dyn Any>".to_string());
+            CAUGHT.set(Some(Panicked {
+                message,
+                location: info
+                    .location()
+                    .map_or("unknown".to_string(), |l| l.to_string()),
+                backtrace: Some(Backtrace::force_capture()),
+            }));
+        }));
+    });
+}
+
+/// Run `f`, turning a panic in it into an error instead of unwinding further,
+/// so one session can't take down the others sharing the task.
+pub fn catch<T>(f: impl FnOnce() -> T) -> Result<T, Panicked> {
+    install_hook();
+    let outer = CATCHING.replace(true);
+    let res = panic::catch_unwind(AssertUnwindSafe(f));
+    CATCHING.set(outer);
+    res.map_err(|_| {
+        CAUGHT.take().unwrap_or(Panicked {
+            message: "unknown".to_string(),
+            location: "unknown".to_string(),
+            backtrace: None,
+        })
+    })
+}
+
+/// [`catch`] for every poll of `fut`.
+pub async fn catch_async<F: Future>(fut: F) -> Result<F::Output, Panicked> {
+    let mut fut = std::pin::pin!(fut);
+    std::future::poll_fn(|cx| match catch(|| fut.as_mut().poll(cx)) {
+        Ok(Poll::Ready(out)) => Poll::Ready(Ok(out)),
+        Ok(Poll::Pending) => Poll::Pending,
+        Err(panicked) => Poll::Ready(Err(panicked)),
+    })
+    .await
+}
+
+/// The start of a frame for a dump, printable bytes as they are.
+pub fn frame_head(buf: &[u8]) -> String {
+    const SHOWN: usize = 64;
+    let mut head: String = buf
+        .iter()
+        .take(SHOWN)
+        .map(|b| match b {
+            0x20..=0x7e => (*b as char).to_string(),
+            _ => format!("\\x{:02x}", b),
+        })
+        .collect();
+    if buf.len() > SHOWN {
+        head.push_str("...");
+    }
+    head
+}
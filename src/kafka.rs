@@ -0,0 +1,138 @@
+use crate::sink::{Delivery, Payload, Sink};
+use anyhow::{anyhow, Result};
+use rskafka::{
+    chrono::Utc,
+    client::{
+        partition::{Compression, PartitionClient, UnknownTopicHandling},
+        Client, ClientBuilder,
+    },
+    record::Record,
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    time::Duration,
+};
+use tracing::debug;
+
+/// Produces payloads to kafka, either into one topic with a `method` header
+/// or into one topic per method.
+pub struct KafkaSink {
+    brokers: Vec<String>,
+    topic: String,
+    topic_per_method: bool,
+    batch_size: usize,
+    linger: Duration,
+    client: Option<Client>,
+    partitions: HashMap<String, PartitionClient>,
+}
+
+impl KafkaSink {
+    pub fn new(
+        brokers: Vec<String>,
+        topic: String,
+        topic_per_method: bool,
+        batch_size: usize,
+        linger_ms: u64,
+    ) -> Self {
+        Self {
+            brokers,
+            topic,
+            topic_per_method,
+            batch_size: batch_size.max(1),
+            linger: Duration::from_millis(linger_ms),
+            client: None,
+            partitions: HashMap::new(),
+        }
+    }
+
+    fn topic_of(&self, method: &str) -> String {
+        if self.topic_per_method {
+            // methods start with '.', e.g. `majsoul` + `.lq.ActionPrototype`
+            format!("{}{}", self.topic, method)
+        } else {
+            self.topic.clone()
+        }
+    }
+
+    async fn partition(&mut self, topic: &str) -> Result<&PartitionClient> {
+        if self.client.is_none() {
+            self.client = Some(ClientBuilder::new(self.brokers.clone()).build().await?);
+        }
+        if !self.partitions.contains_key(topic) {
+            let client = self.client.as_ref().ok_or(anyhow!("Kafka not connected"))?;
+            let partition = client
+                .partition_client(topic, 0, UnknownTopicHandling::Retry)
+                .await?;
+            self.partitions.insert(topic.to_string(), partition);
+        }
+        self.partitions
+            .get(topic)
+            .ok_or(anyhow!("No partition client for {}", topic))
+    }
+
+    async fn produce(&mut self, topic: &str, records: Vec<Record>) -> Result<()> {
+        let partition = self.partition(topic).await?;
+        match partition.produce(records, Compression::NoCompression).await {
+            // offsets are only returned once the brokers acknowledged the records
+            Ok(offsets) => {
+                debug!("Kafka {} acknowledged offsets {:?}", topic, offsets);
+                Ok(())
+            }
+            Err(e) => {
+                // reconnect on next batch
+                self.client = None;
+                self.partitions.clear();
+                Err(e.into())
+            }
+        }
+    }
+}
+
+fn to_record(delivery: Delivery) -> Result<Record> {
+    let (value, content_type) = match delivery.payload {
+        Payload::Json(json) => (serde_json::to_vec(&json)?, "application/json"),
+        Payload::Serialized(bytes) => (bytes.to_vec(), "application/json"),
+        Payload::Binary(bin) => (bin.to_vec(), "application/octet-stream"),
+    };
+    let headers = BTreeMap::from([
+        ("method".to_string(), delivery.method.as_bytes().to_vec()),
+        ("content-type".to_string(), content_type.as_bytes().to_vec()),
+    ]);
+    Ok(Record {
+        key: Some(delivery.method.as_bytes().to_vec()),
+        value: Some(value),
+        headers,
+        timestamp: Utc::now(),
+    })
+}
+
+impl Sink for KafkaSink {
+    async fn send(&mut self, delivery: Delivery) -> Result<()> {
+        self.send_batch(vec![delivery]).await
+    }
+
+    async fn send_batch(&mut self, batch: Vec<Delivery>) -> Result<()> {
+        // group by topic, keeping the order inside every topic
+        let mut topics: Vec<(String, Vec<Record>)> = vec![];
+        for delivery in batch {
+            let topic = self.topic_of(&delivery.method);
+            let record = to_record(delivery)?;
+            match topics.iter_mut().find(|(t, _)| *t == topic) {
+                Some((_, records)) => records.push(record),
+                None => topics.push((topic, vec![record])),
+            }
+        }
+        for (topic, records) in topics {
+            self.produce(&topic, records).await?;
+        }
+        Ok(())
+    }
+
+    fn batch_size(&self) -> usize {
+        self.batch_size
+    }
+
+    fn linger(&self) -> Duration {
+        self.linger
+    }
+}
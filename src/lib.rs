@@ -1,22 +1,206 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
+use config::ConfigCommand;
+use dump::ParseArgs;
+use history::StatsArgs;
 use once_cell::sync::Lazy;
+use record::{ConvertArgs, RecordCommand};
+use secrets::SecretCommand;
+use self_test::SelfTestArgs;
+use self_update::SelfUpdateArgs;
+use service::ServiceCommand;
 use settings::Settings;
+use simulate::SimulateCommand;
+use status::StatusArgs;
 
+pub mod activity;
+pub mod anonymize;
+pub mod api;
+#[cfg(feature = "arena")]
+pub mod arena;
+pub mod audit;
 pub mod base;
+pub mod batch;
+pub mod board;
+pub mod cert;
+pub mod clock;
+pub mod config;
+pub mod contest;
+pub mod daily;
+pub mod desync;
+pub mod doctor;
+pub mod dump;
+pub mod engine;
+pub mod events;
+pub mod export;
+pub mod filter;
+pub mod flight;
+pub mod format;
 pub mod helper;
+pub mod hint;
+pub mod history;
+pub mod i18n;
+pub mod image;
+pub mod instance;
+pub mod interest;
+pub mod isolation;
+#[cfg(feature = "kafka")]
+pub mod kafka;
+pub mod library;
+pub mod lobby;
+pub mod logging;
 pub mod lq;
 pub mod lq_config;
+pub mod manpage;
+pub mod memory;
+pub mod mjai;
+#[cfg(any(test, feature = "mock"))]
+pub mod mock;
 pub mod modder;
+pub mod notes;
+pub mod notify;
 pub mod parser;
+pub mod players;
+pub mod pool;
+pub mod record;
+pub mod reload;
+pub mod report;
+#[cfg(feature = "webrtc")]
+pub mod rtc;
+pub mod safety;
+pub mod schedule;
+pub mod secrets;
+pub mod selection;
+pub mod self_test;
+pub mod self_update;
+pub mod service;
+pub mod session;
 pub mod settings;
+pub mod shanten;
 pub mod sheets;
+pub mod simulate;
+pub mod sink;
+pub mod snapshot;
+pub mod spool;
+pub mod state;
+pub mod stats;
+pub mod status;
+pub mod store;
+pub mod summary;
+pub mod systemd;
+pub mod tenhou;
+pub mod text;
+pub mod tile;
+pub mod tray;
+pub mod tui;
+pub mod verify;
+pub mod viewer;
+pub mod watch;
+pub mod watchdog;
+pub mod wizard;
+pub mod yaku;
 
 pub static SETTINGS: Lazy<Settings> = Lazy::new(Settings::new);
 pub const ARBITRARY_MD5: &str = "0123456789abcdef0123456789abcdef";
 pub static ARG: Lazy<Arg> = Lazy::new(Arg::parse);
 
 #[derive(Parser, Debug)]
+#[clap(version)]
 pub struct Arg {
-    #[clap(short, long, default_value = "./liqi_config/")]
+    #[clap(short, long, global = true, default_value = settings::DEFAULT_CONFIG_DIR)]
     config_dir: String,
+    /// Keep the config, CA, logs and records next to the executable instead
+    /// of the working or user config folder, also on with a `portable` file there
+    #[clap(long, global = true)]
+    portable: bool,
+    /// Run as a separate instance of this name, with its own records and logs
+    /// and the entry of `profiles` of the same name if there is one
+    #[clap(long, global = true)]
+    name: Option<String>,
+    /// TOML file layered over the other settings files
+    #[clap(long, global = true)]
+    config: Option<std::path::PathBuf>,
+    /// entry of `profiles` in the settings layered over the files, e.g. `akagi`
+    #[clap(long, global = true)]
+    profile: Option<String>,
+    /// overrides `proxyAddr`
+    #[clap(long, global = true)]
+    proxy_addr: Option<String>,
+    /// overrides `adminAddr`
+    #[clap(long, global = true)]
+    admin_addr: Option<String>,
+    /// overrides any setting, e.g. `--set tileNotation=unicode` or `--set engine.url=...`
+    #[clap(long = "set", global = true, value_name = "KEY=VALUE")]
+    overrides: Vec<String>,
+    /// Parse and log everything without sending to any sink or modifying and
+    /// injecting messages, for trying a new setup or liqi version in a real game
+    #[clap(long, global = true)]
+    pub dry_run: bool,
+    /// Print the tracked board after every game action
+    #[clap(long, global = true)]
+    print_board: bool,
+    /// Show a live dashboard of sessions, throughput, decoded messages, sinks
+    /// and the board instead of scrolling logs, requires the `tui` feature
+    #[clap(long, global = true)]
+    pub tui: bool,
+    /// `run` when none is given
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Run the proxy with the mod and the helper
+    Run,
+    /// Decode a dump of captured frames offline and print it as JSON
+    Parse(ParseArgs),
+    /// Work with game records instead of running the proxy
+    #[clap(subcommand)]
+    Record(RecordCommand),
+    /// Convert a record for other tools, same as `record convert`
+    Convert(ConvertArgs),
+    /// Check the CA, ports, system proxy, liqi version and helper endpoints
+    Doctor,
+    /// Decode the bundled sample frames and compare them with what they should
+    /// decode to, checking liqi before the game goes through the proxy
+    SelfTest(SelfTestArgs),
+    /// Walk through the first-run setup again: CA, port, server and sink
+    Setup,
+    /// Work with the settings files
+    #[clap(subcommand)]
+    Config(ConfigCommand),
+    /// Create a CA of its own for the proxy instead of the built-in one
+    GenCert {
+        /// the config folder by default, where the proxy looks for it
+        #[clap(long)]
+        dir: Option<std::path::PathBuf>,
+        /// replace the CA already there
+        #[clap(long)]
+        force: bool,
+    },
+    /// Placement and hand metrics over stored games
+    Stats(StatsArgs),
+    /// Run the proxy behind a tray icon, requires the `tray` feature on Windows or macOS
+    Tray,
+    /// Run the proxy as a Windows service starting at boot
+    #[clap(subcommand)]
+    Service(ServiceCommand),
+    /// Replace this executable with the latest release for the platform
+    SelfUpdate(SelfUpdateArgs),
+    /// Show the state of the running instance, exits with 3 when none answers
+    Status(StatusArgs),
+    /// List the instances running, named with `--name` or not
+    List,
+    /// Keep tokens in the OS keychain instead of the settings, requires the `keychain` feature
+    #[clap(subcommand)]
+    Secret(SecretCommand),
+    /// Feed the sinks with synthetic games
+    #[clap(subcommand)]
+    Simulate(SimulateCommand),
+    /// Print the completion script of a shell, e.g. `completions bash > /etc/bash_completion.d/majsoul_max_rs`
+    Completions { shell: clap_complete::Shell },
+    /// Write man pages of every command and the settings keys
+    Manpage {
+        #[clap(long, default_value = "./man/")]
+        dir: std::path::PathBuf,
+    },
 }
@@ -0,0 +1,162 @@
+use crate::{
+    players::{GameInfo, RoomType},
+    record::{record_files, Record},
+};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+};
+use tracing::{info, warn};
+
+pub const INDEX_FILE: &str = "index.json";
+
+/// What the index knows of a stored record.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct IndexEntry {
+    /// file name in the folder
+    pub file: String,
+    /// FNV-1a of the actions
+    pub hash: String,
+    pub start_time: Option<i64>,
+    pub players: Vec<String>,
+    pub room: RoomType,
+    pub mode_id: u32,
+    /// every starting hand is known, unlike in a live record
+    pub complete: bool,
+}
+
+/// A records folder with its `index.json`, keeping one file per game.
+pub struct Library {
+    dir: PathBuf,
+    /// by uuid
+    entries: BTreeMap<String, IndexEntry>,
+    /// uuid by content hash
+    hashes: HashMap<String, String>,
+}
+
+fn content_hash(record: &Record) -> Result<String> {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for b in serde_json::to_vec(&record.actions)? {
+        hash ^= b as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    Ok(format!("{:016x}", hash))
+}
+
+/// Records downloaded from the lobby have every starting hand, live ones only the own.
+fn is_complete(record: &Record) -> bool {
+    record
+        .actions
+        .iter()
+        .find(|a| a.name == "RecordNewRound")
+        .is_none_or(|a| a.data["tiles0"].as_array().is_some_and(|t| !t.is_empty()))
+}
+
+impl Library {
+    /// Load the index of `dir`, building it from the files there when it is missing.
+    pub fn open(dir: &Path) -> Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        let mut library = Library {
+            dir: dir.to_path_buf(),
+            entries: BTreeMap::new(),
+            hashes: HashMap::new(),
+        };
+        match std::fs::read(dir.join(INDEX_FILE)) {
+            Ok(bytes) => {
+                library.entries = serde_json::from_slice(&bytes)?;
+                library.hashes = library
+                    .entries
+                    .iter()
+                    .map(|(uuid, e)| (e.hash.clone(), uuid.clone()))
+                    .collect();
+            }
+            Err(_) => library.rebuild()?,
+        }
+        Ok(library)
+    }
+
+    fn rebuild(&mut self) -> Result<()> {
+        for file in record_files(std::slice::from_ref(&self.dir))? {
+            let record = match Record::load(&file) {
+                Ok(record) => record,
+                Err(e) => {
+                    warn!("Skipped {}: {:?}", file.display(), e);
+                    continue;
+                }
+            };
+            let name = file.file_name().unwrap_or_default().to_string_lossy();
+            self.insert(&record, name.to_string())?;
+        }
+        if !self.entries.is_empty() {
+            info!(
+                "Indexed {} records of {}",
+                self.entries.len(),
+                self.dir.display()
+            );
+        }
+        self.save()
+    }
+
+    fn insert(&mut self, record: &Record, file: String) -> Result<()> {
+        let hash = content_hash(record)?;
+        let uuid = record.head["uuid"]
+            .as_str()
+            .map(String::from)
+            .unwrap_or_else(|| hash.clone());
+        let info = GameInfo::from_record(&record.head, 0);
+        self.hashes.insert(hash.clone(), uuid.clone());
+        self.entries.insert(
+            uuid,
+            IndexEntry {
+                file,
+                hash,
+                start_time: record.head["start_time"].as_i64(),
+                players: info.names(),
+                room: info.room,
+                mode_id: info.mode_id,
+                complete: is_complete(record),
+            },
+        );
+        Ok(())
+    }
+
+    fn save(&self) -> Result<()> {
+        let bytes = serde_json::to_vec_pretty(&self.entries)?;
+        std::fs::write(self.dir.join(INDEX_FILE), bytes)?;
+        Ok(())
+    }
+
+    /// Whether a record of `uuid` is worth storing: the game is missing, or
+    /// only its live record is stored and a `complete` one is offered.
+    pub fn wants(&self, uuid: &str, complete: bool) -> bool {
+        match self.entries.get(uuid) {
+            Some(entry) => (complete && !entry.complete) || !self.dir.join(&entry.file).exists(),
+            None => true,
+        }
+    }
+
+    /// Write `record` as `<uuid>.json` unless the game or the same content is
+    /// already stored, returning the path written.
+    pub fn add(&mut self, record: &Record) -> Result<Option<PathBuf>> {
+        let hash = content_hash(record)?;
+        let uuid = record.head["uuid"].as_str().unwrap_or(&hash).to_string();
+        if let Some(same) = self.hashes.get(&hash).filter(|u| **u != uuid) {
+            info!("Record {} is a copy of {}, not stored", uuid, same);
+            return Ok(None);
+        }
+        if !self.wants(&uuid, is_complete(record)) {
+            return Ok(None);
+        }
+        let file = format!("{}.json", uuid);
+        let path = self.dir.join(&file);
+        std::fs::write(&path, serde_json::to_vec_pretty(record)?)?;
+        if let Some(old) = self.entries.get(&uuid) {
+            self.hashes.remove(&old.hash);
+        }
+        self.insert(record, file)?;
+        self.save()?;
+        Ok(Some(path))
+    }
+}
@@ -0,0 +1,190 @@
+use crate::{
+    base::BaseMessage,
+    parser::{dyn_to_json, to_fqn},
+    settings::get_version,
+    SETTINGS,
+};
+use anyhow::{anyhow, ensure, Result};
+use hudsucker::{
+    futures::{SinkExt, StreamExt},
+    tokio_tungstenite::{connect_async, tungstenite::Message, MaybeTlsStream, WebSocketStream},
+};
+use prost::Message as _;
+use prost_reflect::{DynamicMessage, MessageDescriptor};
+use serde_json::{json, Value as JsonValue};
+use std::collections::VecDeque;
+use tokio::net::TcpStream;
+use tracing::{debug, info};
+
+pub const DEFAULT_GATEWAY: &str = "wss://gateway-hw.maj-soul.com/gateway";
+
+/// A lobby connection of its own, for fetching records outside of the game client;
+/// also used for the observer server of a spectated game.
+pub struct LobbyClient {
+    ws: WebSocketStream<MaybeTlsStream<TcpStream>>,
+    next_id: u16,
+    /// e.g. `0.11.120.w`
+    resource: String,
+    /// e.g. `web-0.11.120`
+    pub client_version: String,
+    /// set after login
+    pub account_id: Option<u32>,
+    /// received while waiting for a response
+    notifies: VecDeque<(String, JsonValue)>,
+}
+
+impl LobbyClient {
+    pub async fn connect(gateway: &str) -> Result<Self> {
+        let (ws, _) = connect_async(gateway).await?;
+        let version = get_version().await?;
+        info!("Connected to {}, client version {}", gateway, version);
+        Ok(LobbyClient {
+            ws,
+            next_id: 1,
+            resource: version.clone(),
+            account_id: None,
+            notifies: VecDeque::new(),
+            client_version: format!("web-{}", version.trim_end_matches(".w")),
+        })
+    }
+
+    /// Log in with an access token as kept by the web client.
+    pub async fn login(&mut self, token: &str, token_type: u32) -> Result<JsonValue> {
+        let check = self
+            .call(
+                ".lq.Lobby.oauth2Check",
+                json!({"type": token_type, "access_token": token}),
+            )
+            .await?;
+        ensure!(
+            check["has_account"].as_bool().unwrap_or_default(),
+            "Token has no account or has expired"
+        );
+        let res = self
+            .call(
+                ".lq.Lobby.oauth2Login",
+                json!({
+                    "type": token_type,
+                    "access_token": token,
+                    "reconnect": false,
+                    "device": {"is_browser": true, "platform": "pc", "hardware": "pc", "os": "windows", "sale_platform": "web"},
+                    "random_key": uuid_like(),
+                    "client_version": {"resource": self.resource},
+                    "currency_platforms": [2],
+                    "client_version_string": self.client_version,
+                }),
+            )
+            .await?;
+        info!("Logged in as {}", res["account"]["nickname"]);
+        self.account_id = res["account_id"].as_u64().map(|id| id as u32);
+        Ok(res)
+    }
+
+    /// Call a lobby method such as `.lq.Lobby.fetchGameRecord`, failing on a response error.
+    pub async fn call(&mut self, method: &str, req: JsonValue) -> Result<JsonValue> {
+        let (req_type, res_type) = method_types(method)?;
+        let data = DynamicMessage::deserialize(req_type, req)?.encode_to_vec();
+        let wrapper = BaseMessage {
+            method_name: method.to_string(),
+            data,
+        };
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1).max(1);
+        let mut frame = vec![0x02];
+        frame.extend_from_slice(&id.to_le_bytes());
+        frame.extend(wrapper.encode_to_vec());
+        self.ws.send(Message::Binary(frame)).await?;
+        while let Some(msg) = self.ws.next().await {
+            let Message::Binary(buf) = msg? else {
+                continue;
+            };
+            if buf.first() == Some(&0x01) {
+                match decode_notify(&buf) {
+                    Ok(notify) => self.notifies.push_back(notify),
+                    Err(e) => debug!("Skipped notify: {:?}", e),
+                }
+                continue;
+            }
+            // skip answers to other requests
+            if buf.len() < 3 || buf[0] != 0x03 || u16::from_le_bytes([buf[1], buf[2]]) != id {
+                continue;
+            }
+            let block = BaseMessage::decode(&buf[3..])?;
+            let res = dyn_to_json(DynamicMessage::decode(res_type, block.data.as_slice())?)?;
+            let code = res["error"]["code"].as_u64().unwrap_or_default();
+            ensure!(code == 0, "{} failed with error code {}", method, code);
+            debug!("{} answered", method);
+            return Ok(res);
+        }
+        Err(anyhow!("Lobby connection closed"))
+    }
+
+    /// Wait for the next notify such as `.lq.NotifyFriendStateChange`, failing
+    /// once the connection is closed.
+    pub async fn next_notify(&mut self) -> Result<(String, JsonValue)> {
+        if let Some(notify) = self.notifies.pop_front() {
+            return Ok(notify);
+        }
+        while let Some(msg) = self.ws.next().await {
+            let Message::Binary(buf) = msg? else {
+                continue;
+            };
+            if buf.first() != Some(&0x01) {
+                continue;
+            }
+            match decode_notify(&buf) {
+                Ok(notify) => return Ok(notify),
+                Err(e) => debug!("Skipped notify: {:?}", e),
+            }
+        }
+        Err(anyhow!("Lobby connection closed"))
+    }
+}
+
+fn decode_notify(buf: &[u8]) -> Result<(String, JsonValue)> {
+    let block = BaseMessage::decode(&buf[1..])?;
+    let name = block.method_name.trim_start_matches(".lq.");
+    let msg_type = SETTINGS
+        .desc()
+        .get_message_by_name(&to_fqn(name))
+        .ok_or(anyhow!("Invalid message type: {}", name))?;
+    let data = dyn_to_json(DynamicMessage::decode(msg_type, block.data.as_slice())?)?;
+    Ok((block.method_name, data))
+}
+
+pub(crate) fn method_types(method: &str) -> Result<(MessageDescriptor, MessageDescriptor)> {
+    let parts: Vec<&str> = method.split('.').collect();
+    ensure!(parts.len() == 4, "Invalid method: {}", method);
+    let domain =
+        &SETTINGS.proto_json()["nested"][parts[1]]["nested"][parts[2]]["methods"][parts[3]];
+    let lookup = |key: &str| {
+        let name = domain[key]
+            .as_str()
+            .ok_or(anyhow!("No {} for {}", key, method))?;
+        SETTINGS
+            .desc()
+            .get_message_by_name(&to_fqn(name))
+            .ok_or(anyhow!("Invalid message type: {}", name))
+    };
+    Ok((lookup("requestType")?, lookup("responseType")?))
+}
+
+/// Random hex in uuid layout, the server only wants something unique per login.
+fn uuid_like() -> String {
+    let seed = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    let hex = format!(
+        "{:032x}",
+        seed.wrapping_mul(0x9e37_79b9_7f4a_7c15_f39c_c060_5ced_c835)
+    );
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    )
+}
@@ -0,0 +1,233 @@
+use crate::{activity::LogWriter, daily::DailyLog};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::{
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+use tracing::{metadata::LevelFilter, warn};
+use tracing_appender::rolling::{self, RollingFileAppender};
+use tracing_subscriber::{
+    fmt::{self, format::FmtSpan, time::ChronoLocal, MakeWriter},
+    layer::SubscriberExt,
+    reload,
+    util::SubscriberInitExt,
+    EnvFilter, Layer, Registry,
+};
+
+const FILE_PREFIX: &str = "majsoul_max_rs";
+
+/// Target of the spans of the frame pipeline, from interception to the sinks.
+pub const PIPELINE: &str = "majsoul_max_rs::pipeline";
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum LogFormat {
+    /// one line per event
+    #[default]
+    Compact,
+    /// several lines per event with its fields and source
+    Pretty,
+    /// one JSON object per line
+    Json,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum Rotation {
+    /// a folder per day of the `timezone` setting
+    #[default]
+    Daily,
+    Hourly,
+    Never,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct LogConfig {
+    /// console output
+    #[serde(default)]
+    pub format: LogFormat,
+    /// folder of log files, relative to the config folder, none are written when unset
+    #[serde(default)]
+    pub dir: Option<PathBuf>,
+    #[serde(default)]
+    pub rotation: Rotation,
+    /// files kept before the oldest is deleted, 0 keeps all of them
+    #[serde(default = "default_keep")]
+    pub keep: usize,
+    #[serde(default = "default_file_format")]
+    pub file_format: LogFormat,
+    /// log the busy and idle time of the pipeline spans when they close
+    #[serde(default)]
+    pub span_timings: bool,
+    /// tokio-console endpoint, needs the `console` feature
+    #[serde(default)]
+    pub console: Option<SocketAddr>,
+}
+
+fn default_keep() -> usize {
+    14
+}
+
+fn default_file_format() -> LogFormat {
+    LogFormat::Json
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        LogConfig {
+            format: LogFormat::default(),
+            dir: None,
+            rotation: Rotation::default(),
+            keep: default_keep(),
+            file_format: default_file_format(),
+            span_timings: false,
+            console: None,
+        }
+    }
+}
+
+/// `RUST_LOG`, else warnings and the info logs of this crate.
+pub fn default_filter() -> EnvFilter {
+    EnvFilter::builder()
+        .with_default_directive(LevelFilter::WARN.into())
+        .from_env()
+        .unwrap_or_default()
+        .add_directive("majsoul_max_rs=info".parse().unwrap_or_default())
+}
+
+/// Console logging of the default filter, for the settings to be loaded with.
+pub fn bootstrap() -> impl tracing::Subscriber + Send + Sync {
+    tracing_subscriber::fmt()
+        .with_env_filter(default_filter())
+        .with_timer(ChronoLocal::new("%H:%M:%S%.3f".to_string()))
+        .compact()
+        .finish()
+}
+
+type BoxedLayer = Box<dyn Layer<Registry> + Send + Sync>;
+
+/// Colored for the console, plain for files.
+fn fmt_layer<W>(format: LogFormat, ansi: bool, spans: FmtSpan, writer: W) -> BoxedLayer
+where
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    // lines read later, by people or tools, carry the date
+    let timer = match ansi && format != LogFormat::Json {
+        true => "%H:%M:%S%.3f",
+        false => "%Y-%m-%d %H:%M:%S%.3f",
+    };
+    let layer = fmt::layer()
+        .with_timer(ChronoLocal::new(timer.to_string()))
+        .with_ansi(ansi)
+        .with_span_events(spans)
+        .with_writer(writer);
+    match format {
+        LogFormat::Compact => layer.compact().boxed(),
+        LogFormat::Pretty => layer.pretty().boxed(),
+        LogFormat::Json => layer.json().boxed(),
+    }
+}
+
+#[cfg(feature = "console")]
+fn console_layer(addr: SocketAddr) -> Result<BoxedLayer> {
+    Ok(console_subscriber::ConsoleLayer::builder()
+        .server_addr(addr)
+        .spawn()
+        .boxed())
+}
+
+#[cfg(not(feature = "console"))]
+fn console_layer(_addr: SocketAddr) -> Result<BoxedLayer> {
+    anyhow::bail!("tokio-console support is not compiled in")
+}
+
+fn appender(config: &LogConfig, dir: &Path) -> Result<RollingFileAppender> {
+    let rotation = match config.rotation {
+        Rotation::Hourly => rolling::Rotation::HOURLY,
+        _ => rolling::Rotation::NEVER,
+    };
+    let mut builder = RollingFileAppender::builder()
+        .rotation(rotation)
+        .filename_prefix(FILE_PREFIX)
+        .filename_suffix("log");
+    if config.keep > 0 {
+        builder = builder.max_log_files(config.keep);
+    }
+    Ok(builder.build(dir)?)
+}
+
+/// Install the global subscriber: the console, or the dashboard when
+/// `dashboard` is set, and rotating files under `config.dir` of `config_dir`
+/// if set, all behind one filter of `level` or the default. Returns a setter
+/// swapping the filter, for hot reload.
+pub fn init(
+    config: &LogConfig,
+    config_dir: Option<&Path>,
+    level: Option<&str>,
+    dashboard: bool,
+) -> impl Fn(Option<&str>) -> Result<()> + Send + 'static {
+    let mut problems = vec![];
+    let filter = match level.map(EnvFilter::try_new) {
+        Some(Ok(filter)) => filter,
+        Some(Err(e)) => {
+            problems.push(format!("Invalid logLevel {:?}: {}", level, e));
+            default_filter()
+        }
+        None => default_filter(),
+    };
+    let (filter, handle) = reload::Layer::new(filter);
+    let spans = match config.span_timings {
+        true => FmtSpan::CLOSE,
+        false => FmtSpan::NONE,
+    };
+    let console = match dashboard {
+        // short plain lines fit its log panel
+        true => fmt::layer()
+            .with_timer(ChronoLocal::new("%H:%M:%S".to_string()))
+            .with_ansi(false)
+            .with_span_events(spans.clone())
+            .with_writer(|| LogWriter)
+            .compact()
+            .boxed(),
+        false => fmt_layer(config.format, true, spans.clone(), std::io::stdout),
+    };
+    let mut layers = vec![console];
+    let dir = config
+        .dir
+        .as_ref()
+        .map(|dir| config_dir.map_or(dir.clone(), |base| base.join(dir)));
+    if let Some(dir) = dir {
+        let layer = match config.rotation {
+            Rotation::Daily => DailyLog::new(&dir, &format!("{}.log", FILE_PREFIX), config.keep)
+                .map(|log| fmt_layer(config.file_format, false, spans, Mutex::new(log))),
+            _ => appender(config, &dir)
+                .map(|appender| fmt_layer(config.file_format, false, spans, appender)),
+        };
+        match layer {
+            Ok(layer) => layers.push(layer),
+            Err(e) => problems.push(format!("无法写入日志目录 {}: {}", dir.display(), e)),
+        }
+    }
+    // the filter is the logs', the console sees the runtime traces of its own
+    let mut layers = vec![layers.with_filter(filter).boxed()];
+    if let Some(addr) = config.console {
+        match console_layer(addr) {
+            Ok(layer) => layers.push(layer),
+            Err(e) => problems.push(e.to_string()),
+        }
+    }
+    tracing_subscriber::registry().with(layers).init();
+    for problem in problems {
+        warn!("{}", problem);
+    }
+    move |level: Option<&str>| {
+        let filter = match level {
+            Some(level) => EnvFilter::try_new(level)?,
+            None => default_filter(),
+        };
+        Ok(handle.reload(filter)?)
+    }
+}
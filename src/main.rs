@@ -1,27 +1,42 @@
+use anyhow::{Context, Result};
 use bytes::Bytes;
 use hudsucker::{
-    certificate_authority::RcgenAuthority,
     futures::{Sink, SinkExt, Stream, StreamExt},
-    rcgen::{CertificateParams, KeyPair},
     tokio_tungstenite::tungstenite::{self, Message},
     *,
 };
-use metadata::LevelFilter;
-use std::{net::SocketAddr, str::FromStr, sync::Arc};
-use tokio::sync::mpsc::{channel, Sender};
-use tracing::*;
-use tracing_subscriber::{fmt::time::ChronoLocal, EnvFilter};
+use std::{
+    future::Future, net::SocketAddr, process::ExitCode, str::FromStr, sync::Arc, time::Duration,
+};
+use tokio::{
+    net::TcpListener,
+    sync::mpsc::{channel, Sender},
+};
+use tracing::{instrument::WithSubscriber, *};
 
 use majsoul_max_rs::{
-    helper::helper_worker,
+    activity::ACTIVITY,
+    api, cert, config, daily, doctor, dump, flight,
+    helper::{enqueue, helper_worker, Frame},
+    history, i18n, instance,
+    isolation::{self, frame_head},
+    logging::{self, LogConfig, PIPELINE},
+    manpage,
     modder::{Modder, MOD_SETTINGS},
-    parser::Parser,
-    SETTINGS,
+    record, reload, schedule, secrets, self_test, self_update,
+    service::{self, ServiceCommand},
+    simulate, sink, status, systemd, tr, tray,
+    tui::Dashboard,
+    watchdog::{Alarm, Watch},
+    wizard, Command, ARG, SETTINGS,
 };
 
+/// How long the sinks get at exit to write what they hold back.
+const SINK_CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
 #[derive(Clone)]
 struct Handler {
-    sender: Sender<(Bytes, char)>,
+    sender: Sender<Frame>,
     modder: Option<Arc<Modder>>,
     inject_msg: Option<Message>,
 }
@@ -33,6 +48,11 @@ impl WebSocketHandler for Handler {
         mut stream: impl Stream<Item = Result<Message, tungstenite::Error>> + Unpin + Send + 'static,
         mut sink: impl Sink<Message, Error = tungstenite::Error> + Unpin + Send + 'static,
     ) {
+        // both directions of a game websocket share one session
+        let _session = match ctx {
+            WebSocketContext::ServerToClient { .. } => Some(ACTIVITY.session()),
+            WebSocketContext::ClientToServer { .. } => None,
+        };
         if let WebSocketContext::ServerToClient { .. } = ctx {
             if let Some(msg) = self.inject_msg.take() {
                 if let Err(e) = sink.send(msg).await {
@@ -40,11 +60,59 @@ impl WebSocketHandler for Handler {
                 }
             }
         }
-        while let Some(message) = stream.next().await {
+        let mut watch = Watch::new(&ctx, SETTINGS.upstream_stall_secs);
+        loop {
+            let message = tokio::select! {
+                message = stream.next() => match message {
+                    Some(message) => message,
+                    None => break,
+                },
+                alarm = watch.next() => match alarm {
+                    Alarm::Probe => {
+                        if let Err(e) = sink.send(watch.probe()).await {
+                            debug!("Failed to ping the server: {}", e);
+                        }
+                        continue;
+                    }
+                    Alarm::Stalled(stall) => {
+                        ACTIVITY.stalled();
+                        warn!(
+                            "{} silent for {} seconds, closing the connection for the client to reconnect",
+                            stall.server, stall.silent_secs
+                        );
+                        let _ = sink.send(Message::Close(None)).await;
+                        break;
+                    }
+                },
+            };
             match message {
                 Ok(message) => {
-                    let Some(message) = self.handle_message(&ctx, message).await else {
+                    if watch.seen(&message) {
                         continue;
+                    }
+                    let (kind, len) = (message_kind(&message), message.len());
+                    // enough of it for the dump, the message itself is moved
+                    let head = match &message {
+                        Message::Binary(buf) => buf[..buf.len().min(65)].to_vec(),
+                        _ => vec![],
+                    };
+                    let handled = isolation::catch_async(self.handle_message(&ctx, message)).await;
+                    let message = match handled {
+                        Ok(Some(message)) => message,
+                        Ok(None) => continue,
+                        Err(panicked) => {
+                            ACTIVITY.panicked();
+                            error!(
+                                "Session {:?} crashed, closing it; {} message of {} bytes: {}\n{}",
+                                ctx,
+                                kind,
+                                len,
+                                frame_head(&head),
+                                panicked
+                            );
+                            let _ = sink.send(Message::Close(None)).await;
+                            break;
+                        }
                     };
 
                     match sink.send(message).await {
@@ -69,29 +137,34 @@ impl WebSocketHandler for Handler {
     }
 
     async fn handle_message(&mut self, _ctx: &WebSocketContext, msg: Message) -> Option<Message> {
-        let (direction_char, uri) = match _ctx {
-            WebSocketContext::ServerToClient { src, .. } => ('\u{2193}', src),
-            WebSocketContext::ClientToServer { dst, .. } => ('\u{2191}', dst),
+        let (direction_char, uri, client) = match _ctx {
+            WebSocketContext::ServerToClient { src, dst, .. } => ('\u{2193}', src, dst),
+            WebSocketContext::ClientToServer { src, dst, .. } => ('\u{2191}', dst, src),
         };
 
-        if uri.path() == "/ob" {
-            // ignore ob messages
-            return Some(msg);
-        }
+        let spectated = uri.path() == "/ob";
 
         debug!("{} {}", direction_char, uri);
 
-        if SETTINGS.helper_on() {
+        if SETTINGS.helper_on() && schedule::capturing() {
             if let Message::Binary(ref buf) = msg {
-                if let Err(e) = self
-                    .sender
-                    .send((Bytes::copy_from_slice(buf), direction_char))
-                    .await
-                {
-                    error!("Failed to send message to channel: {:?}", e);
-                }
+                let frame = Frame {
+                    buf: Bytes::copy_from_slice(buf),
+                    session: Some(*client),
+                    direction: direction_char,
+                    spectated,
+                    simulated: false,
+                };
+                let span = debug_span!(target: PIPELINE, "intercept", direction = %direction_char, len = buf.len());
+                enqueue(&self.sender, frame, SETTINGS.frame_queue.overflow)
+                    .instrument(span)
+                    .await;
             }
         }
+        if spectated {
+            // never mod ob messages
+            return Some(msg);
+        }
         if let Some(ref modder) = self.modder {
             if let Message::Binary(buf) = msg {
                 let res = modder.modify(buf, direction_char == '\u{2191}').await;
@@ -105,108 +178,295 @@ impl WebSocketHandler for Handler {
     }
 }
 
-async fn shutdown_signal() {
-    tokio::signal::ctrl_c()
-        .await
-        .expect("Failed to install CTRL+C signal handler");
+fn message_kind(message: &Message) -> &'static str {
+    match message {
+        Message::Text(_) => "text",
+        Message::Binary(_) => "binary",
+        Message::Ping(_) => "ping",
+        Message::Pong(_) => "pong",
+        Message::Close(_) => "close",
+        Message::Frame(_) => "frame",
+    }
 }
 
 #[tokio::main]
-async fn main() {
-    // chrono formatted timer
-    let timer = ChronoLocal::new("%H:%M:%S%.3f".to_string());
-    let filter = EnvFilter::builder()
-        .with_default_directive(LevelFilter::WARN.into())
-        .from_env()
-        .unwrap_or_default()
-        .add_directive("majsoul_max_rs=info".parse().unwrap_or_default());
-    tracing_subscriber::fmt()
-        .with_env_filter(filter)
-        .with_timer(timer)
-        .compact()
-        .init();
-
-    let key_pair = include_str!("./ca/hudsucker.key");
-    let ca_cert = include_str!("./ca/hudsucker.cer");
-    let key_pair = KeyPair::from_pem(key_pair).expect("Failed to parse private key");
-    let ca_cert = CertificateParams::from_ca_cert_pem(ca_cert)
-        .expect("Failed to parse CA certificate")
-        .self_signed(&key_pair)
-        .expect("Failed to sign CA certificate");
-
-    let ca = RcgenAuthority::new(key_pair, ca_cert, 1_000);
+async fn main() -> ExitCode {
+    self_update::cleanup();
+    if wizard::wanted() {
+        let setup = async {
+            let res = wizard::run().await;
+            if let Err(e) = &res {
+                error!("{:?}", e);
+            }
+            res
+        };
+        let res = setup.with_subscriber(logging::bootstrap()).await;
+        if res.is_err() {
+            return status::exit_code(&res);
+        }
+    }
+    // settings are logged to the console while the configured logging is not set up yet
+    // `status --json` keeps stdout to its JSON, loading the settings quietly
+    let quiet = matches!(&ARG.command, Some(Command::Status(args)) if args.json);
+    let settings = tracing::subscriber::with_default(logging::bootstrap(), || {
+        match &ARG.command {
+            _ if quiet => None,
+            // the commands that may run without loadable settings
+            Some(Command::GenCert { dir: Some(_), .. }) => None,
+            // reports what would make loading them fail
+            Some(Command::Config(_)) => None,
+            Some(Command::SelfUpdate(_)) => None,
+            Some(Command::List) => None,
+            Some(Command::Secret(_)) => None,
+            Some(Command::Completions { .. } | Command::Manpage { .. }) => None,
+            _ => Some(&*SETTINGS),
+        }
+    });
+    i18n::set(settings.and_then(|s| s.language));
+    daily::set(settings.and_then(|s| s.timezone));
+    let mut log = settings.map_or_else(LogConfig::default, |s| s.log.clone());
+    if let Some(Command::Service(ServiceCommand::Run)) = &ARG.command {
+        // nobody sees the console of a service
+        log.dir.get_or_insert_with(|| "logs".into());
+    }
+    let dashboard = match &ARG.command {
+        None | Some(Command::Run) if ARG.tui => Some(Dashboard::open()),
+        _ => None,
+    };
+    let set_log_level = logging::init(
+        &log,
+        settings.map(|s| s.config_dir()),
+        match quiet {
+            true => Some("off"),
+            false => settings.and_then(|s| s.log_level.as_deref()),
+        },
+        matches!(dashboard, Some(Ok(_))),
+    );
+
+    let res = match &ARG.command {
+        None | Some(Command::Run) => match dashboard {
+            Some(Err(e)) => Err(e),
+            Some(Ok(dashboard)) => {
+                reload::spawn(set_log_level);
+                run(async {
+                    tokio::select! {
+                        _ = systemd::shutdown_signal() => (),
+                        _ = dashboard.run() => (),
+                    }
+                })
+                .await
+            }
+            None => {
+                reload::spawn(set_log_level);
+                run(systemd::shutdown_signal()).await
+            }
+        },
+        Some(Command::Service(ServiceCommand::Run)) => {
+            reload::spawn(set_log_level);
+            let runtime = tokio::runtime::Handle::current();
+            tokio::task::block_in_place(|| {
+                service::dispatch(move |stop| {
+                    runtime.block_on(serve(async {
+                        let _ = stop.await;
+                    }))
+                })
+            })
+        }
+        Some(Command::Tray) => {
+            reload::spawn(set_log_level);
+            let runtime = tokio::runtime::Handle::current();
+            // the tray needs the main thread on macOS
+            tokio::task::block_in_place(|| {
+                tray::run(runtime, |stop| {
+                    Box::pin(serve(async {
+                        let _ = stop.await;
+                    }))
+                })
+            })
+        }
+        Some(Command::Service(command)) => service::manage(command),
+        Some(Command::Parse(args)) => dump::run(args),
+        Some(Command::Record(command)) => record::run(command).await,
+        Some(Command::Convert(args)) => record::convert(args).await,
+        Some(Command::Doctor) => doctor::run().await,
+        Some(Command::SelfTest(args)) => self_test::run(args),
+        Some(Command::Config(command)) => config::run(command).await,
+        // done by the wizard before the settings were loaded
+        Some(Command::Setup) => Ok(()),
+        Some(Command::GenCert { dir, force }) => {
+            let dir = dir.as_deref().unwrap_or_else(|| SETTINGS.config_dir());
+            cert::generate(dir, *force)
+        }
+        Some(Command::Stats(args)) => history::run(args),
+        Some(Command::SelfUpdate(args)) => self_update::run(args).await,
+        Some(Command::Status(args)) => status::run(args).await,
+        Some(Command::List) => instance::list(),
+        Some(Command::Secret(command)) => secrets::run(command),
+        Some(Command::Simulate(command)) => simulate::run(command).await,
+        Some(Command::Completions { shell }) => {
+            manpage::completions(*shell);
+            Ok(())
+        }
+        Some(Command::Manpage { dir }) => manpage::generate(dir),
+    };
+    match &res {
+        Err(e) if quiet => eprintln!("{:#}", e),
+        Err(e) => error!("{:?}", e),
+        Ok(()) => (),
+    }
+    status::exit_code(&res)
+}
+
+/// Run the proxy until `shutdown` completes, along with the helper and the
+/// admin API, which stop with it.
+async fn run(shutdown: impl Future<Output = ()> + Send + 'static) -> Result<()> {
+    let ca = cert::load_ca().context("Failed to load CA certificate")?;
 
     // print red declaimer text
     println!(
         "
     MajsoulMax-rs {}
     \x1b[31m
-    本项目完全免费开源，如果您购买了此程序，请立即退款！
+    {}
+    \x1b[0m",
+        env!("CARGO_PKG_VERSION"),
+        tr!(
+            "本项目完全免费开源，如果您购买了此程序，请立即退款！
     项目地址: https://github.com/Xerxes-2/MajsoulMax-rs
     
     本程序仅供学习交流使用，严禁用于商业用途！
-    请遵守当地法律法规，对于使用本程序所产生的任何后果，作者概不负责！
-    \x1b[0m",
-        env!("CARGO_PKG_VERSION")
+    请遵守当地法律法规，对于使用本程序所产生的任何后果，作者概不负责！",
+            "本プロジェクトは完全無料のオープンソースです。購入した場合はすぐに返金を求めてください！
+    プロジェクト: https://github.com/Xerxes-2/MajsoulMax-rs
+    
+    本プログラムは学習・交流のみを目的としており、商用利用を禁じます！
+    現地の法令を守ってください。本プログラムの使用によるいかなる結果についても作者は責任を負いません！",
+            "This project is free and open source, if you paid for it, ask for a refund now!
+    Project: https://github.com/Xerxes-2/MajsoulMax-rs
+    
+    This program is for learning only, commercial use is prohibited!
+    Obey local laws, the authors take no responsibility for any consequences of using it!"
+        )
     );
 
-    let proxy_addr = match SocketAddr::from_str(SETTINGS.proxy_addr.as_str()) {
-        Ok(addr) => addr,
-        Err(e) => {
-            error!(
-                "Failed to parse proxy address: {:?}, url: {}",
-                e, SETTINGS.proxy_addr
-            );
-            return;
-        }
-    };
+    let proxy_addr = SocketAddr::from_str(SETTINGS.proxy_addr.as_str())
+        .with_context(|| format!("Failed to parse proxy address: {}", SETTINGS.proxy_addr))?;
+
+    if SETTINGS.check_updates {
+        tokio::spawn(self_update::notify_newer());
+    }
 
     if SETTINGS.auto_update() {
-        info!("自动更新liqi已开启");
+        info!(
+            "{}",
+            tr!(
+                "自动更新liqi已开启",
+                "liqi の自動更新はオンです",
+                "Auto update of liqi is on"
+            )
+        );
         let mut new_settings = SETTINGS.clone();
         match new_settings.update().await {
-            Err(e) => warn!("更新liqi失败: {}", e),
+            Err(e) => warn!(
+                "{}",
+                tr!(
+                    "更新liqi失败: {}",
+                    "liqi の更新に失敗しました: {}",
+                    "Failed to update liqi: {}",
+                    e
+                )
+            ),
             Ok(true) => {
-                info!("liqi更新成功, 请重启程序");
+                info!(
+                    "{}",
+                    tr!(
+                        "liqi更新成功, 请重启程序",
+                        "liqi を更新しました。プログラムを再起動してください",
+                        "liqi updated, please restart the program"
+                    )
+                );
                 tokio::time::sleep(std::time::Duration::from_secs(5)).await;
-                return;
+                return Ok(());
             }
             Ok(false) => (),
         }
     }
 
-    // show mod and helper switch status, green for on, red for off
-    println!(
-        "\n\x1b[{}mmod: {}\x1b[0m\n\x1b[{}mhelper: {}\x1b[0m\n",
-        if SETTINGS.mod_on() { 32 } else { 31 },
-        if SETTINGS.mod_on() { "on" } else { "off" },
-        if SETTINGS.helper_on() { 32 } else { 31 },
-        if SETTINGS.helper_on() { "on" } else { "off" }
+    SETTINGS.load_liqi();
+
+    if ARG.dry_run {
+        warn!(
+            "{}",
+            tr!(
+                "试运行：只解析并记录消息，不发送到任何输出目标，也不修改或注入消息",
+                "ドライラン：メッセージを解析して記録するだけで、出力先への送信やメッセージの改変・注入は行いません",
+                "Dry run: messages are parsed and logged only, nothing is sent to the sinks, modified or injected"
+            )
+        );
+    }
+
+    let mod_on = SETTINGS.mod_on() && !ARG.dry_run;
+    let switch = |on: bool| if on { "on" } else { "off" };
+    info!(
+        mod_on,
+        helper_on = SETTINGS.helper_on(),
+        "mod: {}, helper: {}",
+        switch(mod_on),
+        switch(SETTINGS.helper_on())
     );
 
     let mut modder = None;
-    if SETTINGS.mod_on() {
+    if mod_on {
         // start mod worker
         info!("Mod worker started");
         if MOD_SETTINGS.read().await.auto_update() {
-            info!("自动更新mod已开启");
+            info!(
+                "{}",
+                tr!(
+                    "自动更新mod已开启",
+                    "mod の自動更新はオンです",
+                    "Auto update of the mod is on"
+                )
+            );
             let mut new_mod_settings = MOD_SETTINGS.read().await.clone();
             match new_mod_settings.get_lqc().await {
-                Err(e) => warn!("更新mod失败: {}", e),
+                Err(e) => warn!(
+                    "{}",
+                    tr!(
+                        "更新mod失败: {}",
+                        "mod の更新に失敗しました: {}",
+                        "Failed to update the mod: {}",
+                        e
+                    )
+                ),
                 Ok(false) => (),
                 Ok(true) => {
-                    info!("mod更新成功, 请重启程序");
+                    info!(
+                        "{}",
+                        tr!(
+                            "mod更新成功, 请重启程序",
+                            "mod を更新しました。プログラムを再起動してください",
+                            "Mod updated, please restart the program"
+                        )
+                    );
                     tokio::time::sleep(std::time::Duration::from_secs(5)).await;
-                    return;
+                    return Ok(());
                 }
             }
             modder = Some(Arc::new(Modder::new().await));
         }
     }
 
-    let (tx, rx) = channel::<(Bytes, char)>(100);
+    let _instance = instance::register()?;
+    // bound here so that readiness is only reported once it listens
+    let listener = TcpListener::bind(proxy_addr)
+        .await
+        .with_context(|| format!("Failed to listen on {}", proxy_addr))?;
+    let capacity = SETTINGS.frame_queue.capacity.max(1);
+    ACTIVITY.set_queue_capacity(capacity);
+    let (tx, rx) = channel::<Frame>(capacity);
     let proxy = Proxy::builder()
-        .with_addr(proxy_addr)
+        .with_listener(listener)
         .with_rustls_client()
         .with_ca(ca)
         .with_websocket_handler(Handler {
@@ -214,16 +474,49 @@ async fn main() {
             modder,
             inject_msg: None,
         })
-        .with_graceful_shutdown(shutdown_signal())
+        .with_graceful_shutdown(shutdown)
         .build();
 
+    let mut tasks = vec![];
     if SETTINGS.helper_on() {
         // start helper worker
         info!("Helper worker started");
-        tokio::spawn(helper_worker(rx, Parser::default()));
+        tasks.push(tokio::spawn(helper_worker(rx)));
+        tasks.push(tokio::spawn(flight::on_signal()));
+        if !SETTINGS.admin_addr.is_empty() {
+            match SocketAddr::from_str(&SETTINGS.admin_addr) {
+                Ok(addr) => {
+                    tasks.push(tokio::spawn(async move {
+                        if let Err(e) = api::serve(addr).await {
+                            error!("Admin API stopped: {:?}", e);
+                        }
+                    }));
+                }
+                Err(e) => error!(
+                    "Failed to parse admin address: {:?}, addr: {}",
+                    e, SETTINGS.admin_addr
+                ),
+            }
+        }
+    }
+
+    if !SETTINGS.schedule.capture.is_empty() || SETTINGS.schedule.sync.is_some() {
+        tasks.push(tokio::spawn(schedule::run()));
     }
 
-    if let Err(e) = proxy.start().await {
-        error!("{}", e);
+    systemd::notify("READY=1");
+    let res = proxy.start().await;
+    for task in tasks {
+        task.abort();
+    }
+    // the helper dropped the sinks, let them write what they hold back
+    sink::stopped(SINK_CLOSE_TIMEOUT).await;
+    Ok(res?)
+}
+
+/// `run` for the hosts of the proxy that only log its failure.
+async fn serve(shutdown: impl Future<Output = ()> + Send + 'static) {
+    if let Err(e) = run(shutdown).await {
+        error!("{:?}", e);
     }
 }
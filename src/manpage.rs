@@ -0,0 +1,73 @@
+use crate::{settings::Settings, tr, wizard::TEMPLATE, Arg};
+use anyhow::Result;
+use clap::CommandFactory;
+use clap_complete::Shell;
+use clap_mangen::{
+    roff::{bold, roman, Roff},
+    Man,
+};
+use serde_json::Value;
+use std::path::Path;
+
+/// Print the completion script of `shell` to stdout.
+pub fn completions(shell: Shell) {
+    let mut command = Arg::command();
+    let name = command.get_name().to_string();
+    clap_complete::generate(shell, &mut command, name, &mut std::io::stdout());
+}
+
+/// The settings keys with the values of the bundled settings.json, missing
+/// keys with their defaults.
+fn config_section() -> Result<Roff> {
+    let settings: Settings = serde_json::from_str(TEMPLATE)?;
+    let mut roff = Roff::new();
+    roff.control("SH", ["CONFIGURATION"]);
+    roff.text([roman(
+        "Settings are read from settings.json in the config folder, overridden by \
+         settings.toml files, --config, --profile, MAJSOUL_MAX_* variables and --set. \
+         Keys and their default values:",
+    )]);
+    if let Value::Object(keys) = serde_json::to_value(&settings)? {
+        for (key, value) in keys {
+            roff.control("TP", []);
+            roff.text([bold(key)]);
+            roff.text([roman(value.to_string())]);
+        }
+    }
+    Ok(roff)
+}
+
+/// `name.1`, then a page per subcommand as `name-sub.1`, `name-sub-subsub.1`.
+fn write(command: &clap::Command, name: &str, dir: &Path, extra: Option<&Roff>) -> Result<usize> {
+    let mut page = vec![];
+    let man = Man::new(command.clone()).title(name);
+    man.render(&mut page)?;
+    if let Some(extra) = extra {
+        extra.to_writer(&mut page)?;
+    }
+    std::fs::write(dir.join(format!("{}.1", name)), page)?;
+    let mut written = 1;
+    for sub in command.get_subcommands().filter(|s| s.get_name() != "help") {
+        written += write(sub, &format!("{}-{}", name, sub.get_name()), dir, None)?;
+    }
+    Ok(written)
+}
+
+/// Write the man pages of every command to `dir`, with the settings keys in the main one.
+pub fn generate(dir: &Path) -> Result<()> {
+    std::fs::create_dir_all(dir)?;
+    let command = Arg::command();
+    let name = command.get_name().to_string();
+    let written = write(&command, &name, dir, Some(&config_section()?))?;
+    println!(
+        "{}",
+        tr!(
+            "已生成 {} 个手册页到 {}",
+            "{} 個の man ページを {} に生成しました",
+            "Wrote {} man pages to {}",
+            written,
+            dir.display()
+        )
+    );
+    Ok(())
+}
@@ -0,0 +1,183 @@
+use crate::{tr, SETTINGS};
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use tracing::warn;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct MemoryConfig {
+    /// MiB the accounted consumers may hold together, 0 for no cap
+    #[serde(default = "default_cap_mb")]
+    pub cap_mb: usize,
+}
+
+fn default_cap_mb() -> usize {
+    512
+}
+
+impl Default for MemoryConfig {
+    fn default() -> Self {
+        MemoryConfig {
+            cap_mb: default_cap_mb(),
+        }
+    }
+}
+
+/// A big holder of memory, accounted against the cap.
+#[derive(Debug, Clone, Copy)]
+pub enum Consumer {
+    /// the requests waiting for their responses in the parsers
+    Pending,
+    /// payloads queued for the sinks
+    Sinks,
+    /// the game of the live recorder
+    Recorder,
+    /// the last frames of each session kept for a debug dump
+    Debug,
+}
+
+static USED: [AtomicUsize; 4] = [const { AtomicUsize::new(0) }; 4];
+/// Something was shed since the memory was last under the cap.
+static SHEDDING: AtomicBool = AtomicBool::new(false);
+
+/// Bytes held by a consumer, counted until dropped.
+#[derive(Debug)]
+pub struct Charge {
+    consumer: Consumer,
+    bytes: usize,
+}
+
+impl Charge {
+    pub fn new(consumer: Consumer, bytes: usize) -> Self {
+        USED[consumer as usize].fetch_add(bytes, Ordering::Relaxed);
+        Charge { consumer, bytes }
+    }
+
+    pub fn grow(&mut self, bytes: usize) {
+        USED[self.consumer as usize].fetch_add(bytes, Ordering::Relaxed);
+        self.bytes += bytes;
+    }
+
+    /// Count `bytes` instead, e.g. once the holder is cleared.
+    pub fn set(&mut self, bytes: usize) {
+        USED[self.consumer as usize].fetch_sub(self.bytes, Ordering::Relaxed);
+        USED[self.consumer as usize].fetch_add(bytes, Ordering::Relaxed);
+        self.bytes = bytes;
+    }
+}
+
+impl Clone for Charge {
+    fn clone(&self) -> Self {
+        Charge::new(self.consumer, self.bytes)
+    }
+}
+
+impl Drop for Charge {
+    fn drop(&mut self) {
+        USED[self.consumer as usize].fetch_sub(self.bytes, Ordering::Relaxed);
+    }
+}
+
+/// Rough size of a JSON tree in memory.
+pub fn json_size(value: &JsonValue) -> usize {
+    const NODE: usize = std::mem::size_of::<JsonValue>();
+    NODE + match value {
+        JsonValue::String(s) => s.len(),
+        JsonValue::Array(items) => items.iter().map(json_size).sum(),
+        JsonValue::Object(fields) => fields.iter().map(|(k, v)| k.len() + json_size(v)).sum(),
+        _ => 0,
+    }
+}
+
+fn used(consumer: Consumer) -> usize {
+    USED[consumer as usize].load(Ordering::Relaxed)
+}
+
+fn total() -> usize {
+    USED.iter().map(|u| u.load(Ordering::Relaxed)).sum()
+}
+
+fn cap() -> usize {
+    SETTINGS.memory.cap_mb << 20
+}
+
+/// Warn the first time something is shed since the memory was under the cap.
+fn shedding(held: usize) {
+    if !SHEDDING.swap(true, Ordering::Relaxed) {
+        warn!(
+            "{}",
+            tr!(
+                "内存占用 {} MiB 超过上限 {} MiB，先清空调试用的最近帧并停止录制对局，再丢弃非关键输出目标的消息",
+                "メモリ使用量 {} MiB が上限 {} MiB を超えました。デバッグ用の直近のフレームを消して対局の記録を止め、次に重要でない出力先のメッセージを破棄します",
+                "Memory use of {} MiB is over the cap of {} MiB, dropping the frames kept for debugging and the live recording first, then the payloads of non-critical sinks",
+                held >> 20,
+                cap() >> 20
+            )
+        );
+    }
+}
+
+fn over(held: usize) -> bool {
+    cap() > 0 && held > cap()
+}
+
+/// The frames kept for debug dumps are dropped first.
+pub fn shed_debug() -> bool {
+    let held = total();
+    if !over(held) {
+        SHEDDING.store(false, Ordering::Relaxed);
+        return false;
+    }
+    shedding(held);
+    true
+}
+
+/// The live recording is given up next.
+pub fn shed_recording() -> bool {
+    if !over(total()) {
+        SHEDDING.store(false, Ordering::Relaxed);
+        return false;
+    }
+    let held = total().saturating_sub(used(Consumer::Debug));
+    if !over(held) {
+        return false;
+    }
+    shedding(held);
+    true
+}
+
+/// Non-critical sinks drop their payloads when the recording was not enough.
+pub fn shed_sinks() -> bool {
+    if !over(total()) {
+        SHEDDING.store(false, Ordering::Relaxed);
+        return false;
+    }
+    let held = total().saturating_sub(used(Consumer::Recorder) + used(Consumer::Debug));
+    if !over(held) {
+        return false;
+    }
+    shedding(held);
+    true
+}
+
+/// Bytes held by each consumer, for the status.
+#[derive(Debug, Clone, Serialize)]
+pub struct MemoryStats {
+    pub pending: usize,
+    pub sinks: usize,
+    pub recorder: usize,
+    pub debug: usize,
+    /// 0 for no cap
+    pub cap: usize,
+}
+
+pub fn stats() -> MemoryStats {
+    MemoryStats {
+        pending: used(Consumer::Pending),
+        sinks: used(Consumer::Sinks),
+        recorder: used(Consumer::Recorder),
+        debug: used(Consumer::Debug),
+        cap: cap(),
+    }
+}
@@ -0,0 +1,262 @@
+use crate::{
+    parser::{str_field, str_list, u32_field, u32_list},
+    players::GameInfo,
+    tile::{kan_tiles, to_mjai},
+};
+use anyhow::{anyhow, Result};
+use serde_json::{json, Value as JsonValue};
+
+const WINDS: [&str; 4] = ["E", "S", "W", "N"];
+
+/// Stateful majsoul action -> mjai event converter.
+///
+/// Accepts both live `Action*` and record `Record*` actions, with the prefix already stripped.
+/// In live games hidden tiles are emitted as `"?"`.
+#[derive(Debug, Default)]
+pub struct MjaiConverter {
+    seat: Option<u32>,
+    names: Vec<String>,
+    account_ids: Vec<u32>,
+    started: bool,
+    doras: usize,
+    last_discard: u32,
+    players: usize,
+}
+
+fn mjai_list(tiles: &[&str]) -> Result<Vec<String>> {
+    tiles.iter().map(|t| to_mjai(t)).collect()
+}
+
+impl MjaiConverter {
+    pub fn set_seat(&mut self, seat: u32) {
+        self.seat = Some(seat);
+    }
+
+    /// Own seat and the players of a joined game.
+    pub fn set_game(&mut self, game: &GameInfo) {
+        self.seat = Some(game.seat);
+        self.names = game.names();
+        self.account_ids = game.players.iter().map(|p| p.account_id).collect();
+    }
+
+    pub fn feed(&mut self, name: &str, data: &JsonValue) -> Result<Vec<JsonValue>> {
+        let mut events = Vec::new();
+        match name {
+            "NewRound" => self.new_round(data, &mut events)?,
+            "DealTile" => {
+                let seat = u32_field(data, "seat")?;
+                self.reach_accepted(data, &mut events);
+                self.new_doras(data, &mut events)?;
+                let tile = str_field(data, "tile")?;
+                events.push(json!({"type": "tsumo", "actor": seat, "pai": to_mjai(tile)?}));
+            }
+            "DiscardTile" => {
+                let seat = u32_field(data, "seat")?;
+                let riichi = data["is_liqi"].as_bool().unwrap_or_default()
+                    || data["is_wliqi"].as_bool().unwrap_or_default();
+                if riichi {
+                    events.push(json!({"type": "reach", "actor": seat}));
+                }
+                events.push(json!({
+                    "type": "dahai",
+                    "actor": seat,
+                    "pai": to_mjai(str_field(data, "tile")?)?,
+                    "tsumogiri": data["moqie"].as_bool().unwrap_or_default(),
+                }));
+                self.new_doras(data, &mut events)?;
+                self.last_discard = seat;
+            }
+            "ChiPengGang" => {
+                self.reach_accepted(data, &mut events);
+                let seat = u32_field(data, "seat")?;
+                let tiles = str_list(data, "tiles");
+                let froms = u32_list(data, "froms");
+                let called = froms
+                    .iter()
+                    .position(|f| *f != seat)
+                    .ok_or(anyhow!("No called tile in ChiPengGang"))?;
+                let consumed: Vec<&str> = tiles
+                    .iter()
+                    .enumerate()
+                    .filter(|(i, _)| *i != called)
+                    .map(|(_, t)| *t)
+                    .collect();
+                let kind = match u32_field(data, "type")? {
+                    0 => "chi",
+                    1 => "pon",
+                    _ => "daiminkan",
+                };
+                events.push(json!({
+                    "type": kind,
+                    "actor": seat,
+                    "target": froms[called],
+                    "pai": to_mjai(tiles[called])?,
+                    "consumed": mjai_list(&consumed)?,
+                }));
+            }
+            "AnGangAddGang" => {
+                let seat = u32_field(data, "seat")?;
+                let tile = str_field(data, "tiles")?;
+                let kind = u32_field(data, "type")?;
+                let others = kan_tiles(tile)?;
+                if kind == 3 {
+                    let mut consumed = vec![tile];
+                    consumed.extend(others);
+                    events.push(json!({
+                        "type": "ankan",
+                        "actor": seat,
+                        "consumed": mjai_list(&consumed)?,
+                    }));
+                } else {
+                    events.push(json!({
+                        "type": "kakan",
+                        "actor": seat,
+                        "pai": to_mjai(tile)?,
+                        "consumed": mjai_list(&others)?,
+                    }));
+                }
+                self.new_doras(data, &mut events)?;
+            }
+            "BaBei" => {
+                // mjai extension used by sanma bots
+                let seat = u32_field(data, "seat")?;
+                events.push(json!({"type": "nukidora", "actor": seat, "pai": "N"}));
+                self.new_doras(data, &mut events)?;
+            }
+            "Hule" => {
+                let hules = data["hules"]
+                    .as_array()
+                    .ok_or(anyhow!("hules field invalid"))?;
+                for hule in hules {
+                    let seat = u32_field(hule, "seat")?;
+                    let zimo = hule["zimo"].as_bool().unwrap_or_default();
+                    // doras revealed by a rinshan win are only listed in the hule
+                    self.new_doras(hule, &mut events)?;
+                    events.push(json!({
+                        "type": "hora",
+                        "actor": seat,
+                        "target": if zimo { seat } else { self.last_discard },
+                        "pai": to_mjai(str_field(hule, "hu_tile")?)?,
+                        "ura_markers": mjai_list(&str_list(hule, "li_doras"))?,
+                        "deltas": data["delta_scores"],
+                        "scores": data["scores"],
+                    }));
+                }
+                self.end_kyoku(data["gameend"].is_object(), &mut events);
+            }
+            "NoTile" => {
+                let mut deltas = vec![0i64; self.players];
+                for score in data["scores"].as_array().into_iter().flatten() {
+                    for (i, d) in score["delta_scores"]
+                        .as_array()
+                        .into_iter()
+                        .flatten()
+                        .enumerate()
+                    {
+                        if let (Some(d), Some(acc)) = (d.as_i64(), deltas.get_mut(i)) {
+                            *acc += d;
+                        }
+                    }
+                }
+                events.push(json!({"type": "ryukyoku", "deltas": deltas}));
+                self.end_kyoku(data["gameend"].as_bool().unwrap_or_default(), &mut events);
+            }
+            "LiuJu" => {
+                events.push(json!({"type": "ryukyoku"}));
+                self.end_kyoku(data["gameend"].is_object(), &mut events);
+            }
+            _ => {}
+        }
+        Ok(events)
+    }
+
+    /// Emit `end_game` if a game is in progress, used when the game end notify arrives.
+    pub fn end_game(&mut self) -> Vec<JsonValue> {
+        if !self.started {
+            return vec![];
+        }
+        self.started = false;
+        vec![json!({"type": "end_game"})]
+    }
+
+    fn new_round(&mut self, data: &JsonValue, events: &mut Vec<JsonValue>) -> Result<()> {
+        let scores = data["scores"]
+            .as_array()
+            .ok_or(anyhow!("scores field invalid"))?;
+        let chang = u32_field(data, "chang")?;
+        let ju = u32_field(data, "ju")?;
+        let own = str_list(data, "tiles");
+        if self.seat.is_none() && own.len() == 14 {
+            // only the dealer starts with 14 tiles
+            self.seat = Some(ju);
+        }
+        if !self.started {
+            self.started = true;
+            events.push(json!({
+                "type": "start_game",
+                "id": self.seat.unwrap_or_default(),
+                "names": self.names,
+                "account_ids": self.account_ids,
+            }));
+        }
+        let mut first_tsumo = "?".to_string();
+        let mut tehais = Vec::with_capacity(scores.len());
+        for seat in 0..scores.len() as u32 {
+            let mut hand = str_list(data, &format!("tiles{}", seat));
+            if hand.is_empty() && self.seat == Some(seat) {
+                hand.clone_from(&own);
+            }
+            let mut hand = if hand.is_empty() {
+                vec!["?".to_string(); 13]
+            } else {
+                mjai_list(&hand)?
+            };
+            if hand.len() == 14 {
+                first_tsumo = hand.pop().unwrap_or_default();
+            }
+            tehais.push(hand);
+        }
+        let doras = match str_list(data, "doras") {
+            d if d.is_empty() => vec![str_field(data, "dora")?],
+            d => d,
+        };
+        self.doras = doras.len();
+        self.players = scores.len();
+        events.push(json!({
+            "type": "start_kyoku",
+            "bakaze": WINDS[chang as usize % 4],
+            "dora_marker": to_mjai(doras[0])?,
+            "kyoku": ju + 1,
+            "honba": data["ben"],
+            "kyotaku": data["liqibang"],
+            "oya": ju,
+            "scores": scores,
+            "tehais": tehais,
+        }));
+        events.push(json!({"type": "tsumo", "actor": ju, "pai": first_tsumo}));
+        Ok(())
+    }
+
+    fn reach_accepted(&mut self, data: &JsonValue, events: &mut Vec<JsonValue>) {
+        if let Some(liqi) = data.get("liqi").filter(|l| l.is_object()) {
+            let seat = liqi["seat"].as_u64().unwrap_or_default();
+            events.push(json!({"type": "reach_accepted", "actor": seat}));
+        }
+    }
+
+    fn new_doras(&mut self, data: &JsonValue, events: &mut Vec<JsonValue>) -> Result<()> {
+        let doras = str_list(data, "doras");
+        for dora in doras.iter().skip(self.doras) {
+            events.push(json!({"type": "dora", "dora_marker": to_mjai(dora)?}));
+        }
+        self.doras = self.doras.max(doras.len());
+        Ok(())
+    }
+
+    fn end_kyoku(&mut self, game_end: bool, events: &mut Vec<JsonValue>) {
+        events.push(json!({"type": "end_kyoku"}));
+        if game_end {
+            events.extend(self.end_game());
+        }
+    }
+}
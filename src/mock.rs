@@ -0,0 +1,287 @@
+//! A stand-in for the game server, for end-to-end tests of sinks and other
+//! consumers of the proxy without the real game: [`MockServer`] replays a
+//! [`Recording`] over a websocket in liqi framing, answers requests with the
+//! recorded responses, and can drop the connection to test reconnects.
+//! [`connect_through`] opens the client side through a running proxy, so the
+//! frames go the way the game's do.
+
+use crate::{
+    base::BaseMessage,
+    dump::{read_frames, DUMP_MAGIC},
+};
+use anyhow::{anyhow, bail, ensure, Result};
+use bytes::Bytes;
+use hudsucker::{
+    futures::{SinkExt, StreamExt},
+    tokio_tungstenite::{accept_async, client_async, tungstenite::Message, WebSocketStream},
+};
+use prost::Message as _;
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    ops::Range,
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+    task::JoinHandle,
+    time::{interval, MissedTickBehavior},
+};
+
+/// Frames of the fake game of `simulate fake-game`, accounts and names made up.
+const SAMPLE: &[u8] = include_bytes!("../samples/fake_game.dump");
+
+/// A notify frame, `.lq.ActionPrototype` for example.
+pub fn notify(method: &str, data: Vec<u8>) -> Bytes {
+    frame(&[1], method, data)
+}
+
+/// A request frame of the method `.lq.FastTest.authGame` for example.
+pub fn request(id: u16, method: &str, data: Vec<u8>) -> Bytes {
+    let [a, b] = id.to_le_bytes();
+    frame(&[2, a, b], method, data)
+}
+
+/// A response frame, naming no method as the game's don't.
+pub fn response(id: u16, data: Vec<u8>) -> Bytes {
+    let [a, b] = id.to_le_bytes();
+    frame(&[3, a, b], "", data)
+}
+
+fn frame(head: &[u8], method: &str, data: Vec<u8>) -> Bytes {
+    let envelope = BaseMessage {
+        method_name: method.to_string(),
+        data,
+    };
+    let mut buf = head.to_vec();
+    envelope.encode(&mut buf).expect("a Vec grows as needed");
+    buf.into()
+}
+
+/// What a server sent in a recorded session.
+#[derive(Debug, Clone, Default)]
+pub struct Recording {
+    /// streamed in order
+    pub notifies: Vec<Bytes>,
+    /// the envelope of the response to each method, after the type and id
+    pub responses: HashMap<String, Bytes>,
+}
+
+impl Recording {
+    /// The frames of a binary dump of a `raw` file sink, its responses being
+    /// kept with their method. Requests, sent by the client, are skipped.
+    pub fn from_dump(bytes: &[u8]) -> Result<Self> {
+        ensure!(
+            bytes.starts_with(DUMP_MAGIC),
+            "Not a binary dump, set `binary` on the file sink"
+        );
+        let mut recording = Recording::default();
+        for frame in read_frames(bytes) {
+            let buf = frame.buf?;
+            match (buf.first(), frame.method) {
+                (Some(1), _) => recording.notifies.push(buf.into()),
+                (Some(3), Some(method)) if buf.len() >= 3 => {
+                    recording
+                        .responses
+                        .insert(method, Bytes::from(buf).slice(3..));
+                }
+                _ => (),
+            }
+        }
+        ensure!(!recording.notifies.is_empty(), "No notifies in the dump");
+        Ok(recording)
+    }
+
+    /// The bundled fake game: a hanchan of four made-up players.
+    pub fn sample() -> Self {
+        Recording::from_dump(SAMPLE).expect("the bundled sample dump")
+    }
+
+    /// The frame answering the request `buf`, the recorded response to its
+    /// method, else an empty one, i.e. the response message with defaults.
+    fn answer(&self, buf: &[u8]) -> Option<Bytes> {
+        let [2, a, b, envelope @ ..] = buf else {
+            return None;
+        };
+        let method = BaseMessage::decode(envelope).ok()?.method_name;
+        let mut out = vec![3, *a, *b];
+        match self.responses.get(&method) {
+            Some(envelope) => out.extend_from_slice(envelope),
+            None => BaseMessage::default().encode(&mut out).ok()?,
+        }
+        Some(out.into())
+    }
+}
+
+/// How [`MockServer`] replays, by default the recording once as fast as it can.
+#[derive(Debug, Clone, Default)]
+pub struct MockOptions {
+    /// notifies sent per second, 0 for as fast as the client takes them
+    pub rate: u64,
+    /// notifies sent in all, looping over the recording, none for it once
+    pub frames: Option<usize>,
+    /// close the websocket after this many notifies, the next connection
+    /// going on from there as the game server does after a reconnect
+    pub disconnect_every: Option<usize>,
+}
+
+/// A notify as it left the server, to time its way through the proxy.
+#[derive(Debug, Clone)]
+pub struct Sent {
+    /// counted over all connections
+    pub index: usize,
+    pub frame: Bytes,
+    pub at: Instant,
+}
+
+/// A mock game server on localhost, stopped when dropped.
+pub struct MockServer {
+    addr: SocketAddr,
+    task: JoinHandle<()>,
+}
+
+impl MockServer {
+    /// Listen on a free port of localhost, serving one connection at a time.
+    /// The receiver yields the notifies as they are sent.
+    pub async fn start(
+        recording: Recording,
+        options: MockOptions,
+    ) -> Result<(Self, UnboundedReceiver<Sent>)> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let (tx, sent) = unbounded_channel();
+        let task = tokio::spawn(serve(listener, recording, options, tx));
+        Ok((MockServer { addr, task }, sent))
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Where the game would connect, e.g. `ws://127.0.0.1:40000/gateway`.
+    pub fn url(&self) -> String {
+        format!("ws://{}/gateway", self.addr)
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn serve(
+    listener: TcpListener,
+    recording: Recording,
+    options: MockOptions,
+    sent: UnboundedSender<Sent>,
+) {
+    let total = options.frames.unwrap_or(recording.notifies.len());
+    let mut next = 0;
+    while let Ok((stream, _)) = listener.accept().await {
+        let Ok(ws) = accept_async(stream).await else {
+            continue;
+        };
+        let until = options
+            .disconnect_every
+            .map_or(total, |every| total.min(next + every));
+        match replay(
+            ws,
+            &recording,
+            options.rate,
+            next..until,
+            until < total,
+            &sent,
+        )
+        .await
+        {
+            Ok(()) => next = until,
+            // the client went away midway, the next connection gets the range again
+            Err(_) => continue,
+        }
+    }
+}
+
+/// Send the notifies `range` on `ws` while answering its requests, then
+/// close it to `hang_up`, else wait for the client to close it.
+async fn replay(
+    mut ws: WebSocketStream<TcpStream>,
+    recording: &Recording,
+    rate: u64,
+    range: Range<usize>,
+    hang_up: bool,
+    sent: &UnboundedSender<Sent>,
+) -> Result<()> {
+    let notifies = &recording.notifies;
+    // a tick a millisecond, the notifies due by then sent together
+    let mut ticks = interval(Duration::from_millis(1));
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Burst);
+    let started = Instant::now();
+    let mut next = range.start;
+    while next < range.end {
+        tokio::select! {
+            _ = ticks.tick() => {
+                let due = match rate {
+                    0 => range.end,
+                    rate => range.start + (started.elapsed().as_secs_f64() * rate as f64) as usize,
+                };
+                while next < due.min(range.end) {
+                    let frame = notifies[next % notifies.len()].clone();
+                    let _ = sent.send(Sent { index: next, frame: frame.clone(), at: Instant::now() });
+                    ws.feed(Message::Binary(frame.to_vec())).await?;
+                    next += 1;
+                }
+                ws.flush().await?;
+            }
+            message = ws.next() => match message {
+                Some(Ok(Message::Binary(buf))) => {
+                    if let Some(answer) = recording.answer(&buf) {
+                        ws.send(Message::Binary(answer.to_vec())).await?;
+                    }
+                }
+                Some(Ok(_)) => (),
+                _ => bail!("The client closed the connection"),
+            }
+        }
+    }
+    if hang_up {
+        ws.close(None).await?;
+        return Ok(());
+    }
+    while let Some(message) = ws.next().await {
+        if let Message::Binary(buf) = message? {
+            if let Some(answer) = recording.answer(&buf) {
+                ws.send(Message::Binary(answer.to_vec())).await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// A websocket to `url` through the proxy at `proxy`, as the game opens it.
+pub async fn connect_through(proxy: SocketAddr, url: &str) -> Result<WebSocketStream<TcpStream>> {
+    let uri: hudsucker::hyper::Uri = url.parse()?;
+    let authority = uri
+        .authority()
+        .ok_or(anyhow!("No host in {}", url))?
+        .as_str();
+    let mut stream = TcpStream::connect(proxy).await?;
+    stream
+        .write_all(format!("CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n\r\n", authority).as_bytes())
+        .await?;
+    let mut head = vec![];
+    while !head.ends_with(b"\r\n\r\n") {
+        head.push(stream.read_u8().await?);
+    }
+    ensure!(
+        head.starts_with(b"HTTP/1.1 200"),
+        "The proxy refused the tunnel: {}",
+        String::from_utf8_lossy(&head).trim()
+    );
+    let (ws, _) = client_async(url, stream)
+        .await
+        .map_err(|e| anyhow!("Failed to open {}: {}", url, e))?;
+    Ok(ws)
+}
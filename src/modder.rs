@@ -6,7 +6,7 @@ use crate::{
     settings::ModSettings,
     sheets,
 };
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, ensure, Result};
 use bytes::Bytes;
 use const_format::formatcp;
 use once_cell::sync::Lazy;
@@ -71,6 +71,14 @@ fn to_vec<T: Message + std::default::Default>(buf: &[Vec<u8>]) -> Vec<T> {
         .collect()
 }
 
+/// The id after the type byte of a request or response.
+fn msg_id(buf: &[u8]) -> Result<u16> {
+    match buf {
+        [_, lo, hi, ..] => Ok(u16::from_le_bytes([*lo, *hi])),
+        _ => Err(anyhow!("Too short for a message id")),
+    }
+}
+
 pub struct ModifyResult {
     pub msg: Option<Bytes>,
     pub inject_msg: Option<Bytes>,
@@ -129,12 +137,12 @@ impl Modder {
 
     pub async fn modify(&self, buf: Vec<u8>, from_client: bool) -> ModifyResult {
         let buf = Bytes::from(buf);
-        let msg_type = buf[0];
-        let res = match msg_type {
-            0x01 => self.modify_notify(buf.clone()).await,
-            0x02 => self.modify_req(buf.clone(), from_client).await,
-            0x03 => self.modify_res(buf.clone(), from_client).await,
-            _ => Err(anyhow!("Unimplemented message type: {}", msg_type)),
+        let res = match buf.first() {
+            Some(0x01) => self.modify_notify(buf.clone()).await,
+            Some(0x02) => self.modify_req(buf.clone(), from_client).await,
+            Some(0x03) => self.modify_res(buf.clone(), from_client).await,
+            Some(msg_type) => Err(anyhow!("Unimplemented message type: {}", msg_type)),
+            None => Err(anyhow!("Empty message")),
         };
         if let Err(e) = PARSER.write().await.parse(buf.clone()) {
             error!("Mod: Failed to parse message: {:?}", e);
@@ -152,16 +160,16 @@ impl Modder {
     }
 
     async fn modify_res(&self, buf: Bytes, from_client: bool) -> Result<ModifyResult> {
-        let msg_id = u16::from_le_bytes([buf[1], buf[2]]) as usize;
+        ensure!(!from_client, "Response message from client");
+        let msg_id = msg_id(&buf)?;
         let mut msg_block = BaseMessage::decode(&buf[3..])?;
-        assert!(!from_client);
         if !msg_block.method_name.is_empty() {
             return Err(anyhow!("Non-empty respond method name"));
         }
-        if !PARSER.read().await.respond_type.contains_key(&msg_id) {
-            return Err(anyhow!("No request message with id: {}", msg_id));
-        }
-        let method_name = PARSER.read().await.respond_type[&msg_id].0.clone();
+        let method_name = match PARSER.read().await.respond_type.get(msg_id) {
+            Some((method_name, _)) => method_name.clone(),
+            None => return Err(anyhow!("No request message with id: {}", msg_id)),
+        };
         let mut modified_data: Option<Vec<u8>> = None;
         match method_name.as_ref() {
             ".lq.Lobby.fetchAccountInfo" => {
@@ -482,14 +490,11 @@ impl Modder {
     }
 
     async fn modify_req(&self, buf: Bytes, from_client: bool) -> Result<ModifyResult> {
-        let msg_id = u16::from_le_bytes([buf[1], buf[2]]) as usize;
-        let mut msg_block = BaseMessage::decode(&buf[3..])?;
         // Request message must be from client
-        assert!(from_client);
-        if msg_id >= 1 << 16 {
-            return Err(anyhow!("Invalid request message id: {}", msg_id));
-        }
-        if PARSER.read().await.respond_type.contains_key(&msg_id) {
+        ensure!(from_client, "Request message from server");
+        let msg_id = msg_id(&buf)?;
+        let mut msg_block = BaseMessage::decode(&buf[3..])?;
+        if PARSER.read().await.respond_type.contains(msg_id) {
             return Err(anyhow!("Duplicate request message id: {}", msg_id));
         }
         let mut fake = false;
@@ -679,16 +684,13 @@ impl Modder {
                 }
                 modified_data = Some(msg.encode_to_vec());
             }
-            ".lq.NotifyCustomContestSystemMsg" => {
-                if MOD_SETTINGS.read().await.show_server() {
-                    let mut msg =
-                        lq::NotifyCustomContestSystemMsg::decode(msg_block.data.as_ref())?;
-                    if let Some(ref mut game) = msg.game_start {
-                        game.players.iter_mut().for_each(|p| {
-                            p.nickname = add_zone_id(p.account_id, &p.nickname);
-                        });
-                        modified_data = Some(msg.encode_to_vec());
-                    }
+            ".lq.NotifyCustomContestSystemMsg" if MOD_SETTINGS.read().await.show_server() => {
+                let mut msg = lq::NotifyCustomContestSystemMsg::decode(msg_block.data.as_ref())?;
+                if let Some(ref mut game) = msg.game_start {
+                    game.players.iter_mut().for_each(|p| {
+                        p.nickname = add_zone_id(p.account_id, &p.nickname);
+                    });
+                    modified_data = Some(msg.encode_to_vec());
                 }
             }
             _ => {}
@@ -771,3 +773,19 @@ fn to_var_int(mut x: u64) -> Bytes {
     }
     data.to_le_bytes()[..length].to_vec().into()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn short_and_misdirected_frames_are_errors() {
+        let modder = Modder::default();
+        let short = |b: &'static [u8]| Bytes::from_static(b);
+        assert!(modder.modify_res(short(&[3]), false).await.is_err());
+        assert!(modder.modify_res(short(&[3, 1, 0]), true).await.is_err());
+        assert!(modder.modify_req(short(&[2, 1]), true).await.is_err());
+        assert!(modder.modify_req(short(&[2, 1, 0]), false).await.is_err());
+        assert_eq!(msg_id(&[2, 0x34, 0x12]).unwrap(), 0x1234);
+    }
+}
@@ -0,0 +1,147 @@
+use crate::{
+    events::final_scores, instance, players::GameInfo, sink::Event, state::GameState, SETTINGS,
+};
+use anyhow::Result;
+use chrono::Local;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value as JsonValue};
+use std::{collections::BTreeMap, path::PathBuf};
+use tokio::sync::RwLock;
+use tracing::warn;
+
+/// Notes and past games by opponent account id, kept in `opponents.json` of the config dir.
+pub static NOTES: Lazy<RwLock<Notes>> = Lazy::new(|| RwLock::new(Notes::load()));
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Encounter {
+    pub game_uuid: Option<String>,
+    pub date: String,
+    /// 1 for the top
+    pub own_rank: u32,
+    pub rank: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Opponent {
+    /// latest nickname seen
+    pub nickname: String,
+    #[serde(default)]
+    pub note: String,
+    #[serde(default)]
+    pub games: Vec<Encounter>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct HeadToHead {
+    pub games: usize,
+    /// games finished above the opponent
+    pub wins: usize,
+    pub losses: usize,
+}
+
+/// Data of `.helper.SeenBefore`
+#[derive(Serialize, Debug, Clone)]
+pub struct SeenBefore {
+    pub seat: u32,
+    pub account_id: u32,
+    pub nickname: String,
+    pub note: String,
+    pub head_to_head: HeadToHead,
+    pub last_game: Option<Encounter>,
+}
+
+#[derive(Debug, Default)]
+pub struct Notes {
+    path: PathBuf,
+    players: BTreeMap<u32, Opponent>,
+}
+
+impl Notes {
+    fn load() -> Self {
+        let path = instance::suffixed(&SETTINGS.config_dir().join("opponents.json"));
+        let players = match std::fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_else(|e| {
+                warn!("Failed to parse {}: {:?}", path.display(), e);
+                BTreeMap::new()
+            }),
+            Err(_) => BTreeMap::new(),
+        };
+        Notes { path, players }
+    }
+
+    fn save(&self) -> Result<()> {
+        std::fs::write(&self.path, serde_json::to_vec_pretty(&self.players)?)?;
+        Ok(())
+    }
+
+    pub fn get(&self, account_id: u32) -> Option<&Opponent> {
+        self.players.get(&account_id)
+    }
+
+    pub fn set_note(&mut self, account_id: u32, note: String) -> Result<()> {
+        self.players.entry(account_id).or_default().note = note;
+        self.save()
+    }
+
+    /// `.helper.SeenBefore` for every opponent with a note or a past game.
+    pub fn encounter(&self, game: &GameInfo) -> Vec<Event> {
+        game.players
+            .iter()
+            .filter(|p| p.seat != game.seat && p.account_id != 0)
+            .filter_map(|p| {
+                let known = self.players.get(&p.account_id)?;
+                let wins = known.games.iter().filter(|g| g.own_rank < g.rank).count();
+                let seen = SeenBefore {
+                    seat: p.seat,
+                    account_id: p.account_id,
+                    nickname: p.nickname.clone(),
+                    note: known.note.clone(),
+                    head_to_head: HeadToHead {
+                        games: known.games.len(),
+                        wins,
+                        losses: known.games.len() - wins,
+                    },
+                    last_game: known.games.last().cloned(),
+                };
+                Some(Event::derived(".helper.SeenBefore", json!(seen)))
+            })
+            .collect()
+    }
+
+    /// Remember the placements of an own game from its `.lq.NotifyGameEndResult`.
+    pub fn record_game(&mut self, state: &GameState, end: &JsonValue) -> Result<()> {
+        let (Some(game), Some(seat)) = (state.game.as_ref(), state.seat) else {
+            return Ok(());
+        };
+        let mut scores = final_scores(end, state);
+        scores.sort_by_key(|s| (-s.total_point, s.seat));
+        let rank_of = |seat: u32| {
+            scores
+                .iter()
+                .position(|s| s.seat == seat)
+                .map(|i| i as u32 + 1)
+        };
+        let Some(own_rank) = rank_of(seat) else {
+            return Ok(());
+        };
+        let date = Local::now().to_rfc3339();
+        for player in &game.players {
+            if player.seat == seat || player.account_id == 0 {
+                continue;
+            }
+            let Some(rank) = rank_of(player.seat) else {
+                continue;
+            };
+            let opponent = self.players.entry(player.account_id).or_default();
+            opponent.nickname.clone_from(&player.nickname);
+            opponent.games.push(Encounter {
+                game_uuid: game.game_uuid.clone(),
+                date: date.clone(),
+                own_rank,
+                rank,
+            });
+        }
+        self.save()
+    }
+}
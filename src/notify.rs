@@ -0,0 +1,119 @@
+use crate::sink::Event;
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use tracing::error;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum NotifyKind {
+    /// a ranked match or friend room game starts
+    MatchFound,
+    /// own win of mangan or more
+    BigWin,
+    /// own deal-in of mangan or more
+    BigDealIn,
+    /// own final placement
+    GameEnd,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct NotifyConfig {
+    #[serde(default = "default_kinds")]
+    pub events: Vec<NotifyKind>,
+}
+
+fn default_kinds() -> Vec<NotifyKind> {
+    vec![
+        NotifyKind::MatchFound,
+        NotifyKind::BigWin,
+        NotifyKind::BigDealIn,
+        NotifyKind::GameEnd,
+    ]
+}
+
+/// Shows desktop notifications for a few events of own games, requires the `notify` feature.
+pub struct Notifier {
+    kinds: Vec<NotifyKind>,
+}
+
+impl Notifier {
+    pub fn new(config: NotifyConfig) -> Option<Self> {
+        if cfg!(not(feature = "notify")) {
+            error!("Desktop notification support is not compiled in");
+            return None;
+        }
+        Some(Notifier {
+            kinds: config.events,
+        })
+    }
+
+    /// Notify if `event` is one of the configured kinds, `seat` being the own seat.
+    pub fn feed(&self, event: &Event, seat: Option<u32>) {
+        if event.spectated {
+            return;
+        }
+        if let Some((kind, body)) = message(event, seat) {
+            if self.kinds.contains(&kind) {
+                show(body);
+            }
+        }
+    }
+}
+
+fn message(event: &Event, seat: Option<u32>) -> Option<(NotifyKind, String)> {
+    let data = &event.msg.data;
+    match event.msg.method_name.as_ref() {
+        ".lq.NotifyMatchGameStart" | ".lq.NotifyRoomGameStart" => {
+            Some((NotifyKind::MatchFound, "对局开始".to_string()))
+        }
+        ".helper.HandSummary" => {
+            let seat = seat?;
+            let wins = data["wins"].as_array()?;
+            let big = |w: &&JsonValue| !w["limit"].as_str().unwrap_or_default().is_empty();
+            if let Some(win) = wins.iter().filter(big).find(|w| w["seat"] == seat) {
+                return Some((NotifyKind::BigWin, format!("和了 {}", describe(win))));
+            }
+            let win = wins.iter().filter(big).find(|w| w["from"] == seat)?;
+            Some((NotifyKind::BigDealIn, format!("放铳 {}", describe(win))))
+        }
+        ".helper.Session" => {
+            let last = data["history"].as_array()?.last()?;
+            Some((
+                NotifyKind::GameEnd,
+                format!(
+                    "对局结束：第{}位，{:+.1}",
+                    last["rank"],
+                    last["total_point"].as_i64().unwrap_or_default() as f64 / 1000.0
+                ),
+            ))
+        }
+        _ => None,
+    }
+}
+
+/// e.g. `跳満 12000点`
+fn describe(win: &JsonValue) -> String {
+    format!(
+        "{} {}点",
+        win["limit"].as_str().unwrap_or_default(),
+        win["points"]
+    )
+}
+
+#[cfg(feature = "notify")]
+fn show(body: String) {
+    // blocks on dbus or the toast API
+    tokio::task::spawn_blocking(move || {
+        if let Err(e) = notify_rust::Notification::new()
+            .summary(env!("CARGO_PKG_NAME"))
+            .body(&body)
+            .show()
+        {
+            error!("Failed to show notification: {:?}", e);
+        }
+    });
+}
+
+#[cfg(not(feature = "notify"))]
+fn show(_body: String) {}
@@ -1,143 +1,540 @@
-use anyhow::{anyhow, ensure, Result};
+use anyhow::{anyhow, bail, ensure, Context, Result};
 use base64::prelude::*;
-use bytes::Bytes;
+use bytes::{BufMut, Bytes, BytesMut};
+use once_cell::sync::OnceCell;
 use prost::Message;
-use prost_reflect::{DescriptorPool, DynamicMessage, MessageDescriptor, SerializeOptions};
+use prost_reflect::{
+    DescriptorPool, DeserializeOptions, DynamicMessage, MessageDescriptor, SerializeOptions, Value,
+};
+use serde::{Deserialize, Serialize};
 use serde_json::{value::Serializer, Value as JsonValue};
-use std::{collections::HashMap, sync::Arc};
+use std::{cell::RefCell, collections::HashMap, fmt, sync::Arc};
 
-use crate::{base::BaseMessage, SETTINGS};
+use crate::{
+    base::BaseMessage,
+    interest,
+    memory::{Charge, Consumer},
+    SETTINGS,
+};
+use tracing::warn;
 
 const SERIALIZE_OPTIONS: SerializeOptions = SerializeOptions::new()
     .skip_default_fields(false)
     .use_proto_field_name(true);
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Eq)]
 pub enum MessageType {
     Notify = 1,
     Request = 2,
     Response = 3,
 }
 
+impl MessageType {
+    pub fn name(&self) -> &'static str {
+        match self {
+            MessageType::Notify => "notify",
+            MessageType::Request => "request",
+            MessageType::Response => "response",
+        }
+    }
+}
+
+/// What the parser does with a response whose request it never saw, e.g.
+/// after the proxy restarted in the middle of a session.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum OrphanResponses {
+    /// fail with [`ParseError::Orphan`], logged as a warning by the proxy
+    #[default]
+    Skip,
+    /// decode it as the response type its fields fit best
+    Guess,
+    /// fail with [`ParseError::Orphan`], logged as an error
+    Error,
+}
+
+/// Why a frame could not be parsed, counted by kind for the status.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseError {
+    /// empty, or too short for a message id
+    Truncated,
+    /// the first byte is not of a notify, request or response
+    InvalidType,
+    /// the method name and data around the body don't decode
+    Envelope,
+    /// the method, message or action is not in liqi, e.g. after a client update
+    DescriptorMiss,
+    /// the body or the action in it doesn't decode as its message
+    Body,
+    /// a response whose request was not seen
+    Orphan,
+}
+
+impl ParseError {
+    pub const ALL: [ParseError; 6] = [
+        ParseError::Truncated,
+        ParseError::InvalidType,
+        ParseError::Envelope,
+        ParseError::DescriptorMiss,
+        ParseError::Body,
+        ParseError::Orphan,
+    ];
+
+    /// Key of the kind in the status.
+    pub fn name(self) -> &'static str {
+        match self {
+            ParseError::Truncated => "truncated",
+            ParseError::InvalidType => "invalid_type",
+            ParseError::Envelope => "envelope",
+            ParseError::DescriptorMiss => "descriptor_miss",
+            ParseError::Body => "body",
+            ParseError::Orphan => "orphan",
+        }
+    }
+
+    /// The kind of a failed [`Parser::parse`], none for other errors.
+    pub fn of(e: &anyhow::Error) -> Option<Self> {
+        e.downcast_ref().copied()
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ParseError::Truncated => "Frame truncated",
+            ParseError::InvalidType => "Invalid message type",
+            ParseError::Envelope => "Invalid message envelope",
+            ParseError::DescriptorMiss => "Unknown to liqi",
+            ParseError::Body => "Invalid message body",
+            ParseError::Orphan => "No corresponding request",
+        })
+    }
+}
+
+impl std::error::Error for ParseError {}
+
 #[derive(Debug)]
 pub struct LiqiMessage {
     pub id: usize,
     pub msg_type: MessageType,
     pub method_name: Arc<str>,
     pub data: JsonValue,
+    /// `data` as JSON text, serialized once for every sink on first use
+    pub serialized: OnceCell<Bytes>,
+}
+
+impl LiqiMessage {
+    /// `data` as JSON text, shared instead of serialized again by every sink.
+    pub fn json(&self) -> Result<Bytes> {
+        self.serialized
+            .get_or_try_init(|| to_json_bytes(&self.data))
+            .cloned()
+    }
+}
+
+/// Descriptors of a method or notify, resolved on its first message.
+#[derive(Debug, Clone)]
+struct MethodDescriptors {
+    /// shared by every message of the method instead of allocated per frame
+    name: Arc<str>,
+    /// the notify, or the request of a method
+    message: MessageDescriptor,
+    /// none for notifies
+    response: Option<MessageDescriptor>,
+}
+
+/// Method name and response type of a request waiting for its response.
+pub type Pending = (Arc<str>, MessageDescriptor);
+
+/// Requests waiting for their responses, in a slot per message id.
+///
+/// Message ids are u16 counters that wrap around, so an id still pending when
+/// the client reuses it belongs to a request that never got its response and
+/// is replaced.
+#[derive(Debug)]
+pub struct PendingRequests {
+    slots: Box<[Option<Pending>]>,
+    waiting: usize,
+    /// the requests waiting; the table itself is the same for every session
+    /// and freed with it, so it is left out
+    charge: Charge,
+}
+
+impl Default for PendingRequests {
+    fn default() -> Self {
+        Self {
+            slots: vec![None; 1 << 16].into_boxed_slice(),
+            waiting: 0,
+            charge: Charge::new(Consumer::Pending, 0),
+        }
+    }
+}
+
+impl PendingRequests {
+    /// Wait for the response of `id`, returns the request it replaces.
+    pub fn insert(&mut self, id: u16, pending: Pending) -> Option<Pending> {
+        let replaced = self.slots[id as usize].replace(pending);
+        if replaced.is_none() {
+            self.count(1);
+        }
+        replaced
+    }
+
+    pub fn get(&self, id: u16) -> Option<&Pending> {
+        self.slots[id as usize].as_ref()
+    }
+
+    pub fn contains(&self, id: u16) -> bool {
+        self.slots[id as usize].is_some()
+    }
+
+    /// The request `id` answered by its response.
+    pub fn take(&mut self, id: u16) -> Option<Pending> {
+        let taken = self.slots[id as usize].take();
+        if taken.is_some() {
+            self.count(-1);
+        }
+        taken
+    }
+
+    fn count(&mut self, delta: isize) {
+        self.waiting = self.waiting.saturating_add_signed(delta);
+        self.charge
+            .set(self.waiting * std::mem::size_of::<Option<Pending>>());
+    }
+
+    /// Ids and methods of the requests waiting.
+    pub fn iter(&self) -> impl Iterator<Item = (u16, &Arc<str>)> {
+        self.slots
+            .iter()
+            .enumerate()
+            .filter_map(|(id, slot)| Some((id as u16, &slot.as_ref()?.0)))
+    }
 }
 
 #[derive(Debug)]
 pub struct Parser {
     total: usize,
-    pub respond_type: HashMap<usize, (Arc<str>, MessageDescriptor)>,
+    pub respond_type: PendingRequests,
+    /// by method name, e.g. `.lq.Lobby.login` or `.lq.ActionPrototype`
+    methods: HashMap<Arc<str>, MethodDescriptors>,
+    /// by action name, e.g. `ActionDiscardTile`
+    actions: HashMap<Arc<str>, MessageDescriptor>,
     proto_json: &'static JsonValue,
     pub pool: &'static DescriptorPool,
+    /// only decode the bodies of methods the sinks or trackers read, see [`crate::interest`]
+    pub lazy: bool,
+    pub orphans: OrphanResponses,
+    /// response types to guess from, named by their method when only one has it
+    responses: OnceCell<Vec<Pending>>,
 }
 
 pub fn dyn_to_json(msg: DynamicMessage) -> Result<JsonValue> {
     Ok(msg.serialize_with_options(Serializer, &SERIALIZE_OPTIONS)?)
 }
 
+/// Serialize `value` straight into a buffer the sinks can share.
+pub fn to_json_bytes(value: &JsonValue) -> Result<Bytes> {
+    let mut writer = BytesMut::new().writer();
+    serde_json::to_writer(&mut writer, value)?;
+    Ok(writer.into_inner().freeze())
+}
+
 impl Default for Parser {
     fn default() -> Self {
+        Self::new(SETTINGS.proto_json(), SETTINGS.desc())
+    }
+}
+
+impl Parser {
+    /// A parser of other descriptors than the loaded settings', e.g. in benches.
+    pub fn new(proto_json: &'static JsonValue, pool: &'static DescriptorPool) -> Self {
         Self {
             total: 0,
-            respond_type: HashMap::new(),
-            proto_json: &SETTINGS.proto_json,
-            pool: &SETTINGS.desc,
+            respond_type: PendingRequests::default(),
+            methods: HashMap::new(),
+            actions: HashMap::new(),
+            proto_json,
+            pool,
+            lazy: false,
+            orphans: OrphanResponses::default(),
+            responses: OnceCell::new(),
         }
     }
-}
 
-impl Parser {
-    pub fn parse(&mut self, buf: Bytes) -> Result<LiqiMessage> {
-        let msg_type_byte = buf[0];
+    /// Frames parsed so far.
+    pub fn parsed(&self) -> usize {
+        self.total
+    }
+
+    /// Descriptors of the notify `method_name`, e.g. `.lq.ActionPrototype`.
+    fn notify(&mut self, method_name: &str) -> Result<MethodDescriptors> {
+        if let Some(descriptors) = self.methods.get(method_name) {
+            return Ok(descriptors.clone());
+        }
+        let message_name = method_name
+            .split('.')
+            .nth(2)
+            .ok_or(anyhow!("Invalid notify name: {}", method_name))?;
+        let message = self
+            .pool
+            .get_message_by_name(&to_fqn(message_name))
+            .ok_or(anyhow!("Invalid message type: {}", message_name))?;
+        let descriptors = MethodDescriptors {
+            name: Arc::from(method_name),
+            message,
+            response: None,
+        };
+        self.methods
+            .insert(descriptors.name.clone(), descriptors.clone());
+        Ok(descriptors)
+    }
+
+    /// Descriptors of the method `method_name`, e.g. `.lq.Lobby.login`.
+    fn method(&mut self, method_name: &str) -> Result<MethodDescriptors> {
+        if let Some(descriptors) = self.methods.get(method_name) {
+            return Ok(descriptors.clone());
+        }
+        let method_name_list: Vec<&str> = method_name.split('.').collect();
         ensure!(
-            (1..=3).contains(&msg_type_byte),
-            "Invalid message type: {}",
-            msg_type_byte
+            method_name_list.len() == 4,
+            "Invalid method name: {}",
+            method_name
         );
+        let lq = method_name_list[1];
+        let service = method_name_list[2];
+        let rpc = method_name_list[3];
+        let proto_domain = &self.proto_json["nested"][lq]["nested"][service]["methods"][rpc];
+        let req_type_name = &proto_domain["requestType"]
+            .as_str()
+            .ok_or(anyhow!("Invalid request type"))?;
+        let message = self
+            .pool
+            .get_message_by_name(&to_fqn(req_type_name))
+            .ok_or(anyhow!("Invalid request type: {}", req_type_name))?;
+        let res_type_name = proto_domain["responseType"]
+            .as_str()
+            .ok_or(anyhow!("Invalid response type"))?;
+        let response = self
+            .pool
+            .get_message_by_name(&to_fqn(res_type_name))
+            .ok_or(anyhow!("Invalid response type: {}", res_type_name))?;
+        let descriptors = MethodDescriptors {
+            name: Arc::from(method_name),
+            message,
+            response: Some(response),
+        };
+        self.methods
+            .insert(descriptors.name.clone(), descriptors.clone());
+        Ok(descriptors)
+    }
+
+    /// Descriptor of the action `name`, e.g. `ActionDiscardTile`.
+    fn action(&mut self, name: &str) -> Result<MessageDescriptor> {
+        if let Some(descriptor) = self.actions.get(name) {
+            return Ok(descriptor.clone());
+        }
+        let descriptor = self
+            .pool
+            .get_message_by_name(&to_fqn(name))
+            .ok_or(anyhow!("Invalid action type: {}", name))?;
+        self.actions.insert(Arc::from(name), descriptor.clone());
+        Ok(descriptor)
+    }
+
+    /// The type of the request, or the response, of the method `method_name`.
+    pub fn body_type(&mut self, method_name: &str, response: bool) -> Result<MessageDescriptor> {
+        let descriptors = self.method(method_name)?;
+        match response {
+            true => descriptors
+                .response
+                .ok_or(anyhow!("No response type: {}", method_name)),
+            false => Ok(descriptors.message),
+        }
+    }
+
+    /// Decode the response `id` as one of `method`, for responses whose request
+    /// was not seen, e.g. in dumps.
+    pub fn expect_response(&mut self, id: u16, method: &str) -> Result<()> {
+        let descriptors = self.method(method)?;
+        let response = descriptors
+            .response
+            .ok_or(anyhow!("No response type: {}", method))?;
+        self.respond_type.insert(id, (descriptors.name, response));
+        Ok(())
+    }
+
+    /// The response type `data` fits best: the one decoding the most fields
+    /// with none unknown, named by its method, or by itself, e.g.
+    /// `lq.ResCommon`, when several methods answer with it. Fails when
+    /// several fit as well, rather than guessing among them.
+    fn guess_response(&self, data: &[u8]) -> Result<Pending> {
+        let responses = self.responses.get_or_init(|| {
+            let mut methods: HashMap<&str, Vec<String>> = HashMap::new();
+            let services = self.proto_json["nested"]["lq"]["nested"].as_object();
+            for (service, methods_json) in services
+                .into_iter()
+                .flatten()
+                .filter_map(|(name, s)| Some((name, s["methods"].as_object()?)))
+            {
+                for (rpc, method) in methods_json {
+                    if let Some(response) = method["responseType"].as_str() {
+                        let name = format!(".lq.{}.{}", service, rpc);
+                        methods.entry(response).or_default().push(name);
+                    }
+                }
+            }
+            let mut responses: Vec<Pending> = methods
+                .into_iter()
+                .filter_map(|(response, methods)| {
+                    let descriptor = self.pool.get_message_by_name(&to_fqn(response))?;
+                    let name = match &methods[..] {
+                        [method] => Arc::from(method.as_str()),
+                        _ => Arc::from(descriptor.full_name()),
+                    };
+                    Some((name, descriptor))
+                })
+                .collect();
+            // the same guess for the same fields every time
+            responses.sort_by(|a, b| a.0.cmp(&b.0));
+            responses
+        });
+        let fits: Vec<(usize, &Pending)> = responses
+            .iter()
+            .filter_map(|response| {
+                let msg = DynamicMessage::decode(response.1.clone(), data).ok()?;
+                Some((known_fields(&msg)?, response))
+            })
+            .collect();
+        let most = fits.iter().map(|(count, _)| *count).max().unwrap_or(0);
+        ensure!(most > 0, "No response type fits its fields");
+        let best: Vec<&Pending> = fits
+            .into_iter()
+            .filter(|(count, _)| *count == most)
+            .map(|(_, response)| response)
+            .collect();
+        match best[..] {
+            [response] => Ok(response.clone()),
+            _ => {
+                let names: Vec<&str> = best.iter().take(3).map(|(name, _)| &**name).collect();
+                bail!(
+                    "{} response types fit its fields equally, e.g. {}",
+                    best.len(),
+                    names.join(", ")
+                )
+            }
+        }
+    }
+
+    /// Whether the body of `method` is decoded, else its data is null.
+    fn wanted(&self, method: &str) -> bool {
+        !self.lazy || interest::wanted(method)
+    }
+
+    /// Parse a frame, failing with a [`ParseError`] as the outermost context.
+    pub fn parse(&mut self, buf: Bytes) -> Result<LiqiMessage> {
+        let msg_type_byte = *buf.first().ok_or(ParseError::Truncated)?;
         let msg_type = match msg_type_byte {
             1 => MessageType::Notify,
             2 => MessageType::Request,
             3 => MessageType::Response,
-            _ => unreachable!(),
+            _ => {
+                return Err(anyhow!("Type byte {}", msg_type_byte).context(ParseError::InvalidType))
+            }
         };
+        if msg_type != MessageType::Notify && buf.len() < 3 {
+            return Err(anyhow!("Too short for a message id").context(ParseError::Truncated));
+        }
         let method_name: Arc<str>;
         let mut data_obj: JsonValue;
         let msg_id: usize;
         match msg_type {
             MessageType::Notify => {
-                let msg_block = BaseMessage::decode(&buf[1..])?;
+                let msg_block = BaseMessage::decode(&buf[1..]).context(ParseError::Envelope)?;
                 let data = msg_block.data;
-                method_name = Arc::from(msg_block.method_name);
-                let method_name_list: Vec<&str> = method_name.split('.').collect();
-                let message_name = method_name_list[2];
-                let message_type = self
-                    .pool
-                    .get_message_by_name(&to_fqn(message_name))
-                    .ok_or(anyhow!("Invalid message type: {}", message_name))?;
-                let dyn_msg = DynamicMessage::decode(message_type, data.as_ref())?;
-                data_obj = dyn_to_json(dyn_msg)?;
+                let descriptors = self
+                    .notify(&msg_block.method_name)
+                    .context(ParseError::DescriptorMiss)?;
+                method_name = descriptors.name;
+                data_obj = match self.wanted(&method_name) {
+                    true => decode_body(descriptors.message, &data)?,
+                    false => JsonValue::Null,
+                };
                 if let Some(b64) = data_obj.get("data") {
                     let action_name = data_obj
                         .get("name")
                         .and_then(|n| n.as_str())
-                        .ok_or(anyhow!("name field invalid"))?;
+                        .ok_or(anyhow!("name field invalid"))
+                        .context(ParseError::Body)?;
                     let b64 = b64.as_str().unwrap_or_default();
-                    let action_obj = decode_action(action_name, b64, self.pool)?;
+                    let action_type = self
+                        .action(action_name)
+                        .context(ParseError::DescriptorMiss)?;
+                    let action_obj =
+                        decode_action_with(action_type, b64).context(ParseError::Body)?;
                     data_obj
                         .as_object_mut()
-                        .ok_or(anyhow!("data is not an object"))?
+                        .ok_or(anyhow!("data is not an object"))
+                        .context(ParseError::Body)?
                         .insert("data".to_string(), action_obj);
                 }
                 msg_id = self.total;
             }
             MessageType::Request => {
                 // little endian, msg_id = unpack("<H", buf[1:3])[0]
-                msg_id = u16::from_le_bytes([buf[1], buf[2]]) as usize;
-                let msg_block = BaseMessage::decode(&buf[3..])?;
+                let id = u16::from_le_bytes([buf[1], buf[2]]);
+                msg_id = id as usize;
+                let msg_block = BaseMessage::decode(&buf[3..]).context(ParseError::Envelope)?;
                 let data = msg_block.data;
-                method_name = Arc::from(msg_block.method_name);
-                let method_name_list: Vec<&str> = method_name.split('.').collect();
-                let lq = method_name_list[1];
-                let service = method_name_list[2];
-                let rpc = method_name_list[3];
-                let proto_domain =
-                    &self.proto_json["nested"][lq]["nested"][service]["methods"][rpc];
-                let req_type_name = &proto_domain["requestType"]
-                    .as_str()
-                    .ok_or(anyhow!("Invalid request type"))?;
-                let req_type = self
-                    .pool
-                    .get_message_by_name(&to_fqn(req_type_name))
-                    .ok_or(anyhow!("Invalid request type: {}", req_type_name))?;
-                let dyn_msg = DynamicMessage::decode(req_type, data.as_ref())?;
-                data_obj = dyn_to_json(dyn_msg)?;
-                let res_type_name = proto_domain["responseType"]
-                    .as_str()
-                    .ok_or(anyhow!("Invalid response type"))?;
-                let resp_type = self
-                    .pool
-                    .get_message_by_name(&to_fqn(res_type_name))
-                    .ok_or(anyhow!("Invalid response type: {}", res_type_name))?;
-                self.respond_type
-                    .insert(msg_id, (method_name.clone(), resp_type));
+                let descriptors = self
+                    .method(&msg_block.method_name)
+                    .context(ParseError::DescriptorMiss)?;
+                method_name = descriptors.name;
+                data_obj = match self.wanted(&method_name) {
+                    true => decode_body(descriptors.message, &data)?,
+                    false => JsonValue::Null,
+                };
+                let resp_type = descriptors
+                    .response
+                    .ok_or(anyhow!("No response type: {}", method_name))
+                    .context(ParseError::DescriptorMiss)?;
+                if let Some((unanswered, _)) = self
+                    .respond_type
+                    .insert(id, (method_name.clone(), resp_type))
+                {
+                    warn!(
+                        "Request {} left unanswered, its id {} reused by {}",
+                        unanswered, id, method_name
+                    );
+                }
             }
             MessageType::Response => {
-                msg_id = u16::from_le_bytes([buf[1], buf[2]]) as usize;
-                let msg_block = BaseMessage::decode(&buf[3..])?;
+                let id = u16::from_le_bytes([buf[1], buf[2]]);
+                msg_id = id as usize;
+                let msg_block = BaseMessage::decode(&buf[3..]).context(ParseError::Envelope)?;
                 let data = msg_block.data;
                 let method = msg_block.method_name;
-                assert!(method.is_empty());
+                if !method.is_empty() {
+                    return Err(anyhow!("Response with a method name: {}", method)
+                        .context(ParseError::Envelope));
+                }
                 let resp_type: MessageDescriptor;
-                (method_name, resp_type) = self
-                    .respond_type
-                    .remove(&msg_id)
-                    .ok_or(anyhow!("No corresponding request"))?;
-                let dyn_msg = DynamicMessage::decode(resp_type, data.as_ref())?;
-                data_obj = dyn_to_json(dyn_msg)?;
+                (method_name, resp_type) = match self.respond_type.take(id) {
+                    Some(pending) => pending,
+                    None if self.orphans == OrphanResponses::Guess => {
+                        let guessed = self.guess_response(&data).context(ParseError::Orphan)?;
+                        warn!(
+                            "Request of response {} not seen, guessed {} from its fields",
+                            id, guessed.0
+                        );
+                        guessed
+                    }
+                    None => return Err(ParseError::Orphan.into()),
+                };
+                data_obj = match self.wanted(&method_name) {
+                    true => decode_body(resp_type, &data)?,
+                    false => JsonValue::Null,
+                };
             }
         }
         self.total += 1;
@@ -146,31 +543,440 @@ impl Parser {
             msg_type,
             method_name,
             data: data_obj,
+            serialized: OnceCell::new(),
         })
     }
 }
 
+/// Fields set in `msg` and the messages in it, none when any is unknown.
+fn known_fields(msg: &DynamicMessage) -> Option<usize> {
+    if msg.unknown_fields().next().is_some() {
+        return None;
+    }
+    fn nested(value: &Value) -> Option<usize> {
+        match value {
+            Value::Message(msg) => known_fields(msg),
+            Value::List(values) => values.iter().map(nested).sum(),
+            Value::Map(map) => map.values().map(nested).sum(),
+            _ => Some(0),
+        }
+    }
+    msg.fields()
+        .map(|(_, value)| Some(1 + nested(value)?))
+        .sum()
+}
+
+/// The body of a message as JSON.
+fn decode_body(desc: MessageDescriptor, data: &[u8]) -> Result<JsonValue> {
+    DynamicMessage::decode(desc, data)
+        .map_err(anyhow::Error::from)
+        .and_then(dyn_to_json)
+        .context(ParseError::Body)
+}
+
 pub fn to_fqn(method_name: &str) -> String {
     format!("lq.{}", method_name)
 }
 
+pub fn str_field<'a>(data: &'a JsonValue, key: &str) -> Result<&'a str> {
+    data.get(key)
+        .and_then(|v| v.as_str())
+        .ok_or(anyhow!("{} field invalid", key))
+}
+
+pub fn u32_field(data: &JsonValue, key: &str) -> Result<u32> {
+    data.get(key)
+        .and_then(|v| v.as_u64())
+        .map(|v| v as u32)
+        .ok_or(anyhow!("{} field invalid", key))
+}
+
+pub fn str_list<'a>(data: &'a JsonValue, key: &str) -> Vec<&'a str> {
+    data.get(key)
+        .and_then(|v| v.as_array())
+        .map(|a| a.iter().filter_map(|t| t.as_str()).collect())
+        .unwrap_or_default()
+}
+
+pub fn u32_list(data: &JsonValue, key: &str) -> Vec<u32> {
+    data.get(key)
+        .and_then(|v| v.as_array())
+        .map(|a| {
+            a.iter()
+                .filter_map(|f| f.as_u64())
+                .map(|f| f as u32)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+pub fn i32_list(data: &JsonValue, key: &str) -> Vec<i32> {
+    data.get(key)
+        .and_then(|v| v.as_array())
+        .map(|a| {
+            a.iter()
+                .filter_map(|f| f.as_i64())
+                .map(|f| f as i32)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
 pub fn decode_action(name: &str, data: &str, pool: &DescriptorPool) -> Result<JsonValue> {
-    let mut decoded = BASE64_STANDARD.decode(data)?;
-    wtf_decode(&mut decoded);
     let action_type = pool
         .get_message_by_name(&to_fqn(name))
         .ok_or(anyhow!("Invalid action type: {}", name))?;
-    let action_msg = DynamicMessage::decode(action_type, Bytes::from(decoded))?;
-    dyn_to_json(action_msg)
-}
-
-fn wtf_decode(data: &mut [u8]) {
-    const KEYS: [usize; 9] = [0x84, 0x5E, 0x4E, 0x42, 0x39, 0xA2, 0x1F, 0x60, 0x1C];
-    let d = data.len();
-    KEYS.iter()
-        .cycle()
-        .zip(data.iter_mut())
-        .enumerate()
-        .map(|(i, (key, b))| (((23 ^ d) + 5 * i + key) & 255, b))
-        .for_each(|(k, b)| *b ^= k as u8);
+    decode_action_with(action_type, data)
+}
+
+/// Decode the obfuscated base64 `data` of an action of type `action_type`.
+fn decode_action_with(action_type: MessageDescriptor, data: &str) -> Result<JsonValue> {
+    with_base64(data, |decoded| {
+        wtf_decode(decoded);
+        dyn_to_json(DynamicMessage::decode(action_type, decoded.as_slice())?)
+    })
+}
+
+thread_local! {
+    /// Base64 output of the actions decoded on this thread, kept between them.
+    static BASE64_BUF: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Capacity above which the buffer is not kept, e.g. after a whole record.
+const BASE64_BUF_MAX: usize = 1 << 20;
+
+/// Call `f` with `data` base64-decoded into the buffer of this thread, a new
+/// one when it is already in use.
+fn with_base64<T>(data: &str, f: impl FnOnce(&mut Vec<u8>) -> Result<T>) -> Result<T> {
+    BASE64_BUF.with(|buf| {
+        let mut fresh = Vec::new();
+        let mut kept = buf.try_borrow_mut();
+        let buf = match kept.as_deref_mut() {
+            Ok(buf) => buf,
+            Err(_) => &mut fresh,
+        };
+        buf.clear();
+        BASE64_STANDARD.decode_vec(data, buf)?;
+        let res = f(buf);
+        if buf.capacity() > BASE64_BUF_MAX {
+            *buf = Vec::new();
+        }
+        res
+    })
+}
+
+/// Decode a `lq.Wrapper` and the message inside, returning e.g. `("RecordNewRound", data)`.
+pub fn decode_wrapper(data: &[u8], pool: &DescriptorPool) -> Result<(String, JsonValue)> {
+    let wrapper_type = pool
+        .get_message_by_name("lq.Wrapper")
+        .ok_or(anyhow!("No lq.Wrapper in descriptors"))?;
+    let wrapper = DynamicMessage::decode(wrapper_type, data)?;
+    let name = wrapper
+        .get_field_by_name("name")
+        .and_then(|n| n.as_str().map(|n| n.trim_start_matches(".lq.").to_string()))
+        .ok_or(anyhow!("Wrapper has no name"))?;
+    let inner = wrapper
+        .get_field_by_name("data")
+        .and_then(|d| d.as_bytes().cloned())
+        .unwrap_or_default();
+    let inner_type = pool
+        .get_message_by_name(&to_fqn(&name))
+        .ok_or(anyhow!("Invalid wrapped type: {}", name))?;
+    let json = dyn_to_json(DynamicMessage::decode(inner_type, inner)?)?;
+    Ok((name, json))
+}
+
+/// Actions of a game record, `data` being the base64 `ResGameRecord.data`.
+pub fn record_actions(data: &str, pool: &DescriptorPool) -> Result<Vec<(String, JsonValue)>> {
+    record_bytes_actions(&BASE64_STANDARD.decode(data)?, pool)
+}
+
+/// Generations of `GameDetailRecords`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordFormat {
+    /// up to mid 2021, only the results in `records`
+    Records,
+    /// `actions` with the user inputs between the results, by `version`
+    Actions(u32),
+}
+
+impl RecordFormat {
+    pub fn detect(details: &JsonValue) -> Self {
+        match details["actions"].as_array() {
+            Some(actions) if !actions.is_empty() => {
+                RecordFormat::Actions(details["version"].as_u64().unwrap_or_default() as u32)
+            }
+            _ => RecordFormat::Records,
+        }
+    }
+}
+
+/// Same as [`record_actions`] for the raw record, e.g. downloaded from `data_url`.
+pub fn record_bytes_actions(
+    data: &[u8],
+    pool: &DescriptorPool,
+) -> Result<Vec<(String, JsonValue)>> {
+    let details = match decode_wrapper(data, pool) {
+        Ok((_, details)) => details,
+        // the oldest records are not wrapped
+        Err(e) => {
+            let details_type = pool
+                .get_message_by_name("lq.GameDetailRecords")
+                .ok_or(anyhow!("No lq.GameDetailRecords in descriptors"))?;
+            match DynamicMessage::decode(details_type, data) {
+                Ok(details) => dyn_to_json(details)?,
+                Err(_) => return Err(e),
+            }
+        }
+    };
+    let format = RecordFormat::detect(&details);
+    let results: Vec<&str> = match format {
+        RecordFormat::Actions(_) => details["actions"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|a| a["result"].as_str())
+            .collect(),
+        RecordFormat::Records => str_list(&details, "records"),
+    };
+    let mut actions = vec![];
+    for result in results.into_iter().filter(|r| !r.is_empty()) {
+        match with_base64(result, |bytes| Ok(decode_wrapper(bytes, pool)))? {
+            Ok(action) => actions.push(action),
+            // a kind of action liqi no longer has, the rest is still usable
+            Err(e) => warn!("Skipped an undecodable {:?} record action: {:?}", format, e),
+        }
+    }
+    if format == RecordFormat::Records {
+        fill_left_tiles(&mut actions);
+    }
+    Ok(actions)
+}
+
+/// Old records don't count the wall, count it from the draws.
+fn fill_left_tiles(actions: &mut [(String, JsonValue)]) {
+    let mut left = None;
+    for (name, data) in actions.iter_mut() {
+        match name.as_str() {
+            "RecordNewRound" => {
+                left = match data["left_tile_count"].as_u64() {
+                    Some(0) | None => {
+                        let players = data["scores"].as_array().map_or(4, Vec::len) as u64;
+                        let tiles = if players == 3 { 108 } else { 136 };
+                        // minus the dead wall and the deal
+                        Some(tiles - 14 - 13 * players - 1)
+                    }
+                    Some(_) => None,
+                };
+            }
+            "RecordDealTile" => {
+                if let Some(n) = left.as_mut() {
+                    *n = n.saturating_sub(1);
+                }
+            }
+            _ => continue,
+        }
+        if let Some(n) = left {
+            data["left_tile_count"] = n.into();
+        }
+    }
+}
+
+/// Encode JSON as `desc`, ignoring fields the message doesn't have.
+pub fn json_to_bytes(desc: MessageDescriptor, json: &JsonValue) -> Result<Vec<u8>> {
+    let options = DeserializeOptions::new().deny_unknown_fields(false);
+    Ok(DynamicMessage::deserialize_with_options(desc, json, &options)?.encode_to_vec())
+}
+
+/// The `data` of an `ActionPrototype` named `name`, the inverse of [`decode_action`].
+pub fn encode_action(name: &str, data: &JsonValue, pool: &DescriptorPool) -> Result<Vec<u8>> {
+    let action_type = pool
+        .get_message_by_name(&to_fqn(name))
+        .ok_or(anyhow!("Invalid action type: {}", name))?;
+    let mut encoded = json_to_bytes(action_type, data)?;
+    // xor, its own inverse
+    wtf_decode(&mut encoded);
+    Ok(encoded)
+}
+
+const KEYS: [u8; 9] = [0x84, 0x5E, 0x4E, 0x42, 0x39, 0xA2, 0x1F, 0x60, 0x1C];
+
+/// The key stream repeats as `5 * i` wraps at 256 and the keys at 9.
+const KEY_PERIOD: usize = 256 * KEYS.len();
+
+/// `5 * i + KEYS[i % 9]` over one period, before the start of the length is added.
+static KEY_STREAM: [u8; KEY_PERIOD] = {
+    let mut stream = [0; KEY_PERIOD];
+    let mut i = 0;
+    while i < KEY_PERIOD {
+        stream[i] = ((5 * i) as u8).wrapping_add(KEYS[i % KEYS.len()]);
+        i += 1;
+    }
+    stream
+};
+
+/// Undo the xor of action data, byte `i` being xored with
+/// `(23 ^ len) + 5 * i + KEYS[i % 9]`, its own inverse.
+pub fn wtf_decode(data: &mut [u8]) {
+    let start = (23 ^ data.len()) as u8;
+    if data.len() < 64 {
+        // most actions, too short for the vector loop to pay off: a row of
+        // 9 keys, each next 9 being 5 * 9 further
+        let mut row: [u8; 9] =
+            std::array::from_fn(|j| start.wrapping_add(5 * j as u8).wrapping_add(KEYS[j]));
+        for chunk in data.chunks_mut(KEYS.len()) {
+            for (b, k) in chunk.iter_mut().zip(row) {
+                *b ^= k;
+            }
+            for k in row.iter_mut() {
+                *k = k.wrapping_add(5 * KEYS.len() as u8);
+            }
+        }
+        return;
+    }
+    for period in data.chunks_mut(KEY_PERIOD) {
+        // a plain zip of slices, which the compiler turns into SIMD
+        for (b, k) in period.iter_mut().zip(&KEY_STREAM) {
+            *b ^= k.wrapping_add(start);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lq;
+    use once_cell::sync::Lazy;
+
+    static POOL: Lazy<DescriptorPool> = Lazy::new(|| {
+        DescriptorPool::decode(include_bytes!("../liqi_config/liqi.desc").as_slice()).unwrap()
+    });
+
+    static PROTO_JSON: Lazy<JsonValue> =
+        Lazy::new(|| serde_json::from_str(include_str!("../liqi_config/liqi.json")).unwrap());
+
+    fn parser() -> Parser {
+        Parser::new(&PROTO_JSON, &POOL)
+    }
+
+    fn frame(msg_type: MessageType, id: u16, method_name: &str, data: impl Message) -> Bytes {
+        let notify = msg_type == MessageType::Notify;
+        let mut buf = vec![msg_type as u8];
+        if !notify {
+            buf.extend(id.to_le_bytes());
+        }
+        let block = BaseMessage {
+            method_name: method_name.to_string(),
+            data: data.encode_to_vec(),
+        };
+        buf.extend(block.encode_to_vec());
+        Bytes::from(buf)
+    }
+
+    fn account_info() -> lq::ResAccountInfo {
+        lq::ResAccountInfo {
+            account: Some(lq::Account {
+                account_id: 10001,
+                nickname: "雀士".to_string(),
+                login_time: 1_700_000_000,
+                gold: 3000,
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn wtf_decode_matches_the_per_byte_formula() {
+        for len in [0, 1, 63, 64, 65, 2303, 2304, 2305] {
+            let input: Vec<u8> = (0..len).map(|i| (i * 7 + 3) as u8).collect();
+            let expected: Vec<u8> = input
+                .iter()
+                .enumerate()
+                .map(|(i, b)| {
+                    let key = ((23 ^ len) as u8)
+                        .wrapping_add((5 * i) as u8)
+                        .wrapping_add(KEYS[i % KEYS.len()]);
+                    b ^ key
+                })
+                .collect();
+            let mut data = input.clone();
+            wtf_decode(&mut data);
+            assert_eq!(data, expected, "length {}", len);
+            wtf_decode(&mut data);
+            assert_eq!(data, input, "length {}", len);
+        }
+    }
+
+    #[test]
+    fn pairs_responses_with_their_requests() {
+        let mut parser = parser();
+        let req = frame(
+            MessageType::Request,
+            7,
+            ".lq.Lobby.fetchAccountInfo",
+            lq::ReqAccountInfo { account_id: 10001 },
+        );
+        let msg = parser.parse(req).unwrap();
+        assert_eq!(msg.method_name.as_ref(), ".lq.Lobby.fetchAccountInfo");
+        assert_eq!(msg.data["account_id"], 10001);
+        assert_eq!(parser.respond_type.waiting, 1);
+        let msg = parser
+            .parse(frame(MessageType::Response, 7, "", account_info()))
+            .unwrap();
+        assert_eq!(msg.msg_type, MessageType::Response);
+        assert_eq!(msg.method_name.as_ref(), ".lq.Lobby.fetchAccountInfo");
+        assert_eq!(msg.data["account"]["nickname"], "雀士");
+        assert_eq!(parser.parsed(), 2);
+        assert_eq!(parser.respond_type.waiting, 0);
+        // each request is answered once
+        let again = parser.parse(frame(MessageType::Response, 7, "", account_info()));
+        assert_eq!(
+            ParseError::of(&again.unwrap_err()),
+            Some(ParseError::Orphan)
+        );
+    }
+
+    #[test]
+    fn classifies_broken_frames() {
+        let mut parser = parser();
+        let kind = |parser: &mut Parser, buf: &'static [u8]| {
+            ParseError::of(&parser.parse(Bytes::from_static(buf)).unwrap_err())
+        };
+        assert_eq!(kind(&mut parser, &[]), Some(ParseError::Truncated));
+        assert_eq!(kind(&mut parser, &[2, 1]), Some(ParseError::Truncated));
+        assert_eq!(kind(&mut parser, &[9, 0, 0]), Some(ParseError::InvalidType));
+        assert_eq!(kind(&mut parser, &[1, 0xff]), Some(ParseError::Envelope));
+        let unknown = frame(
+            MessageType::Notify,
+            0,
+            ".lq.NotifyNothing",
+            lq::ReqCommon {},
+        );
+        let e = parser.parse(unknown).unwrap_err();
+        assert_eq!(ParseError::of(&e), Some(ParseError::DescriptorMiss));
+        assert_eq!(parser.parsed(), 0);
+    }
+
+    #[test]
+    fn guesses_orphan_responses_from_their_fields() {
+        let mut parser = parser();
+        let orphan = frame(MessageType::Response, 3, "", account_info());
+        let e = parser.parse(orphan.clone()).unwrap_err();
+        assert_eq!(ParseError::of(&e), Some(ParseError::Orphan));
+        parser.orphans = OrphanResponses::Guess;
+        let msg = parser.parse(orphan).unwrap();
+        assert_eq!(msg.data["account"]["account_id"], 10001);
+        assert_eq!(msg.data["account"]["nickname"], "雀士");
+        assert_eq!(msg.method_name.as_ref(), ".lq.Lobby.fetchAccountInfo");
+        // a body no response type decodes, its field 2 holding a zero tag
+        let unknown = BaseMessage {
+            method_name: String::new(),
+            data: vec![0; 4],
+        };
+        let e = parser
+            .parse(frame(MessageType::Response, 4, "", unknown))
+            .unwrap_err();
+        assert_eq!(ParseError::of(&e), Some(ParseError::Orphan));
+    }
 }
@@ -0,0 +1,247 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+
+/// Majsoul rank, `id` such as 10301 for expert 1 in four player games.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Rank {
+    pub id: u32,
+    pub score: u32,
+    /// e.g. `雀杰1`
+    pub name: String,
+}
+
+impl Rank {
+    /// Parse an `AccountLevel`, `None` when absent.
+    pub fn from_level(level: &JsonValue) -> Option<Self> {
+        const MAJOR: [&str; 7] = ["初心", "雀士", "雀杰", "雀豪", "雀圣", "魂天", "魂天"];
+        let id = level["id"].as_u64()? as u32;
+        let major = (id / 100 % 100) as usize;
+        let name = match MAJOR.get(major.wrapping_sub(1)) {
+            Some(major) => format!("{}{}", major, id % 100),
+            None => String::new(),
+        };
+        Some(Rank {
+            id,
+            score: level["score"].as_u64().unwrap_or_default() as u32,
+            name,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct PlayerInfo {
+    pub seat: u32,
+    /// 0 for AI players
+    pub account_id: u32,
+    pub nickname: String,
+    pub rank: Option<Rank>,
+    /// rank in three player games
+    pub rank3: Option<Rank>,
+}
+
+impl PlayerInfo {
+    /// From an `AccountInfo`-like view, `None` for AI seats.
+    fn from_view(seat: u32, account_id: u32, view: Option<&JsonValue>) -> Self {
+        PlayerInfo {
+            seat,
+            account_id,
+            nickname: view
+                .and_then(|p| p["nickname"].as_str())
+                .unwrap_or("AI")
+                .to_string(),
+            rank: view.and_then(|p| Rank::from_level(&p["level"])),
+            rank3: view.and_then(|p| Rank::from_level(&p["level3"])),
+        }
+    }
+
+    /// Rank of the game's player count.
+    pub fn rank_for(&self, players: usize) -> Option<&Rank> {
+        if players == 3 {
+            self.rank3.as_ref()
+        } else {
+            self.rank.as_ref()
+        }
+    }
+}
+
+/// `GameConfig.category`
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+#[serde(rename_all = "camelCase")]
+pub enum RoomType {
+    #[default]
+    Unknown,
+    Friend,
+    Ranked,
+    Contest,
+}
+
+impl RoomType {
+    fn from_category(category: u64) -> Self {
+        match category {
+            1 => RoomType::Friend,
+            2 => RoomType::Ranked,
+            4 => RoomType::Contest,
+            _ => RoomType::Unknown,
+        }
+    }
+}
+
+/// Event mode rules of `GameDetailRule`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum SpecialMode {
+    Guyi,
+    Dora3,
+    BeginOpen,
+    Jiuchao,
+    Muyu,
+    OpenHand,
+    Xuezhandaodi,
+    Huansanzhang,
+    Chuanma,
+    RevealDiscard,
+    FieldSpell,
+    Zhanxing,
+    Tianming,
+    Yongchang,
+    Hunzhiyiji,
+}
+
+const MODE_RULES: [(&str, SpecialMode); 15] = [
+    ("guyi_mode", SpecialMode::Guyi),
+    ("dora3_mode", SpecialMode::Dora3),
+    ("begin_open_mode", SpecialMode::BeginOpen),
+    ("jiuchao_mode", SpecialMode::Jiuchao),
+    ("muyu_mode", SpecialMode::Muyu),
+    ("open_hand", SpecialMode::OpenHand),
+    ("xuezhandaodi", SpecialMode::Xuezhandaodi),
+    ("huansanzhang", SpecialMode::Huansanzhang),
+    ("chuanma", SpecialMode::Chuanma),
+    ("reveal_discard", SpecialMode::RevealDiscard),
+    ("field_spell_mode", SpecialMode::FieldSpell),
+    ("zhanxing", SpecialMode::Zhanxing),
+    ("tianming_mode", SpecialMode::Tianming),
+    ("yongchang_mode", SpecialMode::Yongchang),
+    ("hunzhiyiji_mode", SpecialMode::Hunzhiyiji),
+];
+
+impl SpecialMode {
+    /// Modes that only change yaku, scoring or what is shown keep the tracked table right.
+    pub fn breaks_tracking(self) -> bool {
+        !matches!(
+            self,
+            SpecialMode::Guyi
+                | SpecialMode::Dora3
+                | SpecialMode::BeginOpen
+                | SpecialMode::Muyu
+                | SpecialMode::OpenHand
+        )
+    }
+}
+
+/// Players and room of a joined game, from the authGame response.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct GameInfo {
+    /// own seat
+    pub seat: u32,
+    pub game_uuid: Option<String>,
+    pub room: RoomType,
+    /// ranked room, e.g. 12 for the jade room south game
+    pub mode_id: u32,
+    /// friend room number
+    pub room_id: u32,
+    pub contest_uid: u32,
+    /// base seconds of every turn
+    pub time_fixed: u32,
+    /// seconds of the time bank
+    pub time_add: u32,
+    /// event modes of the room rules
+    #[serde(default)]
+    pub modes: Vec<SpecialMode>,
+    /// by seat
+    pub players: Vec<PlayerInfo>,
+}
+
+impl GameInfo {
+    /// Parse a `ResAuthGame`, AI seats are named `AI`.
+    pub fn from_auth(data: &JsonValue, seat: u32, game_uuid: Option<String>) -> Self {
+        let players = data["seat_list"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .enumerate()
+            .map(|(seat, id)| {
+                let id = id.as_u64().unwrap_or_default();
+                let view = data["players"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .find(|p| id != 0 && p["account_id"].as_u64() == Some(id));
+                PlayerInfo::from_view(seat as u32, id as u32, view)
+            })
+            .collect();
+        Self::from_config(&data["game_config"], seat, game_uuid, players)
+    }
+
+    /// Parse the head of a game record, a `RecordGame`, as seen from `seat`.
+    pub fn from_record(head: &JsonValue, seat: u32) -> Self {
+        let accounts = head["accounts"]
+            .as_array()
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        // AI seats have no account, but every seat has a final score
+        let seats = head["result"]["players"]
+            .as_array()
+            .map(Vec::len)
+            .filter(|n| *n > 0)
+            .unwrap_or(accounts.len());
+        let players = (0..seats as u32)
+            .map(|seat| {
+                let view = accounts
+                    .iter()
+                    .find(|a| a["seat"].as_u64() == Some(seat as u64));
+                let id = view
+                    .and_then(|a| a["account_id"].as_u64())
+                    .unwrap_or_default();
+                PlayerInfo::from_view(seat, id as u32, view)
+            })
+            .collect();
+        let game_uuid = head["uuid"].as_str().map(String::from);
+        Self::from_config(&head["config"], seat, game_uuid, players)
+    }
+
+    fn from_config(
+        config: &JsonValue,
+        seat: u32,
+        game_uuid: Option<String>,
+        players: Vec<PlayerInfo>,
+    ) -> Self {
+        let meta = &config["meta"];
+        let rule = &config["mode"]["detail_rule"];
+        GameInfo {
+            seat,
+            game_uuid,
+            room: RoomType::from_category(config["category"].as_u64().unwrap_or_default()),
+            mode_id: meta["mode_id"].as_u64().unwrap_or_default() as u32,
+            room_id: meta["room_id"].as_u64().unwrap_or_default() as u32,
+            contest_uid: meta["contest_uid"].as_u64().unwrap_or_default() as u32,
+            time_fixed: rule["time_fixed"].as_u64().unwrap_or_default() as u32,
+            time_add: rule["time_add"].as_u64().unwrap_or_default() as u32,
+            modes: MODE_RULES
+                .iter()
+                .filter(|(key, _)| rule[*key].as_u64().is_some_and(|v| v != 0))
+                .map(|(_, mode)| *mode)
+                .collect(),
+            players,
+        }
+    }
+
+    /// The game has actions the tracker doesn't understand, its analyses are unreliable.
+    pub fn degraded(&self) -> bool {
+        self.modes.iter().any(|m| m.breaks_tracking())
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.players.iter().map(|p| p.nickname.clone()).collect()
+    }
+}
@@ -0,0 +1,194 @@
+use crate::{
+    activity::ACTIVITY,
+    audit,
+    flight::{self, Recent},
+    helper::Frame,
+    isolation::{self, frame_head},
+    logging::PIPELINE,
+    parser::{LiqiMessage, OrphanResponses, ParseError, Parser},
+    SETTINGS,
+};
+use bytes::Bytes;
+use std::{
+    collections::HashMap,
+    fmt,
+    hash::{DefaultHasher, Hash, Hasher},
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+use tokio::sync::{
+    broadcast::error::RecvError,
+    mpsc::{channel, Receiver, Sender},
+};
+use tracing::{debug, debug_span, error, field, warn};
+
+/// Frames waiting for each parse task.
+const WORKER_QUEUE: usize = 100;
+/// Parsers of websockets quiet for this long are dropped when another one opens.
+const IDLE: Duration = Duration::from_secs(3600);
+
+/// A frame from the game parsed by the pool.
+pub struct Parsed {
+    pub msg: LiqiMessage,
+    pub raw: Bytes,
+    pub spectated: bool,
+    pub simulated: bool,
+}
+
+/// Parse frames on `workers` tasks, 0 for one per CPU core. Every websocket
+/// stays on one task with its own parser, so its messages keep their order
+/// and request ids of different connections don't mix.
+pub fn spawn(mut frames: Receiver<Frame>, workers: usize) -> Receiver<Parsed> {
+    let workers = match workers {
+        0 => std::thread::available_parallelism().map_or(1, |n| n.get()),
+        n => n,
+    };
+    let (tx, rx) = channel(WORKER_QUEUE * workers);
+    let senders: Vec<Sender<Frame>> = (0..workers)
+        .map(|_| {
+            let (worker_tx, worker_rx) = channel(WORKER_QUEUE);
+            tokio::spawn(worker(worker_rx, tx.clone()));
+            worker_tx
+        })
+        .collect();
+    tokio::spawn(async move {
+        while let Some(frame) = frames.recv().await {
+            ACTIVITY.frame();
+            ACTIVITY.queued(frames.len());
+            let mut hasher = DefaultHasher::new();
+            frame.session.hash(&mut hasher);
+            let worker = &senders[hasher.finish() as usize % senders.len()];
+            if worker.send(frame).await.is_err() {
+                error!("Parse worker stopped");
+            }
+        }
+    });
+    rx
+}
+
+/// The parser of one websocket and the frames it saw last.
+struct Session {
+    parser: Parser,
+    used: Instant,
+    recent: Recent,
+}
+
+/// A frame as text for the debug log, bytes outside printable ASCII in hex;
+/// only formatted when the log is enabled.
+struct Printable<'a>(&'a [u8]);
+
+impl fmt::Display for Printable<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for b in self.0 {
+            match b {
+                0x20..=0x7e => write!(f, "{}", *b as char)?,
+                _ => write!(f, "{:02x} ", b)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+fn new_parser() -> Parser {
+    let mut parser = Parser::default();
+    parser.lazy = true;
+    parser.orphans = SETTINGS.orphan_responses;
+    parser
+}
+
+async fn worker(mut frames: Receiver<Frame>, out: Sender<Parsed>) {
+    let mut sessions: HashMap<Option<SocketAddr>, Session> = HashMap::new();
+    let mut dumps = flight::requests();
+    loop {
+        let frame = tokio::select! {
+            frame = frames.recv() => match frame {
+                Some(frame) => frame,
+                None => return,
+            },
+            request = dumps.recv() => {
+                match request {
+                    Ok(request) => request.write(
+                        sessions
+                            .iter_mut()
+                            .map(|(addr, session)| (addr, &mut session.parser, &session.recent)),
+                    ),
+                    Err(RecvError::Lagged(_)) => (),
+                    Err(RecvError::Closed) => return,
+                }
+                continue;
+            }
+        };
+        if !sessions.contains_key(&frame.session) {
+            sessions.retain(|_, session| session.used.elapsed() < IDLE);
+        }
+        let session = sessions.entry(frame.session).or_insert_with(|| Session {
+            parser: new_parser(),
+            used: Instant::now(),
+            recent: Recent::default(),
+        });
+        session.used = Instant::now();
+        debug!("{} {}", frame.direction, Printable(&frame.buf));
+        let span =
+            debug_span!(target: PIPELINE, "parse", len = frame.buf.len(), method = field::Empty);
+        let parser = &mut session.parser;
+        let parsed = match isolation::catch(|| span.in_scope(|| parser.parse(frame.buf.clone()))) {
+            Ok(parsed) => parsed,
+            Err(panicked) => {
+                // its pending requests may be broken, the other sessions are
+                // not, and the frames that led here are kept for a dump
+                session.parser = new_parser();
+                session.recent.push(frame.buf.clone(), None);
+                session.recent.failed(format!("panicked: {}", panicked));
+                ACTIVITY.panicked();
+                error!(
+                    "Parser of session {:?} crashed, starting it over; {} frame of {} bytes: {}\n{}",
+                    frame.session,
+                    frame.direction,
+                    frame.buf.len(),
+                    frame_head(&frame.buf),
+                    panicked
+                );
+                continue;
+            }
+        };
+        let msg = match parsed {
+            Ok(msg) => msg,
+            Err(e) => {
+                session.recent.push(frame.buf.clone(), None);
+                session.recent.failed(format!("{:#}", e));
+                ACTIVITY.parse_failed(&e);
+                match (ParseError::of(&e), session.parser.orphans) {
+                    (Some(ParseError::Orphan), OrphanResponses::Skip) => {
+                        warn!("Skipped a response to an unseen request, the proxy may have restarted mid-session: {}", e)
+                    }
+                    _ => error!("Failed to parse message: {:?}", e),
+                }
+                continue;
+            }
+        };
+        session
+            .recent
+            .push(frame.buf.clone(), Some(msg.method_name.clone()));
+        span.record("method", msg.method_name.as_ref());
+        debug!(
+            "Method: {}, {}, {:?}, {}",
+            frame.direction, msg.id, msg.msg_type, msg.method_name
+        );
+        ACTIVITY.decoded(frame.direction, &msg.method_name, frame.spectated);
+        if let (Some(session), false) = (frame.session, frame.simulated) {
+            audit::record(session, &msg);
+        }
+        if frame.direction == '\u{2191}' {
+            continue;
+        }
+        let parsed = Parsed {
+            msg,
+            raw: frame.buf,
+            spectated: frame.spectated,
+            simulated: frame.simulated,
+        };
+        if out.send(parsed).await.is_err() {
+            return;
+        }
+    }
+}
@@ -0,0 +1,1124 @@
+use crate::{
+    anonymize::Anonymizer,
+    batch, daily,
+    export::{action_rows, hand_rows, ActionRow, HandRow, Table},
+    format::{game_actions, strip_action},
+    instance,
+    library::{Library, INDEX_FILE},
+    lobby::{LobbyClient, DEFAULT_GATEWAY},
+    memory::{self, json_size, Charge, Consumer},
+    mjai::MjaiConverter,
+    parser::{record_actions, record_bytes_actions, u32_list},
+    players::GameInfo,
+    secrets,
+    selection::{GameFacts, GameFilter},
+    settings::REQUEST_CLIENT,
+    simulate::{simulate, Speed},
+    sink::Event,
+    state::GameState,
+    store::Store,
+    tenhou::TenhouConverter,
+    tr,
+    verify::verify,
+    viewer,
+    watch::watch,
+    SETTINGS,
+};
+use anyhow::{anyhow, Result};
+use clap::{Subcommand, ValueEnum};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value as JsonValue};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tokio::time::MissedTickBehavior;
+use tracing::{error, info, warn};
+
+#[derive(Subcommand, Debug)]
+pub enum RecordCommand {
+    /// Download a replay by its uuid and write it decoded as JSON
+    Fetch {
+        /// game uuid, as in a replay link
+        uuid: String,
+        #[clap(flatten)]
+        login: LoginArgs,
+        /// output file, `<uuid>.json` by default
+        #[clap(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Download every game of the account not archived yet
+    Sync {
+        #[clap(flatten)]
+        login: LoginArgs,
+        /// records are stored as `<uuid>.json` here, with the progress in `sync.json`,
+        /// `records/` of the config folder by default
+        #[clap(short, long)]
+        dir: Option<PathBuf>,
+        /// games per list page
+        #[clap(long, default_value_t = 30)]
+        page_size: u32,
+        /// minimum delay between two requests to the lobby
+        #[clap(long, default_value_t = 1000)]
+        interval_ms: u64,
+        /// walk the whole list instead of stopping at the last synced game
+        #[clap(long)]
+        full: bool,
+        /// games to download, the others are left for a later `--full` sync
+        #[clap(flatten)]
+        filter: GameFilter,
+    },
+    /// Download every game of a custom contest, with the contest details
+    Contest {
+        /// contest id, as shown in the contest lobby
+        contest_id: u32,
+        /// an account of the contest, e.g. its manager
+        #[clap(flatten)]
+        login: LoginArgs,
+        /// records are stored as `<uuid>.json` here, with the details in `contest.json`,
+        /// `./contest_<id>/` by default
+        #[clap(short, long)]
+        dir: Option<PathBuf>,
+        /// minimum delay between two requests to the lobby
+        #[clap(long, default_value_t = 1000)]
+        interval_ms: u64,
+    },
+    /// Spectate friends' games as they start and archive each one when it ends
+    Watch {
+        #[clap(flatten)]
+        login: LoginArgs,
+        /// records are stored as `<uuid>.json` here, `records/` of the config
+        /// folder by default
+        #[clap(short, long)]
+        dir: Option<PathBuf>,
+        /// account id of a friend to watch, every friend by default
+        #[clap(long = "friend")]
+        friends: Vec<u32>,
+    },
+    /// Convert a record for other tools
+    Convert(ConvertArgs),
+    /// Add record files to the SQLite database, requires the `sqlite` feature
+    Import {
+        /// record files or folders of them, as written by `record fetch` or `record sync`
+        #[clap(required = true)]
+        inputs: Vec<PathBuf>,
+        /// database file, `database` of settings.json by default
+        #[clap(long)]
+        db: Option<PathBuf>,
+        /// own account, to fill in the own seat of each game
+        #[clap(long)]
+        account_id: Option<u32>,
+    },
+    /// Write record files as a table, one row per hand or per action
+    Export {
+        /// record files or folders of them
+        #[clap(required = true)]
+        inputs: Vec<PathBuf>,
+        #[clap(short, long, value_enum, default_value = "csv")]
+        format: ExportFormat,
+        #[clap(long, value_enum, default_value = "hands")]
+        table: ExportTable,
+        /// seat the riichi, calls, shanten and score change columns are for, 0 by default
+        #[clap(long)]
+        seat: Option<u32>,
+        /// use the seat of this account in each game, games without it are skipped
+        #[clap(long, conflicts_with = "seat")]
+        account_id: Option<u32>,
+        /// output file, `<table>.<format>` by default, e.g. `hands.csv`
+        #[clap(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Check record files for corrupted or truncated games before analyzing them
+    Verify {
+        /// record files or folders of them
+        #[clap(required = true)]
+        inputs: Vec<PathBuf>,
+    },
+    /// Strip identities from a record file so it can be shared
+    Anonymize {
+        input: PathBuf,
+        /// output file, `<new uuid>.json` by default
+        #[clap(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Write a standalone HTML page to step through a record file
+    Render {
+        input: PathBuf,
+        /// output file, `<uuid>.html` by default
+        #[clap(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Replay a record file through the helper as if it was played live,
+    /// feeding the sinks, the table tracker and the engine
+    Simulate {
+        input: PathBuf,
+        /// e.g. `4x` for four actions a second, `0` for no pause
+        #[clap(long, default_value = "1x")]
+        speed: Speed,
+        /// seat to play from, seat 0 by default
+        #[clap(long)]
+        seat: Option<u32>,
+        /// play from the seat of this account
+        #[clap(long, conflicts_with = "seat")]
+        account_id: Option<u32>,
+    },
+}
+
+#[derive(clap::Args, Debug)]
+pub struct ConvertArgs {
+    /// files written by `record fetch` or `record sync`, folders of them, or a
+    /// game uuid to download
+    #[clap(required = true)]
+    inputs: Vec<String>,
+    #[clap(short, long, value_enum)]
+    format: ConvertFormat,
+    /// seat the log is seen from, by default the logged in account's or else 0
+    #[clap(long)]
+    seat: Option<u32>,
+    /// needed only to download by uuid
+    #[clap(flatten)]
+    login: LoginArgs,
+    /// output file, `<uuid>.<ext>` by default; the output folder when
+    /// converting several files
+    #[clap(short, long)]
+    output: Option<PathBuf>,
+    /// games to convert out of folders or several files
+    #[clap(flatten)]
+    filter: GameFilter,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy)]
+pub enum ConvertFormat {
+    /// mjai events as JSON lines, as read by Mortal
+    Mjai,
+    /// a tenhou.net/6 JSON document, as read by tenhou compatible viewers
+    Tenhou6,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy)]
+pub enum ExportFormat {
+    /// comma separated values with a header line
+    Csv,
+    /// a Parquet file, as read by pandas and polars, needs the `parquet` feature
+    Parquet,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy)]
+pub enum ExportTable {
+    /// one row per hand, from the seat given
+    Hands,
+    /// one row per action, with the hand it is in and its data as JSON
+    Actions,
+}
+
+impl ExportFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Parquet => "parquet",
+        }
+    }
+}
+
+/// Rows of one record for `record export`.
+enum Rows {
+    Hands(Vec<HandRow>),
+    Actions(Vec<ActionRow>),
+}
+
+impl ConvertFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            ConvertFormat::Mjai => "jsonl",
+            ConvertFormat::Tenhou6 => "json",
+        }
+    }
+}
+
+#[derive(clap::Args, Debug)]
+pub struct LoginArgs {
+    /// access token of the web client, found in its local storage, the one
+    /// stored with `secret set token` when not given
+    #[clap(long)]
+    pub token: Option<String>,
+    /// store `--token` in the OS keychain for the next time
+    #[clap(long, requires = "token")]
+    pub save_token: bool,
+    /// oauth2 type of the token, 0 for a Mahjong Soul account
+    #[clap(long, default_value_t = 0)]
+    pub token_type: u32,
+    #[clap(long, default_value = DEFAULT_GATEWAY)]
+    pub gateway: String,
+}
+
+impl LoginArgs {
+    pub async fn connect(&self) -> Result<LobbyClient> {
+        let token = match &self.token {
+            Some(token) => {
+                if self.save_token {
+                    secrets::set(secrets::LOGIN_TOKEN, token)?;
+                }
+                token.clone()
+            }
+            None => secrets::get(secrets::LOGIN_TOKEN)
+                .ok()
+                .flatten()
+                .ok_or(anyhow!(
+                    "--token is needed to log in, or a token stored with `secret set token`"
+                ))?,
+        };
+        let mut lobby = LobbyClient::connect(&self.gateway).await?;
+        lobby.login(&token, self.token_type).await?;
+        Ok(lobby)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RecordAction {
+    /// e.g. `RecordNewRound`
+    pub name: String,
+    pub data: JsonValue,
+}
+
+/// A decoded game record: the `RecordGame` head and every action in order.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Record {
+    pub head: JsonValue,
+    pub actions: Vec<RecordAction>,
+}
+
+impl Record {
+    fn from_actions(head: JsonValue, actions: Vec<(String, JsonValue)>) -> Self {
+        Record {
+            head,
+            actions: actions
+                .into_iter()
+                .map(|(name, data)| RecordAction { name, data })
+                .collect(),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        Ok(serde_json::from_slice(&std::fs::read(path)?)?)
+    }
+
+    /// Seat of an account in this game.
+    pub fn seat_of(&self, account_id: u32) -> Option<u32> {
+        self.head["accounts"]
+            .as_array()?
+            .iter()
+            .find(|a| a["account_id"].as_u64() == Some(account_id as u64))
+            .and_then(|a| a["seat"].as_u64())
+            .map(|seat| seat as u32)
+    }
+
+    /// The whole game as mjai events seen from `seat`, with every hand open.
+    pub fn to_mjai(&self, seat: u32) -> Result<Vec<JsonValue>> {
+        let mut converter = MjaiConverter::default();
+        converter.set_game(&GameInfo::from_record(&self.head, seat));
+        let mut events = vec![];
+        for action in &self.actions {
+            events.extend(converter.feed(strip_action(&action.name), &action.data)?);
+        }
+        events.extend(converter.end_game());
+        Ok(events)
+    }
+
+    /// The whole game as a tenhou.net/6 document seen from `seat`.
+    pub fn to_tenhou(&self, seat: u32) -> Result<JsonValue> {
+        let mut converter = TenhouConverter::default();
+        converter.set_game(&GameInfo::from_record(&self.head, seat));
+        for action in &self.actions {
+            converter.feed(strip_action(&action.name), &action.data)?;
+        }
+        Ok(converter.document())
+    }
+
+    /// The converted file contents.
+    pub fn convert(&self, format: ConvertFormat, seat: u32) -> Result<String> {
+        Ok(match format {
+            ConvertFormat::Mjai => {
+                let mut lines = String::new();
+                for event in self.to_mjai(seat)? {
+                    lines.push_str(&serde_json::to_string(&event)?);
+                    lines.push('\n');
+                }
+                lines
+            }
+            ConvertFormat::Tenhou6 => serde_json::to_string(&self.to_tenhou(seat)?)?,
+        })
+    }
+}
+
+/// Where `sync` and `watch` archive records unless given a folder, one of each
+/// instance.
+pub fn default_dir() -> PathBuf {
+    instance::nested(SETTINGS.config_dir().join("records"))
+}
+
+pub async fn run(command: &RecordCommand) -> Result<()> {
+    match command {
+        RecordCommand::Fetch {
+            uuid,
+            login,
+            output,
+        } => {
+            let mut lobby = login.connect().await?;
+            let record = fetch(&mut lobby, uuid).await?;
+            let path = output
+                .clone()
+                .unwrap_or_else(|| PathBuf::from(format!("{}.json", record_uuid(uuid))));
+            tokio::fs::write(&path, serde_json::to_vec_pretty(&record)?).await?;
+            info!(
+                "Record with {} actions written to {}",
+                record.actions.len(),
+                path.display()
+            );
+            Ok(())
+        }
+        RecordCommand::Sync {
+            login,
+            dir,
+            page_size,
+            interval_ms,
+            full,
+            filter,
+        } => {
+            let mut lobby = login.connect().await?;
+            sync(
+                &mut lobby,
+                &dir.clone().unwrap_or_else(default_dir),
+                *page_size,
+                Duration::from_millis(*interval_ms),
+                *full,
+                filter,
+            )
+            .await
+        }
+        RecordCommand::Contest {
+            contest_id,
+            login,
+            dir,
+            interval_ms,
+        } => {
+            let mut lobby = login.connect().await?;
+            let dir = dir
+                .clone()
+                .unwrap_or_else(|| PathBuf::from(format!("./contest_{}/", contest_id)));
+            contest(
+                &mut lobby,
+                *contest_id,
+                &dir,
+                Duration::from_millis(*interval_ms),
+            )
+            .await
+        }
+        RecordCommand::Watch {
+            login,
+            dir,
+            friends,
+        } => {
+            let mut lobby = login.connect().await?;
+            let dir = dir.clone().unwrap_or_else(default_dir);
+            watch(&mut lobby, &dir, friends).await
+        }
+        RecordCommand::Convert(args) => convert(args).await,
+        RecordCommand::Import {
+            inputs,
+            db,
+            account_id,
+        } => {
+            let path = db.as_ref().or(SETTINGS.database.as_ref()).ok_or(anyhow!(
+                "No database given, set --db or database in settings.json"
+            ))?;
+            let store = Store::open(path)?;
+            let mut imported = 0;
+            for file in record_files(inputs)? {
+                let record = match Record::load(&file) {
+                    Ok(record) => record,
+                    Err(e) => {
+                        warn!("Skipped {}: {:?}", file.display(), e);
+                        continue;
+                    }
+                };
+                store.import(&record, account_id.and_then(|id| record.seat_of(id)));
+                imported += 1;
+            }
+            // blocks until the writer is done
+            tokio::task::spawn_blocking(move || store.close()).await?;
+            info!("{} records imported into {}", imported, path.display());
+            Ok(())
+        }
+        RecordCommand::Export {
+            inputs,
+            format,
+            table,
+            seat,
+            account_id,
+            output,
+        } => {
+            let files = record_files(inputs)?;
+            let (table, seat, account_id) = (*table, *seat, *account_id);
+            let rows = tokio::task::spawn_blocking(move || {
+                batch::run(&tr!("读取", "読み込み", "Reading"), &files, |file| {
+                    let record = Record::load(file)?;
+                    let seat = match account_id {
+                        Some(id) => record.seat_of(id),
+                        None => Some(seat.unwrap_or_default()),
+                    };
+                    Ok(match table {
+                        ExportTable::Hands => Rows::Hands(
+                            seat.map(|seat| hand_rows(&record, seat))
+                                .unwrap_or_default(),
+                        ),
+                        ExportTable::Actions => Rows::Actions(action_rows(&record)),
+                    })
+                })
+            })
+            .await?;
+            let (mut hands, mut actions) = (vec![], vec![]);
+            for rows in rows.into_iter().flatten() {
+                match rows {
+                    Rows::Hands(rows) => hands.extend(rows),
+                    Rows::Actions(rows) => actions.extend(rows),
+                }
+            }
+            let table = match table {
+                ExportTable::Hands => Table::hands(&hands),
+                ExportTable::Actions => Table::actions(&actions),
+            };
+            let path = output
+                .clone()
+                .unwrap_or_else(|| PathBuf::from(format!("{}.{}", table.name, format.extension())));
+            match format {
+                ExportFormat::Csv => tokio::fs::write(&path, table.to_csv()).await?,
+                ExportFormat::Parquet => table.write_parquet(&path)?,
+            }
+            info!(
+                "{} {} written to {}",
+                table.rows(),
+                table.name,
+                path.display()
+            );
+            Ok(())
+        }
+        RecordCommand::Verify { inputs } => {
+            let files = record_files(inputs)?;
+            let reports = {
+                let files = files.clone();
+                tokio::task::spawn_blocking(move || {
+                    batch::run(&tr!("校验", "検証", "Verifying"), &files, |file| {
+                        Ok(verify(&Record::load(file)?))
+                    })
+                })
+                .await?
+            };
+            let mut bad = 0;
+            for (file, problems) in files.iter().zip(&reports) {
+                match problems {
+                    Some(problems) if problems.is_empty() => continue,
+                    Some(problems) => {
+                        println!("{}", file.display());
+                        for problem in problems {
+                            println!("  {}", problem);
+                        }
+                    }
+                    // unreadable, already reported
+                    None => println!(
+                        "{}\n  {}",
+                        file.display(),
+                        tr!("无法读取", "読み込めません", "unreadable")
+                    ),
+                }
+                bad += 1;
+            }
+            match bad {
+                0 => info!("{} records verified, no problems found", files.len()),
+                bad => warn!("{} of {} records have problems", bad, files.len()),
+            }
+            Ok(())
+        }
+        RecordCommand::Anonymize { input, output } => {
+            let mut record = Record::load(input)?;
+            Anonymizer::new()?.anonymize(&mut record);
+            let path = output.clone().unwrap_or_else(|| {
+                let uuid = record.head["uuid"].as_str().unwrap_or("anonymized");
+                PathBuf::from(format!("{}.json", uuid))
+            });
+            tokio::fs::write(&path, serde_json::to_vec_pretty(&record)?).await?;
+            info!("Anonymized record written to {}", path.display());
+            Ok(())
+        }
+        RecordCommand::Render { input, output } => {
+            let record = Record::load(input)?;
+            let path = output.clone().unwrap_or_else(|| {
+                let uuid = record.head["uuid"].as_str().unwrap_or("record");
+                PathBuf::from(format!("{}.html", uuid))
+            });
+            tokio::fs::write(&path, viewer::render(&record)?).await?;
+            info!("Viewer written to {}", path.display());
+            Ok(())
+        }
+        RecordCommand::Simulate {
+            input,
+            speed,
+            seat,
+            account_id,
+        } => {
+            let record = Record::load(input)?;
+            let seat = match account_id {
+                Some(id) => record
+                    .seat_of(*id)
+                    .ok_or(anyhow!("Account {} is not in this game", id))?,
+                None => seat.unwrap_or_default(),
+            };
+            simulate(&record, seat, *speed).await
+        }
+    }
+}
+
+/// `record convert`, one file or uuid as is, several files or folders in parallel.
+pub async fn convert(args: &ConvertArgs) -> Result<()> {
+    let ConvertArgs {
+        inputs,
+        format,
+        seat,
+        login,
+        output,
+        filter,
+    } = args;
+    let paths: Vec<PathBuf> = inputs.iter().map(PathBuf::from).collect();
+    if let [input] = inputs.as_slice() {
+        if !paths[0].is_dir() {
+            return convert_one(input, *format, *seat, login, output.as_deref()).await;
+        }
+    }
+    let files = record_files(&paths)?;
+    let dir = output.clone().unwrap_or_else(|| PathBuf::from("."));
+    tokio::fs::create_dir_all(&dir).await?;
+    let (format, seat, filter) = (*format, seat.unwrap_or_default(), filter.clone());
+    let converted = tokio::task::spawn_blocking(move || {
+        batch::run(&tr!("转换", "変換", "Converting"), &files, |file| {
+            let record = Record::load(file)?;
+            if !filter.matches(&GameFacts::from_head(&record.head)) {
+                return Ok(false);
+            }
+            let uuid = record.head["uuid"].as_str().map(String::from);
+            let name = uuid.unwrap_or_else(|| {
+                let stem = file.file_stem().unwrap_or_default();
+                stem.to_string_lossy().to_string()
+            });
+            let path = dir.join(format!("{}.{}", name, format.extension()));
+            std::fs::write(path, record.convert(format, seat)?)?;
+            Ok(true)
+        })
+    })
+    .await?;
+    let done = converted.iter().filter(|c| **c == Some(true)).count();
+    info!("{} of {} records converted", done, converted.len());
+    Ok(())
+}
+
+/// Convert a single file, or a game downloaded by uuid, from the logged in seat.
+async fn convert_one(
+    input: &str,
+    format: ConvertFormat,
+    seat: Option<u32>,
+    login: &LoginArgs,
+    output: Option<&Path>,
+) -> Result<()> {
+    let (record, own_id) = match tokio::fs::read(input).await {
+        Ok(bytes) => (serde_json::from_slice::<Record>(&bytes)?, None),
+        // not a file, download it by uuid
+        Err(_) => {
+            let mut lobby = login.connect().await?;
+            (fetch(&mut lobby, input).await?, lobby.account_id)
+        }
+    };
+    let seat = seat
+        .or_else(|| own_id.and_then(|id| record.seat_of(id)))
+        .unwrap_or_default();
+    let converted = record.convert(format, seat)?;
+    let path = output.map(Path::to_path_buf).unwrap_or_else(|| {
+        let uuid = record.head["uuid"].as_str().unwrap_or("record");
+        PathBuf::from(format!("{}.{}", uuid, format.extension()))
+    });
+    tokio::fs::write(&path, converted).await?;
+    info!("Converted record written to {}", path.display());
+    Ok(())
+}
+
+/// Builds a [`Record`] of an own game from live traffic, written when the game ends.
+///
+/// Actions are renamed to their `Record` form but keep the live contents, so
+/// other players' starting hands stay unknown.
+pub struct LiveRecorder {
+    dir: PathBuf,
+    record: Option<Record>,
+    charge: Charge,
+    /// uuid of the game given up as the memory was over the cap
+    given_up: Option<JsonValue>,
+}
+
+impl LiveRecorder {
+    pub fn new(dir: PathBuf) -> Self {
+        LiveRecorder {
+            dir,
+            record: None,
+            charge: Charge::new(Consumer::Recorder, 0),
+            given_up: None,
+        }
+    }
+
+    /// Feed an event of an own game, `state` being already updated with it.
+    pub fn feed(&mut self, event: &Event, state: &GameState) {
+        if event.spectated {
+            return;
+        }
+        let data = &event.msg.data;
+        match event.msg.method_name.as_ref() {
+            ".lq.FastTest.authGame" => {
+                let now = chrono::Utc::now().timestamp();
+                let uuid = state
+                    .game
+                    .as_ref()
+                    .and_then(|g| g.game_uuid.clone())
+                    .unwrap_or_else(|| format!("live-{}", now));
+                // a reconnect to the same game keeps what was recorded
+                if self.record.as_ref().is_some_and(|r| r.head["uuid"] == uuid)
+                    || self.given_up.as_ref().is_some_and(|u| *u == uuid)
+                {
+                    return;
+                }
+                let head = live_head(&uuid, now, data);
+                self.charge.set(json_size(&head));
+                self.record = Some(Record {
+                    head,
+                    actions: vec![],
+                });
+            }
+            ".lq.ActionPrototype" | ".lq.FastTest.syncGame" => {
+                let Some(record) = self.record.as_mut() else {
+                    return;
+                };
+                if memory::shed_recording() {
+                    warn!("Memory over the cap, the record of this game is dropped");
+                    self.given_up = Some(record.head["uuid"].clone());
+                    self.record = None;
+                    self.charge.set(0);
+                    return;
+                }
+                let actions = match game_actions(event) {
+                    Ok(actions) => actions,
+                    Err(e) => {
+                        error!("Failed to decode actions for the live record: {:?}", e);
+                        return;
+                    }
+                };
+                if event.msg.method_name.as_ref() == ".lq.FastTest.syncGame" {
+                    // the restore repeats the current hand from its NewRound
+                    if let Some(start) = record
+                        .actions
+                        .iter()
+                        .rposition(|a| a.name == "RecordNewRound")
+                        .filter(|_| actions.first().is_some_and(|(n, _)| n == "ActionNewRound"))
+                    {
+                        record.actions.truncate(start);
+                        let held = record.actions.iter().map(|a| json_size(&a.data));
+                        self.charge
+                            .set(json_size(&record.head) + held.sum::<usize>());
+                    }
+                }
+                for (name, data) in actions {
+                    self.charge.grow(json_size(&data));
+                    record.actions.push(RecordAction {
+                        name: format!("Record{}", strip_action(&name)),
+                        data,
+                    });
+                }
+            }
+            ".lq.NotifyGameEndResult" => {
+                let Some(mut record) = self.record.take() else {
+                    return;
+                };
+                self.charge.set(0);
+                record.head["end_time"] = json!(chrono::Utc::now().timestamp());
+                record.head["result"] = data["result"].clone();
+                let dir = match SETTINGS.live_record_daily {
+                    true => daily::day_dir(&self.dir, daily::today()),
+                    false => self.dir.clone(),
+                };
+                tokio::task::spawn_blocking(move || {
+                    match Library::open(&dir).and_then(|mut l| l.add(&record)) {
+                        Ok(Some(path)) => info!(
+                            "{}",
+                            tr!(
+                                "对局记录已保存到 {}",
+                                "対局記録を {} に保存しました",
+                                "Game record saved to {}",
+                                path.display()
+                            )
+                        ),
+                        Ok(None) => info!(
+                            "{}",
+                            tr!(
+                                "对局记录已存在，未重复保存",
+                                "対局記録は既に保存されています",
+                                "Game record already saved"
+                            )
+                        ),
+                        Err(e) => error!("Failed to save live record: {:?}", e),
+                    }
+                });
+            }
+            _ => (),
+        }
+    }
+}
+
+/// A record head for a game joined live, out of its `players`, `seat_list`
+/// and `game_config`, as in `authGame` or a spectated game's `GameLiveHead`.
+pub(crate) fn live_head(uuid: &str, start_time: i64, game: &JsonValue) -> JsonValue {
+    let seats = u32_list(game, "seat_list");
+    let accounts: Vec<JsonValue> = game["players"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|p| {
+            let id = p["account_id"].as_u64()? as u32;
+            let seat = seats.iter().position(|s| *s == id)?;
+            let mut account = p.clone();
+            account["seat"] = json!(seat);
+            Some(account)
+        })
+        .collect();
+    json!({
+        "uuid": uuid,
+        "start_time": start_time,
+        "config": game["game_config"],
+        "accounts": accounts,
+    })
+}
+
+/// Files of `inputs`, with folders expanded to their `.json` files except `sync.json`.
+pub(crate) fn record_files(inputs: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    for input in inputs {
+        if !input.is_dir() {
+            files.push(input.clone());
+            continue;
+        }
+        let mut entries: Vec<PathBuf> = std::fs::read_dir(input)?
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|p| p.extension().is_some_and(|e| e == "json"))
+            .filter(|p| {
+                p.file_name()
+                    .and_then(|n| n.to_str())
+                    .is_some_and(|n| !["sync.json", "contest.json", INDEX_FILE].contains(&n))
+            })
+            .collect();
+        entries.sort();
+        files.extend(entries);
+    }
+    Ok(files)
+}
+
+/// Shared links append the sharer as `_a<id>`, the lobby only wants the uuid.
+fn record_uuid(uuid: &str) -> &str {
+    uuid.split('_').next().unwrap_or(uuid)
+}
+
+/// Fetch and decode one record, downloading the data from `data_url` for older games.
+pub async fn fetch(lobby: &mut LobbyClient, uuid: &str) -> Result<Record> {
+    let res = lobby
+        .call(
+            ".lq.Lobby.fetchGameRecord",
+            json!({
+                "game_uuid": record_uuid(uuid),
+                "client_version_string": lobby.client_version,
+            }),
+        )
+        .await?;
+    let actions = match res["data"].as_str() {
+        Some(data) if !data.is_empty() => record_actions(data, SETTINGS.desc())?,
+        _ => {
+            let url = res["data_url"].as_str().unwrap_or_default();
+            info!("Downloading record data from {}", url);
+            let bytes = REQUEST_CLIENT
+                .get(url)
+                .send()
+                .await?
+                .error_for_status()?
+                .bytes()
+                .await?;
+            record_bytes_actions(&bytes, SETTINGS.desc())?
+        }
+    };
+    Ok(Record::from_actions(res["head"].clone(), actions))
+}
+
+/// Fetch a record into `library`, a failed fetch is only logged so the next run retries it.
+async fn download(lobby: &mut LobbyClient, uuid: &str, library: &mut Library) -> Result<bool> {
+    match fetch(lobby, uuid).await {
+        Ok(record) => Ok(library.add(&record)?.is_some()),
+        Err(e) => {
+            warn!("Failed to download record {}: {:?}", uuid, e);
+            Ok(false)
+        }
+    }
+}
+
+/// The newest game of a finished sync, later syncs stop when they reach it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct SyncCursor {
+    uuid: String,
+    end_time: i64,
+}
+
+impl SyncCursor {
+    fn of(game: &JsonValue) -> Option<Self> {
+        Some(SyncCursor {
+            uuid: game["uuid"].as_str()?.to_string(),
+            end_time: game["end_time"].as_i64().unwrap_or_default(),
+        })
+    }
+
+    /// `game` is this one or older, the list being newest first.
+    fn reached(&self, game: &JsonValue) -> bool {
+        game["uuid"].as_str() == Some(self.uuid.as_str())
+            || game["end_time"].as_i64().is_some_and(|t| t < self.end_time)
+    }
+}
+
+/// Where an interrupted sync stopped, the list being newest first.
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct SyncProgress {
+    /// list offset reached
+    start: u32,
+    /// games of the account at that time
+    total_count: u32,
+    /// the whole list was walked once
+    complete: bool,
+    /// everything up to this game is archived
+    #[serde(default)]
+    cursor: Option<SyncCursor>,
+}
+
+/// `sync.json`, by account id as several accounts may share a folder.
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct SyncState(BTreeMap<u32, SyncProgress>);
+
+impl SyncState {
+    async fn load(path: &Path, account_id: u32) -> Self {
+        let Ok(bytes) = tokio::fs::read(path).await else {
+            return SyncState::default();
+        };
+        if let Ok(state) = serde_json::from_slice(&bytes) {
+            return state;
+        }
+        // written before accounts were told apart
+        match serde_json::from_slice(&bytes) {
+            Ok(progress) => SyncState(BTreeMap::from([(account_id, progress)])),
+            Err(_) => SyncState::default(),
+        }
+    }
+
+    async fn save(&self, path: &Path) -> Result<()> {
+        tokio::fs::write(path, serde_json::to_vec_pretty(self)?).await?;
+        Ok(())
+    }
+}
+
+/// Walk `fetchGameRecordList` and download the records missing from `dir`.
+///
+/// A sync stops at the newest game of the previous finished one. Without it, a
+/// page without anything new means the newer games are caught up: a finished
+/// archive stops there, an interrupted one jumps to where it stopped. `full`
+/// walks the whole list again.
+pub async fn sync(
+    lobby: &mut LobbyClient,
+    dir: &Path,
+    page_size: u32,
+    interval: Duration,
+    full: bool,
+    filter: &GameFilter,
+) -> Result<()> {
+    let mut library = Library::open(dir)?;
+    let account_id = lobby.account_id.unwrap_or_default();
+    let progress_path = dir.join("sync.json");
+    let mut state = SyncState::load(&progress_path, account_id).await;
+    let previous = state.0.remove(&account_id).unwrap_or_default();
+    let cursor = previous
+        .cursor
+        .clone()
+        .filter(|_| previous.complete && !full);
+    let mut newest = None;
+    let mut resumed = false;
+    let mut ticker = tokio::time::interval(interval);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let (mut start, mut downloaded) = (0, 0);
+    loop {
+        ticker.tick().await;
+        let page = lobby
+            .call(
+                ".lq.Lobby.fetchGameRecordList",
+                json!({"start": start, "count": page_size, "type": 0}),
+            )
+            .await?;
+        let total_count = page["total_count"].as_u64().unwrap_or_default() as u32;
+        let games = page["record_list"].as_array().cloned().unwrap_or_default();
+        if start == 0 {
+            newest = games.first().and_then(SyncCursor::of);
+        }
+        let (mut missing, mut caught_up) = (0, false);
+        for game in &games {
+            if !resumed && cursor.as_ref().is_some_and(|c| c.reached(game)) {
+                caught_up = true;
+                break;
+            }
+            let Some(uuid) = game["uuid"].as_str() else {
+                continue;
+            };
+            if !library.wants(uuid, true) || !filter.matches(&GameFacts::from_head(game)) {
+                continue;
+            }
+            missing += 1;
+            ticker.tick().await;
+            if download(lobby, uuid, &mut library).await? {
+                downloaded += 1;
+            }
+        }
+        let end = start + games.len() as u32;
+        let complete = games.is_empty() || end >= total_count;
+        // nothing new on a page of a finished archive
+        let caught_up = caught_up || (missing == 0 && !resumed && !full && previous.complete);
+        // games played since then pushed the old position down
+        let frontier = match previous.total_count {
+            0 => 0,
+            old => previous.start + total_count.saturating_sub(old),
+        };
+        let progress = SyncProgress {
+            start: if resumed { end } else { end.max(frontier) },
+            total_count,
+            complete: complete || previous.complete,
+            cursor: match complete || caught_up {
+                true => newest.clone(),
+                false => previous.cursor.clone(),
+            },
+        };
+        state.0.insert(account_id, progress);
+        state.save(&progress_path).await?;
+        info!(
+            "Synced {}/{} records, {} new",
+            end.min(total_count),
+            total_count,
+            downloaded
+        );
+        if complete || caught_up {
+            break;
+        }
+        if missing == 0 && !resumed && !full {
+            resumed = true;
+            start = end.max(frontier);
+            continue;
+        }
+        start = end;
+    }
+    info!("{} new records saved to {}", downloaded, dir.display());
+    Ok(())
+}
+
+/// Archive a custom contest: its details to `contest.json` and every game
+/// listed by `fetchCustomizedContestGameRecords` missing from `dir`.
+///
+/// Goes through the lobby like the contest lobby page, the separate contest
+/// management backend is not part of liqi.
+pub async fn contest(
+    lobby: &mut LobbyClient,
+    contest_id: u32,
+    dir: &Path,
+    interval: Duration,
+) -> Result<()> {
+    let mut library = Library::open(dir)?;
+    let mut ticker = tokio::time::interval(interval);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    ticker.tick().await;
+    let info = lobby
+        .call(
+            ".lq.Lobby.fetchCustomizedContestByContestId",
+            json!({ "contest_id": contest_id }),
+        )
+        .await?["contest_info"]
+        .take();
+    let unique_id = info["unique_id"]
+        .as_u64()
+        .filter(|id| *id != 0)
+        .ok_or(anyhow!("Contest {} not found", contest_id))?;
+    info!(
+        "赛事 {}：{}",
+        contest_id,
+        info["contest_name"].as_str().unwrap_or_default()
+    );
+    // rules and notices, only shown to accounts allowed in
+    ticker.tick().await;
+    let detail = match lobby
+        .call(
+            ".lq.Lobby.enterCustomizedContest",
+            json!({ "unique_id": unique_id }),
+        )
+        .await
+    {
+        Ok(mut res) => {
+            ticker.tick().await;
+            if let Err(e) = lobby
+                .call(".lq.Lobby.leaveCustomizedContest", json!({}))
+                .await
+            {
+                warn!("Failed to leave contest: {:?}", e);
+            }
+            res["detail_info"].take()
+        }
+        Err(e) => {
+            warn!("Failed to fetch contest details: {:?}", e);
+            JsonValue::Null
+        }
+    };
+    let details = json!({ "contest_info": info, "detail_info": detail });
+    tokio::fs::write(
+        dir.join("contest.json"),
+        serde_json::to_vec_pretty(&details)?,
+    )
+    .await?;
+
+    let (mut last_index, mut listed, mut downloaded) = (0, 0, 0);
+    loop {
+        ticker.tick().await;
+        let mut page = lobby
+            .call(
+                ".lq.Lobby.fetchCustomizedContestGameRecords",
+                json!({ "unique_id": unique_id, "last_index": last_index }),
+            )
+            .await?;
+        let games = page["record_list"].take();
+        let games = games.as_array().map(Vec::as_slice).unwrap_or_default();
+        for uuid in games.iter().filter_map(|g| g["uuid"].as_str()) {
+            listed += 1;
+            if !library.wants(uuid, true) {
+                continue;
+            }
+            ticker.tick().await;
+            if download(lobby, uuid, &mut library).await? {
+                downloaded += 1;
+            }
+        }
+        info!("Listed {} contest records, {} new", listed, downloaded);
+        let next_index = page["next_index"].as_u64().unwrap_or_default() as u32;
+        if games.is_empty() || next_index == 0 || next_index == last_index {
+            break;
+        }
+        last_index = next_index;
+    }
+    info!("{} new records saved to {}", downloaded, dir.display());
+    Ok(())
+}
@@ -0,0 +1,126 @@
+use crate::{settings::Settings, systemd::Hangup, tr, SETTINGS};
+use anyhow::Result;
+use once_cell::sync::Lazy;
+use std::{
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+use tokio::sync::watch;
+use tracing::{info, warn};
+
+/// How often the settings files are checked for changes.
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Sink settings after the latest reload that changed them.
+static SINK_SETTINGS: Lazy<watch::Sender<Option<Arc<Settings>>>> =
+    Lazy::new(|| watch::channel(None).0);
+
+/// Yields the settings to rebuild the sinks from whenever `sinks`, `apiUrl`,
+/// `sendMethod` or `sendAction` changed; the helper keeps its parsers and game state meanwhile.
+pub fn subscribe() -> watch::Receiver<Option<Arc<Settings>>> {
+    SINK_SETTINGS.subscribe()
+}
+
+fn modified(files: &[PathBuf]) -> Vec<Option<SystemTime>> {
+    files
+        .iter()
+        .map(|path| std::fs::metadata(path).and_then(|m| m.modified()).ok())
+        .collect()
+}
+
+/// Sink part of the settings, compared to tell whether the sinks need a restart.
+fn sink_key(settings: &Settings) -> serde_json::Value {
+    serde_json::json!([
+        settings.sinks,
+        settings.api_url,
+        settings.send_method,
+        settings.send_action
+    ])
+}
+
+/// Watch the settings files and apply changes of the sinks (endpoints, formats,
+/// filters, the methods and actions sent in the liqi format) and of `logLevel` at runtime, through `set_log_level`. Anything else
+/// still needs a restart. SIGHUP reloads at once and restarts the sinks even if
+/// unchanged, e.g. to reopen files after log rotation.
+pub fn spawn(set_log_level: impl Fn(Option<&str>) -> Result<()> + Send + 'static) {
+    let files = SETTINGS.layer_files();
+    tokio::spawn(async move {
+        let mut stamps = modified(&files);
+        let mut current = SETTINGS.clone();
+        let mut interval = tokio::time::interval(POLL_INTERVAL);
+        let mut hangup = Hangup::new();
+        loop {
+            let forced = tokio::select! {
+                _ = interval.tick() => false,
+                _ = hangup.recv() => {
+                    info!(
+                        "{}",
+                        tr!(
+                            "收到SIGHUP，重新载入配置",
+                            "SIGHUPを受信しました。設定を再読み込みします",
+                            "Received SIGHUP, reloading the settings"
+                        )
+                    );
+                    true
+                }
+            };
+            let now = modified(&files);
+            if now == stamps && !forced {
+                continue;
+            }
+            stamps = now;
+            let settings = match current.reload() {
+                Ok(settings) => settings,
+                Err(e) => {
+                    warn!(
+                        "{}",
+                        tr!(
+                            "配置文件有误，未重新载入: {:#}",
+                            "設定ファイルに誤りがあるため、再読み込みしませんでした: {:#}",
+                            "Settings not reloaded, they have errors: {:#}",
+                            e
+                        )
+                    );
+                    continue;
+                }
+            };
+            if settings.log_level != current.log_level {
+                match set_log_level(settings.log_level.as_deref()) {
+                    Ok(()) => info!(
+                        "{}",
+                        tr!(
+                            "日志级别已更新为 {:?}",
+                            "ログレベルを {:?} に更新しました",
+                            "Log level updated to {:?}",
+                            settings.log_level
+                        )
+                    ),
+                    Err(e) => warn!("Invalid logLevel {:?}: {}", settings.log_level, e),
+                }
+            }
+            if forced || sink_key(&settings) != sink_key(&current) {
+                match forced {
+                    true => info!(
+                        "{}",
+                        tr!(
+                            "重新启动各sink",
+                            "各sinkを再起動します",
+                            "Restarting the sinks"
+                        )
+                    ),
+                    false => info!(
+                        "{}",
+                        tr!(
+                            "输出目标配置已更改，重新启动各sink",
+                            "出力先の設定が変わったため、各sinkを再起動します",
+                            "Sink settings changed, restarting the sinks"
+                        )
+                    ),
+                }
+                SINK_SETTINGS.send_replace(Some(Arc::new(settings.clone())));
+            }
+            current = settings;
+        }
+    });
+}
@@ -0,0 +1,351 @@
+use crate::{
+    board::wind_char,
+    events::final_scores,
+    parser::{str_field, str_list, u32_field},
+    players::GameInfo,
+    shanten::waits,
+    state::{yaku_list, GameState, Wind},
+    summary::limit,
+    tile::{counts34, TILES34},
+};
+use anyhow::Result;
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use std::{fmt::Write, path::PathBuf};
+use tracing::info;
+
+/// Hands listed under the biggest hands of a report.
+const BIGGEST: usize = 3;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum ReportFormat {
+    Markdown,
+    Json,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ReportConfig {
+    pub dir: PathBuf,
+    #[serde(default = "default_formats")]
+    pub formats: Vec<ReportFormat>,
+}
+
+fn default_formats() -> Vec<ReportFormat> {
+    vec![ReportFormat::Markdown, ReportFormat::Json]
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
+pub struct Round {
+    pub round_wind: Wind,
+    pub ju: u32,
+    pub honba: u32,
+}
+
+impl std::fmt::Display for Round {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}{}局{}本场",
+            wind_char(self.round_wind),
+            self.ju + 1,
+            self.honba
+        )
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HandRecord {
+    pub round: Round,
+    pub seat: u32,
+    pub zimo: bool,
+    pub han: u32,
+    pub fu: u32,
+    pub limit: String,
+    pub points: u32,
+    pub yaku: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DealIn {
+    pub round: Round,
+    pub seat: u32,
+    pub winner: u32,
+    pub tile: String,
+    /// every tile the winner was waiting on
+    pub wait: Vec<String>,
+    pub points: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct RiichiStats {
+    pub seat: u32,
+    pub declared: u32,
+    pub won: u32,
+    pub dealt_in: u32,
+}
+
+/// Hands of the game so far, kept in [`GameState`] until the game ends.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Tally {
+    pub hands: u32,
+    pub wins: Vec<HandRecord>,
+    pub deal_ins: Vec<DealIn>,
+    pub riichi: Vec<RiichiStats>,
+}
+
+/// Add one action to the tally, `state` having already been fed with it.
+pub fn record(state: &mut GameState, name: &str, data: &JsonValue) -> Result<()> {
+    let round = Round {
+        round_wind: state.round_wind,
+        ju: state.ju,
+        honba: state.honba,
+    };
+    let sanma = state.is_sanma();
+    let riichi: Vec<bool> = state.players.iter().map(|p| p.riichi).collect();
+    let tally = &mut state.tally;
+    if tally.riichi.len() < riichi.len() {
+        tally.riichi = (0..riichi.len() as u32)
+            .map(|seat| RiichiStats {
+                seat,
+                ..Default::default()
+            })
+            .collect();
+    }
+    match name {
+        "DiscardTile"
+            if data["is_liqi"].as_bool().unwrap_or_default()
+                || data["is_wliqi"].as_bool().unwrap_or_default() =>
+        {
+            if let Some(stats) = tally.riichi.get_mut(u32_field(data, "seat")? as usize) {
+                stats.declared += 1;
+            }
+        }
+        "Hule" => {
+            tally.hands += 1;
+            for hule in data["hules"].as_array().into_iter().flatten() {
+                let seat = u32_field(hule, "seat")?;
+                let zimo = hule["zimo"].as_bool().unwrap_or_default();
+                let han = u32_field(hule, "count")?;
+                let fu = u32_field(hule, "fu")?;
+                let points = u32_field(hule, "point_sum")?;
+                if riichi.get(seat as usize) == Some(&true) {
+                    tally.riichi[seat as usize].won += 1;
+                }
+                tally.wins.push(HandRecord {
+                    round,
+                    seat,
+                    zimo,
+                    han,
+                    fu,
+                    limit: limit(han, fu, hule["yiman"].as_bool().unwrap_or_default()).to_string(),
+                    points,
+                    yaku: yaku_list(hule).into_iter().map(|y| y.name).collect(),
+                });
+                if zimo {
+                    continue;
+                }
+                let Some(from) = state.current else {
+                    continue;
+                };
+                if riichi.get(from as usize) == Some(&true) {
+                    tally.riichi[from as usize].dealt_in += 1;
+                }
+                let tile = str_field(hule, "hu_tile")?;
+                tally.deal_ins.push(DealIn {
+                    round,
+                    seat: from,
+                    winner: seat,
+                    tile: tile.to_string(),
+                    wait: wait_of(hule, tile, sanma),
+                    points,
+                });
+            }
+        }
+        "NoTile" | "LiuJu" => tally.hands += 1,
+        _ => (),
+    }
+    Ok(())
+}
+
+/// Waits of a winning hand, from its concealed tiles without the winning one.
+fn wait_of(hule: &JsonValue, tile: &str, sanma: bool) -> Vec<String> {
+    let mut hand = str_list(hule, "hand");
+    if hand.len() % 3 == 2 {
+        if let Some(i) = hand.iter().position(|t| *t == tile) {
+            hand.remove(i);
+        }
+    }
+    let melds = hule["ming"].as_array().map_or(0, |m| m.len());
+    waits(&counts34(&hand), melds, sanma)
+        .into_iter()
+        .map(|i| TILES34[i].to_string())
+        .collect()
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Placement {
+    /// 1 for the top
+    pub rank: u32,
+    pub seat: u32,
+    pub nickname: Option<String>,
+    pub score: i32,
+    /// final points with oka and uma, ×1000 as majsoul sends them
+    pub total_point: i32,
+}
+
+/// Written for every own game when `report` is configured.
+#[derive(Serialize, Debug, Clone)]
+pub struct GameReport {
+    pub game: Option<GameInfo>,
+    pub ended_at: String,
+    pub hands: u32,
+    pub placements: Vec<Placement>,
+    pub biggest_hands: Vec<HandRecord>,
+    pub deal_ins: Vec<DealIn>,
+    pub riichi: Vec<RiichiStats>,
+}
+
+impl GameReport {
+    /// Build from the tally and the `.lq.NotifyGameEndResult`.
+    pub fn new(state: &GameState, end: &JsonValue) -> Self {
+        let mut scores = final_scores(end, state);
+        // ties go to the earlier seat
+        scores.sort_by_key(|s| (-s.total_point, s.seat));
+        let nickname = |seat: u32| {
+            state
+                .game
+                .as_ref()
+                .and_then(|g| g.players.get(seat as usize))
+                .map(|p| p.nickname.clone())
+        };
+        let placements = scores
+            .iter()
+            .enumerate()
+            .map(|(i, s)| Placement {
+                rank: i as u32 + 1,
+                seat: s.seat,
+                nickname: nickname(s.seat),
+                score: s.score,
+                total_point: s.total_point,
+            })
+            .collect();
+        let mut biggest_hands = state.tally.wins.clone();
+        biggest_hands.sort_by_key(|h| std::cmp::Reverse(h.points));
+        biggest_hands.truncate(BIGGEST);
+        GameReport {
+            game: state.game.clone(),
+            ended_at: Local::now().to_rfc3339(),
+            hands: state.tally.hands,
+            placements,
+            biggest_hands,
+            deal_ins: state.tally.deal_ins.clone(),
+            riichi: state.tally.riichi.clone(),
+        }
+    }
+
+    fn name(&self, seat: u32) -> String {
+        self.game
+            .as_ref()
+            .and_then(|g| g.players.get(seat as usize))
+            .map(|p| p.nickname.clone())
+            .unwrap_or_else(|| format!("座位{}", seat))
+    }
+
+    pub fn markdown(&self) -> String {
+        let mut out = String::new();
+        let uuid = self.game.as_ref().and_then(|g| g.game_uuid.as_deref());
+        let _ = match uuid {
+            Some(uuid) => writeln!(out, "# 对局报告 {}\n", uuid),
+            None => writeln!(out, "# 对局报告\n"),
+        };
+        let _ = writeln!(out, "结束于 {}，共{}局\n", self.ended_at, self.hands);
+        let _ = writeln!(
+            out,
+            "## 顺位\n\n| 顺位 | 玩家 | 点数 | 得点 |\n|---|---|---|---|"
+        );
+        for p in &self.placements {
+            let _ = writeln!(
+                out,
+                "| {} | {} | {} | {:.1} |",
+                p.rank,
+                self.name(p.seat),
+                p.score,
+                p.total_point as f64 / 1000.0
+            );
+        }
+        let _ = writeln!(out, "\n## 最大和牌\n");
+        for h in &self.biggest_hands {
+            let _ = writeln!(
+                out,
+                "- {} {} {} {}番{}符{} {}点：{}",
+                h.round,
+                self.name(h.seat),
+                if h.zimo { "自摸" } else { "荣和" },
+                h.han,
+                h.fu,
+                h.limit,
+                h.points,
+                h.yaku.join("、")
+            );
+        }
+        let _ = writeln!(out, "\n## 放铳\n");
+        for d in &self.deal_ins {
+            let _ = writeln!(
+                out,
+                "- {} {} 放铳 {} {}，听{}，{}点",
+                d.round,
+                self.name(d.seat),
+                self.name(d.winner),
+                d.tile,
+                d.wait.join(""),
+                d.points
+            );
+        }
+        let _ = writeln!(
+            out,
+            "\n## 立直\n\n| 玩家 | 立直 | 和了 | 放铳 | 成功率 |\n|---|---|---|---|---|"
+        );
+        for r in &self.riichi {
+            let rate = match r.declared {
+                0 => "-".to_string(),
+                n => format!("{:.0}%", r.won as f64 * 100.0 / n as f64),
+            };
+            let _ = writeln!(
+                out,
+                "| {} | {} | {} | {} | {} |",
+                self.name(r.seat),
+                r.declared,
+                r.won,
+                r.dealt_in,
+                rate
+            );
+        }
+        out
+    }
+
+    /// Save into `config.dir`, one file per format.
+    pub async fn write(&self, config: &ReportConfig) -> Result<()> {
+        tokio::fs::create_dir_all(&config.dir).await?;
+        let stem = format!(
+            "{}_{}",
+            Local::now().format("%Y%m%d-%H%M%S"),
+            self.game
+                .as_ref()
+                .and_then(|g| g.game_uuid.as_deref())
+                .unwrap_or("game")
+        );
+        for format in &config.formats {
+            let (ext, body) = match format {
+                ReportFormat::Markdown => ("md", self.markdown()),
+                ReportFormat::Json => ("json", serde_json::to_string_pretty(self)?),
+            };
+            let path = config.dir.join(format!("{}.{}", stem, ext));
+            tokio::fs::write(&path, body).await?;
+            info!("Game report saved to {}", path.display());
+        }
+        Ok(())
+    }
+}
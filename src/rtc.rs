@@ -0,0 +1,281 @@
+use crate::sink::{Delivery, Payload, Sink};
+use anyhow::{anyhow, Result};
+use bytes::Bytes;
+use http_body_util::{BodyExt, Full, LengthLimitError, Limited};
+use hudsucker::{
+    hyper::{
+        body::Incoming, header, header::HeaderValue, server::conn::http1, service::service_fn,
+        Method, Request, Response, StatusCode,
+    },
+    hyper_util::rt::TokioIo,
+};
+use std::{
+    convert::Infallible,
+    io::ErrorKind,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::{net::TcpListener, task::JoinHandle, time::sleep};
+use tracing::{error, info, warn};
+use webrtc::{
+    api::{APIBuilder, API},
+    data_channel::{data_channel_state::RTCDataChannelState, RTCDataChannel},
+    ice_transport::ice_server::RTCIceServer,
+    peer_connection::{
+        configuration::RTCConfiguration, peer_connection_state::RTCPeerConnectionState,
+        sdp::session_description::RTCSessionDescription, RTCPeerConnection,
+    },
+};
+
+/// Sends payloads over WebRTC data channels opened by remote overlays.
+///
+/// Signaling is a single HTTP exchange: the overlay POSTs its SDP offer
+/// (with a data channel) to `listen` and gets the SDP answer back,
+/// ICE candidates are gathered before answering so no trickle is needed.
+pub struct WebRtcSink {
+    channels: Arc<Mutex<Vec<Arc<RTCDataChannel>>>>,
+    /// the signaling server, stopped with the sink, e.g. replaced by a reload
+    server: JoinHandle<()>,
+}
+
+/// How long the signaling server of a reloaded sink waits for the one it
+/// replaces to let go of the port.
+const REBIND: Duration = Duration::from_secs(5);
+
+/// Largest SDP offer accepted, real ones are a few KiB.
+const OFFER_LIMIT: usize = 64 << 10;
+
+struct Signaling {
+    api: API,
+    config: RTCConfiguration,
+    token: Option<String>,
+    allowed_origins: Vec<String>,
+    channels: Arc<Mutex<Vec<Arc<RTCDataChannel>>>>,
+    peers: Arc<Mutex<Vec<Arc<RTCPeerConnection>>>>,
+}
+
+impl WebRtcSink {
+    pub fn new(
+        listen: SocketAddr,
+        ice_servers: Vec<String>,
+        token: Option<String>,
+        allowed_origins: Vec<String>,
+    ) -> Self {
+        let channels = Arc::new(Mutex::new(vec![]));
+        let signaling = Arc::new(Signaling {
+            api: APIBuilder::new().build(),
+            config: RTCConfiguration {
+                ice_servers: vec![RTCIceServer {
+                    urls: ice_servers,
+                    ..Default::default()
+                }],
+                ..Default::default()
+            },
+            token,
+            allowed_origins,
+            channels: channels.clone(),
+            peers: Arc::new(Mutex::new(vec![])),
+        });
+        let server = tokio::spawn(async move {
+            if let Err(e) = serve(listen, signaling).await {
+                error!("WebRTC signaling server on {} stopped: {:?}", listen, e);
+            }
+        });
+        Self { channels, server }
+    }
+}
+
+impl Drop for WebRtcSink {
+    fn drop(&mut self) {
+        self.server.abort();
+    }
+}
+
+async fn serve(listen: SocketAddr, signaling: Arc<Signaling>) -> Result<()> {
+    let started = Instant::now();
+    let listener = loop {
+        match TcpListener::bind(listen).await {
+            Ok(listener) => break listener,
+            Err(e) if e.kind() == ErrorKind::AddrInUse && started.elapsed() < REBIND => {
+                sleep(Duration::from_millis(100)).await
+            }
+            Err(e) => return Err(e.into()),
+        }
+    };
+    info!("WebRTC signaling listening on http://{}", listen);
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let signaling = signaling.clone();
+        tokio::spawn(async move {
+            let service = service_fn(|req| handle(signaling.clone(), req));
+            if let Err(e) = http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .await
+            {
+                warn!("WebRTC signaling connection error: {}", e);
+            }
+        });
+    }
+}
+
+fn respond(status: StatusCode, body: String) -> Response<Full<Bytes>> {
+    let mut res = Response::new(Full::new(Bytes::from(body)));
+    *res.status_mut() = status;
+    let headers = res.headers_mut();
+    headers.insert(
+        header::ACCESS_CONTROL_ALLOW_HEADERS,
+        HeaderValue::from_static("authorization, content-type"),
+    );
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/sdp"),
+    );
+    res
+}
+
+async fn handle(
+    signaling: Arc<Signaling>,
+    req: Request<Incoming>,
+) -> Result<Response<Full<Bytes>>, Infallible> {
+    // overlays are usually served from another origin, but only the listed ones
+    let origin = req.headers().get(header::ORIGIN).cloned();
+    let mut res = match &origin {
+        Some(origin) if !signaling.allows(origin) => {
+            respond(StatusCode::FORBIDDEN, "origin not allowed".to_string())
+        }
+        _ => answer(&signaling, req).await,
+    };
+    if let Some(origin) = origin.filter(|origin| signaling.allows(origin)) {
+        let headers = res.headers_mut();
+        headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+        headers.insert(header::VARY, HeaderValue::from_static("origin"));
+    }
+    Ok(res)
+}
+
+async fn answer(signaling: &Signaling, req: Request<Incoming>) -> Response<Full<Bytes>> {
+    match *req.method() {
+        Method::OPTIONS => return respond(StatusCode::NO_CONTENT, String::new()),
+        Method::POST => (),
+        _ => return respond(StatusCode::METHOD_NOT_ALLOWED, String::new()),
+    }
+    if let Some(token) = &signaling.token {
+        let auth = req
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok());
+        if auth != Some(&format!("Bearer {}", token)) {
+            return respond(StatusCode::UNAUTHORIZED, String::new());
+        }
+    }
+    let offer = match Limited::new(req.into_body(), OFFER_LIMIT).collect().await {
+        Ok(body) => String::from_utf8_lossy(&body.to_bytes()).into_owned(),
+        Err(e) if e.is::<LengthLimitError>() => {
+            return respond(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                format!("offers are limited to {} bytes", OFFER_LIMIT),
+            )
+        }
+        Err(e) => return respond(StatusCode::BAD_REQUEST, e.to_string()),
+    };
+    match signaling.answer(offer).await {
+        Ok(answer) => respond(StatusCode::CREATED, answer),
+        Err(e) => {
+            warn!("WebRTC offer rejected: {:?}", e);
+            respond(StatusCode::BAD_REQUEST, e.to_string())
+        }
+    }
+}
+
+impl Signaling {
+    /// Whether a browser page from `origin` may signal.
+    fn allows(&self, origin: &HeaderValue) -> bool {
+        let Ok(origin) = origin.to_str() else {
+            return false;
+        };
+        let origin = origin.trim_end_matches('/');
+        self.allowed_origins
+            .iter()
+            .any(|allowed| allowed.trim_end_matches('/') == origin)
+    }
+
+    async fn answer(&self, offer: String) -> Result<String> {
+        let pc = Arc::new(self.api.new_peer_connection(self.config.clone()).await?);
+        let channels = self.channels.clone();
+        pc.on_data_channel(Box::new(move |dc| {
+            info!("Overlay data channel {} opened", dc.label());
+            if let Ok(mut channels) = channels.lock() {
+                channels.push(dc);
+            }
+            Box::pin(async {})
+        }));
+        let peers = self.peers.clone();
+        let weak = Arc::downgrade(&pc);
+        pc.on_peer_connection_state_change(Box::new(move |state| {
+            if matches!(
+                state,
+                RTCPeerConnectionState::Failed | RTCPeerConnectionState::Closed
+            ) {
+                info!("Overlay peer {}", state);
+                if let (Ok(mut peers), Some(pc)) = (peers.lock(), weak.upgrade()) {
+                    peers.retain(|p| !Arc::ptr_eq(p, &pc));
+                }
+            }
+            Box::pin(async {})
+        }));
+        pc.set_remote_description(RTCSessionDescription::offer(offer)?)
+            .await?;
+        let answer = pc.create_answer(None).await?;
+        let mut gathered = pc.gathering_complete_promise().await;
+        pc.set_local_description(answer).await?;
+        let _ = gathered.recv().await;
+        let sdp = pc
+            .local_description()
+            .await
+            .ok_or(anyhow!("No local description"))?
+            .sdp;
+        self.peers
+            .lock()
+            .map_err(|_| anyhow!("Peers poisoned"))?
+            .push(pc);
+        Ok(sdp)
+    }
+}
+
+impl Sink for WebRtcSink {
+    async fn send(&mut self, delivery: Delivery) -> Result<()> {
+        let open: Vec<Arc<RTCDataChannel>> = {
+            let mut channels = self
+                .channels
+                .lock()
+                .map_err(|_| anyhow!("Channels poisoned"))?;
+            channels.retain(|dc| {
+                !matches!(
+                    dc.ready_state(),
+                    RTCDataChannelState::Closing | RTCDataChannelState::Closed
+                )
+            });
+            channels
+                .iter()
+                .filter(|dc| dc.ready_state() == RTCDataChannelState::Open)
+                .cloned()
+                .collect()
+        };
+        // a broken overlay must not mark the whole sink down
+        for dc in open {
+            let sent = match &delivery.payload {
+                Payload::Json(json) => dc.send_text(serde_json::to_string(json)?).await,
+                Payload::Serialized(bytes) => {
+                    dc.send_text(String::from_utf8_lossy(bytes).into_owned())
+                        .await
+                }
+                Payload::Binary(bin) => dc.send(bin).await,
+            };
+            if let Err(e) = sent {
+                warn!("Overlay data channel {} send failed: {}", dc.label(), e);
+            }
+        }
+        Ok(())
+    }
+}
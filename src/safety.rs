@@ -0,0 +1,217 @@
+use crate::{
+    shanten::visible_counts,
+    state::GameState,
+    tile::{index34, TILES34},
+};
+use serde::Serialize;
+
+/// How safe a tile is against one opponent, safest first.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "camelCase")]
+pub enum Safety {
+    /// discarded by the opponent, or by anyone after their riichi
+    Genbutsu,
+    /// both ryanmen waits on it are ruled out by genbutsu
+    Suji,
+    /// every ryanmen wait on it needs a tile with all four copies visible
+    Kabe,
+    Unknown,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct TileSafety {
+    pub tile: &'static str,
+    pub safety: Safety,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct OpponentSafety {
+    pub seat: u32,
+    pub riichi: bool,
+    /// all 34 tile kinds
+    pub tiles: Vec<TileSafety>,
+}
+
+/// Data of `.helper.Safety`, emitted after every discard.
+#[derive(Serialize, Debug, Clone)]
+pub struct SafetyReport {
+    pub opponents: Vec<OpponentSafety>,
+}
+
+/// Classify all tile kinds against every opponent, or every player if the own seat is unknown.
+pub fn analyze(state: &GameState) -> SafetyReport {
+    let visible = visible_counts(state);
+    let opponents = state
+        .players
+        .iter()
+        .filter(|p| Some(p.seat) != state.seat)
+        .map(|p| {
+            let genbutsu = genbutsu(state, p.seat);
+            let tiles = (0..34)
+                .map(|i| TileSafety {
+                    tile: TILES34[i],
+                    safety: classify(i, &genbutsu, &visible, state.is_sanma()),
+                })
+                .collect();
+            OpponentSafety {
+                seat: p.seat,
+                riichi: p.riichi,
+                tiles,
+            }
+        })
+        .collect();
+    SafetyReport { opponents }
+}
+
+fn genbutsu(state: &GameState, seat: u32) -> [bool; 34] {
+    let mut safe = [false; 34];
+    let Some(player) = state.player(seat) else {
+        return safe;
+    };
+    for discard in &player.discards {
+        if let Ok(i) = index34(&discard.tile) {
+            safe[i] = true;
+        }
+    }
+    let Some(riichi_at) = player.discards.iter().find(|d| d.riichi).map(|d| d.index) else {
+        return safe;
+    };
+    // a riichi player can't call ron on tiles they passed
+    for discard in state.players.iter().flat_map(|p| &p.discards) {
+        if discard.index > riichi_at {
+            if let Ok(i) = index34(&discard.tile) {
+                safe[i] = true;
+            }
+        }
+    }
+    safe
+}
+
+fn classify(i: usize, genbutsu: &[bool; 34], visible: &[u8; 34], sanma: bool) -> Safety {
+    if genbutsu[i] {
+        return Safety::Genbutsu;
+    }
+    // honors, and 1m and 9m of sanma where no sequence of manzu exists, are
+    // only dealt in to tanki and shanpon waits, which neither suji nor kabe
+    // rule out
+    if i >= 27 || (sanma && i < 9) {
+        return Safety::Unknown;
+    }
+    let n = i % 9;
+    // ryanmen waits on i: (i+1, i+2) which also waits on i+3, (i-2, i-1) which also waits on i-3
+    let upper = n <= 5;
+    let lower = n >= 3;
+    if (!upper || genbutsu[i + 3]) && (!lower || genbutsu[i - 3]) {
+        return Safety::Suji;
+    }
+    let blocked = |a: usize, b: usize| visible[a] >= 4 || visible[b] >= 4;
+    if (!upper || blocked(i + 1, i + 2)) && (!lower || blocked(i - 1, i - 2)) {
+        return Safety::Kabe;
+    }
+    Safety::Unknown
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        state::{Discard, PlayerState},
+        tile::tiles,
+    };
+
+    /// A four player table seen from seat 0, `discards` are (seat, tile,
+    /// riichi declaration) in the order they were made.
+    fn table(hand: &str, discards: &[(u32, &str, bool)]) -> GameState {
+        let mut state = GameState {
+            seat: Some(0),
+            players: (0..4)
+                .map(|seat| PlayerState {
+                    seat,
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        };
+        state.players[0].hand = tiles(hand);
+        for (index, (seat, tile, riichi)) in discards.iter().enumerate() {
+            state.players[*seat as usize].discards.push(Discard {
+                tile: tile.to_string(),
+                index,
+                tsumogiri: false,
+                riichi: *riichi,
+                called: false,
+            });
+        }
+        state
+    }
+
+    fn safety(state: &GameState, seat: u32, tile: &str) -> Safety {
+        let report = analyze(state);
+        let opponent = report.opponents.iter().find(|o| o.seat == seat).unwrap();
+        opponent.tiles[index34(tile).unwrap()].safety
+    }
+
+    #[test]
+    fn own_seat_is_not_an_opponent() {
+        let report = analyze(&table("", &[]));
+        let seats: Vec<u32> = report.opponents.iter().map(|o| o.seat).collect();
+        assert_eq!(seats, [1, 2, 3]);
+    }
+
+    #[test]
+    fn genbutsu_and_suji() {
+        let state = table("", &[(1, "4m", false), (1, "6p", true)]);
+        assert_eq!(safety(&state, 1, "4m"), Safety::Genbutsu);
+        assert_eq!(safety(&state, 1, "1m"), Safety::Suji);
+        assert_eq!(safety(&state, 1, "7m"), Safety::Suji);
+        assert_eq!(safety(&state, 1, "9p"), Safety::Suji);
+        // 45p is the only ryanmen waiting on 3p, 12p is a penchan
+        assert_eq!(safety(&state, 1, "3p"), Safety::Suji);
+        assert_eq!(safety(&state, 1, "5m"), Safety::Unknown);
+        assert_eq!(safety(&state, 2, "4m"), Safety::Unknown);
+    }
+
+    #[test]
+    fn both_sides_are_needed_for_middle_tiles() {
+        let state = table("", &[(1, "2s", false)]);
+        assert_eq!(safety(&state, 1, "5s"), Safety::Unknown);
+        let state = table("", &[(1, "2s", false), (1, "8s", false)]);
+        assert_eq!(safety(&state, 1, "5s"), Safety::Suji);
+    }
+
+    #[test]
+    fn tiles_passed_after_riichi_are_genbutsu() {
+        let state = table(
+            "",
+            &[
+                (2, "3p", false),
+                (1, "9s", true),
+                (2, "5p", false),
+                (3, "1z", false),
+            ],
+        );
+        assert_eq!(safety(&state, 1, "5p"), Safety::Genbutsu);
+        assert_eq!(safety(&state, 1, "1z"), Safety::Genbutsu);
+        // before the declaration
+        assert_eq!(safety(&state, 1, "3p"), Safety::Unknown);
+        // only against the riichi player
+        assert_eq!(safety(&state, 3, "5p"), Safety::Unknown);
+    }
+
+    #[test]
+    fn honors_are_never_suji() {
+        let state = table("", &[(1, "1z", true)]);
+        assert_eq!(safety(&state, 1, "1z"), Safety::Genbutsu);
+        assert_eq!(safety(&state, 1, "2z"), Safety::Unknown);
+    }
+
+    #[test]
+    fn kabe() {
+        let state = table("8888m", &[]);
+        assert_eq!(safety(&state, 1, "9m"), Safety::Kabe);
+        // 7m is still a wait of 56m
+        assert_eq!(safety(&state, 1, "7m"), Safety::Unknown);
+        let state = table("8888m2222m", &[]);
+        assert_eq!(safety(&state, 1, "1m"), Safety::Kabe);
+    }
+}
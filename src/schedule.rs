@@ -0,0 +1,251 @@
+use crate::{
+    daily,
+    record::{self, LoginArgs},
+    secrets,
+    selection::GameFilter,
+    tr, SETTINGS,
+};
+use anyhow::{anyhow, bail, Result};
+use chrono::{NaiveTime, Timelike};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::{fmt, path::PathBuf, str::FromStr, time::Duration};
+use tokio::time::sleep;
+use tracing::{error, info};
+
+const MINUTES_PER_DAY: u32 = 24 * 60;
+/// How often capture windows are checked for logging when they open or close.
+const TICK: Duration = Duration::from_secs(30);
+
+/// Minutes since midnight of `HH:MM`, `24:00` being the end of the day.
+fn parse_minutes(s: &str) -> Result<u32> {
+    let (h, m) = s
+        .trim()
+        .split_once(':')
+        .ok_or(anyhow!("Invalid time {}, expected HH:MM", s))?;
+    let (h, m) = (h.parse::<u32>()?, m.parse::<u32>()?);
+    if h > 24 || m >= 60 || h * 60 + m > MINUTES_PER_DAY {
+        bail!("Invalid time {}, expected HH:MM", s);
+    }
+    Ok(h * 60 + m)
+}
+
+fn format_minutes(minutes: u32) -> String {
+    format!("{:02}:{:02}", minutes / 60, minutes % 60)
+}
+
+/// A daily window such as `19:00-24:00`, or `22:00-02:00` across midnight.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Window {
+    start: u32,
+    end: u32,
+}
+
+impl Window {
+    fn contains(&self, minute: u32) -> bool {
+        match self.start <= self.end {
+            true => self.start <= minute && minute < self.end,
+            false => minute >= self.start || minute < self.end,
+        }
+    }
+}
+
+impl FromStr for Window {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (start, end) = s
+            .split_once('-')
+            .ok_or(anyhow!("Invalid window {}, expected e.g. 19:00-24:00", s))?;
+        Ok(Window {
+            start: parse_minutes(start)?,
+            end: parse_minutes(end)?,
+        })
+    }
+}
+
+impl fmt::Display for Window {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}-{}",
+            format_minutes(self.start),
+            format_minutes(self.end)
+        )
+    }
+}
+
+impl Serialize for Window {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Window {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// `record sync` run every day by the scheduler.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SyncJob {
+    /// time of day, `HH:MM`
+    #[serde(deserialize_with = "time_of_day", serialize_with = "format_time")]
+    pub at: NaiveTime,
+    /// `records/` of the config folder when unset
+    #[serde(default)]
+    pub dir: Option<PathBuf>,
+    /// the token stored with `secret set token` when unset, `keychain:<key>`
+    /// reads another one from the OS keychain
+    #[serde(default)]
+    pub token: Option<String>,
+    #[serde(default)]
+    pub token_type: u32,
+    #[serde(default)]
+    pub gateway: Option<String>,
+}
+
+impl SyncJob {
+    pub fn dir(&self) -> PathBuf {
+        self.dir.clone().unwrap_or_else(record::default_dir)
+    }
+}
+
+fn time_of_day<'de, D: Deserializer<'de>>(deserializer: D) -> Result<NaiveTime, D::Error> {
+    let s = String::deserialize(deserializer)?;
+    NaiveTime::parse_from_str(&s, "%H:%M")
+        .map_err(|_| serde::de::Error::custom(format!("Invalid time {}, expected HH:MM", s)))
+}
+
+fn format_time<S: Serializer>(at: &NaiveTime, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(&at.format("%H:%M"))
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct ScheduleConfig {
+    /// windows game traffic is captured in, all day when empty
+    #[serde(default)]
+    pub capture: Vec<Window>,
+    #[serde(default)]
+    pub sync: Option<SyncJob>,
+}
+
+fn minute_now() -> u32 {
+    let now = daily::now();
+    now.hour() * 60 + now.minute()
+}
+
+/// Whether game traffic goes to the helper now, the proxy forwards it anyway.
+pub fn capturing() -> bool {
+    let windows = &SETTINGS.schedule.capture;
+    windows.is_empty() || windows.iter().any(|w| w.contains(minute_now()))
+}
+
+/// Time until `at` comes next in the `timezone` setting, a day when it is now.
+fn until(at: NaiveTime) -> Duration {
+    let now = daily::now().time();
+    match (at - now).num_milliseconds().rem_euclid(24 * 3600 * 1000) {
+        0 => Duration::from_secs(24 * 3600),
+        ms => Duration::from_millis(ms as u64),
+    }
+}
+
+async fn sync(job: &SyncJob) -> Result<()> {
+    let login = LoginArgs {
+        token: job.token.as_deref().map(secrets::resolve).transpose()?,
+        save_token: false,
+        token_type: job.token_type,
+        gateway: job
+            .gateway
+            .clone()
+            .unwrap_or_else(|| crate::lobby::DEFAULT_GATEWAY.to_string()),
+    };
+    let mut lobby = login.connect().await?;
+    record::sync(
+        &mut lobby,
+        &job.dir(),
+        30,
+        Duration::from_secs(1),
+        false,
+        &GameFilter::default(),
+    )
+    .await
+}
+
+/// Run the `schedule` setting until the proxy stops: log capture windows
+/// opening and closing, and start the nightly sync.
+pub async fn run() {
+    let schedule = &SETTINGS.schedule;
+    let mut was_capturing = capturing();
+    if !schedule.capture.is_empty() {
+        let windows: Vec<String> = schedule.capture.iter().map(Window::to_string).collect();
+        info!(
+            "{}",
+            tr!(
+                "仅在 {} 记录对局，当前{}",
+                "{} の間だけ対局を記録します。現在は{}",
+                "Capturing games only during {}, now {}",
+                windows.join(", "),
+                match was_capturing {
+                    true => tr!("记录中", "記録中", "capturing"),
+                    false => tr!("暂停", "停止中", "paused"),
+                }
+            )
+        );
+    }
+    if let Some(job) = &schedule.sync {
+        info!(
+            "{}",
+            tr!(
+                "每天 {} 同步牌谱到 {}",
+                "毎日 {} に牌譜を {} へ同期します",
+                "Syncing records daily at {} to {}",
+                job.at.format("%H:%M"),
+                job.dir().display()
+            )
+        );
+    }
+    loop {
+        let left = schedule.sync.as_ref().map(|job| until(job.at));
+        let wait = left.map_or(TICK, |left| left.min(TICK));
+        sleep(wait).await;
+        let now = capturing();
+        if now != was_capturing {
+            was_capturing = now;
+            info!(
+                "{}",
+                match now {
+                    true => tr!(
+                        "进入记录时段，开始记录对局",
+                        "記録時間帯に入りました。対局の記録を始めます",
+                        "Capture window opened, capturing games"
+                    ),
+                    false => tr!(
+                        "离开记录时段，暂停记录对局",
+                        "記録時間帯を過ぎました。対局の記録を止めます",
+                        "Capture window closed, pausing capture"
+                    ),
+                }
+            );
+        }
+        if let (Some(job), Some(left)) = (&schedule.sync, left) {
+            if left <= wait {
+                info!(
+                    "{}",
+                    tr!(
+                        "开始定时同步牌谱",
+                        "定時の牌譜同期を開始します",
+                        "Scheduled record sync started"
+                    )
+                );
+                if let Err(e) = sync(job).await {
+                    error!("Scheduled record sync failed: {:#}", e);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,151 @@
+use crate::{instance, tr};
+use anyhow::{anyhow, Result};
+use clap::Subcommand;
+use std::io::BufRead;
+
+/// Prefix of a setting value read from the keychain, e.g. `keychain:overlay`.
+const PREFIX: &str = "keychain:";
+/// Key of the login token used by `record` when `--token` is not given.
+pub const LOGIN_TOKEN: &str = "token";
+
+#[derive(Subcommand, Debug)]
+pub enum SecretCommand {
+    /// Store a secret, read from stdin when no value is given
+    Set { key: String, value: Option<String> },
+    /// Tell whether a secret is stored, without printing it
+    Has { key: String },
+    /// Remove a secret
+    Delete { key: String },
+}
+
+/// Secrets of each instance apart, e.g. the tokens of different accounts.
+fn account(key: &str) -> String {
+    format!("{}/{}", instance::name(), key)
+}
+
+/// The secret stored for `key`, `None` when there is none.
+pub fn get(key: &str) -> Result<Option<String>> {
+    imp::get(&account(key))
+}
+
+pub fn set(key: &str, value: &str) -> Result<()> {
+    imp::set(&account(key), value)
+}
+
+/// Whether there was one to remove.
+pub fn delete(key: &str) -> Result<bool> {
+    imp::delete(&account(key))
+}
+
+/// A setting value, from the keychain when it is `keychain:<key>`.
+pub fn resolve(value: &str) -> Result<String> {
+    match value.strip_prefix(PREFIX) {
+        Some(key) => get(key)?.ok_or(anyhow!(tr!(
+            "钥匙串中没有 {}，请先运行`secret set {}`",
+            "キーチェーンに {} がありません。先に `secret set {}` を実行してください",
+            "No {} in the keychain, run `secret set {}` first",
+            key,
+            key
+        ))),
+        None => Ok(value.to_string()),
+    }
+}
+
+pub fn run(command: &SecretCommand) -> Result<()> {
+    match command {
+        SecretCommand::Set { key, value } => {
+            let value = match value {
+                Some(value) => value.clone(),
+                None => {
+                    eprintln!(
+                        "{}",
+                        tr!(
+                            "输入 {} 的值：",
+                            "{} の値を入力してください：",
+                            "Value of {}:",
+                            key
+                        )
+                    );
+                    let mut line = String::new();
+                    std::io::stdin().lock().read_line(&mut line)?;
+                    line.trim().to_string()
+                }
+            };
+            set(key, &value)?;
+            println!(
+                "{}",
+                tr!(
+                    "已保存 {} 到钥匙串",
+                    "{} をキーチェーンに保存しました",
+                    "Stored {} in the keychain",
+                    key
+                )
+            );
+        }
+        SecretCommand::Has { key } => match get(key)? {
+            Some(_) => println!(
+                "{}",
+                tr!("已保存 {}", "{} は保存済みです", "{} is stored", key)
+            ),
+            None => anyhow::bail!(tr!("没有 {}", "{} はありません", "No {} stored", key)),
+        },
+        SecretCommand::Delete { key } => match delete(key)? {
+            true => println!(
+                "{}",
+                tr!("已删除 {}", "{} を削除しました", "Deleted {}", key)
+            ),
+            false => println!("{}", tr!("没有 {}", "{} はありません", "No {} stored", key)),
+        },
+    }
+    Ok(())
+}
+
+#[cfg(feature = "keychain")]
+mod imp {
+    use anyhow::Result;
+    use keyring::{Entry, Error};
+
+    /// Windows Credential Manager, the macOS Keychain or the Secret Service.
+    fn entry(account: &str) -> Result<Entry> {
+        Ok(Entry::new(env!("CARGO_PKG_NAME"), account)?)
+    }
+
+    pub fn get(account: &str) -> Result<Option<String>> {
+        match entry(account)?.get_password() {
+            Ok(secret) => Ok(Some(secret)),
+            Err(Error::NoEntry) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn set(account: &str, value: &str) -> Result<()> {
+        Ok(entry(account)?.set_password(value)?)
+    }
+
+    pub fn delete(account: &str) -> Result<bool> {
+        match entry(account)?.delete_credential() {
+            Ok(()) => Ok(true),
+            Err(Error::NoEntry) => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+#[cfg(not(feature = "keychain"))]
+mod imp {
+    use anyhow::{bail, Result};
+
+    const MISSING: &str = "Keychain support is not compiled in, it needs the keychain feature";
+
+    pub fn get(_account: &str) -> Result<Option<String>> {
+        bail!(MISSING)
+    }
+
+    pub fn set(_account: &str, _value: &str) -> Result<()> {
+        bail!(MISSING)
+    }
+
+    pub fn delete(_account: &str) -> Result<bool> {
+        bail!(MISSING)
+    }
+}
@@ -0,0 +1,157 @@
+use crate::players::{GameInfo, RoomType};
+use chrono::{DateTime, Local, NaiveDate};
+use serde_json::Value as JsonValue;
+
+/// Rank tier of a ranked room.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Tier {
+    Bronze,
+    Silver,
+    Gold,
+    Jade,
+    Throne,
+}
+
+impl Tier {
+    pub fn from_mode_id(mode_id: u32) -> Option<Self> {
+        match mode_id {
+            2 | 3 | 17 | 18 => Some(Tier::Bronze),
+            5 | 6 | 19 | 20 => Some(Tier::Silver),
+            8 | 9 | 21 | 22 => Some(Tier::Gold),
+            11 | 12 | 23 | 24 => Some(Tier::Jade),
+            15 | 16 | 25 | 26 => Some(Tier::Throne),
+            _ => None,
+        }
+    }
+}
+
+/// East only or half game.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Length {
+    East,
+    South,
+}
+
+impl Length {
+    /// From `GameMode.mode`.
+    fn from_mode(mode: u64) -> Option<Self> {
+        match mode {
+            1 | 11 => Some(Length::East),
+            2 | 12 => Some(Length::South),
+            _ => None,
+        }
+    }
+
+    /// Ranked rooms alternate east and south modes.
+    fn from_mode_id(mode_id: u32) -> Option<Self> {
+        match mode_id {
+            2 | 5 | 8 | 11 | 15 | 17 | 19 | 21 | 23 | 25 => Some(Length::East),
+            3 | 6 | 9 | 12 | 16 | 18 | 20 | 22 | 24 | 26 => Some(Length::South),
+            _ => None,
+        }
+    }
+}
+
+/// What a filter looks at, from a record head or the database.
+#[derive(Debug, Clone, Default)]
+pub struct GameFacts {
+    pub start_time: Option<i64>,
+    pub room: RoomType,
+    /// ranked room mode, e.g. 12 for the jade room south game
+    pub mode_id: u32,
+    pub player_count: usize,
+    pub length: Option<Length>,
+}
+
+impl GameFacts {
+    /// From a `RecordGame`, as in a record file or the record list.
+    pub fn from_head(head: &JsonValue) -> Self {
+        let info = GameInfo::from_record(head, 0);
+        let length = head["config"]["mode"]["mode"]
+            .as_u64()
+            .and_then(Length::from_mode)
+            .or_else(|| Length::from_mode_id(info.mode_id));
+        GameFacts {
+            start_time: head["start_time"].as_i64(),
+            room: info.room,
+            mode_id: info.mode_id,
+            player_count: info.players.len(),
+            length,
+        }
+    }
+
+    /// Without the room rules, only ranked games have a known length.
+    pub fn from_stored(
+        start_time: Option<i64>,
+        room: RoomType,
+        mode_id: u32,
+        player_count: usize,
+    ) -> Self {
+        GameFacts {
+            start_time,
+            room,
+            mode_id,
+            player_count,
+            length: Length::from_mode_id(mode_id),
+        }
+    }
+}
+
+/// Which games a batch command works on, every game when nothing is set.
+#[derive(clap::Args, Debug, Clone, Default)]
+pub struct GameFilter {
+    /// first day included, e.g. 2024-01-01
+    #[clap(long)]
+    pub since: Option<NaiveDate>,
+    /// last day included
+    #[clap(long)]
+    pub until: Option<NaiveDate>,
+    #[clap(long, value_enum)]
+    pub room: Option<RoomType>,
+    /// ranked room tier, implies a ranked room
+    #[clap(long, value_enum)]
+    pub tier: Option<Tier>,
+    /// ranked room mode, e.g. 12 for the jade room south game
+    #[clap(long)]
+    pub mode_id: Option<u32>,
+    /// 3 or 4 player games only
+    #[clap(long)]
+    pub players: Option<usize>,
+    /// east only or half games
+    #[clap(long, value_enum)]
+    pub length: Option<Length>,
+}
+
+impl GameFilter {
+    pub fn is_empty(&self) -> bool {
+        self.since.is_none()
+            && self.until.is_none()
+            && self.room.is_none()
+            && self.tier.is_none()
+            && self.mode_id.is_none()
+            && self.players.is_none()
+            && self.length.is_none()
+    }
+
+    pub fn matches(&self, game: &GameFacts) -> bool {
+        let day = game
+            .start_time
+            .and_then(|t| DateTime::from_timestamp(t, 0))
+            .map(|t| t.with_timezone(&Local).date_naive());
+        let in_range = match (self.since, self.until, day) {
+            (None, None, _) => true,
+            (_, _, None) => false,
+            (since, until, Some(day)) => {
+                since.is_none_or(|s| day >= s) && until.is_none_or(|u| day <= u)
+            }
+        };
+        in_range
+            && self.room.is_none_or(|r| r == game.room)
+            && self.tier.is_none_or(|t| {
+                game.room == RoomType::Ranked && Tier::from_mode_id(game.mode_id) == Some(t)
+            })
+            && self.mode_id.is_none_or(|m| m == game.mode_id)
+            && self.players.is_none_or(|p| p == game.player_count)
+            && self.length.is_none_or(|l| game.length == Some(l))
+    }
+}
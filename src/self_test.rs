@@ -0,0 +1,161 @@
+use crate::{
+    dump::{decode_frame, read_frames},
+    parser::{LiqiMessage, MessageType, Parser},
+    tr,
+};
+use anyhow::{bail, ensure, Result};
+use serde_json::{json, Value as JsonValue};
+use std::path::PathBuf;
+
+/// Frames of the fake game of `simulate fake-game`, accounts and names made up.
+const DUMP: &[u8] = include_bytes!("../samples/fake_game.dump");
+/// What they decode to, a line per frame, written by `self-test --write`.
+const EXPECTED: &str = include_str!("../samples/fake_game.expected.jsonl");
+/// Mismatches printed, the rest are only counted.
+const SHOWN: usize = 10;
+
+#[derive(clap::Args, Debug)]
+pub struct SelfTestArgs {
+    /// write what the frames decode to with the loaded liqi to this file instead
+    /// of comparing, e.g. `samples/fake_game.expected.jsonl` after a liqi update
+    #[clap(long)]
+    write: Option<PathBuf>,
+}
+
+fn snapshot(msg: &LiqiMessage) -> JsonValue {
+    // of a notify, only how many frames came before, shifted by any failed one
+    let id = match msg.msg_type {
+        MessageType::Notify => JsonValue::Null,
+        _ => json!(msg.id),
+    };
+    json!({
+        "type": msg.msg_type.name(),
+        "id": id,
+        "method": msg.method_name.as_ref(),
+        "data": msg.data,
+    })
+}
+
+/// Where `got` differs from `expected` as a JSON pointer. Fields only in
+/// `got` are fine, a newer liqi decodes the fields it adds as their defaults.
+fn difference(expected: &JsonValue, got: &JsonValue, path: &str) -> Option<String> {
+    match (expected, got) {
+        (JsonValue::Object(expected), JsonValue::Object(got)) => {
+            expected.iter().find_map(|(key, value)| {
+                let path = format!("{}/{}", path, key);
+                match got.get(key) {
+                    Some(got) => difference(value, got, &path),
+                    None => Some(tr!("{} 缺失", "{} がありません", "{} missing", path)),
+                }
+            })
+        }
+        (JsonValue::Array(expected), JsonValue::Array(got)) if expected.len() == got.len() => {
+            expected
+                .iter()
+                .zip(got)
+                .enumerate()
+                .find_map(|(i, (expected, got))| {
+                    difference(expected, got, &format!("{}/{}", path, i))
+                })
+        }
+        _ if expected == got => None,
+        _ => Some(tr!(
+            "{} 为 {}，应为 {}",
+            "{} が {}、期待値 {}",
+            "{} is {} instead of {}",
+            path,
+            got,
+            expected
+        )),
+    }
+}
+
+/// Decode the bundled frames with the loaded liqi and compare them with the
+/// snapshot, failing when any differs.
+pub fn run(args: &SelfTestArgs) -> Result<()> {
+    let frames = read_frames(DUMP);
+    let mut parser = Parser::default();
+    let decoded: Vec<Result<JsonValue>> = frames
+        .iter()
+        .map(|frame| decode_frame(&mut parser, frame).map(|msg| snapshot(&msg)))
+        .collect();
+    if let Some(path) = &args.write {
+        let mut out = String::new();
+        for got in decoded {
+            out.push_str(&serde_json::to_string(&got?)?);
+            out.push('\n');
+        }
+        std::fs::write(path, out)?;
+        println!(
+            "{}",
+            tr!(
+                "已把 {} 帧的解码结果写入 {}",
+                "{} フレームのデコード結果を {} に書き込みました",
+                "Wrote what {} frames decode to to {}",
+                frames.len(),
+                path.display()
+            )
+        );
+        return Ok(());
+    }
+    let expected: Vec<JsonValue> = EXPECTED
+        .lines()
+        .map(serde_json::from_str)
+        .collect::<Result<_, _>>()?;
+    ensure!(
+        expected.len() == frames.len(),
+        "The snapshot has {} frames, the dump {}",
+        expected.len(),
+        frames.len()
+    );
+    let mut failed = 0;
+    for (i, (got, expected)) in decoded.iter().zip(&expected).enumerate() {
+        let problem = match got {
+            Ok(got) => difference(expected, got, ""),
+            Err(e) => Some(format!("{:#}", e)),
+        };
+        let Some(problem) = problem else {
+            continue;
+        };
+        failed += 1;
+        if failed <= SHOWN {
+            println!(
+                "  #{} {} {}: {}",
+                i,
+                expected["type"].as_str().unwrap_or_default(),
+                expected["method"].as_str().unwrap_or_default(),
+                problem
+            );
+        }
+    }
+    if failed > SHOWN {
+        println!(
+            "{}",
+            tr!(
+                "  ……另有 {} 帧不符",
+                "  ……ほか {} フレームが不一致",
+                "  ... and {} more",
+                failed - SHOWN
+            )
+        );
+    }
+    if failed > 0 {
+        bail!(tr!(
+            "自检失败：{} 帧中有 {} 帧与预期不符，liqi可能已过旧或损坏，可运行`doctor`检查",
+            "セルフテスト失敗：{} フレーム中 {} フレームが期待と異なります。liqi が古いか壊れている可能性があります。`doctor` で確認してください",
+            "Self-test failed: {1} of {0} frames differ from what they should decode to, liqi may be outdated or broken, see `doctor`",
+            frames.len(),
+            failed
+        ));
+    }
+    println!(
+        "{}",
+        tr!(
+            "自检通过：{} 帧均按预期解码",
+            "セルフテスト成功：{} フレームすべて期待どおりにデコードされました",
+            "Self-test passed: all {} frames decode as they should",
+            frames.len()
+        )
+    );
+    Ok(())
+}
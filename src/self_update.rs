@@ -0,0 +1,292 @@
+use crate::{settings::REQUEST_CLIENT, tr};
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use clap::Args;
+use semver::Version;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::{
+    io::Read,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tracing::{info, warn};
+
+const LATEST: &str = "https://api.github.com/repos/Xerxes-2/MajsoulMax-rs/releases/latest";
+const BINARY: &str = env!("CARGO_PKG_NAME");
+
+#[derive(Args, Debug)]
+pub struct SelfUpdateArgs {
+    /// only report whether a newer release exists
+    #[clap(long)]
+    check: bool,
+    /// install the latest release even when it is not newer
+    #[clap(long)]
+    force: bool,
+}
+
+/// Target of the release archives built for this platform, see the release workflow.
+fn target() -> Option<&'static str> {
+    if cfg!(all(windows, target_arch = "x86_64")) {
+        Some("x86_64-pc-windows-msvc")
+    } else if cfg!(all(target_os = "macos", target_arch = "x86_64")) {
+        Some("x86_64-apple-darwin")
+    } else if cfg!(all(target_os = "macos", target_arch = "aarch64")) {
+        Some("aarch64-apple-darwin")
+    } else if cfg!(all(target_os = "android", target_arch = "aarch64")) {
+        Some("aarch64-linux-android")
+    } else if cfg!(all(target_os = "linux", target_arch = "x86_64")) {
+        Some("x86_64-unknown-linux-musl")
+    } else {
+        None
+    }
+}
+
+struct Release {
+    tag: String,
+    version: Version,
+    /// name and download url of each asset
+    assets: Vec<(String, String)>,
+}
+
+impl Release {
+    fn asset(&self, name: &str) -> Option<&str> {
+        self.assets
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, url)| url.as_str())
+    }
+}
+
+async fn latest() -> Result<Release> {
+    let resp = REQUEST_CLIENT
+        .get(LATEST)
+        .timeout(Duration::from_secs(10))
+        .send()
+        .await?;
+    if resp
+        .headers()
+        .get("x-ratelimit-remaining")
+        .is_some_and(|remaining| remaining == "0")
+    {
+        bail!("GitHub API rate limit exceeded");
+    }
+    let json: Value = resp.error_for_status()?.json().await?;
+    let tag = json["tag_name"]
+        .as_str()
+        .ok_or(anyhow!("No tag found in latest release"))?
+        .to_string();
+    let version = Version::parse(tag.trim_start_matches('v'))
+        .with_context(|| format!("Release tag {} is not a version", tag))?;
+    let assets = json["assets"]
+        .as_array()
+        .ok_or(anyhow!("No assets found in latest release"))?
+        .iter()
+        .filter_map(|asset| {
+            Some((
+                asset["name"].as_str()?.to_string(),
+                asset["browser_download_url"].as_str()?.to_string(),
+            ))
+        })
+        .collect();
+    Ok(Release {
+        tag,
+        version,
+        assets,
+    })
+}
+
+fn current() -> Version {
+    Version::parse(env!("CARGO_PKG_VERSION")).expect("the package version is semver")
+}
+
+async fn download(url: &str) -> Result<Vec<u8>> {
+    let resp = REQUEST_CLIENT
+        .get(url)
+        .timeout(Duration::from_secs(300))
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(resp.bytes().await?.to_vec())
+}
+
+/// The hex digest of a `sha256sum` line, `<hex>  <name>`.
+fn expected_digest(text: &str) -> Result<String> {
+    let digest = text
+        .split_whitespace()
+        .next()
+        .ok_or(anyhow!("Empty checksum file"))?;
+    ensure!(
+        digest.len() == 64 && digest.chars().all(|c| c.is_ascii_hexdigit()),
+        "Malformed checksum {}",
+        digest
+    );
+    Ok(digest.to_lowercase())
+}
+
+/// The executable in a release archive, `<dir>/majsoul_max_rs[.exe]`.
+fn extract(name: &str, archive: &[u8]) -> Result<Vec<u8>> {
+    let binary = format!("{}{}", BINARY, std::env::consts::EXE_SUFFIX);
+    let is_binary = |path: &Path| path.file_name().is_some_and(|n| n == binary.as_str());
+    let mut out = vec![];
+    if name.ends_with(".zip") {
+        let mut zip = zip::ZipArchive::new(std::io::Cursor::new(archive))?;
+        for i in 0..zip.len() {
+            let mut file = zip.by_index(i)?;
+            if file.enclosed_name().is_some_and(|p| is_binary(&p)) {
+                file.read_to_end(&mut out)?;
+                return Ok(out);
+            }
+        }
+    } else {
+        let mut tar = tar::Archive::new(flate2::read::GzDecoder::new(archive));
+        for entry in tar.entries()? {
+            let mut entry = entry?;
+            if is_binary(&entry.path()?) {
+                entry.read_to_end(&mut out)?;
+                return Ok(out);
+            }
+        }
+    }
+    bail!("{} not found in {}", binary, name)
+}
+
+/// Where the running executable is moved before being replaced, Windows
+/// does not allow overwriting it.
+fn old_path(exe: &Path) -> PathBuf {
+    let mut name = exe.file_name().unwrap_or_default().to_os_string();
+    name.push(".old");
+    exe.with_file_name(name)
+}
+
+/// Put `binary` in place of the running executable, effective on the next start.
+fn replace(binary: &[u8]) -> Result<PathBuf> {
+    let exe = std::env::current_exe()?;
+    let mut name = exe.file_name().unwrap_or_default().to_os_string();
+    name.push(".new");
+    let new = exe.with_file_name(name);
+    std::fs::write(&new, binary)
+        .with_context(|| format!("Cannot write next to {}", exe.display()))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&new, std::fs::Permissions::from_mode(0o755))?;
+    }
+    if cfg!(windows) {
+        let old = old_path(&exe);
+        let _ = std::fs::remove_file(&old);
+        std::fs::rename(&exe, &old)?;
+        if let Err(e) = std::fs::rename(&new, &exe) {
+            // put the running one back
+            let _ = std::fs::rename(&old, &exe);
+            return Err(e.into());
+        }
+    } else {
+        std::fs::rename(&new, &exe)?;
+    }
+    Ok(exe)
+}
+
+/// Remove the executable replaced by the last update, once it no longer runs.
+pub fn cleanup() {
+    if let Ok(exe) = std::env::current_exe() {
+        let _ = std::fs::remove_file(old_path(&exe));
+    }
+}
+
+/// Download the release archive for this platform, check it against the
+/// `.sha256` published with it and swap the executable.
+pub async fn run(args: &SelfUpdateArgs) -> Result<()> {
+    let release = latest().await?;
+    let current = current();
+    if release.version <= current && !args.force {
+        info!(
+            "{}",
+            tr!(
+                "已是最新版本 {}",
+                "最新バージョン {} です",
+                "Already the latest version {}",
+                current
+            )
+        );
+        return Ok(());
+    }
+    info!(
+        "{}",
+        tr!(
+            "新版本 {}，当前版本 {}",
+            "新しいバージョン {}、現在のバージョン {}",
+            "New version {}, current version {}",
+            release.version,
+            current
+        )
+    );
+    if args.check {
+        return Ok(());
+    }
+    let target = target().ok_or(anyhow!(
+        "No release is built for {}-{}",
+        std::env::consts::ARCH,
+        std::env::consts::OS
+    ))?;
+    let extension = if cfg!(windows) { "zip" } else { "tar.gz" };
+    let name = format!("{}-{}-{}.{}", BINARY, release.tag, target, extension);
+    let url = release
+        .asset(&name)
+        .ok_or(anyhow!("Release {} has no {}", release.tag, name))?;
+    let checksum = release.asset(&format!("{}.sha256", name)).ok_or(anyhow!(tr!(
+        "发布 {} 没有校验文件 {}.sha256，无法验证，请手动下载",
+        "リリース {} に検証ファイル {}.sha256 がないため検証できません。手動でダウンロードしてください",
+        "Release {} has no {}.sha256 to verify it against, download it by hand",
+        release.tag,
+        name
+    )))?;
+    let expected = expected_digest(&String::from_utf8_lossy(&download(checksum).await?))?;
+    info!(
+        "{}",
+        tr!("正在下载 {}", "{} をダウンロード中", "Downloading {}", name)
+    );
+    let archive = download(url).await?;
+    let actual = format!("{:x}", Sha256::digest(&archive));
+    ensure!(
+        actual == expected,
+        "{}",
+        tr!(
+            "{} 校验失败：应为 {}，实为 {}",
+            "{} の検証に失敗しました：期待値 {}、実際 {}",
+            "Checksum of {} does not match: expected {}, got {}",
+            name,
+            expected,
+            actual
+        )
+    );
+    let binary = extract(&name, &archive)?;
+    let exe = replace(&binary)?;
+    info!(
+        "{}",
+        tr!(
+            "已更新到 {}：{}，重启程序后生效",
+            "{} に更新しました：{}。再起動後に有効になります",
+            "Updated to {}: {}, restart the program to use it",
+            release.version,
+            exe.display()
+        )
+    );
+    Ok(())
+}
+
+/// Log when a newer release exists, for the `checkUpdates` setting.
+pub async fn notify_newer() {
+    match latest().await {
+        Ok(release) if release.version > current() => info!(
+            "{}",
+            tr!(
+                "新版本 {} 可用，运行`self-update`更新",
+                "新しいバージョン {} があります。`self-update` で更新できます",
+                "Version {} is available, run `self-update` to update",
+                release.version
+            )
+        ),
+        Ok(_) => {}
+        Err(e) => warn!("Failed to check for updates: {:#}", e),
+    }
+}
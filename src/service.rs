@@ -0,0 +1,245 @@
+use anyhow::Result;
+use clap::Subcommand;
+use tokio::sync::oneshot;
+
+pub const SERVICE_NAME: &str = "majsoul_max_rs";
+
+#[derive(Subcommand, Debug)]
+pub enum ServiceCommand {
+    /// Register the proxy as a service starting at boot, with the current
+    /// `--config-dir`, `--config` and `--profile`
+    Install,
+    /// Stop the service and remove it
+    Uninstall,
+    /// Start the installed service
+    Start,
+    /// Stop the running service
+    Stop,
+    /// Entry point of the service control manager, not for use by hand
+    #[clap(hide = true)]
+    Run,
+}
+
+/// Handle the commands that manage the service, everything but `run`.
+#[cfg(windows)]
+pub fn manage(command: &ServiceCommand) -> Result<()> {
+    windows::manage(command)
+}
+
+/// Hand the process to the service control manager, which calls `serve` with
+/// a receiver firing once the service is asked to stop.
+#[cfg(windows)]
+pub fn dispatch(serve: impl FnOnce(oneshot::Receiver<()>) + Send + 'static) -> Result<()> {
+    windows::dispatch(Box::new(serve))
+}
+
+#[cfg(not(windows))]
+pub fn manage(_command: &ServiceCommand) -> Result<()> {
+    anyhow::bail!("Services are only supported on Windows")
+}
+
+#[cfg(not(windows))]
+pub fn dispatch(_serve: impl FnOnce(oneshot::Receiver<()>) + Send + 'static) -> Result<()> {
+    anyhow::bail!("Services are only supported on Windows")
+}
+
+#[cfg(windows)]
+mod windows {
+    use super::{ServiceCommand, SERVICE_NAME};
+    use crate::{tr, ARG, SETTINGS};
+    use anyhow::{anyhow, Result};
+    use std::{
+        ffi::{OsStr, OsString},
+        sync::Mutex,
+        time::Duration,
+    };
+    use tokio::sync::oneshot;
+    use tracing::{error, info};
+    use windows_service::{
+        define_windows_service,
+        service::{
+            ServiceAccess, ServiceControl, ServiceControlAccept, ServiceErrorControl,
+            ServiceExitCode, ServiceInfo, ServiceStartType, ServiceState, ServiceStatus,
+            ServiceType,
+        },
+        service_control_handler::{self, ServiceControlHandlerResult},
+        service_dispatcher,
+        service_manager::{ServiceManager, ServiceManagerAccess},
+    };
+
+    type Serve = Box<dyn FnOnce(oneshot::Receiver<()>) + Send>;
+
+    /// The service main of the control manager takes no context.
+    static SERVE: Mutex<Option<Serve>> = Mutex::new(None);
+
+    define_windows_service!(ffi_service_main, service_main);
+
+    pub fn dispatch(serve: Serve) -> Result<()> {
+        *SERVE.lock().unwrap() = Some(serve);
+        service_dispatcher::start(SERVICE_NAME, ffi_service_main)?;
+        Ok(())
+    }
+
+    fn status(state: ServiceState) -> ServiceStatus {
+        ServiceStatus {
+            service_type: ServiceType::OWN_PROCESS,
+            current_state: state,
+            controls_accepted: match state {
+                ServiceState::Running => ServiceControlAccept::STOP,
+                _ => ServiceControlAccept::empty(),
+            },
+            exit_code: ServiceExitCode::Win32(0),
+            checkpoint: 0,
+            wait_hint: Duration::default(),
+            process_id: None,
+        }
+    }
+
+    fn service_main(_arguments: Vec<OsString>) {
+        let Some(serve) = SERVE.lock().unwrap().take() else {
+            return;
+        };
+        let (tx, rx) = oneshot::channel();
+        let tx = Mutex::new(Some(tx));
+        let handler = move |control| match control {
+            ServiceControl::Stop => {
+                if let Some(tx) = tx.lock().unwrap().take() {
+                    let _ = tx.send(());
+                }
+                ServiceControlHandlerResult::NoError
+            }
+            ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+            _ => ServiceControlHandlerResult::NotImplemented,
+        };
+        let handle = match service_control_handler::register(SERVICE_NAME, handler) {
+            Ok(handle) => handle,
+            Err(e) => {
+                error!("Failed to register service control handler: {:?}", e);
+                return;
+            }
+        };
+        if let Err(e) = handle.set_service_status(status(ServiceState::Running)) {
+            error!("Failed to report service status: {:?}", e);
+        }
+        info!(
+            "{}",
+            tr!("服务已启动", "サービスを開始しました", "Service started")
+        );
+        serve(rx);
+        info!(
+            "{}",
+            tr!("服务已停止", "サービスを停止しました", "Service stopped")
+        );
+        let _ = handle.set_service_status(status(ServiceState::Stopped));
+    }
+
+    /// Arguments the service is started with, so that it finds the same settings.
+    fn launch_arguments() -> Result<Vec<OsString>> {
+        let dir = std::fs::canonicalize(SETTINGS.config_dir())?;
+        let mut args = vec!["--config-dir".into(), dir.into_os_string()];
+        if let Some(config) = &ARG.config {
+            args.push("--config".into());
+            args.push(std::fs::canonicalize(config)?.into_os_string());
+        }
+        if let Some(profile) = &ARG.profile {
+            args.push("--profile".into());
+            args.push(profile.into());
+        }
+        args.push("service".into());
+        args.push("run".into());
+        Ok(args)
+    }
+
+    pub fn manage(command: &ServiceCommand) -> Result<()> {
+        let manager = ServiceManager::local_computer(
+            None::<&str>,
+            ServiceManagerAccess::CONNECT | ServiceManagerAccess::CREATE_SERVICE,
+        )
+        .map_err(|e| {
+            anyhow!(tr!(
+                "无法连接服务管理器，请以管理员身份运行: {:?}",
+                "サービスマネージャーに接続できません。管理者として実行してください: {:?}",
+                "Cannot connect to the service manager, run as administrator: {:?}",
+                e
+            ))
+        })?;
+        match command {
+            ServiceCommand::Install => {
+                let info = ServiceInfo {
+                    name: SERVICE_NAME.into(),
+                    display_name: "MajsoulMax-rs".into(),
+                    service_type: ServiceType::OWN_PROCESS,
+                    start_type: ServiceStartType::AutoStart,
+                    error_control: ServiceErrorControl::Normal,
+                    executable_path: std::env::current_exe()?,
+                    launch_arguments: launch_arguments()?,
+                    dependencies: vec![],
+                    account_name: None,
+                    account_password: None,
+                };
+                let service = manager.create_service(&info, ServiceAccess::CHANGE_CONFIG)?;
+                service.set_description(tr!(
+                    "雀魂代理，开机自动启动",
+                    "雀魂プロキシ、起動時に自動で開始",
+                    "Mahjong Soul proxy, started at boot"
+                ))?;
+                info!(
+                    "{}",
+                    tr!(
+                        "服务 {} 已安装，将在开机时启动",
+                        "サービス {} をインストールしました。起動時に開始されます",
+                        "Service {} installed, it starts at boot",
+                        SERVICE_NAME
+                    )
+                );
+            }
+            ServiceCommand::Uninstall => {
+                let service = manager.open_service(
+                    SERVICE_NAME,
+                    ServiceAccess::QUERY_STATUS | ServiceAccess::STOP | ServiceAccess::DELETE,
+                )?;
+                if service.query_status()?.current_state != ServiceState::Stopped {
+                    service.stop()?;
+                }
+                service.delete()?;
+                info!(
+                    "{}",
+                    tr!(
+                        "服务 {} 已卸载",
+                        "サービス {} をアンインストールしました",
+                        "Service {} uninstalled",
+                        SERVICE_NAME
+                    )
+                );
+            }
+            ServiceCommand::Start => {
+                let service = manager.open_service(SERVICE_NAME, ServiceAccess::START)?;
+                service.start(&[] as &[&OsStr])?;
+                info!(
+                    "{}",
+                    tr!(
+                        "服务 {} 已启动",
+                        "サービス {} を開始しました",
+                        "Service {} started",
+                        SERVICE_NAME
+                    )
+                );
+            }
+            ServiceCommand::Stop => {
+                let service = manager.open_service(SERVICE_NAME, ServiceAccess::STOP)?;
+                service.stop()?;
+                info!(
+                    "{}",
+                    tr!(
+                        "服务 {} 已停止",
+                        "サービス {} を停止しました",
+                        "Service {} stopped",
+                        SERVICE_NAME
+                    )
+                );
+            }
+            ServiceCommand::Run => return Err(anyhow!("`service run` is started by Windows")),
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,101 @@
+use crate::state::GameState;
+use chrono::{Local, NaiveDate};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use tokio::sync::RwLock;
+
+/// Results of the own games since startup or today, see `sessionReset`.
+pub static SESSION: Lazy<RwLock<Session>> = Lazy::new(|| RwLock::new(Session::default()));
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum SessionReset {
+    /// keep everything since the proxy started
+    #[default]
+    Startup,
+    /// start over on the first game of each local day
+    Daily,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct SessionGame {
+    pub game_uuid: Option<String>,
+    pub players: usize,
+    /// 1 for the top
+    pub rank: u32,
+    pub score: i32,
+    /// final points with oka and uma, ×1000 as majsoul sends them
+    pub total_point: i32,
+    pub ended_at: String,
+}
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct Session {
+    /// local day of the session, only set in daily mode
+    pub day: Option<NaiveDate>,
+    pub games: u32,
+    /// games by final rank, first place first
+    pub placements: [u32; 4],
+    pub avg_rank: f64,
+    pub net_points: i32,
+    /// current run of first places
+    pub top_streak: u32,
+    pub best_top_streak: u32,
+    /// current run of games not finished last
+    pub no_last_streak: u32,
+    pub history: Vec<SessionGame>,
+}
+
+impl Session {
+    /// Add an own game from its `.lq.NotifyGameEndResult`, `None` when the own seat is unknown.
+    pub fn record(
+        &mut self,
+        data: &JsonValue,
+        state: &GameState,
+        reset: SessionReset,
+    ) -> Option<&Session> {
+        let seat = state.seat?;
+        let mut players: Vec<&JsonValue> = data["result"]["players"].as_array()?.iter().collect();
+        // majsoul breaks ties by the starting seat
+        players.sort_by_key(|p| {
+            (
+                -p["total_point"].as_i64().unwrap_or_default(),
+                p["seat"].as_u64().unwrap_or_default(),
+            )
+        });
+        let index = players
+            .iter()
+            .position(|p| p["seat"].as_u64() == Some(seat as u64))?;
+        let own = players[index];
+        let now = Local::now();
+        if reset == SessionReset::Daily && self.day != Some(now.date_naive()) {
+            *self = Session {
+                day: Some(now.date_naive()),
+                ..Default::default()
+            };
+        }
+        let rank = index as u32 + 1;
+        let total_point = own["total_point"].as_i64().unwrap_or_default() as i32;
+        self.history.push(SessionGame {
+            game_uuid: state.game.as_ref().and_then(|g| g.game_uuid.clone()),
+            players: players.len(),
+            rank,
+            score: own["part_point_1"].as_i64().unwrap_or_default() as i32,
+            total_point,
+            ended_at: now.to_rfc3339(),
+        });
+        self.games += 1;
+        self.placements[index.min(3)] += 1;
+        self.net_points += total_point;
+        self.avg_rank = self.history.iter().map(|g| g.rank as f64).sum::<f64>() / self.games as f64;
+        self.top_streak = if rank == 1 { self.top_streak + 1 } else { 0 };
+        self.best_top_streak = self.best_top_streak.max(self.top_streak);
+        self.no_last_streak = if index + 1 < players.len() {
+            self.no_last_streak + 1
+        } else {
+            0
+        };
+        Some(self)
+    }
+}
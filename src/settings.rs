@@ -1,13 +1,20 @@
-use crate::{lq::ViewSlot, ARG, SETTINGS};
+use crate::{
+    api::ApiConfig, daily::Timezone, engine::EngineConfig, helper::FrameQueueConfig, i18n::Lang,
+    image::HandImageConfig, instance, logging::LogConfig, lq::ViewSlot, memory::MemoryConfig,
+    notify::NotifyConfig, parser::OrphanResponses, report::ReportConfig, schedule::ScheduleConfig,
+    session::SessionReset, sink::SinkConfig, stats::StatsApiConfig, tile::Notation, tr, ARG,
+    SETTINGS,
+};
 use anyhow::{anyhow, Result};
 use bytes::Bytes;
-use once_cell::sync::Lazy;
+use once_cell::sync::{Lazy, OnceCell};
 use prost_reflect::DescriptorPool;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::{
     collections::{HashMap, HashSet},
-    path::PathBuf,
+    path::{Path, PathBuf},
+    sync::Arc,
 };
 use tracing::info;
 
@@ -18,6 +25,92 @@ pub struct Settings {
     pub send_action: Vec<String>,
     pub proxy_addr: String,
     pub api_url: String,
+    /// sinks receiving parsed messages, defaults to posting liqi JSON to `api_url`
+    #[serde(default)]
+    pub sinks: Vec<SinkConfig>,
+    /// address of the local admin API, disabled when empty
+    #[serde(default)]
+    pub admin_addr: String,
+    /// mjai engine asked for a move at every own decision
+    #[serde(default)]
+    pub engine: Option<EngineConfig>,
+    /// public stats API the opponents of own games are looked up on
+    #[serde(default)]
+    pub stats_api: Option<StatsApiConfig>,
+    /// report written at the end of every own game
+    #[serde(default)]
+    pub report: Option<ReportConfig>,
+    /// seconds between snapshots of the own game, restored after a restart, 0 to disable
+    #[serde(default = "default_snapshot_interval")]
+    pub snapshot_interval_secs: u64,
+    /// PNG of the own hand rewritten after every action
+    #[serde(default)]
+    pub hand_image: Option<HandImageConfig>,
+    /// desktop notifications of own games
+    #[serde(default)]
+    pub notifications: Option<NotifyConfig>,
+    /// when the session summary starts over
+    #[serde(default)]
+    pub session_reset: SessionReset,
+    /// tile notation of the printed board and logs
+    #[serde(default)]
+    pub tile_notation: Notation,
+    /// SQLite database own games are written to, requires the `sqlite` feature
+    #[serde(default)]
+    pub database: Option<PathBuf>,
+    /// file every request of the client is logged to, see [`crate::audit`]
+    #[serde(default)]
+    pub audit_log: Option<PathBuf>,
+    /// folder own games are saved to as records when they end
+    #[serde(default)]
+    pub live_record_dir: Option<PathBuf>,
+    /// save them in a folder per day under `live_record_dir`
+    #[serde(default)]
+    pub live_record_daily: bool,
+    /// IANA name like `Asia/Tokyo` or UTC offset the days of daily folders are
+    /// counted in, the system timezone when unset
+    #[serde(default)]
+    pub timezone: Option<Timezone>,
+    /// capture windows and the nightly record sync, in `timezone`
+    #[serde(default)]
+    pub schedule: ScheduleConfig,
+    /// frames waiting for the helper, and what happens when it falls behind
+    #[serde(default)]
+    pub frame_queue: FrameQueueConfig,
+    /// what happens to responses whose request was not seen, see [`OrphanResponses`]
+    #[serde(default)]
+    pub orphan_responses: OrphanResponses,
+    /// last frames kept of each session for `POST /debug/dump`, see [`crate::flight`]
+    #[serde(default = "default_debug_frames")]
+    pub debug_frames: usize,
+    /// cap of the memory held by requests, sink queues and the live recorder
+    #[serde(default)]
+    pub memory: MemoryConfig,
+    /// the web pages the admin API answers, see [`ApiConfig`]
+    #[serde(default)]
+    pub api: ApiConfig,
+    /// seconds the game server may stay silent before its websocket is closed
+    /// for the client to reconnect, 0 to wait forever, see [`crate::watchdog`]
+    #[serde(default = "default_upstream_stall")]
+    pub upstream_stall_secs: u64,
+    /// log filter such as `majsoul_max_rs=debug`, in place of `RUST_LOG`
+    #[serde(default)]
+    pub log_level: Option<String>,
+    /// console format and log files
+    #[serde(default)]
+    pub log: LogConfig,
+    /// folder of the liqi files, e.g. for a profile of another server
+    #[serde(default)]
+    liqi_dir: Option<PathBuf>,
+    /// server the live liqi version is checked against
+    #[serde(default)]
+    pub region: Region,
+    /// `zh-CN`, `ja` or `en` for messages to the user, the system locale when unset
+    #[serde(default)]
+    pub language: Option<Lang>,
+    /// look for a newer release at startup, see `self-update`
+    #[serde(default)]
+    pub check_updates: bool,
     helper_switch: i32,
     mod_switch: i32,
     auto_update: i32,
@@ -27,15 +120,56 @@ pub struct Settings {
     #[serde(skip)]
     actions_set: HashSet<String>,
     #[serde(skip)]
-    pub desc: DescriptorPool,
-    #[serde(skip)]
-    pub proto_json: Value,
+    liqi: Arc<Liqi>,
     #[serde(skip)]
     dir: PathBuf,
 }
 
+/// liqi.desc and liqi.json, each read at first use, as most subcommands need
+/// neither and `parse` rarely needs liqi.json.
+#[derive(Debug, Default)]
+struct Liqi {
+    desc: OnceCell<DescriptorPool>,
+    proto_json: OnceCell<Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum Region {
+    /// 国服
+    #[default]
+    Cn,
+    /// 日服
+    Jp,
+    /// 国际服
+    En,
+}
+
+impl Region {
+    /// Where the game client loads its resources from.
+    pub fn resource_url(self) -> &'static str {
+        match self {
+            Region::Cn => "https://game.maj-soul.com/1",
+            Region::Jp => "https://game.mahjongsoul.com",
+            Region::En => "https://mahjongsoul.game.yo-star.com",
+        }
+    }
+}
+
+fn default_snapshot_interval() -> u64 {
+    10
+}
+
+fn default_upstream_stall() -> u64 {
+    60
+}
+
+fn default_debug_frames() -> usize {
+    500
+}
+
 static APP_USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
-static REQUEST_CLIENT: Lazy<reqwest::Client> = Lazy::new(|| {
+pub(crate) static REQUEST_CLIENT: Lazy<reqwest::Client> = Lazy::new(|| {
     reqwest::Client::builder()
         .user_agent(APP_USER_AGENT)
         .build()
@@ -43,37 +177,234 @@ static REQUEST_CLIENT: Lazy<reqwest::Client> = Lazy::new(|| {
 });
 
 impl Settings {
+    /// Load the settings in layers, each overriding the ones before:
+    /// `settings.json` of the config folder, `settings.toml` of the user config
+    /// folder, `settings.toml` of the config folder, the `--config` file, the
+    /// `--profile` of `profiles`, `MAJSOUL_MAX_*` environment variables and at
+    /// last the command line.
     pub fn new() -> Self {
+        let dir = Self::find_dir();
+        let mut settings = Self::load(&dir).expect("无法解析配置");
+        settings.dir = dir;
+        settings
+    }
+
+    /// The descriptors of liqi.desc.
+    pub fn desc(&self) -> &DescriptorPool {
+        self.liqi.desc.get_or_init(|| {
+            let bytes =
+                std::fs::read(self.liqi_dir().join("liqi.desc")).expect("无法读取liqi.desc");
+            DescriptorPool::decode(bytes.as_slice()).expect("无法解析liqi.desc")
+        })
+    }
+
+    /// The services of liqi.json, for the request and response types of methods.
+    pub fn proto_json(&self) -> &Value {
+        self.liqi.proto_json.get_or_init(|| {
+            let json = std::fs::read_to_string(self.liqi_dir().join("liqi.json"))
+                .expect("无法读取liqi.json");
+            serde_json::from_str(&json).expect("无法解析liqi.json")
+        })
+    }
+
+    /// Read both liqi files now, so that the proxy fails at startup rather
+    /// than at the first frame.
+    pub fn load_liqi(&self) {
+        self.desc();
+        self.proto_json();
+    }
+
+    /// `--config-dir` if it exists, else `liqi_config` next to the executable,
+    /// which is also used in portable mode unless `--config-dir` is given.
+    pub fn find_dir() -> PathBuf {
         let arg_dir = std::path::Path::new(&ARG.config_dir);
         let exe = std::env::current_exe().expect("无法获取当前可执行文件路径");
-        let dir = if arg_dir.is_dir() {
+        if let Some(base) = portable_dir().filter(|_| ARG.config_dir == DEFAULT_CONFIG_DIR) {
+            base.join("liqi_config")
+        } else if arg_dir.is_dir() {
             arg_dir.to_path_buf()
         } else {
             // current executable path
             exe.parent()
                 .expect("无法获取当前可执行文件路径的父目录")
                 .join("liqi_config")
-        };
-        let settings =
-            std::fs::read_to_string(dir.join("settings.json")).expect("无法读取settings.json");
-        let mut settings: Settings =
-            serde_json::from_str(&settings).expect("无法解析settings.json");
-        info!("已载入配置");
+        }
+    }
+
+    /// Merge the layers of settings found for `dir`, without the liqi files.
+    fn load(dir: &Path) -> Result<Self> {
+        let (merged, sources) = Self::merged(dir)?;
+        let mut settings: Settings = serde_json::from_value(merged)?;
+        if sources.is_empty() {
+            info!(
+                "{}",
+                tr!("已载入配置", "設定を読み込みました", "Settings loaded")
+            );
+        } else {
+            info!(
+                "{}",
+                tr!(
+                    "已载入配置，覆盖自: {}",
+                    "設定を読み込みました。上書き元: {}",
+                    "Settings loaded, overridden by: {}",
+                    sources.join(", ")
+                )
+            );
+        }
+        if let Some(base) = portable_dir() {
+            info!(
+                "{}",
+                tr!(
+                    "便携模式，数据保存在 {}",
+                    "ポータブルモード、データは {} に保存されます",
+                    "Portable mode, data is kept in {}",
+                    base.display()
+                )
+            );
+            // relative to the executable rather than the working folder
+            for dir in [&mut settings.database, &mut settings.live_record_dir]
+                .into_iter()
+                .flatten()
+            {
+                *dir = base.join(&*dir);
+            }
+        }
+        if let Some(name) = &ARG.name {
+            // records and logs of each instance apart
+            for dir in [&mut settings.live_record_dir, &mut settings.log.dir]
+                .into_iter()
+                .flatten()
+            {
+                dir.push(name);
+            }
+            if let Some(db) = settings.database.as_mut() {
+                *db = instance::suffixed(db);
+            }
+        }
         settings.methods_set = settings.send_method.iter().cloned().collect();
         settings.actions_set = settings.send_action.iter().cloned().collect();
+        Ok(settings)
+    }
 
-        // read desc from file
-        let bytes = std::fs::read(dir.join("liqi.desc")).expect("无法读取liqi.desc");
+    /// The layers for `dir` merged into one JSON value, and the layers over
+    /// settings.json in the order applied.
+    pub(crate) fn merged(dir: &Path) -> Result<(Value, Vec<String>)> {
+        if let Some(name) = &ARG.name {
+            instance::check_name(name)?;
+        }
+        let mut merged = match std::fs::read_to_string(dir.join("settings.json")) {
+            Ok(json) => {
+                serde_json::from_str(&json).map_err(|e| anyhow!("无法解析settings.json: {}", e))?
+            }
+            Err(_) => Value::Object(Default::default()),
+        };
+        let mut sources = vec![];
+        let files = [
+            user_config_dir().map(|d| d.join(SETTINGS_TOML)),
+            Some(dir.join(SETTINGS_TOML)),
+            ARG.config.clone(),
+        ];
+        for path in files.into_iter().flatten().filter(|p| p.is_file()) {
+            let layer =
+                read_toml(&path).map_err(|e| anyhow!("无法解析{}: {}", path.display(), e))?;
+            merge(&mut merged, layer);
+            sources.push(path.display().to_string());
+        }
+        if let Some(path) = ARG.config.as_ref().filter(|p| !p.is_file()) {
+            return Err(anyhow!("无法读取--config指定的配置文件 {}", path.display()));
+        }
+        let profiles = merged["profiles"].as_object().cloned().unwrap_or_default();
+        if let Some(name) = &ARG.profile {
+            let profile = profiles.get(name).cloned().ok_or_else(|| {
+                let names: Vec<&str> = profiles.keys().map(String::as_str).collect();
+                anyhow!("没有名为{}的配置方案，可选: {}", name, names.join(", "))
+            })?;
+            merge(&mut merged, profile);
+            sources.push(format!("profile {}", name));
+        } else if let Some((name, profile)) = ARG
+            .name
+            .as_ref()
+            .and_then(|name| Some((name, profiles.get(name)?.clone())))
+        {
+            // the ports of a named instance
+            merge(&mut merged, profile);
+            sources.push(format!("profile {}", name));
+        }
+        let mut vars: Vec<(String, String)> = std::env::vars().collect();
+        vars.sort();
+        for (key, value) in vars {
+            let Some(key) = key.strip_prefix(ENV_PREFIX) else {
+                continue;
+            };
+            let path: Vec<String> = key.split("__").map(camel_case).collect();
+            merge(&mut merged, nested(&path, parse_value(&value)));
+            sources.push(format!("${}{}", ENV_PREFIX, key));
+        }
+        let flags = [
+            ("proxyAddr", &ARG.proxy_addr),
+            ("adminAddr", &ARG.admin_addr),
+        ];
+        for (key, value) in flags {
+            if let Some(value) = value {
+                merge(
+                    &mut merged,
+                    nested(&[key.to_string()], Value::from(value.as_str())),
+                );
+            }
+        }
+        for item in &ARG.overrides {
+            let (key, value) = item
+                .split_once('=')
+                .ok_or(anyhow!("--set的格式应为KEY=VALUE: {}", item))?;
+            let path: Vec<String> = key.split('.').map(String::from).collect();
+            merge(&mut merged, nested(&path, parse_value(value)));
+        }
+        Ok((merged, sources))
+    }
+
+    /// Read the layers again for hot reload, keeping the liqi files loaded at startup.
+    pub fn reload(&self) -> Result<Self> {
+        let mut settings = Self::load(&self.dir)?;
+        settings.liqi_version = self.liqi_version.clone();
+        settings.liqi = self.liqi.clone();
+        settings.dir = self.dir.clone();
+        Ok(settings)
+    }
 
-        settings.desc = DescriptorPool::decode(bytes.as_slice()).expect("无法解析liqi.desc");
+    /// Files the layers are read from, existing or not, `--config` last.
+    pub fn layer_files(&self) -> Vec<PathBuf> {
+        Self::files(&self.dir)
+    }
 
-        // read liqi.json from file
-        settings.proto_json = serde_json::from_str(
-            &std::fs::read_to_string(dir.join("liqi.json")).expect("无法读取liqi.json"),
-        )
-        .expect("无法解析liqi.json");
-        settings.dir = dir;
-        settings
+    /// Files the layers for `dir` are read from, existing or not, `--config` last.
+    pub fn files(dir: &Path) -> Vec<PathBuf> {
+        [
+            Some(dir.join("settings.json")),
+            user_config_dir().map(|d| d.join(SETTINGS_TOML)),
+            Some(dir.join(SETTINGS_TOML)),
+            ARG.config.clone(),
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+
+    /// Folder of settings.json, and of the liqi files unless `liqiDir` is set.
+    pub fn config_dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Folder of the liqi files, `liqiDir` is relative to the config folder.
+    pub fn liqi_dir(&self) -> PathBuf {
+        match &self.liqi_dir {
+            Some(dir) => self.dir.join(dir),
+            None => self.dir.clone(),
+        }
+    }
+
+    /// Prefix of the liqi files in use, compared with the live one by `update`.
+    pub fn liqi_version(&self) -> &str {
+        &self.liqi_version
     }
 
     pub fn is_method(&self, method: &str) -> bool {
@@ -100,12 +431,26 @@ impl Settings {
         let version = get_version().await?;
         let prefix = get_proto_prefix(&version).await?;
         if self.liqi_version == prefix {
-            info!("无需更新liqi, 当前版本: {}", version);
+            info!(
+                "{}",
+                tr!(
+                    "无需更新liqi, 当前版本: {}",
+                    "liqi は最新です。現在のバージョン: {}",
+                    "liqi is up to date, version: {}",
+                    version
+                )
+            );
             return Ok(false);
         }
         info!(
-            "liqi需要更新, 当前版本: {}, 最新版本: {}",
-            self.liqi_version, prefix
+            "{}",
+            tr!(
+                "liqi需要更新, 当前版本: {}, 最新版本: {}",
+                "liqi の更新が必要です。現在: {}、最新: {}",
+                "liqi needs an update, current: {}, latest: {}",
+                self.liqi_version,
+                prefix
+            )
         );
 
         let req = REQUEST_CLIENT
@@ -125,7 +470,14 @@ impl Settings {
                 }
                 let json: Value = resp.json().await?;
                 if json["tag_name"] == self.liqi_version {
-                    info!("liqi需要更新, 但是AutoLiqi尚未更新, 稍晚再试");
+                    info!(
+                        "{}",
+                        tr!(
+                            "liqi需要更新, 但是AutoLiqi尚未更新, 稍晚再试",
+                            "liqi の更新が必要ですが、AutoLiqi がまだ更新されていません。後でもう一度試してください",
+                            "liqi needs an update but AutoLiqi has not caught up yet, try again later"
+                        )
+                    );
                     return Ok(false);
                 }
                 let assets = json["assets"]
@@ -137,10 +489,22 @@ impl Settings {
             }
             Err(e) => return Err(anyhow!("Failed to get latest release: {:?}", e)),
         }
-        // write settings.json
+        // only the version goes to settings.json, overrides of the other layers stay out of it
         self.liqi_version = prefix;
-        let dir = self.dir.join("settings.json");
-        std::fs::write(dir, serde_json::to_string_pretty(self)?)?;
+        let path = self.dir.join("settings.json");
+        let mut file: Value = match std::fs::read_to_string(&path) {
+            Ok(json) => serde_json::from_str(&json)?,
+            Err(_) => Value::Object(Default::default()),
+        };
+        // a profile with a version of its own keeps it there
+        let slot = match &ARG.profile {
+            Some(name) if file["profiles"][name].get("liqiVersion").is_some() => {
+                &mut file["profiles"][name]
+            }
+            _ => &mut file,
+        };
+        slot["liqiVersion"] = Value::from(self.liqi_version.as_str());
+        std::fs::write(path, serde_json::to_string_pretty(&file)?)?;
         Ok(true)
     }
 
@@ -163,9 +527,17 @@ impl Settings {
         match req {
             Ok(resp) => {
                 let bytes = resp.bytes().await?;
-                let file_dir = self.dir.join(name);
+                let file_dir = self.liqi_dir().join(name);
                 std::fs::write(file_dir, bytes).expect("无法写入文件");
-                info!("下载完成: {}", name);
+                info!(
+                    "{}",
+                    tr!(
+                        "下载完成: {}",
+                        "ダウンロード完了: {}",
+                        "Downloaded {}",
+                        name
+                    )
+                );
                 Ok(())
             }
             Err(e) => Err(anyhow!("Failed to download asset: {:?}", e)),
@@ -173,9 +545,88 @@ impl Settings {
     }
 }
 
-async fn get_version() -> Result<String> {
+const SETTINGS_TOML: &str = "settings.toml";
+const ENV_PREFIX: &str = "MAJSOUL_MAX_";
+
+pub(crate) const DEFAULT_CONFIG_DIR: &str = "./liqi_config/";
+/// Marker next to the executable turning on portable mode like `--portable`.
+const PORTABLE_MARKER: &str = "portable";
+
+/// Folder of the executable in portable mode, where everything is kept.
+pub fn portable_dir() -> Option<PathBuf> {
+    static DIR: Lazy<Option<PathBuf>> = Lazy::new(|| {
+        let dir = std::env::current_exe().ok()?.parent()?.to_path_buf();
+        (ARG.portable || dir.join(PORTABLE_MARKER).is_file()).then_some(dir)
+    });
+    DIR.clone()
+}
+
+/// `majsoul_max_rs` in `%APPDATA%` on Windows, else in `$XDG_CONFIG_HOME` or
+/// `~/.config`, none in portable mode.
+fn user_config_dir() -> Option<PathBuf> {
+    if portable_dir().is_some() {
+        return None;
+    }
+    let base = if cfg!(windows) {
+        std::env::var_os("APPDATA").map(PathBuf::from)
+    } else {
+        std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".config")))
+    };
+    base.map(|b| b.join(env!("CARGO_PKG_NAME")))
+}
+
+/// A TOML file as JSON, with the same keys as settings.json.
+fn read_toml(path: &Path) -> Result<Value> {
+    let table: toml::Table = toml::from_str(&std::fs::read_to_string(path)?)?;
+    Ok(serde_json::to_value(table)?)
+}
+
+/// Objects are merged key by key, anything else is replaced.
+pub(crate) fn merge(base: &mut Value, layer: Value) {
+    match (base, layer) {
+        (Value::Object(base), Value::Object(layer)) => {
+            for (key, value) in layer {
+                merge(base.entry(key).or_insert(Value::Null), value);
+            }
+        }
+        (base, layer) => *base = layer,
+    }
+}
+
+/// `value` under the keys of `path`, outermost first.
+fn nested(path: &[String], value: Value) -> Value {
+    path.iter().rev().fold(value, |value, key| {
+        Value::Object([(key.clone(), value)].into_iter().collect())
+    })
+}
+
+/// JSON such as `1`, `true` or `["a"]`, else the text as a string.
+fn parse_value(text: &str) -> Value {
+    serde_json::from_str(text).unwrap_or_else(|_| Value::from(text))
+}
+
+/// `PROXY_ADDR` to `proxyAddr`.
+fn camel_case(key: &str) -> String {
+    let mut out = String::new();
+    for (i, word) in key.split('_').filter(|w| !w.is_empty()).enumerate() {
+        let word = word.to_lowercase();
+        match i {
+            0 => out.push_str(&word),
+            _ => {
+                let mut chars = word.chars();
+                out.extend(chars.next().map(|c| c.to_ascii_uppercase()));
+                out.push_str(chars.as_str());
+            }
+        }
+    }
+    out
+}
+
+pub(crate) async fn get_version() -> Result<String> {
     let req = REQUEST_CLIENT
-        .get("https://game.maj-soul.com/1/version.json")
+        .get(format!("{}/version.json", SETTINGS.region.resource_url()))
         .timeout(std::time::Duration::from_secs(10))
         .send()
         .await;
@@ -191,9 +642,13 @@ async fn get_version() -> Result<String> {
     }
 }
 
-async fn get_proto_prefix(version: &str) -> Result<String> {
+pub(crate) async fn get_proto_prefix(version: &str) -> Result<String> {
     let req = REQUEST_CLIENT
-        .get(format!("https://game.maj-soul.com/1/resversion{}.json", version).as_str())
+        .get(format!(
+            "{}/resversion{}.json",
+            SETTINGS.region.resource_url(),
+            version
+        ))
         .timeout(std::time::Duration::from_secs(10))
         .send()
         .await;
@@ -211,7 +666,11 @@ async fn get_proto_prefix(version: &str) -> Result<String> {
 
 pub async fn get_lqbin_prefix(version: &str) -> Result<String> {
     let req = REQUEST_CLIENT
-        .get(format!("https://game.maj-soul.com/1/resversion{}.json", version).as_str())
+        .get(format!(
+            "{}/resversion{}.json",
+            SETTINGS.region.resource_url(),
+            version
+        ))
         .timeout(std::time::Duration::from_secs(10))
         .send()
         .await;
@@ -315,7 +774,7 @@ impl ModSettings {
     }
 
     pub async fn get_lqc(&mut self) -> Result<bool> {
-        // get lqc.lqbin prefix from https://game.maj-soul.com/1/{prefix}/res/config/lqc.lqbin
+        // get lqc.lqbin prefix from {resource_url}/{prefix}/res/config/lqc.lqbin
         let version = get_version().await?;
         let prefix = get_lqbin_prefix(&version).await?;
 
@@ -331,7 +790,8 @@ impl ModSettings {
         let req = REQUEST_CLIENT
             .get(
                 format!(
-                    "https://game.maj-soul.com/1/{}/res/config/lqc.lqbin",
+                    "{}/{}/res/config/lqc.lqbin",
+                    SETTINGS.region.resource_url(),
                     prefix
                 )
                 .as_str(),
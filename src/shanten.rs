@@ -0,0 +1,251 @@
+use crate::{
+    state::GameState,
+    tile::{counts34, TILES34},
+};
+use serde::Serialize;
+
+/// Shanten of a hand with `melds` open or closed melds, -1 means a complete hand.
+///
+/// Takes the minimum of the regular form, seven pairs and thirteen orphans.
+pub fn shanten(counts: &[u8; 34], melds: usize) -> i32 {
+    let mut best = regular(counts, melds);
+    if melds == 0 {
+        best = best.min(chiitoi(counts)).min(kokushi(counts));
+    }
+    best
+}
+
+fn regular(counts: &[u8; 34], melds: usize) -> i32 {
+    let mut c = *counts;
+    let mut best = 8;
+    dfs(&mut c, 0, melds as i32, 0, false, &mut best);
+    for i in 0..34 {
+        if c[i] >= 2 {
+            c[i] -= 2;
+            dfs(&mut c, 0, melds as i32, 0, true, &mut best);
+            c[i] += 2;
+        }
+    }
+    best
+}
+
+fn dfs(c: &mut [u8; 34], mut i: usize, sets: i32, partials: i32, pair: bool, best: &mut i32) {
+    while i < 34 && c[i] == 0 {
+        i += 1;
+    }
+    if i == 34 {
+        let partials = partials.min(4 - sets);
+        *best = (*best).min(8 - 2 * sets - partials - pair as i32);
+        return;
+    }
+    let suited = i < 27;
+    let n = i % 9;
+    if c[i] >= 3 {
+        c[i] -= 3;
+        dfs(c, i, sets + 1, partials, pair, best);
+        c[i] += 3;
+    }
+    if suited && n <= 6 && c[i + 1] > 0 && c[i + 2] > 0 {
+        c[i] -= 1;
+        c[i + 1] -= 1;
+        c[i + 2] -= 1;
+        dfs(c, i, sets + 1, partials, pair, best);
+        c[i] += 1;
+        c[i + 1] += 1;
+        c[i + 2] += 1;
+    }
+    if sets + partials < 4 {
+        if c[i] >= 2 {
+            c[i] -= 2;
+            dfs(c, i, sets, partials + 1, pair, best);
+            c[i] += 2;
+        }
+        for gap in [1, 2] {
+            if suited && n + gap <= 8 && c[i + gap] > 0 {
+                c[i] -= 1;
+                c[i + gap] -= 1;
+                dfs(c, i, sets, partials + 1, pair, best);
+                c[i] += 1;
+                c[i + gap] += 1;
+            }
+        }
+    }
+    // leave the rest of this kind isolated
+    let saved = c[i];
+    c[i] = 0;
+    dfs(c, i + 1, sets, partials, pair, best);
+    c[i] = saved;
+}
+
+fn chiitoi(counts: &[u8; 34]) -> i32 {
+    let pairs = counts.iter().filter(|c| **c >= 2).count() as i32;
+    let kinds = counts.iter().filter(|c| **c >= 1).count() as i32;
+    6 - pairs + (7 - kinds).max(0)
+}
+
+fn kokushi(counts: &[u8; 34]) -> i32 {
+    const YAOCHU: [usize; 13] = [0, 8, 9, 17, 18, 26, 27, 28, 29, 30, 31, 32, 33];
+    let kinds = YAOCHU.iter().filter(|i| counts[**i] > 0).count() as i32;
+    let pair = YAOCHU.iter().any(|i| counts[*i] >= 2) as i32;
+    13 - kinds - pair
+}
+
+/// Tile kinds that lower the shanten of a 3n+1 hand, sanma has no 2m-8m.
+pub fn waits(counts: &[u8; 34], melds: usize, sanma: bool) -> Vec<usize> {
+    let current = shanten(counts, melds);
+    let mut c = *counts;
+    (0..34)
+        .filter(|i| {
+            if c[*i] >= 4 || sanma && (1..8).contains(i) {
+                return false;
+            }
+            c[*i] += 1;
+            let better = shanten(&c, melds) < current;
+            c[*i] -= 1;
+            better
+        })
+        .collect()
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct DiscardOption {
+    pub tile: String,
+    pub shanten: i32,
+    /// tiles left that improve the hand, after subtracting visible ones
+    pub ukeire: u32,
+    pub waits: Vec<String>,
+}
+
+/// Data of `.helper.HandAnalysis`, emitted after every own draw.
+#[derive(Serialize, Debug, Clone)]
+pub struct HandAnalysis {
+    pub seat: u32,
+    pub shanten: i32,
+    /// best discards first
+    pub discards: Vec<DiscardOption>,
+}
+
+/// Tiles seen on the table from the own point of view.
+pub fn visible_counts(state: &GameState) -> [u8; 34] {
+    let mut visible = counts34(&state.dora_indicators);
+    let mut add = |tiles: &[String]| {
+        for (v, c) in visible.iter_mut().zip(counts34(tiles)) {
+            *v += c;
+        }
+    };
+    for player in &state.players {
+        add(&player.hand);
+        let discards: Vec<String> = player
+            .discards
+            .iter()
+            // called tiles are counted in the melds
+            .filter(|d| !d.called)
+            .map(|d| d.tile.clone())
+            .collect();
+        add(&discards);
+        for meld in &player.melds {
+            add(&meld.tiles);
+        }
+    }
+    // kita set aside in sanma
+    visible[30] += state.players.iter().map(|p| p.nukidora as u8).sum::<u8>();
+    visible
+}
+
+/// Shanten and ukeire of every discard of the own 3n+2 hand.
+pub fn analyze(state: &GameState) -> Option<HandAnalysis> {
+    let own = state.own()?;
+    if own.hand.len() % 3 != 2 {
+        return None;
+    }
+    let counts = counts34(&own.hand);
+    let visible = visible_counts(state);
+    let melds = own.melds.len();
+    let sanma = state.is_sanma();
+    let mut discards: Vec<DiscardOption> = (0..34)
+        .filter(|i| counts[*i] > 0)
+        .map(|i| {
+            let mut c = counts;
+            c[i] -= 1;
+            let waits = waits(&c, melds, sanma);
+            DiscardOption {
+                tile: TILES34[i].to_string(),
+                shanten: shanten(&c, melds),
+                ukeire: waits
+                    .iter()
+                    .map(|w| 4u32.saturating_sub(visible[*w] as u32))
+                    .sum(),
+                waits: waits.iter().map(|w| TILES34[*w].to_string()).collect(),
+            }
+        })
+        .collect();
+    discards.sort_by(|a, b| a.shanten.cmp(&b.shanten).then(b.ukeire.cmp(&a.ukeire)));
+    Some(HandAnalysis {
+        seat: own.seat,
+        shanten: shanten(&counts, melds),
+        discards,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tile::tiles;
+
+    fn of(text: &str) -> i32 {
+        let tiles = tiles(text);
+        shanten(&counts34(&tiles), (14 - tiles.len()) / 3)
+    }
+
+    #[test]
+    fn complete_and_tenpai() {
+        assert_eq!(of("123m456p789s11122z"), -1);
+        assert_eq!(of("123m456p789s1112z"), 0);
+        assert_eq!(of("123m456p78s11z"), 0);
+        assert_eq!(of("123m456p1z"), 0);
+    }
+
+    #[test]
+    fn thirteen_orphans() {
+        assert_eq!(of("19m19p19s1234567z"), 0);
+        assert_eq!(of("119m19p19s1234567z"), -1);
+        assert_eq!(of("159m19p19s123456z"), 1);
+        // the regular form of the same tiles is far away
+        assert_eq!(regular(&counts34(&tiles("19m19p19s1234567z")), 0), 8);
+    }
+
+    #[test]
+    fn seven_pairs() {
+        assert_eq!(of("1144m2288p3366s5z"), 0);
+        assert_eq!(of("1144m2288p3366s55z"), -1);
+        // four of a kind are not two pairs
+        assert_eq!(of("1111m2288p3366s5z"), 2);
+        assert_eq!(of("1144m2288p3369s5z"), 1);
+    }
+
+    #[test]
+    fn open_hands_skip_special_forms() {
+        // three kinds short of thirteen orphans, but those need a closed hand
+        assert_eq!(of("19m19p19s1234z"), 6);
+    }
+
+    #[test]
+    fn waits_of_tenpai_hands() {
+        let names = |text: &str, sanma: bool| -> Vec<&str> {
+            waits(&counts34(&tiles(text)), 0, sanma)
+                .into_iter()
+                .map(|i| TILES34[i])
+                .collect()
+        };
+        assert_eq!(
+            names("1112345678999m", false),
+            ["1m", "2m", "3m", "4m", "5m", "6m", "7m", "8m", "9m"]
+        );
+        assert_eq!(names("19m19p19s1234567z", false).len(), 13);
+        assert_eq!(names("123m456p789s1112z", false), ["2z"]);
+        assert_eq!(names("111m456p789s1122z", false), ["1z", "2z"]);
+        // no 2m in sanma
+        assert_eq!(names("13m456p789s11122z", false), ["2m"]);
+        assert!(names("13m456p789s11122z", true).is_empty());
+    }
+}
@@ -0,0 +1,275 @@
+use crate::{
+    activity::ACTIVITY,
+    api,
+    base::BaseMessage,
+    format::strip_action,
+    helper::{helper_worker, Frame},
+    lobby::method_types,
+    parser::{encode_action, json_to_bytes, to_fqn},
+    record::Record,
+    tr, SETTINGS,
+};
+use anyhow::{anyhow, Result};
+use base64::prelude::*;
+use bytes::Bytes;
+use clap::Subcommand;
+use prost::Message;
+use serde_json::{json, Value as JsonValue};
+use std::{net::SocketAddr, str::FromStr, time::Duration};
+use tokio::{sync::mpsc::Sender, time::sleep};
+use tracing::{error, info, warn};
+
+/// Account id of the own seat when it is played by AI.
+const SIMULATED_ACCOUNT: u64 = 1;
+/// A short east game with a riichi tsumo, a ron, an exhaustive draw and a pon.
+const FAKE_GAME: &str = include_str!("../samples/fake_game.json");
+
+#[derive(Subcommand, Debug)]
+pub enum SimulateCommand {
+    /// Play a bundled sample game through the configured sinks, to check an
+    /// overlay or helper without queueing for a real match
+    FakeGame {
+        /// e.g. `1x` for one action a second, `0` for no pause
+        #[clap(long, default_value = "4x")]
+        speed: Speed,
+        /// seat to play from
+        #[clap(long, default_value_t = 0)]
+        seat: u32,
+    },
+}
+
+pub async fn run(command: &SimulateCommand) -> Result<()> {
+    match command {
+        SimulateCommand::FakeGame { speed, seat } => {
+            let record: Record = serde_json::from_str(FAKE_GAME)?;
+            simulate(&record, *seat, *speed).await
+        }
+    }
+}
+
+/// Replay speed, e.g. `4x` or `4`; `0` sends every action at once.
+#[derive(Debug, Clone, Copy)]
+pub struct Speed(f64);
+
+impl FromStr for Speed {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let speed: f64 = s
+            .trim_end_matches(['x', 'X'])
+            .parse()
+            .map_err(|_| format!("invalid speed: {}", s))?;
+        if speed.is_finite() && speed >= 0.0 {
+            Ok(Speed(speed))
+        } else {
+            Err(format!("invalid speed: {}", s))
+        }
+    }
+}
+
+impl Speed {
+    /// Pause after each action, one second at 1x.
+    fn delay(self) -> Option<Duration> {
+        (self.0 > 0.0).then(|| Duration::from_secs_f64(1.0 / self.0))
+    }
+}
+
+/// Encodes the frames the client would have received for a game.
+struct FrameWriter {
+    tx: Sender<Frame>,
+    next_id: u16,
+}
+
+impl FrameWriter {
+    async fn send(&self, buf: Vec<u8>, direction: char) -> Result<()> {
+        let frame = Frame {
+            buf: Bytes::from(buf),
+            session: None,
+            direction,
+            spectated: false,
+            simulated: true,
+        };
+        self.tx
+            .send(frame)
+            .await
+            .map_err(|_| anyhow!("Helper worker stopped"))
+    }
+
+    async fn notify(&self, name: &str, data: &JsonValue) -> Result<()> {
+        let desc = SETTINGS
+            .desc()
+            .get_message_by_name(&to_fqn(name))
+            .ok_or(anyhow!("Invalid message type: {}", name))?;
+        let msg = BaseMessage {
+            method_name: format!(".lq.{}", name),
+            data: json_to_bytes(desc, data)?,
+        };
+        let mut buf = vec![1];
+        buf.extend(msg.encode_to_vec());
+        self.send(buf, '\u{2193}').await
+    }
+
+    /// A request and its response, so the parser learns the response type.
+    async fn call(&mut self, method: &str, req: &JsonValue, res: &JsonValue) -> Result<()> {
+        let (req_type, res_type) = method_types(method)?;
+        self.next_id = self.next_id.wrapping_add(1);
+        let id = self.next_id.to_le_bytes();
+        let request = BaseMessage {
+            method_name: method.to_string(),
+            data: json_to_bytes(req_type, req)?,
+        };
+        let mut buf = vec![2, id[0], id[1]];
+        buf.extend(request.encode_to_vec());
+        self.send(buf, '\u{2191}').await?;
+        let response = BaseMessage {
+            method_name: String::new(),
+            data: json_to_bytes(res_type, res)?,
+        };
+        let mut buf = vec![3, id[0], id[1]];
+        buf.extend(response.encode_to_vec());
+        self.send(buf, '\u{2193}').await
+    }
+
+    async fn action(&self, step: usize, name: &str, data: &JsonValue) -> Result<()> {
+        let encoded = encode_action(name, data, SETTINGS.desc())?;
+        let prototype = json!({
+            "step": step,
+            "name": name,
+            "data": BASE64_STANDARD.encode(encoded),
+        });
+        self.notify("ActionPrototype", &prototype).await
+    }
+}
+
+/// The live form of a record action as seen from `seat`: other hands hidden.
+fn live_action(name: &str, data: &JsonValue, seat: u32) -> (String, JsonValue) {
+    let name = format!("Action{}", strip_action(name));
+    let mut data = data.clone();
+    match name.as_str() {
+        "ActionNewRound" => {
+            let own = data[format!("tiles{}", seat)].take();
+            if let Some(map) = data.as_object_mut() {
+                map.retain(|key, _| !key.starts_with("tiles") || key == "tiles");
+                if !own.is_null() {
+                    map.insert("tiles".to_string(), own);
+                }
+            }
+        }
+        "ActionDealTile" if data["seat"].as_u64() != Some(seat as u64) => {
+            data["tile"] = json!("");
+        }
+        _ => (),
+    }
+    (name, data)
+}
+
+/// Push `record` through the helper pipeline as if it was played live from `seat`.
+pub async fn simulate(record: &Record, seat: u32, speed: Speed) -> Result<()> {
+    // replayed frames wait for room instead of being dropped
+    let (tx, rx) = tokio::sync::mpsc::channel::<Frame>(100);
+    ACTIVITY.set_queue_capacity(tx.max_capacity());
+    tokio::spawn(helper_worker(rx));
+    if !SETTINGS.admin_addr.is_empty() {
+        match SocketAddr::from_str(&SETTINGS.admin_addr) {
+            Ok(addr) => {
+                tokio::spawn(async move {
+                    if let Err(e) = api::serve(addr).await {
+                        error!("Admin API stopped: {:?}", e);
+                    }
+                });
+            }
+            Err(e) => error!(
+                "Failed to parse admin address: {:?}, addr: {}",
+                e, SETTINGS.admin_addr
+            ),
+        }
+    }
+    let mut writer = FrameWriter { tx, next_id: 0 };
+    let head = &record.head;
+    let uuid = head["uuid"].as_str().unwrap_or("simulated");
+    let accounts = head["accounts"]
+        .as_array()
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+    let seats = head["result"]["players"]
+        .as_array()
+        .map(Vec::len)
+        .filter(|n| *n > 0)
+        .unwrap_or(accounts.len());
+    let mut seat_list: Vec<u64> = (0..seats as u64)
+        .map(|s| {
+            accounts
+                .iter()
+                .find(|a| a["seat"].as_u64() == Some(s))
+                .and_then(|a| a["account_id"].as_u64())
+                .unwrap_or_default()
+        })
+        .collect();
+    let account_id = match seat_list.get_mut(seat as usize) {
+        Some(0) => {
+            seat_list[seat as usize] = SIMULATED_ACCOUNT;
+            SIMULATED_ACCOUNT
+        }
+        Some(id) => *id,
+        None => return Err(anyhow!("No seat {} in a {} player game", seat, seats)),
+    };
+
+    writer
+        .call(
+            ".lq.Lobby.oauth2Login",
+            &json!({}),
+            &json!({ "account_id": account_id }),
+        )
+        .await?;
+    writer
+        .notify("NotifyMatchGameStart", &json!({ "game_uuid": uuid }))
+        .await?;
+    writer
+        .call(
+            ".lq.FastTest.authGame",
+            &json!({ "account_id": account_id, "game_uuid": uuid }),
+            &json!({
+                "players": accounts,
+                "seat_list": seat_list,
+                "game_config": head["config"],
+            }),
+        )
+        .await?;
+    info!(
+        "{}",
+        tr!(
+            "开始模拟对局 {}，座位 {}，共 {} 个操作",
+            "対局 {} のシミュレーションを開始します。座席 {}、操作 {} 件",
+            "Simulating game {} from seat {}, {} actions",
+            uuid,
+            seat,
+            record.actions.len()
+        )
+    );
+    for (step, action) in record.actions.iter().enumerate() {
+        let (name, data) = live_action(&action.name, &action.data, seat);
+        if let Err(e) = writer.action(step, &name, &data).await {
+            warn!("Skipped {}: {:?}", action.name, e);
+            continue;
+        }
+        if let Some(delay) = speed.delay() {
+            sleep(delay).await;
+        }
+    }
+    if !head["result"].is_null() {
+        writer
+            .notify("NotifyGameEndResult", &json!({ "result": head["result"] }))
+            .await?;
+    }
+    // let the sinks and the engine catch up before exiting
+    sleep(Duration::from_secs(2)).await;
+    info!(
+        "{}",
+        tr!(
+            "模拟对局结束",
+            "シミュレーションが終了しました",
+            "Simulation finished"
+        )
+    );
+    Ok(())
+}
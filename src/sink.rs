@@ -0,0 +1,1076 @@
+#[cfg(feature = "kafka")]
+use crate::kafka::KafkaSink;
+#[cfg(feature = "webrtc")]
+use crate::rtc::WebRtcSink;
+use crate::{
+    activity::{SinkFailure, ACTIVITY},
+    daily::DailyPath,
+    dump::{compress_chunk, compressed_frame_count, encode_frame, CHUNK_SIZE, DUMP_MAGIC},
+    filter::Filter,
+    format::{EventFormat, FormatKind},
+    instance, interest,
+    logging::PIPELINE,
+    memory::{self, json_size, Charge, Consumer},
+    parser::{LiqiMessage, MessageType},
+    settings::Settings,
+    spool::Spool,
+    tile::{notate_json, Notation},
+    ARG,
+};
+use anyhow::{anyhow, Result};
+use base64::prelude::*;
+use bytes::Bytes;
+use once_cell::sync::{Lazy, OnceCell};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value as JsonValue};
+use std::{
+    future::Future,
+    io::{IsTerminal, Write},
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    fs::{File, OpenOptions},
+    io::AsyncWriteExt,
+    sync::mpsc::{
+        channel, error::TrySendError, unbounded_channel, Receiver, Sender, UnboundedReceiver,
+        UnboundedSender,
+    },
+    task::{spawn_blocking, JoinHandle},
+    time::{sleep, sleep_until, timeout},
+};
+use tracing::{debug_span, error, info, warn, Instrument};
+
+/// A parsed message on its way to the sinks.
+#[derive(Debug)]
+pub struct Event {
+    pub msg: LiqiMessage,
+    pub raw: Bytes,
+    pub from_client: bool,
+    /// from a spectated game or a viewed replay instead of an own game
+    pub spectated: bool,
+}
+
+impl Event {
+    /// An event produced by the helper itself instead of the game, e.g. `.helper.SinkStatus`.
+    pub fn derived(method: &str, data: JsonValue) -> Self {
+        Event {
+            msg: LiqiMessage {
+                id: 0,
+                msg_type: MessageType::Notify,
+                method_name: Arc::from(method),
+                data,
+                serialized: OnceCell::new(),
+            },
+            raw: Bytes::new(),
+            from_client: false,
+            spectated: false,
+        }
+    }
+}
+
+/// Encoded output of an [`EventFormat`].
+#[derive(Debug, Clone)]
+pub enum Payload {
+    Json(JsonValue),
+    /// JSON text, serialized once and shared by the sinks
+    Serialized(Bytes),
+    Binary(Bytes),
+}
+
+impl Payload {
+    /// Bytes held, roughly for JSON.
+    pub fn size(&self) -> usize {
+        match self {
+            Payload::Json(json) => json_size(json),
+            Payload::Serialized(bytes) | Payload::Binary(bytes) => bytes.len(),
+        }
+    }
+}
+
+/// A payload queued for one sink, with the method of the message it came from.
+#[derive(Debug, Clone)]
+pub struct Delivery {
+    pub method: Arc<str>,
+    pub payload: Payload,
+    /// the payload counted against the memory cap until sent
+    pub charge: Charge,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SinkConfig {
+    #[serde(default)]
+    pub name: String,
+    #[serde(flatten)]
+    pub target: SinkTarget,
+    #[serde(default)]
+    pub format: FormatKind,
+    /// filter expression, see [`crate::filter`], every event is sent when absent
+    #[serde(default)]
+    pub filter: Option<String>,
+    /// rewrite tiles of JSON payloads, tenhou codes are left alone
+    #[serde(default)]
+    pub notation: Option<Notation>,
+    /// keep sending when the memory is over the cap, see [`crate::memory`]
+    #[serde(default)]
+    pub critical: bool,
+    /// queue payloads on disk until sent, see [`crate::spool`], so none are
+    /// lost to a crash or while the sink is down
+    #[serde(default)]
+    pub durable: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum SinkTarget {
+    /// POST every payload to `url`
+    Http { url: String },
+    /// append every payload to `path`, one per line, binary payloads in base64
+    File {
+        path: PathBuf,
+        /// write length-prefixed frames as read by `parse` instead of lines
+        #[serde(default)]
+        binary: bool,
+        /// write to `<folder of path>/<YYYY-MM-DD>/<file name>` instead
+        #[serde(default)]
+        daily: bool,
+        /// write the binary frames zstd-compressed in chunks, implies `binary`
+        #[serde(default)]
+        compress: bool,
+    },
+    /// print every payload to the terminal, lines of the `text` format in color
+    Console {
+        #[serde(default = "default_color")]
+        color: bool,
+    },
+    /// produce every payload to kafka, requires the `kafka` feature
+    #[serde(rename_all = "camelCase")]
+    Kafka {
+        brokers: Vec<String>,
+        /// topic name, or topic prefix when `topic_per_method` is set
+        topic: String,
+        /// use `{topic}{method}` as topic instead of a `method` header
+        #[serde(default)]
+        topic_per_method: bool,
+        #[serde(default = "default_batch_size")]
+        batch_size: usize,
+        #[serde(default)]
+        linger_ms: u64,
+    },
+    /// fan out to overlays connected over WebRTC data channels, requires the `webrtc` feature
+    #[serde(rename = "webrtc", rename_all = "camelCase")]
+    WebRtc {
+        /// address of the signaling endpoint overlays POST their SDP offer to
+        listen: SocketAddr,
+        #[serde(default = "default_ice_servers")]
+        ice_servers: Vec<String>,
+        /// required as `Authorization: Bearer <token>` when set, `keychain:<key>`
+        /// reads it from the OS keychain; without it `listen` must be a loopback address
+        #[serde(default)]
+        token: Option<String>,
+        /// origins of the overlay pages, like `https://overlay.example.com`,
+        /// that may signal from a browser; other pages are refused
+        #[serde(default)]
+        allowed_origins: Vec<String>,
+    },
+}
+
+fn default_batch_size() -> usize {
+    100
+}
+
+fn default_color() -> bool {
+    true
+}
+
+fn default_ice_servers() -> Vec<String> {
+    vec!["stun:stun.l.google.com:19302".to_string()]
+}
+
+pub trait Sink: Send + 'static {
+    fn send(&mut self, delivery: Delivery) -> impl Future<Output = Result<()>> + Send;
+
+    /// Send payloads in order, sinks with native batching override this.
+    fn send_batch(&mut self, batch: Vec<Delivery>) -> impl Future<Output = Result<()>> + Send {
+        async move {
+            for delivery in batch {
+                self.send(delivery).await?;
+            }
+            Ok(())
+        }
+    }
+
+    /// Max payloads per `send_batch`.
+    fn batch_size(&self) -> usize {
+        1
+    }
+
+    /// How long to wait for a batch to fill up.
+    fn linger(&self) -> Duration {
+        Duration::ZERO
+    }
+
+    /// Skip logging every send, for sinks printing to the terminal themselves.
+    fn quiet(&self) -> bool {
+        false
+    }
+
+    /// How long until [`Sink::tick`] is due without a payload coming, never
+    /// by default.
+    fn tick_in(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Work due without a payload, e.g. writing payloads held back.
+    fn tick(&mut self) -> impl Future<Output = Result<()>> + Send {
+        async { Ok(()) }
+    }
+
+    /// Work due when the sink stops, at exit or after a reload, e.g. writing
+    /// the payloads still held back.
+    fn close(&mut self) -> impl Future<Output = Result<()>> + Send {
+        async { Ok(()) }
+    }
+}
+
+pub struct HttpSink {
+    client: Client,
+    url: String,
+}
+
+impl HttpSink {
+    pub fn new(url: String) -> Self {
+        let client = reqwest::ClientBuilder::new()
+            .danger_accept_invalid_certs(true)
+            .build()
+            .expect("Failed to create reqwest client");
+        Self { client, url }
+    }
+}
+
+impl Sink for HttpSink {
+    async fn send(&mut self, delivery: Delivery) -> Result<()> {
+        // post data to API, an error status counts as a failed send
+        let req = self.client.post(&self.url);
+        let req = match delivery.payload {
+            Payload::Json(json) => req.json(&json),
+            Payload::Serialized(bytes) => {
+                req.header("content-type", "application/json").body(bytes)
+            }
+            Payload::Binary(bin) => req
+                .header("content-type", "application/octet-stream")
+                .body(bin),
+        };
+        req.send().await?.error_for_status()?;
+        Ok(())
+    }
+}
+
+/// Longest time frames of a compressed dump wait for their chunk to fill.
+const CHUNK_LINGER: Duration = Duration::from_secs(10);
+
+/// Frames of a compressed dump not written yet, see [`compress_chunk`].
+#[derive(Default)]
+struct ZstdChunk {
+    raw: Vec<u8>,
+    /// index of the first pending frame in the file
+    first: u64,
+    frames: u32,
+    started: Option<Instant>,
+}
+
+impl ZstdChunk {
+    fn push(&mut self, frame: &[u8]) {
+        self.started.get_or_insert_with(Instant::now);
+        self.raw.extend_from_slice(frame);
+        self.frames += 1;
+    }
+
+    fn due(&self) -> bool {
+        self.raw.len() >= CHUNK_SIZE || self.started.is_some_and(|at| at.elapsed() >= CHUNK_LINGER)
+    }
+
+    /// The pending frames compressed, none when there are none; they stay
+    /// pending until [`ZstdChunk::written`].
+    fn compress(&self) -> Result<Option<Vec<u8>>> {
+        if self.frames == 0 {
+            return Ok(None);
+        }
+        Ok(Some(compress_chunk(self.first, self.frames, &self.raw)?))
+    }
+
+    fn written(&mut self) {
+        self.first += self.frames as u64;
+        self.frames = 0;
+        self.raw.clear();
+        self.started = None;
+    }
+}
+
+pub struct FileSink {
+    path: PathBuf,
+    binary: bool,
+    daily: Option<DailyPath>,
+    file: Option<File>,
+    zstd: Option<ZstdChunk>,
+}
+
+impl FileSink {
+    pub fn new(path: PathBuf, binary: bool, daily: bool, compress: bool) -> Self {
+        Self {
+            daily: daily.then(|| DailyPath::new(&path, "sink")),
+            path,
+            binary: binary || compress,
+            file: None,
+            zstd: compress.then(ZstdChunk::default),
+        }
+    }
+
+    async fn open(&mut self) -> Result<File> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await?;
+        let len = file.metadata().await?.len();
+        match self.zstd.as_mut() {
+            // the magic goes in the first chunk, pending from a failed write or not
+            Some(zstd) if len == 0 => {
+                zstd.first = 0;
+                if !zstd.raw.starts_with(DUMP_MAGIC) {
+                    zstd.raw.splice(0..0, DUMP_MAGIC.iter().copied());
+                }
+            }
+            Some(zstd) => {
+                let path = self.path.clone();
+                zstd.first = spawn_blocking(move || {
+                    compressed_frame_count(&mut std::fs::File::open(&path)?)
+                })
+                .await?
+                .map_err(|e| anyhow!("{} is not a compressed dump: {}", self.path.display(), e))?;
+            }
+            None if self.binary && len == 0 => file.write_all(DUMP_MAGIC).await?,
+            None => (),
+        }
+        Ok(file)
+    }
+
+    /// Write the pending chunk of a compressed dump.
+    async fn flush_chunk(&mut self) -> Result<()> {
+        let (Some(file), Some(zstd)) = (self.file.as_mut(), self.zstd.as_mut()) else {
+            return Ok(());
+        };
+        let Some(chunk) = zstd.compress()? else {
+            return Ok(());
+        };
+        let len = file.metadata().await?.len();
+        // written before a later chunk, or the one left at drop
+        let written = match file.write_all(&chunk).await {
+            Ok(()) => file.flush().await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = written {
+            // the frames stay pending, without a partial chunk before them
+            let _ = file.set_len(len).await;
+            self.file = None;
+            return Err(e.into());
+        }
+        zstd.written();
+        Ok(())
+    }
+}
+
+impl Sink for FileSink {
+    fn tick_in(&self) -> Option<Duration> {
+        let started = self.zstd.as_ref()?.started?;
+        Some(CHUNK_LINGER.saturating_sub(started.elapsed()))
+    }
+
+    async fn tick(&mut self) -> Result<()> {
+        if !self.zstd.as_ref().is_some_and(ZstdChunk::due) {
+            return Ok(());
+        }
+        let flushed = async {
+            if self.file.is_none() {
+                self.file = Some(self.open().await?);
+            }
+            self.flush_chunk().await
+        }
+        .await;
+        if let (Err(_), Some(zstd)) = (&flushed, self.zstd.as_mut()) {
+            // tried again after another linger
+            zstd.started = Some(Instant::now());
+        }
+        flushed
+    }
+
+    async fn close(&mut self) -> Result<()> {
+        if self.zstd.as_ref().is_none_or(|zstd| zstd.frames == 0) {
+            return Ok(());
+        }
+        if self.file.is_none() {
+            self.file = Some(self.open().await?);
+        }
+        self.flush_chunk().await
+    }
+
+    async fn send(&mut self, delivery: Delivery) -> Result<()> {
+        if let Some(path) = self
+            .daily
+            .as_mut()
+            .map(DailyPath::roll)
+            .transpose()?
+            .flatten()
+        {
+            // a new day, the pending frames belong to the last one
+            self.flush_chunk().await?;
+            self.path = path;
+            self.file = None;
+        }
+        if self.file.is_none() {
+            self.file = Some(self.open().await?);
+        }
+        let bytes = match (delivery.payload, self.binary) {
+            (Payload::Json(json), true) => {
+                encode_frame(&delivery.method, &serde_json::to_vec(&json)?)
+            }
+            (Payload::Serialized(bytes), true) => encode_frame(&delivery.method, &bytes),
+            (Payload::Binary(bin), true) => encode_frame(&delivery.method, &bin),
+            (Payload::Json(json), false) => {
+                let mut line = serde_json::to_vec(&json)?;
+                line.push(b'\n');
+                line
+            }
+            (Payload::Serialized(bytes), false) => [&bytes[..], b"\n"].concat(),
+            (Payload::Binary(bin), false) => (BASE64_STANDARD.encode(bin) + "\n").into_bytes(),
+        };
+        if let Some(zstd) = self.zstd.as_mut() {
+            zstd.push(&bytes);
+            if !zstd.due() {
+                return Ok(());
+            }
+            return self.flush_chunk().await;
+        }
+        let file = self.file.as_mut().ok_or(anyhow!("File not opened"))?;
+        if let Err(e) = file.write_all(&bytes).await {
+            // reopen on next payload
+            self.file = None;
+            return Err(e.into());
+        }
+        Ok(())
+    }
+}
+
+pub struct ConsoleSink {
+    color: bool,
+}
+
+impl ConsoleSink {
+    /// Colors only go to a terminal, and not when `NO_COLOR` is set.
+    pub fn new(color: bool) -> Self {
+        Self {
+            color: color
+                && std::io::stdout().is_terminal()
+                && std::env::var_os("NO_COLOR").is_none(),
+        }
+    }
+
+    fn style(&self, kind: &str, text: &str) -> String {
+        if !self.color {
+            return text.to_string();
+        }
+        let code = match kind {
+            "round" => "1;36",
+            "draw" => "2",
+            "call" => "33",
+            "riichi" => "35",
+            "chance" => "1;32",
+            "win" => "1;31",
+            "ryuukyoku" => "34",
+            _ => "0",
+        };
+        format!("\x1b[{}m{}\x1b[0m", code, text)
+    }
+}
+
+impl Sink for ConsoleSink {
+    async fn send(&mut self, delivery: Delivery) -> Result<()> {
+        let line = match delivery.payload {
+            Payload::Json(json) => match (json["kind"].as_str(), json["text"].as_str()) {
+                (Some(kind), Some(text)) => self.style(kind, text),
+                _ => format!("{} {}", delivery.method, json),
+            },
+            Payload::Serialized(bytes) => {
+                format!("{} {}", delivery.method, String::from_utf8_lossy(&bytes))
+            }
+            Payload::Binary(bin) => format!("{} {}", delivery.method, BASE64_STANDARD.encode(bin)),
+        };
+        writeln!(std::io::stdout().lock(), "{}", line)?;
+        Ok(())
+    }
+
+    fn quiet(&self) -> bool {
+        true
+    }
+}
+
+/// Consecutive failures before a sink is reported down.
+const DOWN_THRESHOLD: u32 = 3;
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Tasks of the sinks started, awaited by [`stopped`].
+static TASKS: Lazy<Mutex<Vec<JoinHandle<()>>>> = Lazy::new(Default::default);
+
+/// Connectivity of a sink, also the data of `.helper.SinkStatus` events.
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct SinkHealth {
+    pub sink: String,
+    pub up: bool,
+    /// unix timestamp in seconds
+    pub last_success: Option<u64>,
+    pub consecutive_failures: u32,
+    /// failed sends since start
+    pub failures: u64,
+    /// payloads dropped while the sink was down, or its spool was full
+    pub dropped: u64,
+    /// bytes waiting in the spool of a durable sink
+    pub spooled: u64,
+    pub last_error: Option<String>,
+}
+
+struct SinkHandle {
+    name: String,
+    format: Box<dyn EventFormat>,
+    filter: Option<Filter>,
+    notation: Option<Notation>,
+    critical: bool,
+    tx: Sender<Delivery>,
+    health: Arc<Mutex<SinkHealth>>,
+}
+
+/// All configured sinks, each fed by its own task so a slow sink never blocks the others.
+pub struct Sinks(Vec<SinkHandle>);
+
+impl Sinks {
+    /// Start all sinks, the receiver yields a `.helper.SinkStatus` event whenever a sink goes down or recovers.
+    pub fn from_settings(settings: &Settings) -> (Self, UnboundedReceiver<Event>) {
+        let (status_tx, status_rx) = unbounded_channel();
+        let configs = if settings.sinks.is_empty() {
+            vec![SinkConfig {
+                name: "helper".to_string(),
+                target: SinkTarget::Http {
+                    url: settings.api_url.clone(),
+                },
+                format: FormatKind::Liqi,
+                filter: None,
+                notation: None,
+                // the helper itself
+                critical: true,
+                durable: false,
+            }]
+        } else {
+            settings.sinks.clone()
+        };
+        interest::set(settings, &configs);
+        let handles: Vec<SinkHandle> = configs
+            .into_iter()
+            .enumerate()
+            .filter_map(|(i, config)| {
+                let name = if config.name.is_empty() {
+                    format!("sink{}", i)
+                } else {
+                    config.name
+                };
+                let filter = match config.filter.as_deref().map(Filter::parse).transpose() {
+                    Ok(filter) => filter,
+                    Err(e) => {
+                        error!("Sink {}: invalid filter: {}", name, e);
+                        return None;
+                    }
+                };
+                let health = Arc::new(Mutex::new(SinkHealth {
+                    sink: name.clone(),
+                    up: true,
+                    ..Default::default()
+                }));
+                let monitor = Monitor {
+                    health: health.clone(),
+                    status: status_tx.clone(),
+                    retry_at: None,
+                };
+                let spool = config
+                    .durable
+                    .then(|| instance::nested(settings.config_dir().join("spool")));
+                let tx = match ARG.dry_run {
+                    true => spawn_dry_run(name.clone()),
+                    false => spawn_target(name.clone(), config.target, monitor, spool)?,
+                };
+                info!("Sink {} started, format: {:?}", name, config.format);
+                Some(SinkHandle {
+                    name,
+                    format: config.format.build(settings),
+                    filter,
+                    // tenhou documents only hold numeric codes
+                    notation: config
+                        .notation
+                        .filter(|_| config.format != FormatKind::Tenhou),
+                    critical: config.critical,
+                    tx,
+                    health,
+                })
+            })
+            .collect();
+        ACTIVITY.set_sinks(handles.iter().map(|h| h.health.clone()).collect());
+        (Sinks(handles), status_rx)
+    }
+
+    pub fn health(&self) -> Vec<SinkHealth> {
+        self.0
+            .iter()
+            .map(|h| h.health.lock().map(|h| h.clone()).unwrap_or_default())
+            .collect()
+    }
+
+    pub fn dispatch(&mut self, event: &Event) {
+        for handle in self.0.iter_mut() {
+            let _span = debug_span!(target: PIPELINE, "convert", sink = %handle.name).entered();
+            let payloads = match handle.format.encode(event) {
+                Ok(payloads) => payloads,
+                Err(e) => {
+                    ACTIVITY.sink_failed(SinkFailure::Encode);
+                    error!("Sink {}: failed to encode message: {:?}", handle.name, e);
+                    continue;
+                }
+            };
+            // encode anyway so stateful formats see every event
+            if handle.filter.as_ref().is_some_and(|f| !f.matches(event)) {
+                continue;
+            }
+            for mut payload in payloads {
+                if let (Some(_), Payload::Serialized(bytes)) = (handle.notation, &payload) {
+                    // tiles are rewritten in place, so shared text goes back to JSON
+                    match serde_json::from_slice(bytes) {
+                        Ok(json) => payload = Payload::Json(json),
+                        Err(e) => {
+                            error!("Sink {}: failed to decode payload: {}", handle.name, e);
+                            continue;
+                        }
+                    }
+                }
+                if let (Some(notation), Payload::Json(json)) = (handle.notation, &mut payload) {
+                    notate_json(json, notation);
+                }
+                if !handle.critical && memory::shed_sinks() {
+                    continue;
+                }
+                let delivery = Delivery {
+                    method: event.msg.method_name.clone(),
+                    charge: Charge::new(Consumer::Sinks, payload.size()),
+                    payload,
+                };
+                match handle.tx.try_send(delivery) {
+                    Ok(()) => (),
+                    Err(TrySendError::Full(_)) => {
+                        ACTIVITY.sink_failed(SinkFailure::QueueFull);
+                        warn!("Sink {}: queue full, payload dropped", handle.name)
+                    }
+                    Err(TrySendError::Closed(_)) => {
+                        error!("Sink {}: worker stopped", handle.name)
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn spawn_target(
+    name: String,
+    target: SinkTarget,
+    monitor: Monitor,
+    spool: Option<PathBuf>,
+) -> Option<Sender<Delivery>> {
+    Some(match target {
+        SinkTarget::Http { url } => spawn_sink(name, HttpSink::new(url), monitor, spool),
+        SinkTarget::File {
+            path,
+            binary,
+            daily,
+            compress,
+        } => spawn_sink(
+            name,
+            FileSink::new(path, binary, daily, compress),
+            monitor,
+            spool,
+        ),
+        SinkTarget::Console { color } => spawn_sink(name, ConsoleSink::new(color), monitor, spool),
+        #[cfg(feature = "kafka")]
+        SinkTarget::Kafka {
+            brokers,
+            topic,
+            topic_per_method,
+            batch_size,
+            linger_ms,
+        } => spawn_sink(
+            name,
+            KafkaSink::new(brokers, topic, topic_per_method, batch_size, linger_ms),
+            monitor,
+            spool,
+        ),
+        #[cfg(not(feature = "kafka"))]
+        SinkTarget::Kafka { .. } => {
+            error!("Sink {}: kafka support is not compiled in", name);
+            return None;
+        }
+        #[cfg(feature = "webrtc")]
+        SinkTarget::WebRtc {
+            listen,
+            ice_servers,
+            token,
+            allowed_origins,
+        } => match token.as_deref().map(crate::secrets::resolve).transpose() {
+            Ok(None) if !listen.ip().is_loopback() => {
+                error!(
+                    "Sink {}: a token is required to listen on {}, set one or bind to loopback",
+                    name, listen
+                );
+                return None;
+            }
+            Ok(token) => spawn_sink(
+                name,
+                WebRtcSink::new(listen, ice_servers, token, allowed_origins),
+                monitor,
+                spool,
+            ),
+            Err(e) => {
+                error!("Sink {}: {:#}", name, e);
+                return None;
+            }
+        },
+        #[cfg(not(feature = "webrtc"))]
+        SinkTarget::WebRtc { .. } => {
+            error!("Sink {}: webrtc support is not compiled in", name);
+            return None;
+        }
+    })
+}
+
+/// Health bookkeeping of one sink task.
+struct Monitor {
+    health: Arc<Mutex<SinkHealth>>,
+    status: UnboundedSender<Event>,
+    retry_at: Option<Instant>,
+}
+
+impl Monitor {
+    /// Whether a send should be attempted, payloads are dropped while backing off.
+    fn ready(&mut self) -> bool {
+        match self.retry_at {
+            Some(at) if Instant::now() < at => {
+                if let Ok(mut health) = self.health.lock() {
+                    health.dropped += 1;
+                }
+                false
+            }
+            _ => true,
+        }
+    }
+
+    fn success(&mut self) {
+        let Ok(mut health) = self.health.lock() else {
+            return;
+        };
+        let recovered = !health.up;
+        health.up = true;
+        health.consecutive_failures = 0;
+        health.last_success = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .ok()
+            .map(|d| d.as_secs());
+        self.retry_at = None;
+        if recovered {
+            info!(
+                "Sink {} recovered, {} payloads dropped",
+                health.sink, health.dropped
+            );
+            self.emit(&health);
+        }
+    }
+
+    fn failure(&mut self, e: &anyhow::Error) {
+        let Ok(mut health) = self.health.lock() else {
+            return;
+        };
+        ACTIVITY.sink_failed(SinkFailure::Send);
+        health.consecutive_failures += 1;
+        health.failures += 1;
+        health.last_error = Some(e.to_string());
+        if health.consecutive_failures < DOWN_THRESHOLD {
+            return;
+        }
+        // back off exponentially, reconnecting on the next attempt
+        let exp = (health.consecutive_failures - DOWN_THRESHOLD).min(5);
+        let backoff = (Duration::from_secs(1) * 2u32.pow(exp)).min(MAX_BACKOFF);
+        self.retry_at = Some(Instant::now() + backoff);
+        if health.up {
+            health.up = false;
+            warn!("Sink {} is down: {}", health.sink, e);
+            self.emit(&health);
+        }
+    }
+
+    fn dropped(&mut self, count: u64) {
+        if let Ok(mut health) = self.health.lock() {
+            health.dropped += count;
+        }
+    }
+
+    fn spooled(&mut self, bytes: u64) {
+        if let Ok(mut health) = self.health.lock() {
+            health.spooled = bytes;
+        }
+    }
+
+    fn emit(&self, health: &SinkHealth) {
+        let _ = self
+            .status
+            .send(Event::derived(".helper.SinkStatus", json!(health)));
+    }
+}
+
+/// Log what `--dry-run` keeps from the sink instead of sending it.
+fn spawn_dry_run(name: String) -> Sender<Delivery> {
+    let (tx, mut rx) = channel::<Delivery>(100);
+    tokio::spawn(async move {
+        while let Some(Delivery {
+            method, payload, ..
+        }) = rx.recv().await
+        {
+            match payload {
+                Payload::Json(json) => info!("{} (dry run) {}: {}", name, method, json),
+                Payload::Serialized(bytes) => info!(
+                    "{} (dry run) {}: {}",
+                    name,
+                    method,
+                    String::from_utf8_lossy(&bytes)
+                ),
+                Payload::Binary(bin) => {
+                    info!("{} (dry run) {}: {} bytes", name, method, bin.len())
+                }
+            }
+        }
+    });
+    tx
+}
+
+fn spawn_sink<S: Sink>(
+    name: String,
+    mut sink: S,
+    monitor: Monitor,
+    spool: Option<PathBuf>,
+) -> Sender<Delivery> {
+    let (tx, rx) = channel::<Delivery>(100);
+    let task = tokio::spawn(async move {
+        match spool {
+            None => run_sink(&name, &mut sink, monitor, rx).await,
+            Some(dir) => match Spool::open(&dir, &name).await {
+                Ok(spool) => run_durable(&name, &mut sink, monitor, rx, spool).await,
+                Err(e) => {
+                    error!(
+                        "Sink {}: failed to open the spool, sending directly: {:?}",
+                        name, e
+                    );
+                    run_sink(&name, &mut sink, monitor, rx).await
+                }
+            },
+        }
+        if let Err(e) = sink.close().await {
+            error!("Sink {}: failed to close: {:?}", name, e);
+        }
+    });
+    if let Ok(mut tasks) = TASKS.lock() {
+        tasks.retain(|task| !task.is_finished());
+        tasks.push(task);
+    }
+    tx
+}
+
+/// Wait up to `within` for the sinks to close once their senders are gone,
+/// e.g. for a compressed file sink to write its last chunk at exit.
+pub async fn stopped(within: Duration) {
+    let tasks = match TASKS.lock() {
+        Ok(mut tasks) => std::mem::take(&mut *tasks),
+        Err(_) => return,
+    };
+    let all = async {
+        for task in tasks {
+            let _ = task.await;
+        }
+    };
+    if timeout(within, all).await.is_err() {
+        warn!("Sinks not closed within {:?}", within);
+    }
+}
+
+async fn run_sink<S: Sink>(
+    name: &str,
+    sink: &mut S,
+    mut monitor: Monitor,
+    mut rx: Receiver<Delivery>,
+) {
+    loop {
+        let delivery = match sink.tick_in() {
+            Some(due) => match timeout(due, rx.recv()).await {
+                Ok(delivery) => delivery,
+                Err(_) => {
+                    tick(name, sink, &mut monitor).await;
+                    continue;
+                }
+            },
+            None => rx.recv().await,
+        };
+        let Some(delivery) = delivery else { break };
+        if !monitor.ready() {
+            continue;
+        }
+        let mut batch = vec![delivery];
+        while batch.len() < sink.batch_size() {
+            match timeout(sink.linger(), rx.recv()).await {
+                Ok(Some(delivery)) => batch.push(delivery),
+                _ => break,
+            }
+        }
+        let span = debug_span!(target: PIPELINE, "sink", sink = %name, batch = batch.len());
+        match sink.send_batch(batch).instrument(span).await {
+            Ok(()) => {
+                if !sink.quiet() {
+                    info!("{} 已接收", name);
+                }
+                monitor.success();
+            }
+            Err(e) => {
+                error!("{} 请求失败: {:?}", name, e);
+                monitor.failure(&e);
+            }
+        }
+    }
+}
+
+async fn tick<S: Sink>(name: &str, sink: &mut S, monitor: &mut Monitor) {
+    if let Err(e) = sink.tick().await {
+        error!("Sink {}: {:?}", name, e);
+        monitor.failure(&e);
+    }
+}
+
+/// [`run_sink`] through the spool: payloads are written to disk as they come
+/// and sent from there, kept while the sink is down instead of dropped.
+async fn run_durable<S: Sink>(
+    name: &str,
+    sink: &mut S,
+    mut monitor: Monitor,
+    mut rx: Receiver<Delivery>,
+    mut spool: Spool,
+) {
+    if spool.pending() > 0 {
+        info!("Sink {}: {} bytes spooled to send", name, spool.pending());
+    }
+    loop {
+        monitor.spooled(spool.pending());
+        let retry_at = monitor.retry_at.unwrap_or_else(Instant::now);
+        let tick_in = sink.tick_in();
+        tokio::select! {
+            delivery = rx.recv() => {
+                // replaced by a reload or stopping, the rest stays on disk
+                let Some(delivery) = delivery else { break };
+                let mut deliveries = vec![delivery];
+                while let Ok(delivery) = rx.try_recv() {
+                    deliveries.push(delivery);
+                }
+                match spool.append(deliveries).await {
+                    Ok(0) => (),
+                    Ok(dropped) => {
+                        warn!("Sink {}: spool full, {} payloads dropped", name, dropped);
+                        monitor.dropped(dropped as u64);
+                    }
+                    Err(e) => error!("Sink {}: failed to write the spool: {:?}", name, e),
+                }
+            }
+            _ = sleep(tick_in.unwrap_or_default()), if tick_in.is_some() => {
+                tick(name, sink, &mut monitor).await;
+            }
+            _ = sleep_until(retry_at.into()), if spool.pending() > 0 => {
+                let (batch, end) = match spool.peek(sink.batch_size()).await {
+                    Ok(read) => read,
+                    Err(e) => {
+                        error!("Sink {}: spool corrupt, cleared: {:?}", name, e);
+                        if let Err(e) = spool.clear().await {
+                            error!("Sink {}: failed to clear the spool: {:?}", name, e);
+                            break;
+                        }
+                        continue;
+                    }
+                };
+                let span = debug_span!(target: PIPELINE, "sink", sink = %name, batch = batch.len());
+                match sink.send_batch(batch).instrument(span).await {
+                    // only once taken, for an http sink with a 2xx status
+                    Ok(()) => {
+                        if !sink.quiet() {
+                            info!("{} 已接收", name);
+                        }
+                        monitor.success();
+                        if let Err(e) = spool.ack(end).await {
+                            error!("Sink {}: failed to update the spool: {:?}", name, e);
+                        }
+                    }
+                    Err(e) => {
+                        error!("{} 请求失败: {:?}", name, e);
+                        monitor.failure(&e);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dump::read_frames;
+
+    fn delivery(data: &[u8]) -> Delivery {
+        Delivery {
+            method: Arc::from(".lq.ActionPrototype"),
+            payload: Payload::Binary(Bytes::copy_from_slice(data)),
+            charge: Charge::new(Consumer::Sinks, 0),
+        }
+    }
+
+    #[tokio::test]
+    async fn compressed_file_sink_writes_its_chunk_on_close() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("game.dump");
+        let mut sink = FileSink::new(path.clone(), true, false, true);
+        sink.send(delivery(b"one")).await.unwrap();
+        sink.send(delivery(b"two")).await.unwrap();
+        // held back until the chunk is due
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), 0);
+        sink.close().await.unwrap();
+        // appended to, counting on from the frames already there
+        let mut sink = FileSink::new(path.clone(), true, false, true);
+        sink.send(delivery(b"three")).await.unwrap();
+        assert_eq!(sink.zstd.as_ref().unwrap().first, 2);
+        sink.close().await.unwrap();
+        let frames: Vec<Vec<u8>> = read_frames(&std::fs::read(&path).unwrap())
+            .into_iter()
+            .map(|frame| frame.buf.unwrap())
+            .collect();
+        assert_eq!(
+            frames,
+            [b"one".to_vec(), b"two".to_vec(), b"three".to_vec()]
+        );
+    }
+}
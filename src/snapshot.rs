@@ -0,0 +1,130 @@
+use crate::{instance, report::Tally, state::GameState, SETTINGS};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::{
+    path::PathBuf,
+    time::{Duration, Instant, SystemTime},
+};
+use tracing::{info, warn};
+
+/// Snapshots older than this belong to a game that is surely over.
+const MAX_AGE: Duration = Duration::from_secs(2 * 60 * 60);
+
+#[derive(Serialize, Deserialize)]
+struct Snapshot {
+    state: GameState,
+    tally: Tally,
+}
+
+/// Saves the state of the own game in progress, to be picked up again after a restart.
+pub struct Snapshots {
+    path: PathBuf,
+    /// disabled when zero
+    interval: Duration,
+    last_save: Option<Instant>,
+    /// loaded at startup, waiting for the client to rejoin its game
+    pending: Option<GameState>,
+}
+
+impl Snapshots {
+    pub fn new(interval_secs: u64) -> Self {
+        let path = instance::suffixed(&SETTINGS.config_dir().join("snapshot.json"));
+        let pending = match interval_secs {
+            0 => None,
+            _ => load(&path).unwrap_or_else(|e| {
+                warn!("Failed to load state snapshot: {:?}", e);
+                None
+            }),
+        };
+        if pending.is_some() {
+            info!("Found a snapshot of an unfinished game, waiting for the client to rejoin");
+        }
+        Snapshots {
+            path,
+            interval: Duration::from_secs(interval_secs),
+            last_save: None,
+            pending,
+        }
+    }
+
+    /// Own account of the snapshot game, so that its authGame is recognized without a login.
+    pub fn account_id(&self) -> Option<u32> {
+        let game = self.pending.as_ref()?.game.as_ref()?;
+        game.players.get(game.seat as usize).map(|p| p.account_id)
+    }
+
+    /// Carry the whole-game parts of the snapshot over if `state` just rejoined the same game.
+    ///
+    /// The current round is rebuilt from the syncGame that follows.
+    pub fn restore(&mut self, state: &mut GameState) -> bool {
+        let same_game = |snapshot: &GameState| {
+            let ids = |s: &GameState| {
+                s.game
+                    .as_ref()
+                    .map(|g| g.players.iter().map(|p| p.account_id).collect::<Vec<_>>())
+            };
+            snapshot.seat == state.seat && ids(snapshot).is_some() && ids(snapshot) == ids(state)
+        };
+        let Some(snapshot) = self.pending.take_if(|s| same_game(s)) else {
+            return false;
+        };
+        if let (Some(game), Some(saved)) = (state.game.as_mut(), snapshot.game) {
+            game.game_uuid = game.game_uuid.take().or(saved.game_uuid);
+        }
+        state.clocks = snapshot.clocks;
+        state.tally = snapshot.tally;
+        info!("Restored game state from snapshot");
+        true
+    }
+
+    /// Save `state` if the interval has passed, only own live games are kept.
+    pub fn save(&mut self, state: &GameState) {
+        if self.interval.is_zero() || state.spectated || state.game.is_none() {
+            return;
+        }
+        if self.last_save.is_some_and(|t| t.elapsed() < self.interval) {
+            return;
+        }
+        self.last_save = Some(Instant::now());
+        let snapshot = Snapshot {
+            state: state.clone(),
+            tally: state.tally.clone(),
+        };
+        // written aside and renamed over, so a crash mid-write keeps the last one
+        let tmp = self.path.with_extension("json.tmp");
+        if let Err(e) = serde_json::to_vec(&snapshot)
+            .map_err(anyhow::Error::from)
+            .and_then(|body| Ok(std::fs::write(&tmp, body)?))
+            .and_then(|_| Ok(std::fs::rename(&tmp, &self.path)?))
+        {
+            warn!("Failed to save state snapshot: {:?}", e);
+        }
+    }
+
+    /// Forget the snapshot once the game is over.
+    pub fn clear(&mut self) {
+        self.last_save = None;
+        self.pending = None;
+        if self.path.exists() {
+            if let Err(e) = std::fs::remove_file(&self.path) {
+                warn!("Failed to remove state snapshot: {:?}", e);
+            }
+        }
+    }
+}
+
+fn load(path: &PathBuf) -> Result<Option<GameState>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let age = SystemTime::now()
+        .duration_since(std::fs::metadata(path)?.modified()?)
+        .unwrap_or_default();
+    if age > MAX_AGE {
+        return Ok(None);
+    }
+    let snapshot: Snapshot = serde_json::from_slice(&std::fs::read(path)?)?;
+    let mut state = snapshot.state;
+    state.tally = snapshot.tally;
+    Ok(Some(state))
+}
@@ -0,0 +1,458 @@
+//! On-disk queue of a `durable` sink: payloads are appended to
+//! `spool/<sink>.wal` before they are sent and acknowledged in
+//! `spool/<sink>.ack` once the sink took them, so a crash, a sleeping machine
+//! or a sink down for a while only delays them. Delivery is at least once,
+//! a batch sent just before a crash is sent again.
+
+use crate::{
+    memory::{Charge, Consumer},
+    sink::{Delivery, Payload},
+};
+use anyhow::{anyhow, bail, Result};
+use once_cell::sync::Lazy;
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io::{BufReader, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+use tokio::{
+    sync::{Mutex as AsyncMutex, OwnedMutexGuard},
+    task::spawn_blocking,
+};
+
+/// Most bytes waiting to be sent, payloads are dropped while the spool is full.
+const SPOOL_LIMIT: u64 = 64 << 20;
+/// Sent bytes at the head of the log above which it is rewritten without
+/// them, for a sink that never catches up completely.
+const COMPACT_AT: u64 = 16 << 20;
+const TEXT: u8 = 0;
+const BINARY: u8 = 1;
+
+/// Spools in use, a sink replaced by a reload keeps its own until it stopped.
+static IN_USE: Lazy<Mutex<HashMap<PathBuf, Arc<AsyncMutex<()>>>>> = Lazy::new(Default::default);
+
+pub struct Spool {
+    file: File,
+    path: PathBuf,
+    ack_path: PathBuf,
+    /// end of the records sent
+    acked: u64,
+    len: u64,
+    _lock: OwnedMutexGuard<()>,
+}
+
+impl Spool {
+    /// Open the spool of the sink `name` in `dir`, waiting for a previous
+    /// task of the sink to let go of it, and cut off a record torn by a crash.
+    pub async fn open(dir: &Path, name: &str) -> Result<Self> {
+        let stem: String = name
+            .chars()
+            .map(|c| match c.is_ascii_alphanumeric() || c == '-' {
+                true => c,
+                false => '_',
+            })
+            .collect();
+        let path = dir.join(format!("{}.wal", stem));
+        let lock = IN_USE
+            .lock()
+            .map_err(|_| anyhow!("poisoned"))?
+            .entry(path.clone())
+            .or_default()
+            .clone();
+        let lock = lock.lock_owned().await;
+        std::fs::create_dir_all(dir)?;
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+        let ack_path = path.with_extension("ack");
+        let acked = std::fs::read(&ack_path)
+            .ok()
+            .and_then(|bytes| Some(u64::from_le_bytes(bytes.try_into().ok()?)))
+            .unwrap_or(0);
+        let mut spool = Spool {
+            len: file.metadata()?.len(),
+            file,
+            path,
+            ack_path,
+            acked,
+            _lock: lock,
+        };
+        // the ack is reset before the log is emptied or compacted, but a log
+        // cut by hand must not be skipped
+        spool.acked = spool.acked.min(spool.len);
+        let end = spool.valid_end()?;
+        if end < spool.len {
+            tracing::warn!(
+                "Spool {}: {} bytes of a torn record cut off",
+                spool.path.display(),
+                spool.len - end
+            );
+            spool.file.set_len(end)?;
+            spool.len = end;
+        }
+        Ok(spool)
+    }
+
+    /// End of the last whole record.
+    fn valid_end(&mut self) -> Result<u64> {
+        self.file.seek(SeekFrom::Start(self.acked))?;
+        let mut reader = BufReader::new(&self.file);
+        let mut end = self.acked;
+        while let Some((_, size)) = read_record(&mut reader) {
+            end += size;
+        }
+        Ok(end)
+    }
+
+    /// Bytes waiting to be sent.
+    pub fn pending(&self) -> u64 {
+        self.len - self.acked
+    }
+
+    /// Append `deliveries`, synced to disk before returning. Returns how many
+    /// were dropped as the spool is full.
+    ///
+    /// The write and fsync run on the blocking pool, not on the runtime worker
+    /// of the sink task.
+    pub async fn append(&mut self, deliveries: Vec<Delivery>) -> Result<usize> {
+        let mut buf = vec![];
+        let mut dropped = 0;
+        for delivery in deliveries {
+            let (kind, payload) = match delivery.payload {
+                Payload::Json(json) => (TEXT, serde_json::to_vec(&json)?),
+                Payload::Serialized(bytes) => (TEXT, bytes.to_vec()),
+                Payload::Binary(bytes) => (BINARY, bytes.to_vec()),
+            };
+            let method = delivery.method.as_bytes();
+            let size = 3 + method.len() + payload.len();
+            if self.pending() + (buf.len() + 4 + size) as u64 > SPOOL_LIMIT {
+                dropped += 1;
+                continue;
+            }
+            buf.extend_from_slice(&(size as u32).to_le_bytes());
+            buf.push(kind);
+            buf.extend_from_slice(&(method.len() as u16).to_le_bytes());
+            buf.extend_from_slice(method);
+            buf.extend_from_slice(&payload);
+        }
+        if !buf.is_empty() {
+            let mut file = self.file.try_clone()?;
+            let (at, written) = (self.len, buf.len() as u64);
+            spawn_blocking(move || -> Result<()> {
+                file.seek(SeekFrom::Start(at))?;
+                file.write_all(&buf)?;
+                file.sync_data()?;
+                Ok(())
+            })
+            .await??;
+            self.len += written;
+        }
+        Ok(dropped)
+    }
+
+    /// Up to `max` of the oldest unsent payloads, with the end to [`ack`](Self::ack)
+    /// once sent.
+    pub async fn peek(&mut self, max: usize) -> Result<(Vec<Delivery>, u64)> {
+        let mut file = self.file.try_clone()?;
+        let (acked, len, path) = (self.acked, self.len, self.path.clone());
+        spawn_blocking(move || {
+            file.seek(SeekFrom::Start(acked))?;
+            let mut reader = BufReader::new(&file);
+            let mut deliveries = vec![];
+            let mut end = acked;
+            while deliveries.len() < max.max(1) && end < len {
+                let Some((delivery, size)) = read_record(&mut reader) else {
+                    bail!("Spool {}: truncated at {}", path.display(), end);
+                };
+                deliveries.push(delivery);
+                end += size;
+            }
+            Ok((deliveries, end))
+        })
+        .await?
+    }
+
+    /// Mark the payloads before `end` sent, emptying the log once all are
+    /// and dropping the sent ones from it once they take [`COMPACT_AT`].
+    ///
+    /// The ack is reset on disk before the log changes, a crash in between
+    /// only sends the records again.
+    pub async fn ack(&mut self, end: u64) -> Result<()> {
+        let file = self.file.try_clone()?;
+        let (path, ack_path, len) = (self.path.clone(), self.ack_path.clone(), self.len);
+        let file = spawn_blocking(move || -> Result<Option<File>> {
+            if end == len {
+                write_ack(&ack_path, 0)?;
+                file.set_len(0)?;
+                file.sync_data()?;
+                return Ok(None);
+            }
+            if end < COMPACT_AT {
+                write_ack(&ack_path, end)?;
+                return Ok(None);
+            }
+            let tmp = path.with_extension("wal.tmp");
+            let mut rest = File::create(&tmp)?;
+            let mut file = file;
+            file.seek(SeekFrom::Start(end))?;
+            std::io::copy(&mut (&file).take(len - end), &mut rest)?;
+            rest.sync_all()?;
+            drop(rest);
+            write_ack(&ack_path, 0)?;
+            std::fs::rename(&tmp, &path)?;
+            Ok(Some(OpenOptions::new().read(true).write(true).open(&path)?))
+        })
+        .await??;
+        if end == self.len {
+            (self.acked, self.len) = (0, 0);
+        } else if let Some(file) = file {
+            self.file = file;
+            (self.acked, self.len) = (0, self.len - end);
+        } else {
+            self.acked = end;
+        }
+        Ok(())
+    }
+
+    /// Drop everything, for a log that can't be read.
+    pub async fn clear(&mut self) -> Result<()> {
+        self.ack(self.len).await
+    }
+}
+
+/// Replace the ack file with `acked`, synced so that it never points past
+/// records appended after it.
+fn write_ack(path: &Path, acked: u64) -> Result<()> {
+    let tmp = path.with_extension("ack.tmp");
+    let mut file = File::create(&tmp)?;
+    file.write_all(&acked.to_le_bytes())?;
+    file.sync_all()?;
+    std::fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+/// A record and its size, none at the end of the log or at a torn record.
+fn read_record(reader: &mut impl Read) -> Option<(Delivery, u64)> {
+    let mut size = [0; 4];
+    reader.read_exact(&mut size).ok()?;
+    let size = u32::from_le_bytes(size) as usize;
+    if size < 3 || size as u64 > SPOOL_LIMIT {
+        return None;
+    }
+    let mut record = vec![0; size];
+    reader.read_exact(&mut record).ok()?;
+    let method_len = u16::from_le_bytes([record[1], record[2]]) as usize;
+    let method = std::str::from_utf8(record.get(3..3 + method_len)?).ok()?;
+    let bytes = bytes::Bytes::copy_from_slice(&record[3 + method_len..]);
+    let payload = match record[0] {
+        TEXT => Payload::Serialized(bytes),
+        BINARY => Payload::Binary(bytes),
+        _ => return None,
+    };
+    Some((
+        Delivery {
+            method: method.into(),
+            charge: Charge::new(Consumer::Sinks, payload.size()),
+            payload,
+        },
+        4 + size as u64,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+
+    fn delivery(method: &str, payload: Payload) -> Delivery {
+        Delivery {
+            method: method.into(),
+            charge: Charge::new(Consumer::Sinks, payload.size()),
+            payload,
+        }
+    }
+
+    fn text(method: &str, body: &'static str) -> Delivery {
+        delivery(
+            method,
+            Payload::Serialized(Bytes::from_static(body.as_bytes())),
+        )
+    }
+
+    fn bodies(deliveries: &[Delivery]) -> Vec<(&str, &[u8])> {
+        deliveries
+            .iter()
+            .map(|d| match &d.payload {
+                Payload::Serialized(b) | Payload::Binary(b) => (d.method.as_ref(), b.as_ref()),
+                Payload::Json(_) => panic!("spooled payloads are serialized"),
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn append_peek_ack() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut spool = Spool::open(dir.path(), "http").await.unwrap();
+        let batch = vec![
+            text(".a", "{}"),
+            delivery(".b", Payload::Binary(Bytes::from_static(b"\x00\xff"))),
+            text(".c", "[1]"),
+        ];
+        assert_eq!(spool.append(batch).await.unwrap(), 0);
+        let (first, end) = spool.peek(2).await.unwrap();
+        assert_eq!(bodies(&first), [(".a", &b"{}"[..]), (".b", b"\x00\xff")]);
+        assert!(matches!(first[1].payload, Payload::Binary(_)));
+        // not acked, read again
+        assert_eq!(spool.peek(2).await.unwrap().1, end);
+        spool.ack(end).await.unwrap();
+        let (rest, end) = spool.peek(10).await.unwrap();
+        assert_eq!(bodies(&rest), [(".c", &b"[1]"[..])]);
+        spool.ack(end).await.unwrap();
+        assert_eq!(spool.pending(), 0);
+        assert_eq!(std::fs::metadata(&spool.path).unwrap().len(), 0);
+    }
+
+    #[tokio::test]
+    async fn reopen_resumes_after_the_ack() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut spool = Spool::open(dir.path(), "http").await.unwrap();
+        let batch = vec![text(".a", "1"), text(".b", "2")];
+        spool.append(batch).await.unwrap();
+        let (_, end) = spool.peek(1).await.unwrap();
+        spool.ack(end).await.unwrap();
+        let pending = spool.pending();
+        drop(spool);
+        let mut spool = Spool::open(dir.path(), "http").await.unwrap();
+        assert_eq!(spool.pending(), pending);
+        let (rest, _) = spool.peek(10).await.unwrap();
+        assert_eq!(bodies(&rest), [(".b", &b"2"[..])]);
+    }
+
+    #[tokio::test]
+    async fn reopen_after_truncation() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut spool = Spool::open(dir.path(), "http").await.unwrap();
+        spool.append(vec![text(".a", "1")]).await.unwrap();
+        let (_, end) = spool.peek(1).await.unwrap();
+        spool.ack(end).await.unwrap();
+        drop(spool);
+        let mut spool = Spool::open(dir.path(), "http").await.unwrap();
+        assert_eq!(spool.pending(), 0);
+        spool.append(vec![text(".b", "2")]).await.unwrap();
+        assert_eq!(
+            bodies(&spool.peek(10).await.unwrap().0),
+            [(".b", &b"2"[..])]
+        );
+    }
+
+    #[tokio::test]
+    async fn ack_past_a_truncated_log() {
+        let dir = tempfile::tempdir().unwrap();
+        let spool = Spool::open(dir.path(), "http").await.unwrap();
+        // crashed after emptying the log, before resetting the ack
+        std::fs::write(&spool.ack_path, 1000u64.to_le_bytes()).unwrap();
+        drop(spool);
+        let mut spool = Spool::open(dir.path(), "http").await.unwrap();
+        assert_eq!(spool.pending(), 0);
+        spool.append(vec![text(".a", "1")]).await.unwrap();
+        assert_eq!(
+            bodies(&spool.peek(10).await.unwrap().0),
+            [(".a", &b"1"[..])]
+        );
+    }
+
+    #[tokio::test]
+    async fn torn_tail_is_cut_off() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut spool = Spool::open(dir.path(), "http").await.unwrap();
+        spool.append(vec![text(".a", "1")]).await.unwrap();
+        let whole = spool.pending();
+        spool.append(vec![text(".b", "22")]).await.unwrap();
+        let path = spool.path.clone();
+        drop(spool);
+        let len = std::fs::metadata(&path).unwrap().len();
+        OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(len - 1)
+            .unwrap();
+        let mut spool = Spool::open(dir.path(), "http").await.unwrap();
+        assert_eq!(spool.pending(), whole);
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), whole);
+        assert_eq!(
+            bodies(&spool.peek(10).await.unwrap().0),
+            [(".a", &b"1"[..])]
+        );
+    }
+
+    #[tokio::test]
+    async fn full_spool_drops() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut spool = Spool::open(dir.path(), "http").await.unwrap();
+        let huge = Payload::Binary(Bytes::from(vec![0; SPOOL_LIMIT as usize]));
+        let batch = vec![text(".a", "1"), delivery(".b", huge), text(".c", "3")];
+        assert_eq!(spool.append(batch).await.unwrap(), 1);
+        let (kept, _) = spool.peek(10).await.unwrap();
+        assert_eq!(bodies(&kept), [(".a", &b"1"[..]), (".c", b"3")]);
+    }
+
+    #[tokio::test]
+    async fn clear_drops_everything() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut spool = Spool::open(dir.path(), "http").await.unwrap();
+        spool.append(vec![text(".a", "1")]).await.unwrap();
+        spool.clear().await.unwrap();
+        assert_eq!(spool.pending(), 0);
+        drop(spool);
+        assert_eq!(Spool::open(dir.path(), "http").await.unwrap().pending(), 0);
+    }
+
+    fn mebibyte(method: &str) -> Delivery {
+        delivery(method, Payload::Binary(Bytes::from(vec![0; 1 << 20])))
+    }
+
+    #[tokio::test]
+    async fn sent_records_are_compacted_away() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut spool = Spool::open(dir.path(), "http").await.unwrap();
+        let batch = (0..20).map(|i| mebibyte(&format!(".{}", i))).collect();
+        spool.append(batch).await.unwrap();
+        let (sent, end) = spool.peek(17).await.unwrap();
+        assert!(end >= COMPACT_AT);
+        assert_eq!(sent.len(), 17);
+        let pending = spool.pending() - end;
+        spool.ack(end).await.unwrap();
+        assert_eq!(spool.pending(), pending);
+        assert_eq!(std::fs::metadata(&spool.path).unwrap().len(), pending);
+        let methods = |d: &[Delivery]| d.iter().map(|d| d.method.to_string()).collect::<Vec<_>>();
+        let (rest, _) = spool.peek(10).await.unwrap();
+        assert_eq!(methods(&rest), [".17", ".18", ".19"]);
+        drop(spool);
+        let mut spool = Spool::open(dir.path(), "http").await.unwrap();
+        assert_eq!(spool.pending(), pending);
+        assert_eq!(
+            methods(&spool.peek(10).await.unwrap().0),
+            [".17", ".18", ".19"]
+        );
+    }
+
+    #[tokio::test]
+    async fn only_unsent_records_count_against_the_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut spool = Spool::open(dir.path(), "http").await.unwrap();
+        for _ in 0..3 {
+            let batch = (0..40).map(|_| mebibyte(".a")).collect();
+            assert_eq!(spool.append(batch).await.unwrap(), 0);
+            // always one record behind
+            let (_, end) = spool.peek(39).await.unwrap();
+            spool.ack(end).await.unwrap();
+        }
+        assert_eq!(spool.peek(10).await.unwrap().0.len(), 3);
+    }
+}
@@ -0,0 +1,486 @@
+use crate::{
+    clock::Clock,
+    parser::{i32_list, str_field, str_list, u32_field, u32_list},
+    players::GameInfo,
+    report::Tally,
+    tenhou::YAKU,
+    tile::{dora_of, index34, kan_tiles},
+};
+use anyhow::{anyhow, Result};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use std::time::Instant;
+use tokio::sync::RwLock;
+
+/// Table state of the game in progress, fed by the helper worker.
+pub static GAME_STATE: Lazy<RwLock<GameState>> = Lazy::new(|| RwLock::new(GameState::default()));
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum Wind {
+    #[default]
+    East,
+    South,
+    West,
+    North,
+}
+
+impl Wind {
+    pub fn from_index(i: u32) -> Self {
+        match i % 4 {
+            0 => Wind::East,
+            1 => Wind::South,
+            2 => Wind::West,
+            _ => Wind::North,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Discard {
+    pub tile: String,
+    /// position among all discards of the round
+    pub index: usize,
+    pub tsumogiri: bool,
+    /// the riichi declaration tile
+    pub riichi: bool,
+    /// taken by another player's chi/pon/kan
+    pub called: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum MeldKind {
+    Chi,
+    Pon,
+    Daiminkan,
+    Ankan,
+    Kakan,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Meld {
+    pub kind: MeldKind,
+    pub tiles: Vec<String>,
+    /// seat the called tile came from, `None` for ankan
+    pub from: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct PlayerState {
+    pub seat: u32,
+    pub wind: Wind,
+    pub score: i32,
+    /// concealed tiles, only known for own seat and in records
+    pub hand: Vec<String>,
+    pub hand_count: usize,
+    pub discards: Vec<Discard>,
+    pub melds: Vec<Meld>,
+    pub riichi: bool,
+    /// north tiles set aside in sanma
+    pub nukidora: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Win {
+    pub seat: u32,
+    pub zimo: bool,
+    /// the dealt-in seat of a ron
+    pub from: Option<u32>,
+    pub tile: String,
+    pub han: u32,
+    pub fu: u32,
+    pub yakuman: bool,
+    pub points: u32,
+    pub ura_doras: Vec<String>,
+    pub yaku: Vec<Yaku>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Yaku {
+    /// majsoul fan id
+    pub id: u32,
+    pub name: String,
+    /// han, or yakuman multiples for yakuman
+    pub han: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum RoundResult {
+    Hule {
+        wins: Vec<Win>,
+        deltas: Vec<i32>,
+    },
+    /// exhaustive draw with the seats in tenpai
+    NoTile {
+        tenpai: Vec<u32>,
+        deltas: Vec<i32>,
+    },
+    /// abortive draw, see `ActionLiuJu.type`
+    LiuJu {
+        kind: u32,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct GameState {
+    /// own seat, unknown until authGame or a dealer hand is seen
+    pub seat: Option<u32>,
+    /// a spectated game or viewed replay
+    pub spectated: bool,
+    /// players and room, only known for own games
+    pub game: Option<GameInfo>,
+    pub round_wind: Wind,
+    /// dealer seat, also the round number in the wind
+    pub ju: u32,
+    pub honba: u32,
+    pub riichi_sticks: u32,
+    pub dora_indicators: Vec<String>,
+    /// revealed when a riichi hand wins
+    pub ura_indicators: Vec<String>,
+    /// seat whose riichi stick is not placed until the declaration goes through
+    pub pending_riichi: Option<u32>,
+    /// tiles left in the live wall
+    pub left_tiles: u32,
+    pub players: Vec<PlayerState>,
+    /// seat of the last action
+    pub current: Option<u32>,
+    /// step of the last `ActionPrototype` or syncGame applied
+    pub step: u32,
+    pub result: Option<RoundResult>,
+    /// thinking time by seat over the whole game
+    pub clocks: Vec<Clock>,
+    #[serde(skip)]
+    pub tally: Tally,
+    #[serde(skip)]
+    pub last_action_at: Option<Instant>,
+}
+
+impl GameState {
+    pub fn set_seat(&mut self, seat: u32) {
+        self.seat = Some(seat);
+    }
+
+    /// Three-player game, without 2m-8m and chi but with nukidora.
+    pub fn is_sanma(&self) -> bool {
+        self.players.len() == 3
+    }
+
+    /// An event mode the tracker can't follow is on, see [`GameInfo::degraded`].
+    pub fn degraded(&self) -> bool {
+        self.game.as_ref().is_some_and(GameInfo::degraded)
+    }
+
+    pub fn own(&self) -> Option<&PlayerState> {
+        self.players.get(self.seat? as usize)
+    }
+
+    pub fn player(&self, seat: u32) -> Option<&PlayerState> {
+        self.players.get(seat as usize)
+    }
+
+    /// Dora tiles pointed at by the indicators, with the ura ones once revealed.
+    pub fn doras(&self) -> (Vec<&'static str>, Vec<&'static str>) {
+        let sanma = self.is_sanma();
+        let of = |indicators: &[String]| {
+            indicators
+                .iter()
+                .filter_map(|i| dora_of(i, sanma).ok())
+                .collect()
+        };
+        (of(&self.dora_indicators), of(&self.ura_indicators))
+    }
+
+    /// Update with one action, the name has its `Action`/`Record` prefix stripped.
+    pub fn feed(&mut self, name: &str, data: &JsonValue) -> Result<()> {
+        match name {
+            "NewRound" => self.new_round(data)?,
+            "DealTile" => {
+                let seat = u32_field(data, "seat")?;
+                self.liqi_success(data);
+                self.update_doras(data);
+                if let Some(left) = data["left_tile_count"].as_u64() {
+                    self.left_tiles = left as u32;
+                }
+                let tile = data["tile"].as_str().unwrap_or_default();
+                let player = self.player_mut(seat)?;
+                if !tile.is_empty() {
+                    player.hand.push(tile.to_string());
+                }
+                player.hand_count += 1;
+                self.current = Some(seat);
+            }
+            "DiscardTile" => {
+                let seat = u32_field(data, "seat")?;
+                let tile = str_field(data, "tile")?;
+                let riichi = data["is_liqi"].as_bool().unwrap_or_default()
+                    || data["is_wliqi"].as_bool().unwrap_or_default();
+                let index = self.players.iter().map(|p| p.discards.len()).sum();
+                let player = self.player_mut(seat)?;
+                remove_tile(player, tile);
+                player.riichi |= riichi;
+                player.discards.push(Discard {
+                    tile: tile.to_string(),
+                    index,
+                    tsumogiri: data["moqie"].as_bool().unwrap_or_default(),
+                    riichi,
+                    called: false,
+                });
+                if riichi {
+                    self.pending_riichi = Some(seat);
+                }
+                self.update_doras(data);
+                self.current = Some(seat);
+            }
+            "ChiPengGang" => {
+                self.liqi_success(data);
+                let seat = u32_field(data, "seat")?;
+                let tiles = str_list(data, "tiles");
+                let froms = u32_list(data, "froms");
+                let called = froms
+                    .iter()
+                    .position(|f| *f != seat)
+                    .ok_or(anyhow!("No called tile in ChiPengGang"))?;
+                let from = froms[called];
+                if let Some(discard) = self.player_mut(from)?.discards.last_mut() {
+                    discard.called = true;
+                }
+                let kind = match u32_field(data, "type")? {
+                    0 => MeldKind::Chi,
+                    1 => MeldKind::Pon,
+                    _ => MeldKind::Daiminkan,
+                };
+                let player = self.player_mut(seat)?;
+                for (i, tile) in tiles.iter().enumerate() {
+                    if i != called {
+                        remove_tile(player, tile);
+                    }
+                }
+                player.melds.push(Meld {
+                    kind,
+                    tiles: tiles.iter().map(|t| t.to_string()).collect(),
+                    from: Some(from),
+                });
+                self.current = Some(seat);
+            }
+            "AnGangAddGang" => {
+                let seat = u32_field(data, "seat")?;
+                let tile = str_field(data, "tiles")?;
+                let idx = index34(tile)?;
+                let player = self.player_mut(seat)?;
+                if u32_field(data, "type")? == 3 {
+                    let mut tiles = vec![tile.to_string()];
+                    tiles.extend(kan_tiles(tile)?.into_iter().map(String::from));
+                    for tile in &tiles {
+                        remove_tile(player, tile);
+                    }
+                    player.melds.push(Meld {
+                        kind: MeldKind::Ankan,
+                        tiles,
+                        from: None,
+                    });
+                } else {
+                    remove_tile(player, tile);
+                    let pon = player
+                        .melds
+                        .iter_mut()
+                        .find(|m| {
+                            m.kind == MeldKind::Pon
+                                && m.tiles.first().and_then(|t| index34(t).ok()) == Some(idx)
+                        })
+                        .ok_or(anyhow!("No pon to add {} to", tile))?;
+                    pon.kind = MeldKind::Kakan;
+                    pon.tiles.push(tile.to_string());
+                }
+                self.update_doras(data);
+                self.current = Some(seat);
+            }
+            "BaBei" => {
+                let seat = u32_field(data, "seat")?;
+                let player = self.player_mut(seat)?;
+                remove_tile(player, "4z");
+                player.nukidora += 1;
+                self.update_doras(data);
+                self.current = Some(seat);
+            }
+            "Hule" => {
+                let mut wins = vec![];
+                for hule in data["hules"].as_array().into_iter().flatten() {
+                    let zimo = hule["zimo"].as_bool().unwrap_or_default();
+                    wins.push(Win {
+                        seat: u32_field(hule, "seat")?,
+                        zimo,
+                        // the discarder, or the kakan for chankan
+                        from: if zimo { None } else { self.current },
+                        tile: str_field(hule, "hu_tile")?.to_string(),
+                        han: u32_field(hule, "count")?,
+                        fu: u32_field(hule, "fu")?,
+                        yakuman: hule["yiman"].as_bool().unwrap_or_default(),
+                        points: u32_field(hule, "point_sum")?,
+                        ura_doras: str_list(hule, "li_doras")
+                            .into_iter()
+                            .map(String::from)
+                            .collect(),
+                        yaku: yaku_list(hule),
+                    });
+                    let doras = str_list(hule, "doras");
+                    if doras.len() > self.dora_indicators.len() {
+                        self.dora_indicators = doras.into_iter().map(String::from).collect();
+                    }
+                    if self.ura_indicators.is_empty() {
+                        self.ura_indicators = str_list(hule, "li_doras")
+                            .into_iter()
+                            .map(String::from)
+                            .collect();
+                    }
+                }
+                let deltas = i32_list(data, "delta_scores");
+                for (player, score) in self.players.iter_mut().zip(i32_list(data, "scores")) {
+                    player.score = score;
+                }
+                self.riichi_sticks = 0;
+                self.result = Some(RoundResult::Hule { wins, deltas });
+            }
+            "NoTile" => {
+                let tenpai = data["players"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .enumerate()
+                    .filter(|(_, p)| p["tingpai"].as_bool().unwrap_or_default())
+                    .map(|(i, _)| i as u32)
+                    .collect();
+                let mut deltas = vec![0; self.players.len()];
+                for score in data["scores"].as_array().into_iter().flatten() {
+                    for (acc, d) in deltas.iter_mut().zip(i32_list(score, "delta_scores")) {
+                        *acc += d;
+                    }
+                }
+                for (player, d) in self.players.iter_mut().zip(&deltas) {
+                    player.score += d;
+                }
+                self.left_tiles = 0;
+                self.result = Some(RoundResult::NoTile { tenpai, deltas });
+            }
+            "LiuJu" => {
+                self.liqi_success(data);
+                self.result = Some(RoundResult::LiuJu {
+                    kind: u32_field(data, "type")?,
+                });
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn new_round(&mut self, data: &JsonValue) -> Result<()> {
+        let scores = i32_list(data, "scores");
+        let chang = u32_field(data, "chang")?;
+        let ju = u32_field(data, "ju")?;
+        let own = str_list(data, "tiles");
+        if self.seat.is_none() && own.len() == 14 {
+            // only the dealer starts with 14 tiles
+            self.seat = Some(ju);
+        }
+        let players = scores.len() as u32;
+        self.players = scores
+            .into_iter()
+            .enumerate()
+            .map(|(seat, score)| {
+                let seat = seat as u32;
+                let mut hand = str_list(data, &format!("tiles{}", seat));
+                if hand.is_empty() && self.seat == Some(seat) {
+                    hand.clone_from(&own);
+                }
+                PlayerState {
+                    seat,
+                    wind: Wind::from_index((seat + players - ju % players) % players),
+                    score,
+                    hand: hand.into_iter().map(String::from).collect(),
+                    hand_count: if seat == ju { 14 } else { 13 },
+                    ..Default::default()
+                }
+            })
+            .collect();
+        self.round_wind = Wind::from_index(chang);
+        self.ju = ju;
+        self.honba = u32_field(data, "ben")?;
+        self.riichi_sticks = u32_field(data, "liqibang")?;
+        self.dora_indicators = match str_list(data, "doras") {
+            d if d.is_empty() => vec![str_field(data, "dora")?],
+            d => d,
+        }
+        .into_iter()
+        .map(String::from)
+        .collect();
+        self.ura_indicators.clear();
+        self.pending_riichi = None;
+        self.left_tiles = data["left_tile_count"].as_u64().unwrap_or_default() as u32;
+        self.current = Some(ju);
+        self.result = None;
+        Ok(())
+    }
+
+    fn player_mut(&mut self, seat: u32) -> Result<&mut PlayerState> {
+        self.players
+            .get_mut(seat as usize)
+            .ok_or(anyhow!("Invalid seat {}", seat))
+    }
+
+    /// A riichi is only paid for once the next action shows it went through.
+    fn liqi_success(&mut self, data: &JsonValue) {
+        let Some(liqi) = data.get("liqi").filter(|l| l.is_object()) else {
+            return;
+        };
+        self.pending_riichi = None;
+        if liqi["failed"].as_bool().unwrap_or_default() {
+            return;
+        }
+        let seat = liqi["seat"].as_u64().unwrap_or_default() as usize;
+        if let (Some(player), Some(score)) = (self.players.get_mut(seat), liqi["score"].as_i64()) {
+            player.score = score as i32;
+        }
+        if let Some(sticks) = liqi["liqibang"].as_u64() {
+            self.riichi_sticks = sticks as u32;
+        }
+    }
+
+    fn update_doras(&mut self, data: &JsonValue) {
+        let doras = str_list(data, "doras");
+        if doras.len() > self.dora_indicators.len() {
+            self.dora_indicators = doras.into_iter().map(String::from).collect();
+        }
+    }
+}
+
+pub(crate) fn yaku_list(hule: &JsonValue) -> Vec<Yaku> {
+    hule["fans"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|fan| {
+            let id = fan["id"].as_u64().unwrap_or_default() as u32;
+            Yaku {
+                id,
+                name: YAKU
+                    .get(id as usize)
+                    .copied()
+                    .unwrap_or_default()
+                    .to_string(),
+                han: fan["val"].as_u64().unwrap_or_default() as u32,
+            }
+        })
+        // dora and the like with no han
+        .filter(|y| y.han > 0)
+        .collect()
+}
+
+/// Take `tile` out of a hand, hidden hands only lose the count.
+fn remove_tile(player: &mut PlayerState, tile: &str) {
+    if let Some(i) = player.hand.iter().position(|t| t == tile) {
+        player.hand.remove(i);
+    }
+    player.hand_count = player.hand_count.saturating_sub(1);
+}
@@ -0,0 +1,127 @@
+use crate::{players::GameInfo, sink::Event};
+use anyhow::{anyhow, Result};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value as JsonValue};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tracing::warn;
+
+/// amae-koromo's first recorded games, early 2019.
+const SINCE_MS: u64 = 1_546_300_800_000;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct StatsApiConfig {
+    /// amae-koromo style API root, `pl4/` or `pl3/` is appended
+    #[serde(default = "default_url")]
+    pub url: String,
+    /// `mode` query of the rooms counted, all rooms when empty
+    #[serde(default)]
+    pub modes: String,
+    #[serde(default = "default_timeout")]
+    pub timeout_ms: u64,
+}
+
+fn default_url() -> String {
+    "https://5-data.amae-koromo.com/api/v2/".to_string()
+}
+
+fn default_timeout() -> u64 {
+    5000
+}
+
+/// Looks up the opponents of every own game, answers come back as `.helper.OpponentStats` events.
+pub struct Enricher {
+    client: Client,
+    config: StatsApiConfig,
+    tx: UnboundedSender<Event>,
+}
+
+impl Enricher {
+    pub fn new(config: StatsApiConfig) -> Result<(Self, UnboundedReceiver<Event>)> {
+        let client = Client::builder()
+            .timeout(Duration::from_millis(config.timeout_ms))
+            .build()?;
+        let (tx, rx) = unbounded_channel();
+        Ok((Enricher { client, config, tx }, rx))
+    }
+
+    /// Query every human opponent in the background.
+    pub fn lookup(&self, game: &GameInfo) {
+        let pl = if game.players.len() == 3 {
+            "pl3"
+        } else {
+            "pl4"
+        };
+        for player in &game.players {
+            if player.seat == game.seat || player.account_id == 0 {
+                continue;
+            }
+            let client = self.client.clone();
+            let base = format!("{}{}/", self.config.url, pl);
+            let modes = self.config.modes.clone();
+            let tx = self.tx.clone();
+            let (seat, account_id, nickname) =
+                (player.seat, player.account_id, player.nickname.clone());
+            tokio::spawn(async move {
+                let stats = match fetch(&client, &base, "player_stats", account_id, &modes).await {
+                    Ok(stats) => stats,
+                    Err(e) => {
+                        warn!("Failed to look up stats of {}: {:?}", nickname, e);
+                        return;
+                    }
+                };
+                let extended = fetch(&client, &base, "player_extended_stats", account_id, &modes)
+                    .await
+                    .unwrap_or_else(|e| {
+                        warn!("Failed to look up extended stats of {}: {:?}", nickname, e);
+                        JsonValue::Null
+                    });
+                let data = json!({
+                    "seat": seat,
+                    "account_id": account_id,
+                    "nickname": nickname,
+                    "summary": summarize(&stats, &extended),
+                    "stats": stats,
+                    "extended": extended,
+                });
+                let _ = tx.send(Event::derived(".helper.OpponentStats", data));
+            });
+        }
+    }
+}
+
+async fn fetch(
+    client: &Client,
+    base: &str,
+    endpoint: &str,
+    account_id: u32,
+    modes: &str,
+) -> Result<JsonValue> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+    let mut url = format!("{}{}/{}/{}/{}", base, endpoint, account_id, SINCE_MS, now);
+    if !modes.is_empty() {
+        url = format!("{}?mode={}", url, modes);
+    }
+    let res = client.get(&url).send().await?;
+    if !res.status().is_success() {
+        return Err(anyhow!("{} returned {}", url, res.status()));
+    }
+    Ok(res.json().await?)
+}
+
+/// The figures overlays usually show, null when the API lacks them.
+fn summarize(stats: &JsonValue, extended: &JsonValue) -> JsonValue {
+    json!({
+        "games": stats["count"],
+        "level": stats["level"],
+        "max_level": stats["max_level"],
+        "avg_rank": stats["avg_rank"],
+        "rank_rates": stats["rank_rates"],
+        "win_rate": extended["和牌率"],
+        "deal_in_rate": extended["放铳率"],
+        "riichi_rate": extended["立直率"],
+        "call_rate": extended["副露率"],
+    })
+}
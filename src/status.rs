@@ -0,0 +1,198 @@
+use crate::{settings::REQUEST_CLIENT, tr, SETTINGS};
+use anyhow::{Context, Result};
+use clap::Args;
+use serde_json::{json, Value};
+use std::{fmt, process::ExitCode, time::Duration};
+
+/// Exit code of a command that failed, 2 is what clap exits with on wrong usage.
+pub const EXIT_FAILURE: u8 = 1;
+/// Exit code of `status` when no instance answers on the admin address.
+pub const EXIT_NOT_RUNNING: u8 = 3;
+
+#[derive(Args, Debug)]
+pub struct StatusArgs {
+    /// print the state as JSON for scripts
+    #[clap(long)]
+    pub json: bool,
+}
+
+/// No instance answered on the admin address.
+#[derive(Debug)]
+pub struct NotRunning(String);
+
+impl fmt::Display for NotRunning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            tr!(
+                "没有在 {} 运行的实例（需开启helper与adminAddr）",
+                "{} で動作中のインスタンスがありません（helper と adminAddr が必要です）",
+                "No instance is running on {} (it needs the helper and adminAddr on)",
+                self.0
+            )
+        )
+    }
+}
+
+impl std::error::Error for NotRunning {}
+
+/// The process exit code for the result of a command.
+pub fn exit_code(res: &Result<()>) -> ExitCode {
+    match res {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) if e.is::<NotRunning>() => ExitCode::from(EXIT_NOT_RUNNING),
+        Err(_) => ExitCode::from(EXIT_FAILURE),
+    }
+}
+
+/// Ask the instance running with these settings for its state via the admin API.
+pub async fn run(args: &StatusArgs) -> Result<()> {
+    let addr = &SETTINGS.admin_addr;
+    let resp = match REQUEST_CLIENT
+        .get(format!("http://{}/status", addr))
+        .timeout(Duration::from_secs(3))
+        .send()
+        .await
+    {
+        Ok(resp) => resp,
+        Err(_) => {
+            if args.json {
+                println!("{}", json!({ "running": false }));
+            }
+            return Err(NotRunning(addr.clone()).into());
+        }
+    };
+    let mut status: Value = resp
+        .error_for_status()?
+        .json()
+        .await
+        .context("Not a status from the admin API")?;
+    status["running"] = json!(true);
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&status)?);
+        return Ok(());
+    }
+    let uptime = status["uptime"].as_u64().unwrap_or_default();
+    println!(
+        "{}",
+        tr!(
+            "MajsoulMax-rs {} 运行中，PID {}，已运行 {:02}:{:02}:{:02}",
+            "MajsoulMax-rs {} 動作中、PID {}、稼働 {:02}:{:02}:{:02}",
+            "MajsoulMax-rs {} running, PID {}, up {:02}:{:02}:{:02}",
+            status["version"].as_str().unwrap_or_default(),
+            status["pid"],
+            uptime / 3600,
+            uptime / 60 % 60,
+            uptime % 60
+        )
+    );
+    println!(
+        "{}",
+        tr!(
+            "代理 {}，会话 {}，消息 {}",
+            "プロキシ {}、セッション {}、メッセージ {}",
+            "Proxy {}, sessions {}, messages {}",
+            status["proxy_addr"].as_str().unwrap_or_default(),
+            status["sessions"],
+            status["frames"]
+        )
+    );
+    let queue = &status["queue"];
+    if queue.is_object() {
+        println!(
+            "{}",
+            tr!(
+                "待解析 {}/{}，峰值 {}，已丢弃 {}",
+                "解析待ち {}/{}、最大 {}、破棄 {}",
+                "Queued {}/{}, peak {}, dropped {}",
+                queue["depth"],
+                queue["capacity"],
+                queue["peak"],
+                queue["dropped"]
+            )
+        );
+    }
+    let memory = &status["memory"];
+    if memory.is_object() {
+        let mib = |v: &Value| v.as_u64().unwrap_or_default() as f64 / (1 << 20) as f64;
+        let cap = match memory["cap"].as_u64() {
+            Some(0) | None => "-".to_string(),
+            Some(_) => format!("{:.0}", mib(&memory["cap"])),
+        };
+        println!(
+            "{}",
+            tr!(
+                "内存 {:.1}/{} MiB（请求 {:.1}，输出队列 {:.1}，录制 {:.1}，调试帧 {:.1}）",
+                "メモリ {:.1}/{} MiB（リクエスト {:.1}、出力キュー {:.1}、記録 {:.1}、デバッグ {:.1}）",
+                "Memory {:.1}/{} MiB (requests {:.1}, sink queues {:.1}, recording {:.1}, debug frames {:.1})",
+                mib(&memory["pending"])
+                    + mib(&memory["sinks"])
+                    + mib(&memory["recorder"])
+                    + mib(&memory["debug"]),
+                cap,
+                mib(&memory["pending"]),
+                mib(&memory["sinks"]),
+                mib(&memory["recorder"]),
+                mib(&memory["debug"])
+            )
+        );
+    }
+    let errors = &status["errors"];
+    if errors.is_object() {
+        let parse: Vec<(&String, &Value)> =
+            errors["parse"].as_object().into_iter().flatten().collect();
+        let total: u64 = parse.iter().filter_map(|(_, n)| n.as_u64()).sum();
+        // only the kinds that happened, e.g. `descriptor_miss 3`
+        let kinds: Vec<String> = parse
+            .iter()
+            .filter(|(_, n)| n.as_u64().is_some_and(|n| n > 0))
+            .map(|(kind, n)| format!("{} {}", kind, n))
+            .collect();
+        let kinds = match kinds.is_empty() {
+            true => String::new(),
+            false => format!(" ({})", kinds.join(", ")),
+        };
+        println!(
+            "{}",
+            tr!(
+                "解析失败 {}{}，输出编码失败 {}，队列满丢弃 {}，发送失败 {}",
+                "解析失敗 {}{}、出力エンコード失敗 {}、キュー満杯で破棄 {}、送信失敗 {}",
+                "Parse errors {}{}, sink encode errors {}, dropped on full queues {}, send failures {}",
+                total,
+                kinds,
+                errors["sink_encode"],
+                errors["sink_queue_full"],
+                errors["sink_send"]
+            )
+        );
+    }
+    for sink in status["sinks"].as_array().into_iter().flatten() {
+        let state = match sink["up"].as_bool() {
+            Some(true) => tr!("正常", "正常", "up"),
+            _ => tr!("断开", "切断", "down"),
+        };
+        let error = sink["last_error"].as_str().unwrap_or_default();
+        println!(
+            "  {}: {} {}",
+            sink["sink"].as_str().unwrap_or_default(),
+            state,
+            error
+        );
+    }
+    let game = match status["in_game"].as_bool() {
+        Some(true) => tr!("对局中", "対局中", "in a game"),
+        _ => tr!("未在对局", "対局なし", "not in a game"),
+    };
+    println!(
+        "{}",
+        tr!(
+            "{}，本次已打 {} 局",
+            "{}、今回 {} 局",
+            "{}, {} games this session",
+            game,
+            status["session"]["games"]
+        )
+    );
+    Ok(())
+}
@@ -0,0 +1,468 @@
+use crate::{
+    events::final_scores,
+    format::{game_actions, strip_action},
+    history::GameSample,
+    players::GameInfo,
+    record::Record,
+    sink::Event,
+    state::GameState,
+};
+use anyhow::Result;
+use serde_json::Value as JsonValue;
+use std::path::Path;
+use tokio::sync::mpsc::UnboundedSender;
+use tracing::error;
+
+/// Tables of the database, see the README for what the columns hold.
+pub const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS games (
+    uuid TEXT PRIMARY KEY,
+    source TEXT NOT NULL,
+    start_time INTEGER,
+    end_time INTEGER,
+    room TEXT NOT NULL,
+    mode_id INTEGER NOT NULL,
+    room_id INTEGER NOT NULL,
+    contest_uid INTEGER NOT NULL,
+    player_count INTEGER NOT NULL,
+    own_seat INTEGER
+);
+CREATE TABLE IF NOT EXISTS players (
+    game_uuid TEXT NOT NULL,
+    seat INTEGER NOT NULL,
+    account_id INTEGER NOT NULL,
+    nickname TEXT NOT NULL,
+    rank INTEGER,
+    score INTEGER,
+    total_point INTEGER,
+    PRIMARY KEY (game_uuid, seat)
+);
+CREATE TABLE IF NOT EXISTS hands (
+    game_uuid TEXT NOT NULL,
+    hand INTEGER NOT NULL,
+    chang INTEGER NOT NULL,
+    ju INTEGER NOT NULL,
+    ben INTEGER NOT NULL,
+    result TEXT,
+    PRIMARY KEY (game_uuid, hand)
+);
+CREATE TABLE IF NOT EXISTS actions (
+    game_uuid TEXT NOT NULL,
+    seq INTEGER NOT NULL,
+    hand INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    data TEXT NOT NULL,
+    PRIMARY KEY (game_uuid, seq)
+);
+CREATE INDEX IF NOT EXISTS players_account ON players (account_id);
+";
+
+/// One write to the database, applied in order by the writer thread.
+#[derive(Debug)]
+#[cfg_attr(not(feature = "sqlite"), allow(dead_code))]
+enum StoreOp {
+    /// start or rejoin a game, replacing a stored record of the same uuid if `replace`
+    Game {
+        uuid: String,
+        source: &'static str,
+        info: GameInfo,
+        own_seat: Option<u32>,
+        start_time: Option<i64>,
+        replace: bool,
+    },
+    /// `Record`/`Action` prefix stripped
+    Action {
+        uuid: String,
+        name: String,
+        data: JsonValue,
+    },
+    /// the current hand again from its start, as restored by syncGame
+    Restore {
+        uuid: String,
+        actions: Vec<(String, JsonValue)>,
+    },
+    /// (seat, score, total point) by final rank
+    End {
+        uuid: String,
+        scores: Vec<(u32, i32, i32)>,
+        end_time: Option<i64>,
+    },
+}
+
+/// Writes games, hands and actions to SQLite, requires the `sqlite` feature.
+pub struct Store {
+    tx: UnboundedSender<StoreOp>,
+    writer: std::thread::JoinHandle<()>,
+    /// uuid of the own game being captured
+    live: Option<String>,
+}
+
+impl Store {
+    #[cfg(feature = "sqlite")]
+    pub fn open(path: &Path) -> Result<Self> {
+        let conn = rusqlite::Connection::open(path)?;
+        conn.execute_batch(SCHEMA)?;
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let writer = std::thread::spawn(move || {
+            let mut writer = sqlite::Writer::new(conn);
+            while let Some(op) = rx.blocking_recv() {
+                if let Err(e) = writer.apply(op) {
+                    error!("Failed to write to database: {:?}", e);
+                }
+            }
+        });
+        Ok(Store {
+            tx,
+            writer,
+            live: None,
+        })
+    }
+
+    #[cfg(not(feature = "sqlite"))]
+    pub fn open(_path: &Path) -> Result<Self> {
+        anyhow::bail!("SQLite support is not compiled in")
+    }
+
+    /// Wait until everything sent is written.
+    pub fn close(self) {
+        drop(self.tx);
+        if self.writer.join().is_err() {
+            error!("Database writer panicked");
+        }
+    }
+
+    fn send(&self, op: StoreOp) {
+        if self.tx.send(op).is_err() {
+            error!("Database writer stopped");
+        }
+    }
+
+    /// Capture own live games, `state` being already updated with `event`.
+    pub fn feed(&mut self, event: &Event, state: &GameState) {
+        if event.spectated {
+            return;
+        }
+        let data = &event.msg.data;
+        match event.msg.method_name.as_ref() {
+            ".lq.FastTest.authGame" => {
+                let Some(info) = state.game.clone() else {
+                    return;
+                };
+                let now = chrono::Utc::now().timestamp();
+                // without the match notify there is no uuid to rejoin by
+                let uuid = info
+                    .game_uuid
+                    .clone()
+                    .unwrap_or_else(|| format!("live-{}", now));
+                self.live = Some(uuid.clone());
+                self.send(StoreOp::Game {
+                    uuid,
+                    source: "live",
+                    own_seat: Some(info.seat),
+                    info,
+                    start_time: Some(now),
+                    replace: false,
+                });
+            }
+            ".lq.ActionPrototype" => {
+                let Some(uuid) = self.live.clone() else {
+                    return;
+                };
+                match game_actions(event) {
+                    Ok(actions) => {
+                        for (name, data) in actions {
+                            self.send(StoreOp::Action {
+                                uuid: uuid.clone(),
+                                name: strip_action(&name).to_string(),
+                                data,
+                            });
+                        }
+                    }
+                    Err(e) => error!("Failed to decode actions for the database: {:?}", e),
+                }
+            }
+            ".lq.FastTest.syncGame" => {
+                let Some(uuid) = self.live.clone() else {
+                    return;
+                };
+                match game_actions(event) {
+                    Ok(actions) => self.send(StoreOp::Restore {
+                        uuid,
+                        actions: actions
+                            .into_iter()
+                            .map(|(name, data)| (strip_action(&name).to_string(), data))
+                            .collect(),
+                    }),
+                    Err(e) => error!("Failed to decode actions for the database: {:?}", e),
+                }
+            }
+            ".lq.NotifyGameEndResult" => {
+                let Some(uuid) = self.live.take() else {
+                    return;
+                };
+                self.send(StoreOp::End {
+                    uuid,
+                    scores: final_scores(data, state)
+                        .into_iter()
+                        .map(|s| (s.seat, s.score, s.total_point))
+                        .collect(),
+                    end_time: Some(chrono::Utc::now().timestamp()),
+                });
+            }
+            _ => (),
+        }
+    }
+
+    /// Store a decoded record, replacing an earlier copy of the same game.
+    pub fn import(&self, record: &Record, own_seat: Option<u32>) {
+        let info = GameInfo::from_record(&record.head, own_seat.unwrap_or_default());
+        let Some(uuid) = info.game_uuid.clone() else {
+            error!("Record without uuid, not stored");
+            return;
+        };
+        let time = |key: &str| record.head[key].as_i64();
+        self.send(StoreOp::Game {
+            uuid: uuid.clone(),
+            source: "record",
+            info,
+            own_seat,
+            start_time: time("start_time"),
+            replace: true,
+        });
+        for action in &record.actions {
+            self.send(StoreOp::Action {
+                uuid: uuid.clone(),
+                name: strip_action(&action.name).to_string(),
+                data: action.data.clone(),
+            });
+        }
+        self.send(StoreOp::End {
+            uuid,
+            scores: final_scores(&record.head, &GameState::default())
+                .into_iter()
+                .map(|s| (s.seat, s.score, s.total_point))
+                .collect(),
+            end_time: time("end_time"),
+        });
+    }
+}
+
+/// Games of `account_id` in the database, or the own games when none is given.
+#[cfg(feature = "sqlite")]
+pub fn read_games(path: &Path, account_id: Option<u32>) -> Result<Vec<GameSample>> {
+    sqlite::read_games(&rusqlite::Connection::open(path)?, account_id)
+}
+
+#[cfg(not(feature = "sqlite"))]
+pub fn read_games(_path: &Path, _account_id: Option<u32>) -> Result<Vec<GameSample>> {
+    anyhow::bail!("SQLite support is not compiled in")
+}
+
+#[cfg(feature = "sqlite")]
+mod sqlite {
+    use super::StoreOp;
+    use crate::{history::GameSample, selection::GameFacts};
+    use anyhow::Result;
+    use rusqlite::{params, Connection, OptionalExtension};
+    use serde_json::Value as JsonValue;
+
+    pub struct Writer {
+        conn: Connection,
+    }
+
+    impl Writer {
+        pub fn new(conn: Connection) -> Self {
+            Writer { conn }
+        }
+
+        pub fn apply(&mut self, op: StoreOp) -> Result<()> {
+            let tx = self.conn.transaction()?;
+            match op {
+                StoreOp::Game {
+                    uuid,
+                    source,
+                    info,
+                    own_seat,
+                    start_time,
+                    replace,
+                } => {
+                    if replace {
+                        for table in ["games", "players", "hands", "actions"] {
+                            let key = if table == "games" {
+                                "uuid"
+                            } else {
+                                "game_uuid"
+                            };
+                            tx.execute(
+                                &format!("DELETE FROM {} WHERE {} = ?1", table, key),
+                                [&uuid],
+                            )?;
+                        }
+                    }
+                    let room = serde_json::to_value(info.room)?;
+                    // a rejoined game keeps its row
+                    tx.execute(
+                        "INSERT OR IGNORE INTO games (uuid, source, start_time, room, mode_id, \
+                         room_id, contest_uid, player_count, own_seat) \
+                         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                        params![
+                            uuid,
+                            source,
+                            start_time,
+                            room.as_str(),
+                            info.mode_id,
+                            info.room_id,
+                            info.contest_uid,
+                            info.players.len(),
+                            own_seat,
+                        ],
+                    )?;
+                    for p in &info.players {
+                        tx.execute(
+                            "INSERT OR IGNORE INTO players (game_uuid, seat, account_id, nickname) \
+                             VALUES (?1, ?2, ?3, ?4)",
+                            params![uuid, p.seat, p.account_id, p.nickname],
+                        )?;
+                    }
+                }
+                StoreOp::Action { uuid, name, data } => insert_action(&tx, &uuid, &name, &data)?,
+                StoreOp::Restore { uuid, actions } => {
+                    let (hand, _) = position(&tx, &uuid)?;
+                    let same_hand = match actions.first() {
+                        Some((name, data)) if name == "NewRound" => {
+                            let stored: Option<(u64, u64, u64)> = tx
+                                .query_row(
+                                    "SELECT chang, ju, ben FROM hands \
+                                     WHERE game_uuid = ?1 AND hand = ?2",
+                                    params![uuid, hand],
+                                    |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+                                )
+                                .optional()?;
+                            stored == Some(round_of(data))
+                        }
+                        _ => false,
+                    };
+                    if same_hand {
+                        // rewrite the hand with the actions missed while disconnected
+                        tx.execute(
+                            "DELETE FROM actions WHERE game_uuid = ?1 AND hand >= ?2",
+                            params![uuid, hand],
+                        )?;
+                        tx.execute(
+                            "DELETE FROM hands WHERE game_uuid = ?1 AND hand >= ?2",
+                            params![uuid, hand],
+                        )?;
+                    }
+                    for (name, data) in actions {
+                        insert_action(&tx, &uuid, &name, &data)?;
+                    }
+                }
+                StoreOp::End {
+                    uuid,
+                    scores,
+                    end_time,
+                } => {
+                    for (rank, (seat, score, total_point)) in scores.into_iter().enumerate() {
+                        tx.execute(
+                            "UPDATE players SET rank = ?3, score = ?4, total_point = ?5 \
+                             WHERE game_uuid = ?1 AND seat = ?2",
+                            params![uuid, seat, rank + 1, score, total_point],
+                        )?;
+                    }
+                    if end_time.is_some() {
+                        tx.execute(
+                            "UPDATE games SET end_time = ?2 WHERE uuid = ?1",
+                            params![uuid, end_time],
+                        )?;
+                    }
+                }
+            }
+            tx.commit()?;
+            Ok(())
+        }
+    }
+
+    pub fn read_games(conn: &Connection, account_id: Option<u32>) -> Result<Vec<GameSample>> {
+        let mut games = conn.prepare(
+            "SELECT g.uuid, g.start_time, g.room, g.mode_id, g.player_count, p.seat, p.rank \
+             FROM games g JOIN players p ON p.game_uuid = g.uuid \
+             WHERE (?1 IS NULL AND p.seat = g.own_seat) OR p.account_id = ?1 \
+             ORDER BY g.start_time",
+        )?;
+        let mut actions =
+            conn.prepare("SELECT name, data FROM actions WHERE game_uuid = ?1 ORDER BY seq")?;
+        let rows = games.query_map([account_id], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, Option<i64>>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, u32>(3)?,
+                row.get::<_, usize>(4)?,
+                row.get::<_, u32>(5)?,
+                row.get::<_, Option<u32>>(6)?,
+            ))
+        })?;
+        let mut samples = vec![];
+        for row in rows {
+            let (uuid, start_time, room, mode_id, player_count, seat, rank) = row?;
+            let actions = actions
+                .query_map([&uuid], |row| {
+                    Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+                })?
+                .map(|row| {
+                    let (name, data) = row?;
+                    Ok((name, serde_json::from_str(&data)?))
+                })
+                .collect::<Result<_>>()?;
+            let room = serde_json::from_value(JsonValue::String(room)).unwrap_or_default();
+            samples.push(GameSample {
+                facts: GameFacts::from_stored(start_time, room, mode_id, player_count),
+                seat,
+                rank,
+                actions,
+            });
+        }
+        Ok(samples)
+    }
+
+    fn round_of(data: &JsonValue) -> (u64, u64, u64) {
+        let field = |key: &str| data[key].as_u64().unwrap_or_default();
+        (field("chang"), field("ju"), field("ben"))
+    }
+
+    /// Current hand, -1 before the first, and the next action's seq.
+    fn position(conn: &Connection, uuid: &str) -> Result<(i64, i64)> {
+        let last: Option<(i64, i64)> = conn
+            .query_row(
+                "SELECT hand, seq FROM actions WHERE game_uuid = ?1 ORDER BY seq DESC LIMIT 1",
+                [uuid],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+        Ok(last.map_or((-1, 0), |(hand, seq)| (hand, seq + 1)))
+    }
+
+    fn insert_action(conn: &Connection, uuid: &str, name: &str, data: &JsonValue) -> Result<()> {
+        let (mut hand, seq) = position(conn, uuid)?;
+        if name == "NewRound" {
+            hand += 1;
+            let (chang, ju, ben) = round_of(data);
+            conn.execute(
+                "INSERT OR REPLACE INTO hands (game_uuid, hand, chang, ju, ben) \
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![uuid, hand, chang, ju, ben],
+            )?;
+        }
+        if matches!(name, "Hule" | "NoTile" | "LiuJu") {
+            conn.execute(
+                "UPDATE hands SET result = ?3 WHERE game_uuid = ?1 AND hand = ?2",
+                params![uuid, hand, name],
+            )?;
+        }
+        conn.execute(
+            "INSERT INTO actions (game_uuid, seq, hand, name, data) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![uuid, seq, hand, name, data.to_string()],
+        )?;
+        Ok(())
+    }
+}
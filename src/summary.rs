@@ -0,0 +1,135 @@
+use crate::state::{GameState, RoundResult, Wind};
+use serde::Serialize;
+
+/// Uma by placement used for the placement EV, in thousands of points.
+const UMA4: [f64; 4] = [15.0, 5.0, -5.0, -15.0];
+const UMA3: [f64; 3] = [15.0, 0.0, -15.0];
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Standing {
+    pub seat: u32,
+    pub nickname: Option<String>,
+    pub score_before: i32,
+    pub score: i32,
+    pub delta: i32,
+    /// 1 for the top
+    pub rank_before: u32,
+    pub rank: u32,
+    /// final points if the game ended after this hand
+    pub ev: f64,
+    pub ev_delta: f64,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct WinSummary {
+    pub seat: u32,
+    pub from: Option<u32>,
+    pub han: u32,
+    pub fu: u32,
+    /// 満貫 and up, empty below
+    pub limit: String,
+    pub points: u32,
+}
+
+/// Data of `.helper.HandSummary`, emitted at the end of every hand.
+#[derive(Serialize, Debug, Clone)]
+pub struct HandSummary {
+    pub round_wind: Wind,
+    pub ju: u32,
+    pub honba: u32,
+    pub result: RoundResult,
+    pub wins: Vec<WinSummary>,
+    pub standings: Vec<Standing>,
+}
+
+/// Summarize the hand that just ended, `before` being the scores before the result.
+pub fn summarize(state: &GameState, before: &[i32]) -> Option<HandSummary> {
+    let result = state.result.clone()?;
+    let after: Vec<i32> = state.players.iter().map(|p| p.score).collect();
+    let ranks_before = ranks(before);
+    let ranks_after = ranks(&after);
+    let sanma = state.is_sanma();
+    let standings = (0..after.len())
+        .map(|i| {
+            let ev_before = ev(before[i], ranks_before[i], sanma);
+            let ev = ev(after[i], ranks_after[i], sanma);
+            Standing {
+                seat: i as u32,
+                nickname: state
+                    .game
+                    .as_ref()
+                    .and_then(|g| g.players.get(i))
+                    .map(|p| p.nickname.clone()),
+                score_before: before[i],
+                score: after[i],
+                delta: after[i] - before[i],
+                rank_before: ranks_before[i],
+                rank: ranks_after[i],
+                ev,
+                ev_delta: ev - ev_before,
+            }
+        })
+        .collect();
+    let wins = match &result {
+        RoundResult::Hule { wins, .. } => wins
+            .iter()
+            .map(|w| WinSummary {
+                seat: w.seat,
+                from: w.from,
+                han: w.han,
+                fu: w.fu,
+                limit: limit(w.han, w.fu, w.yakuman).to_string(),
+                points: w.points,
+            })
+            .collect(),
+        _ => vec![],
+    };
+    Some(HandSummary {
+        round_wind: state.round_wind,
+        ju: state.ju,
+        honba: state.honba,
+        result,
+        wins,
+        standings,
+    })
+}
+
+/// Placements by score, ties go to the seat closer to the starting dealer.
+fn ranks(scores: &[i32]) -> Vec<u32> {
+    scores
+        .iter()
+        .enumerate()
+        .map(|(i, s)| {
+            1 + scores
+                .iter()
+                .enumerate()
+                .filter(|(j, t)| *t > s || *t == s && *j < i)
+                .count() as u32
+        })
+        .collect()
+}
+
+/// Score over the starting points plus uma, in thousands.
+fn ev(score: i32, rank: u32, sanma: bool) -> f64 {
+    let (start, uma) = if sanma {
+        (35000, &UMA3[..])
+    } else {
+        (25000, &UMA4[..])
+    };
+    let uma = uma.get(rank as usize - 1).copied().unwrap_or_default();
+    (score - start) as f64 / 1000.0 + uma
+}
+
+pub(crate) fn limit(han: u32, fu: u32, yakuman: bool) -> &'static str {
+    match (yakuman, han) {
+        (true, _) => "役満",
+        (_, 13..) => "数え役満",
+        (_, 11..=12) => "三倍満",
+        (_, 8..=10) => "倍満",
+        (_, 6..=7) => "跳満",
+        (_, 5) => "満貫",
+        (_, 4) if fu >= 40 => "満貫",
+        (_, 3) if fu >= 70 => "満貫",
+        _ => "",
+    }
+}
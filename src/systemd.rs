@@ -0,0 +1,86 @@
+//! The parts of systemd's service protocol a `Type=notify` unit needs, without
+//! linking libsystemd.
+
+/// Send a state such as `READY=1` to the service manager, doing nothing when
+/// not started by systemd.
+#[cfg(unix)]
+pub fn notify(state: &str) {
+    use std::os::unix::{ffi::OsStrExt, net::UnixDatagram};
+    use tracing::{debug, warn};
+
+    let Some(path) = std::env::var_os("NOTIFY_SOCKET") else {
+        return;
+    };
+    let send = || -> std::io::Result<usize> {
+        let socket = UnixDatagram::unbound()?;
+        let path = path.as_bytes();
+        match path.strip_prefix(b"@") {
+            // an abstract socket
+            #[cfg(target_os = "linux")]
+            Some(name) => {
+                use std::os::{linux::net::SocketAddrExt, unix::net::SocketAddr};
+                socket.send_to_addr(state.as_bytes(), &SocketAddr::from_abstract_name(name)?)
+            }
+            _ => socket.send_to(state.as_bytes(), std::ffi::OsStr::from_bytes(path)),
+        }
+    };
+    match send() {
+        Ok(_) => debug!("sd_notify {}", state),
+        Err(e) => warn!("Failed to notify systemd of {}: {}", state, e),
+    }
+}
+
+#[cfg(not(unix))]
+pub fn notify(_state: &str) {}
+
+/// Completes on Ctrl+C, and on SIGTERM as sent by `systemctl stop`.
+pub async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut term = signal(SignalKind::terminate()).expect("Failed to install SIGTERM handler");
+        tokio::select! {
+            res = tokio::signal::ctrl_c() => res.expect("Failed to install CTRL+C signal handler"),
+            _ = term.recv() => (),
+        }
+    }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c()
+        .await
+        .expect("Failed to install CTRL+C signal handler");
+    tracing::info!(
+        "{}",
+        crate::tr!("正在退出", "終了しています", "Shutting down")
+    );
+    notify("STOPPING=1");
+}
+
+/// SIGHUP as sent by `systemctl reload`, never received off unix.
+pub struct Hangup {
+    #[cfg(unix)]
+    signal: Option<tokio::signal::unix::Signal>,
+}
+
+impl Hangup {
+    pub fn new() -> Self {
+        Hangup {
+            #[cfg(unix)]
+            signal: tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()).ok(),
+        }
+    }
+
+    pub async fn recv(&mut self) {
+        #[cfg(unix)]
+        if let Some(signal) = &mut self.signal {
+            signal.recv().await;
+            return;
+        }
+        std::future::pending().await
+    }
+}
+
+impl Default for Hangup {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,465 @@
+use crate::{
+    parser::{str_field, str_list, u32_field, u32_list},
+    players::GameInfo,
+    tile::to_tenhou,
+};
+use anyhow::{anyhow, Result};
+use serde_json::{json, Value as JsonValue};
+
+/// majsoul fan id -> tenhou yaku name
+pub const YAKU: [&str; 51] = [
+    "",
+    "門前清自摸和",
+    "立直",
+    "槍槓",
+    "嶺上開花",
+    "海底摸月",
+    "河底撈魚",
+    "役牌 白",
+    "役牌 發",
+    "役牌 中",
+    "役牌:自風牌",
+    "役牌:場風牌",
+    "断幺九",
+    "一盃口",
+    "平和",
+    "混全帯幺九",
+    "一気通貫",
+    "三色同順",
+    "ダブル立直",
+    "三色同刻",
+    "三槓子",
+    "対々和",
+    "三暗刻",
+    "小三元",
+    "混老頭",
+    "七対子",
+    "純全帯幺九",
+    "混一色",
+    "二盃口",
+    "清一色",
+    "一発",
+    "ドラ",
+    "赤ドラ",
+    "裏ドラ",
+    "抜きドラ",
+    "天和",
+    "地和",
+    "大三元",
+    "四暗刻",
+    "字一色",
+    "緑一色",
+    "清老頭",
+    "国士無双",
+    "小四喜",
+    "四槓子",
+    "九蓮宝燈",
+    "八連荘",
+    "純正九蓮宝燈",
+    "四暗刻単騎",
+    "国士無双１３面",
+    "大四喜",
+];
+
+/// ActionLiuJu.type -> tenhou result name
+const LIUJU: [&str; 6] = [
+    "流局",
+    "九種九牌",
+    "四風連打",
+    "四槓散了",
+    "四家立直",
+    "三家和了",
+];
+
+#[derive(Debug, Default)]
+struct Kyoku {
+    head: [u32; 3],
+    scores: JsonValue,
+    doras: Vec<u32>,
+    uras: Vec<u32>,
+    haipai: Vec<Vec<u32>>,
+    takes: Vec<Vec<JsonValue>>,
+    discards: Vec<Vec<JsonValue>>,
+    last_discard: u32,
+}
+
+/// Stateful majsoul action -> tenhou.net/6 log converter.
+///
+/// Every finished hand yields one `log` entry. Tiles that are hidden in live games are encoded as 0,
+/// so only records produce logs that tenhou-compatible viewers can fully replay.
+#[derive(Debug, Default)]
+pub struct TenhouConverter {
+    seat: Option<u32>,
+    names: Vec<String>,
+    dans: Vec<String>,
+    /// game uuid when known
+    title: String,
+    kyoku: Option<Kyoku>,
+    log: Vec<JsonValue>,
+    players: usize,
+}
+
+impl TenhouConverter {
+    pub fn set_seat(&mut self, seat: u32) {
+        self.seat = Some(seat);
+    }
+
+    /// Own seat, names and ranks of a joined game.
+    pub fn set_game(&mut self, game: &GameInfo) {
+        self.seat = Some(game.seat);
+        self.names = game.names();
+        let players = game.players.len();
+        self.dans = game
+            .players
+            .iter()
+            .map(|p| {
+                p.rank_for(players)
+                    .map(|r| r.name.clone())
+                    .unwrap_or_default()
+            })
+            .collect();
+        self.title = game.game_uuid.clone().unwrap_or_default();
+    }
+
+    /// Feed one action, returns the `log` entry when a hand ends.
+    pub fn feed(&mut self, name: &str, data: &JsonValue) -> Result<Option<JsonValue>> {
+        if name == "NewRound" {
+            let kyoku = self.new_round(data)?;
+            self.players = kyoku.haipai.len();
+            self.kyoku = Some(kyoku);
+            return Ok(None);
+        }
+        let Some(kyoku) = self.kyoku.as_mut() else {
+            return Ok(None);
+        };
+        match name {
+            "DealTile" => {
+                let seat = u32_field(data, "seat")? as usize;
+                let tile = str_field(data, "tile")?;
+                kyoku.update_doras(data)?;
+                kyoku.takes[seat].push(json!(to_tenhou(tile)?));
+            }
+            "DiscardTile" => {
+                let seat = u32_field(data, "seat")? as usize;
+                let tile = str_field(data, "tile")?;
+                let moqie = data["moqie"].as_bool().unwrap_or_default();
+                let code = if moqie { 60 } else { to_tenhou(tile)? };
+                let riichi = data["is_liqi"].as_bool().unwrap_or_default()
+                    || data["is_wliqi"].as_bool().unwrap_or_default();
+                kyoku.discards[seat].push(if riichi {
+                    json!(format!("r{}", code))
+                } else {
+                    json!(code)
+                });
+                kyoku.update_doras(data)?;
+                kyoku.last_discard = seat as u32;
+            }
+            "ChiPengGang" => {
+                let seat = u32_field(data, "seat")?;
+                let tiles = str_list(data, "tiles");
+                let froms = u32_list(data, "froms");
+                let called = froms
+                    .iter()
+                    .position(|f| *f != seat)
+                    .ok_or(anyhow!("No called tile in ChiPengGang"))?;
+                let mut codes = vec![to_tenhou(tiles[called])?];
+                for (i, t) in tiles.iter().enumerate() {
+                    if i != called {
+                        codes.push(to_tenhou(t)?);
+                    }
+                }
+                let rel = relative(froms[called], seat, kyoku.haipai.len() as u32);
+                let kind = u32_field(data, "type")?;
+                let meld = match kind {
+                    0 => meld_string('c', &codes, 0),
+                    1 => meld_string('p', &codes, 3 - rel as usize),
+                    _ => meld_string('m', &codes, if rel == 1 { 3 } else { 3 - rel as usize }),
+                };
+                kyoku.takes[seat as usize].push(json!(meld));
+                if kind == 2 {
+                    kyoku.discards[seat as usize].push(json!(0));
+                }
+            }
+            "AnGangAddGang" => {
+                let seat = u32_field(data, "seat")? as usize;
+                let tile = str_field(data, "tiles")?;
+                let code = to_tenhou(tile)?;
+                let meld = if u32_field(data, "type")? == 3 {
+                    let n = normal(code);
+                    if n % 10 == 5 && n < 40 {
+                        format!("{}{}{}a{}", 50 + n / 10, n, n, n)
+                    } else {
+                        format!("{}{}{}a{}", n, n, n, n)
+                    }
+                } else {
+                    // insert the added tile in front of the pon's called tile
+                    let pon = kyoku.takes[seat]
+                        .iter()
+                        .filter_map(|t| t.as_str())
+                        .find(|t| {
+                            t.contains('p')
+                                && t.replace('p', "")[..2].parse::<u32>().ok().map(normal)
+                                    == Some(normal(code))
+                        })
+                        .unwrap_or_default();
+                    pon.replacen('p', &format!("k{}", code), 1)
+                };
+                kyoku.discards[seat].push(json!(meld));
+                kyoku.update_doras(data)?;
+            }
+            "BaBei" => {
+                let seat = u32_field(data, "seat")? as usize;
+                kyoku.discards[seat].push(json!("f44"));
+                kyoku.update_doras(data)?;
+            }
+            "Hule" => {
+                let hules = data["hules"]
+                    .as_array()
+                    .ok_or(anyhow!("hules field invalid"))?;
+                let mut result = vec![json!("和了")];
+                for (i, hule) in hules.iter().enumerate() {
+                    // a kan dora flipped by the winning rinshan only shows up here
+                    kyoku.update_dora_list(&str_list(hule, "doras"))?;
+                    if kyoku.uras.is_empty() {
+                        for ura in str_list(hule, "li_doras") {
+                            kyoku.uras.push(to_tenhou(ura)?);
+                        }
+                    }
+                    let deltas = if i == 0 {
+                        pad4(data["delta_scores"].clone())
+                    } else {
+                        json!([0, 0, 0, 0])
+                    };
+                    result.push(deltas);
+                    result.push(hule_info(hule, kyoku)?);
+                }
+                return Ok(Some(self.finish_kyoku(result)));
+            }
+            "NoTile" => {
+                let mut deltas = vec![0i64; 4];
+                for score in data["scores"].as_array().into_iter().flatten() {
+                    for (i, d) in score["delta_scores"]
+                        .as_array()
+                        .into_iter()
+                        .flatten()
+                        .enumerate()
+                    {
+                        if let (Some(d), Some(acc)) = (d.as_i64(), deltas.get_mut(i)) {
+                            *acc += d;
+                        }
+                    }
+                }
+                let players = data["players"].as_array().cloned().unwrap_or_default();
+                let tenpai = players
+                    .iter()
+                    .filter(|p| p["tingpai"].as_bool().unwrap_or_default())
+                    .count();
+                let name = if data["liujumanguan"].as_bool().unwrap_or_default() {
+                    "流し満貫"
+                } else if tenpai == players.len() {
+                    "全員聴牌"
+                } else if tenpai == 0 {
+                    "全員不聴"
+                } else {
+                    "流局"
+                };
+                return Ok(Some(self.finish_kyoku(vec![json!(name), json!(deltas)])));
+            }
+            "LiuJu" => {
+                let kind = u32_field(data, "type").unwrap_or_default() as usize;
+                let name = LIUJU.get(kind).unwrap_or(&LIUJU[0]);
+                return Ok(Some(self.finish_kyoku(vec![json!(name)])));
+            }
+            _ => {}
+        }
+        Ok(None)
+    }
+
+    /// All finished hands as a complete tenhou.net/6 document.
+    pub fn document(&self) -> JsonValue {
+        self.document_with(self.log.clone())
+    }
+
+    /// A tenhou.net/6 document with the given `log` entries and the current player names.
+    pub fn document_with(&self, log: Vec<JsonValue>) -> JsonValue {
+        let mut names = self.names.clone();
+        names.resize(4, String::new());
+        let mut dans = self.dans.clone();
+        dans.resize(4, String::new());
+        json!({
+            "title": [self.title, ""],
+            "name": names,
+            "dan": dans,
+            "rule": {"disp": if self.players == 3 { "三麻" } else { "" }, "aka": 1},
+            "log": log,
+        })
+    }
+
+    fn new_round(&self, data: &JsonValue) -> Result<Kyoku> {
+        let scores = data["scores"]
+            .as_array()
+            .ok_or(anyhow!("scores field invalid"))?;
+        let chang = u32_field(data, "chang")?;
+        let ju = u32_field(data, "ju")?;
+        let own = str_list(data, "tiles");
+        let seat = self.seat.or(if own.len() == 14 { Some(ju) } else { None });
+        let players = scores.len();
+        let mut kyoku = Kyoku {
+            head: [
+                chang * 4 + ju,
+                u32_field(data, "ben")?,
+                u32_field(data, "liqibang")?,
+            ],
+            scores: pad4(json!(scores)),
+            takes: vec![vec![]; players],
+            discards: vec![vec![]; players],
+            ..Default::default()
+        };
+        for s in 0..players as u32 {
+            let mut hand = str_list(data, &format!("tiles{}", s));
+            if hand.is_empty() && seat == Some(s) {
+                hand.clone_from(&own);
+            }
+            let mut codes = hand
+                .iter()
+                .map(|t| to_tenhou(t))
+                .collect::<Result<Vec<_>>>()?;
+            if codes.len() == 14 {
+                let first = codes.pop().unwrap_or_default();
+                kyoku.takes[s as usize].push(json!(first));
+            }
+            kyoku.haipai.push(codes);
+        }
+        match str_list(data, "doras") {
+            d if d.is_empty() => kyoku.doras.push(to_tenhou(str_field(data, "dora")?)?),
+            d => kyoku.update_dora_list(&d)?,
+        }
+        Ok(kyoku)
+    }
+
+    fn finish_kyoku(&mut self, result: Vec<JsonValue>) -> JsonValue {
+        let Some(kyoku) = self.kyoku.take() else {
+            return JsonValue::Null;
+        };
+        let mut entry = vec![
+            json!(kyoku.head),
+            kyoku.scores,
+            json!(kyoku.doras),
+            json!(kyoku.uras),
+        ];
+        let mut players = kyoku
+            .haipai
+            .into_iter()
+            .zip(kyoku.takes)
+            .zip(kyoku.discards)
+            .collect::<Vec<_>>();
+        players.resize_with(4, Default::default);
+        for ((haipai, takes), discards) in players {
+            entry.push(json!(haipai));
+            entry.push(json!(takes));
+            entry.push(json!(discards));
+        }
+        entry.push(json!(result));
+        let entry = json!(entry);
+        self.log.push(entry.clone());
+        entry
+    }
+}
+
+impl Kyoku {
+    fn update_doras(&mut self, data: &JsonValue) -> Result<()> {
+        self.update_dora_list(&str_list(data, "doras"))
+    }
+
+    fn update_dora_list(&mut self, doras: &[&str]) -> Result<()> {
+        for dora in doras.iter().skip(self.doras.len()) {
+            self.doras.push(to_tenhou(dora)?);
+        }
+        Ok(())
+    }
+}
+
+/// Seat of `from` relative to `seat`, 1 shimocha, 2 toimen, 3 kamicha, in sanma too.
+fn relative(from: u32, seat: u32, players: u32) -> u32 {
+    let players = players.max(1);
+    match (from + players - seat) % players {
+        2 if players == 3 => 3,
+        rel => rel,
+    }
+}
+
+/// tenhou.net/6 always has four seats, the empty one scoring 0 in sanma.
+fn pad4(scores: JsonValue) -> JsonValue {
+    let mut scores = scores.as_array().cloned().unwrap_or_default();
+    scores.resize(4, json!(0));
+    json!(scores)
+}
+
+/// Red fives map to their normal code.
+fn normal(code: u32) -> u32 {
+    if code > 50 {
+        (code - 50) * 10 + 5
+    } else {
+        code
+    }
+}
+
+/// Insert `marker` before the called tile placed at `pos`, `codes[0]` being the called tile.
+fn meld_string(marker: char, codes: &[u32], pos: usize) -> String {
+    let mut order = codes[1..].to_vec();
+    order.insert(pos.min(order.len()), codes[0]);
+    let mut s = String::new();
+    for (i, c) in order.iter().enumerate() {
+        if i == pos {
+            s.push(marker);
+        }
+        s.push_str(&c.to_string());
+    }
+    s
+}
+
+fn hule_info(hule: &JsonValue, kyoku: &Kyoku) -> Result<JsonValue> {
+    let seat = u32_field(hule, "seat")?;
+    let zimo = hule["zimo"].as_bool().unwrap_or_default();
+    let from = if zimo { seat } else { kyoku.last_discard };
+    let han = u32_field(hule, "count").unwrap_or_default();
+    let fu = u32_field(hule, "fu").unwrap_or_default();
+    let yiman = hule["yiman"].as_bool().unwrap_or_default();
+    let points = if zimo {
+        let qin = u32_field(hule, "point_zimo_qin").unwrap_or_default();
+        let xian = u32_field(hule, "point_zimo_xian").unwrap_or_default();
+        if hule["qinjia"].as_bool().unwrap_or_default() {
+            format!("{}点∀", xian)
+        } else {
+            format!("{}-{}点", xian, qin)
+        }
+    } else {
+        format!("{}点", u32_field(hule, "point_rong").unwrap_or_default())
+    };
+    let title = match (yiman, han) {
+        (true, _) => "役満".to_string(),
+        (_, 13..) => "数え役満".to_string(),
+        (_, 11..=12) => "三倍満".to_string(),
+        (_, 8..=10) => "倍満".to_string(),
+        (_, 6..=7) => "跳満".to_string(),
+        (_, 5) => "満貫".to_string(),
+        _ => format!("{}符{}飜", fu, han),
+    };
+    let mut info = vec![
+        json!(seat),
+        json!(from),
+        json!(seat),
+        json!(title + &points),
+    ];
+    for fan in hule["fans"].as_array().into_iter().flatten() {
+        let id = fan["id"].as_u64().unwrap_or_default() as usize;
+        let val = fan["val"].as_u64().unwrap_or_default();
+        let name = YAKU.get(id).copied().unwrap_or_default();
+        if yiman {
+            info.push(json!(format!("{}(役満)", name)));
+        } else if val > 0 {
+            info.push(json!(format!("{}({}飜)", name, val)));
+        }
+    }
+    Ok(json!(info))
+}
@@ -0,0 +1,372 @@
+use crate::{
+    format::{game_actions, strip_action, EventFormat, Seating},
+    sink::{Event, Payload},
+    tile::{is_red, notate, Notation},
+    tr,
+};
+use anyhow::Result;
+use serde_json::{json, Value as JsonValue};
+
+/// Compact one-line summaries of the game for people following along in a
+/// terminal, e.g. `E1-1: Shimocha discards 🀝*, you can pon`.
+#[derive(Default)]
+pub struct TextFormat {
+    seating: Seating,
+    /// own seat, seats are named by wind while it is unknown
+    seat: Option<u32>,
+    players: u32,
+    chang: u32,
+    ju: u32,
+    ben: u32,
+    last_discard: Option<u32>,
+}
+
+impl EventFormat for TextFormat {
+    fn encode(&mut self, event: &Event) -> Result<Vec<Payload>> {
+        if let Some(game) = self.seating.observe(event) {
+            self.seat = Some(game.seat);
+        }
+        let mut lines = vec![];
+        for (name, data) in game_actions(event)? {
+            lines.extend(self.feed(strip_action(&name), &data));
+        }
+        Ok(lines
+            .into_iter()
+            .map(|(kind, text)| Payload::Json(json!({ "kind": kind, "text": text })))
+            .collect())
+    }
+}
+
+/// A tile as a Unicode glyph, red fives marked with `*` as the glyphs have no red.
+fn glyph(tile: &str) -> String {
+    let glyph = notate(tile, Notation::Unicode);
+    match is_red(tile) {
+        true => glyph + "*",
+        false => glyph,
+    }
+}
+
+fn glyphs(tiles: &JsonValue) -> String {
+    tiles
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(JsonValue::as_str)
+        .map(glyph)
+        .collect()
+}
+
+fn seat_of(data: &JsonValue) -> u32 {
+    data["seat"].as_u64().unwrap_or_default() as u32
+}
+
+impl TextFormat {
+    /// `E1-0` style name of the current hand.
+    fn round(&self) -> String {
+        let n = self.ju + 1;
+        match self.chang {
+            0 => tr!("东{}-{}", "東{}-{}", "E{}-{}", n, self.ben),
+            1 => tr!("南{}-{}", "南{}-{}", "S{}-{}", n, self.ben),
+            2 => tr!("西{}-{}", "西{}-{}", "W{}-{}", n, self.ben),
+            _ => tr!("北{}-{}", "北{}-{}", "N{}-{}", n, self.ben),
+        }
+    }
+
+    /// Seat named relative to the own one, or by its seat wind when spectating.
+    fn who(&self, seat: u32) -> String {
+        let players = self.players.max(1);
+        match self.seat {
+            Some(own) => match (seat + players - own) % players {
+                0 => tr!("自家", "自家", "Self"),
+                1 => tr!("下家", "下家", "Shimocha"),
+                2 if players == 4 => tr!("对家", "対面", "Toimen"),
+                _ => tr!("上家", "上家", "Kamicha"),
+            },
+            None => match (seat + players - self.ju % players) % players {
+                0 => tr!("东家", "東家", "East"),
+                1 => tr!("南家", "南家", "South"),
+                2 => tr!("西家", "西家", "West"),
+                _ => tr!("北家", "北家", "North"),
+            },
+        }
+    }
+
+    /// Calls the own seat is offered on a discard.
+    fn chances(&self, data: &JsonValue) -> Vec<String> {
+        let Some(own) = self.seat else {
+            return vec![];
+        };
+        let operation = &data["operation"];
+        if !operation.is_object() || seat_of(operation) != own {
+            return vec![];
+        }
+        let mut chances: Vec<String> = operation["operation_list"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|op| match op["type"].as_u64()? {
+                2 => Some(tr!("吃", "チー", "chi")),
+                3 => Some(tr!("碰", "ポン", "pon")),
+                5 => Some(tr!("杠", "カン", "kan")),
+                9 => Some(tr!("荣和", "ロン", "ron")),
+                _ => None,
+            })
+            .collect();
+        chances.dedup();
+        chances
+    }
+
+    fn feed(&mut self, name: &str, data: &JsonValue) -> Vec<(&'static str, String)> {
+        let mut lines = vec![];
+        match name {
+            "NewRound" => {
+                self.chang = data["chang"].as_u64().unwrap_or_default() as u32;
+                self.ju = data["ju"].as_u64().unwrap_or_default() as u32;
+                self.ben = data["ben"].as_u64().unwrap_or_default() as u32;
+                self.players = data["scores"]
+                    .as_array()
+                    .map_or(4, |scores| scores.len() as u32);
+                self.last_discard = None;
+                let scores = data["scores"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .map(|s| s.to_string())
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                lines.push((
+                    "round",
+                    tr!(
+                        "{} 开始，宝牌指示牌 {}，点数 {}",
+                        "{} 開始、ドラ表示牌 {}、点数 {}",
+                        "{} starts, dora indicator {}, scores {}",
+                        self.round(),
+                        glyphs(&data["doras"]),
+                        scores
+                    ),
+                ));
+                if self.seat.is_some() && data["tiles"].is_array() {
+                    lines.push((
+                        "draw",
+                        tr!(
+                            "{}: 配牌 {}",
+                            "{}: 配牌 {}",
+                            "{}: dealt {}",
+                            self.round(),
+                            glyphs(&data["tiles"])
+                        ),
+                    ));
+                }
+            }
+            "DealTile" => {
+                let tile = data["tile"].as_str().unwrap_or_default();
+                // others' draws are hidden
+                if !tile.is_empty() {
+                    lines.push((
+                        "draw",
+                        tr!(
+                            "{}: {}摸 {}",
+                            "{}: {}がツモ {}",
+                            "{}: {} draws {}",
+                            self.round(),
+                            self.who(seat_of(data)),
+                            glyph(tile)
+                        ),
+                    ));
+                }
+            }
+            "DiscardTile" => {
+                let seat = seat_of(data);
+                self.last_discard = Some(seat);
+                let who = self.who(seat);
+                if data["is_liqi"].as_bool().unwrap_or_default()
+                    || data["is_wliqi"].as_bool().unwrap_or_default()
+                {
+                    lines.push((
+                        "riichi",
+                        tr!(
+                            "{}: {}立直",
+                            "{}: {}がリーチ",
+                            "{}: {} declares riichi",
+                            self.round(),
+                            who
+                        ),
+                    ));
+                }
+                let tile = glyph(data["tile"].as_str().unwrap_or_default());
+                let mut text = tr!(
+                    "{}: {}打出 {}",
+                    "{}: {}が {} を切り",
+                    "{}: {} discards {}",
+                    self.round(),
+                    who,
+                    tile
+                );
+                let chances = self.chances(data);
+                if !chances.is_empty() {
+                    text += &tr!(
+                        "，可以{}",
+                        "、{}できます",
+                        ", you can {}",
+                        chances.join("/")
+                    );
+                }
+                let kind = match chances.is_empty() {
+                    true => "discard",
+                    false => "chance",
+                };
+                lines.push((kind, text));
+            }
+            "ChiPengGang" => {
+                let (round, who, tiles) = (
+                    self.round(),
+                    self.who(seat_of(data)),
+                    glyphs(&data["tiles"]),
+                );
+                let text = match data["type"].as_u64() {
+                    Some(0) => tr!(
+                        "{}: {}吃 {}",
+                        "{}: {}がチー {}",
+                        "{}: {} chis {}",
+                        round,
+                        who,
+                        tiles
+                    ),
+                    Some(1) => tr!(
+                        "{}: {}碰 {}",
+                        "{}: {}がポン {}",
+                        "{}: {} pons {}",
+                        round,
+                        who,
+                        tiles
+                    ),
+                    _ => tr!(
+                        "{}: {}明杠 {}",
+                        "{}: {}が大明カン {}",
+                        "{}: {} calls kan {}",
+                        round,
+                        who,
+                        tiles
+                    ),
+                };
+                lines.push(("call", text));
+            }
+            "AnGangAddGang" => {
+                let (round, who) = (self.round(), self.who(seat_of(data)));
+                let tile = glyph(data["tiles"].as_str().unwrap_or_default());
+                let text = match data["type"].as_u64() {
+                    Some(3) => tr!(
+                        "{}: {}暗杠 {}",
+                        "{}: {}が暗カン {}",
+                        "{}: {} closed kan {}",
+                        round,
+                        who,
+                        tile
+                    ),
+                    _ => tr!(
+                        "{}: {}加杠 {}",
+                        "{}: {}が加カン {}",
+                        "{}: {} added kan {}",
+                        round,
+                        who,
+                        tile
+                    ),
+                };
+                lines.push(("call", text));
+            }
+            "BaBei" => {
+                lines.push((
+                    "call",
+                    tr!(
+                        "{}: {}拔北",
+                        "{}: {}が北抜き",
+                        "{}: {} takes out north",
+                        self.round(),
+                        self.who(seat_of(data))
+                    ),
+                ));
+            }
+            "Hule" => {
+                for hule in data["hules"].as_array().into_iter().flatten() {
+                    let who = self.who(seat_of(hule));
+                    let han = hule["count"].as_u64().unwrap_or_default();
+                    let fu = hule["fu"].as_u64().unwrap_or_default();
+                    let points = match hule["zimo"].as_bool().unwrap_or_default() {
+                        true => hule["point_sum"].as_u64().unwrap_or_default(),
+                        false => hule["point_rong"].as_u64().unwrap_or_default(),
+                    };
+                    let hand = glyphs(&hule["hand"]);
+                    let tile = glyph(hule["hu_tile"].as_str().unwrap_or_default());
+                    let text = match (
+                        hule["zimo"].as_bool().unwrap_or_default(),
+                        self.last_discard,
+                    ) {
+                        (false, Some(from)) => tr!(
+                            "{}: {}荣和{} {} {}，{}番{}符 {}点",
+                            "{}: {}が{}からロン {} {}、{}翻{}符 {}点",
+                            "{}: {} rons {} with {} {}, {} han {} fu {} points",
+                            self.round(),
+                            who,
+                            self.who(from),
+                            hand,
+                            tile,
+                            han,
+                            fu,
+                            points
+                        ),
+                        _ => tr!(
+                            "{}: {}自摸 {} {}，{}番{}符 {}点",
+                            "{}: {}がツモ和了 {} {}、{}翻{}符 {}点",
+                            "{}: {} tsumo with {} {}, {} han {} fu {} points",
+                            self.round(),
+                            who,
+                            hand,
+                            tile,
+                            han,
+                            fu,
+                            points
+                        ),
+                    };
+                    lines.push(("win", text));
+                }
+            }
+            "NoTile" => {
+                let tenpai: Vec<String> = data["players"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .enumerate()
+                    .filter(|(_, p)| p["tingpai"].as_bool().unwrap_or_default())
+                    .map(|(seat, _)| self.who(seat as u32))
+                    .collect();
+                let tenpai = match tenpai.is_empty() {
+                    true => tr!("无", "なし", "nobody"),
+                    false => tenpai.join(", "),
+                };
+                lines.push((
+                    "ryuukyoku",
+                    tr!(
+                        "{}: 流局，听牌：{}",
+                        "{}: 流局、聴牌：{}",
+                        "{}: exhaustive draw, tenpai: {}",
+                        self.round(),
+                        tenpai
+                    ),
+                ));
+            }
+            "LiuJu" => {
+                lines.push((
+                    "ryuukyoku",
+                    tr!(
+                        "{}: 途中流局",
+                        "{}: 途中流局",
+                        "{}: abortive draw",
+                        self.round()
+                    ),
+                ));
+            }
+            _ => {}
+        }
+        lines
+    }
+}
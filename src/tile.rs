@@ -0,0 +1,276 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+
+const HONORS: [&str; 7] = ["E", "S", "W", "N", "P", "F", "C"];
+
+/// The 34 tile kinds in [`index34`] order.
+pub const TILES34: [&str; 34] = [
+    "1m", "2m", "3m", "4m", "5m", "6m", "7m", "8m", "9m", "1p", "2p", "3p", "4p", "5p", "6p", "7p",
+    "8p", "9p", "1s", "2s", "3s", "4s", "5s", "6s", "7s", "8s", "9s", "1z", "2z", "3z", "4z", "5z",
+    "6z", "7z",
+];
+
+/// Split a majsoul tile such as `"5m"`, `"0p"` or `"7z"` into (number, suit).
+fn split(tile: &str) -> Result<(u8, u8)> {
+    let bytes = tile.as_bytes();
+    if bytes.len() != 2 || !bytes[0].is_ascii_digit() {
+        return Err(anyhow!("Invalid tile: {}", tile));
+    }
+    let num = bytes[0] - b'0';
+    let suit = bytes[1];
+    match suit {
+        b'm' | b'p' | b's' if num <= 9 => Ok((num, suit)),
+        b'z' if (1..=7).contains(&num) => Ok((num, suit)),
+        _ => Err(anyhow!("Invalid tile: {}", tile)),
+    }
+}
+
+/// Index in the 34 tile kinds, red fives share the index of normal fives.
+pub fn index34(tile: &str) -> Result<usize> {
+    let (num, suit) = split(tile)?;
+    let num = if num == 0 { 5 } else { num } as usize;
+    let base = match suit {
+        b'm' => 0,
+        b'p' => 9,
+        b's' => 18,
+        _ => 27,
+    };
+    Ok(base + num - 1)
+}
+
+pub fn is_red(tile: &str) -> bool {
+    tile.starts_with('0')
+}
+
+/// majsoul -> mjai, e.g. `"0m"` -> `"5mr"`, `"1z"` -> `"E"`, unknown tiles become `"?"`
+pub fn to_mjai(tile: &str) -> Result<String> {
+    if tile.is_empty() {
+        return Ok("?".to_string());
+    }
+    let (num, suit) = split(tile)?;
+    Ok(match (num, suit) {
+        (n, b'z') => HONORS[n as usize - 1].to_string(),
+        (0, s) => format!("5{}r", s as char),
+        (n, s) => format!("{}{}", n, s as char),
+    })
+}
+
+/// majsoul -> tenhou.net/6 number, e.g. `"1m"` -> 11, `"0p"` -> 52, `"7z"` -> 47
+pub fn to_tenhou(tile: &str) -> Result<u32> {
+    if tile.is_empty() {
+        return Ok(0);
+    }
+    let (num, suit) = split(tile)?;
+    let suit_no = match suit {
+        b'm' => 1,
+        b'p' => 2,
+        b's' => 3,
+        _ => 4,
+    };
+    Ok(if num == 0 {
+        50 + suit_no
+    } else {
+        suit_no * 10 + num as u32
+    })
+}
+
+/// The other three tiles of a kan on `tile`, assuming one red five per suit.
+pub fn kan_tiles(tile: &str) -> Result<Vec<&'static str>> {
+    const RED: [&str; 3] = ["0m", "0p", "0s"];
+    let idx = index34(tile)?;
+    let normal = TILES34[idx];
+    if idx < 27 && idx % 9 == 4 && !is_red(tile) {
+        Ok(vec![RED[idx / 9], normal, normal])
+    } else {
+        Ok(vec![normal; 3])
+    }
+}
+
+/// Count tiles per kind, invalid tiles are ignored.
+pub fn counts34<S: AsRef<str>>(tiles: &[S]) -> [u8; 34] {
+    let mut counts = [0; 34];
+    for tile in tiles {
+        if let Ok(i) = index34(tile.as_ref()) {
+            counts[i] += 1;
+        }
+    }
+    counts
+}
+
+/// The dora pointed at by an indicator, sanma skips 2m-8m.
+pub fn dora_of(indicator: &str, sanma: bool) -> Result<&'static str> {
+    let idx = index34(indicator)?;
+    let next = match idx {
+        0 if sanma => 8,
+        8 | 17 | 26 => idx - 8,
+        30 => 27,
+        33 => 31,
+        _ => idx + 1,
+    };
+    Ok(TILES34[next])
+}
+
+/// How tiles are written in human-facing output.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Notation {
+    /// `"5m"`, `"0p"` for red fives, as majsoul itself
+    #[default]
+    #[serde(rename = "mpsz")]
+    Mpsz,
+    /// tile-136 index of the first copy, red fives being copy 0 and normal fives copy 1
+    #[serde(rename = "136")]
+    Tile136,
+    /// Unicode mahjong glyphs, red fives look like normal ones
+    #[serde(rename = "unicode")]
+    Unicode,
+}
+
+/// Kind and redness of a majsoul or mjai tile.
+fn parse_any(tile: &str) -> Option<(usize, bool)> {
+    if let Some(n) = HONORS.iter().position(|h| *h == tile) {
+        return Some((27 + n, false));
+    }
+    let tile = match tile.strip_suffix('r') {
+        Some(five) if five.starts_with('5') => return Some((index34(five).ok()?, true)),
+        _ => tile,
+    };
+    Some((index34(tile).ok()?, is_red(tile)))
+}
+
+/// Write a majsoul or mjai tile in `notation`, unknown strings are kept as they are.
+pub fn notate(tile: &str, notation: Notation) -> String {
+    let Some((idx, red)) = parse_any(tile) else {
+        return tile.to_string();
+    };
+    match notation {
+        Notation::Mpsz if red => format!("0{}", &TILES34[idx][1..]),
+        Notation::Mpsz => TILES34[idx].to_string(),
+        Notation::Tile136 => {
+            let five = idx < 27 && idx % 9 == 4 && !red;
+            (idx * 4 + five as usize).to_string()
+        }
+        Notation::Unicode => {
+            // 🀇-🀏 manzu, 🀙-🀡 pinzu, 🀐-🀘 souzu, 🀀-🀃 winds, 🀆🀅🀄 dragons
+            let code = match idx {
+                0..=8 => 0x1F007 + idx,
+                9..=17 => 0x1F019 + idx - 9,
+                18..=26 => 0x1F010 + idx - 18,
+                27..=30 => 0x1F000 + idx - 27,
+                _ => 0x1F006 - (idx - 31),
+            };
+            char::from_u32(code as u32).unwrap_or('?').to_string()
+        }
+    }
+}
+
+/// Fields holding tiles in liqi, mjai and helper JSON.
+const TILE_KEYS: [&str; 18] = [
+    "tile",
+    "tiles",
+    "hand",
+    "waits",
+    "dora",
+    "doras",
+    "dora_indicators",
+    "ura_indicators",
+    "uras",
+    "ura_doras",
+    "li_doras",
+    "indicators",
+    "hu_tile",
+    "pai",
+    "consumed",
+    "dora_marker",
+    "tehais",
+    "ura_markers",
+];
+
+/// Rewrite the tiles of a JSON document in `notation`, in place.
+pub fn notate_json(value: &mut JsonValue, notation: Notation) {
+    fn convert(value: &mut JsonValue, notation: Notation) {
+        match value {
+            JsonValue::String(s) => *s = notate(s, notation),
+            JsonValue::Array(items) => items.iter_mut().for_each(|v| convert(v, notation)),
+            _ => {}
+        }
+    }
+    match value {
+        JsonValue::Object(map) => {
+            for (key, v) in map.iter_mut() {
+                if TILE_KEYS.contains(&key.as_str()) && !v.is_object() {
+                    convert(v, notation);
+                } else {
+                    notate_json(v, notation);
+                }
+            }
+        }
+        JsonValue::Array(items) => items.iter_mut().for_each(|v| notate_json(v, notation)),
+        _ => {}
+    }
+}
+
+/// Tiles of a hand written like `123m055p11z`, for tests.
+#[cfg(test)]
+pub(crate) fn tiles(text: &str) -> Vec<String> {
+    let mut out = vec![];
+    let mut digits = vec![];
+    for c in text.chars() {
+        match c {
+            '0'..='9' => digits.push(c),
+            suit => out.extend(digits.drain(..).map(|d| format!("{}{}", d, suit))),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn notates_red_fives() {
+        assert_eq!(notate("0m", Notation::Mpsz), "0m");
+        assert_eq!(notate("5mr", Notation::Mpsz), "0m");
+        assert_eq!(notate("5m", Notation::Mpsz), "5m");
+        // red fives are copy 0, normal fives copy 1
+        assert_eq!(notate("0p", Notation::Tile136), "52");
+        assert_eq!(notate("5p", Notation::Tile136), "53");
+        assert_eq!(notate("4p", Notation::Tile136), "48");
+        assert_eq!(notate("0s", Notation::Unicode), "🀔");
+        assert_eq!(notate("5s", Notation::Unicode), "🀔");
+    }
+
+    #[test]
+    fn notates_honors() {
+        assert_eq!(notate("E", Notation::Mpsz), "1z");
+        assert_eq!(notate("C", Notation::Mpsz), "7z");
+        assert_eq!(notate("1z", Notation::Tile136), "108");
+        assert_eq!(notate("C", Notation::Tile136), "132");
+        assert_eq!(notate("4z", Notation::Unicode), "🀃");
+        assert_eq!(notate("5z", Notation::Unicode), "🀆");
+        assert_eq!(notate("7z", Notation::Unicode), "🀄");
+        // not a tile
+        assert_eq!(notate("?", Notation::Unicode), "?");
+        assert_eq!(notate("8z", Notation::Tile136), "8z");
+    }
+
+    #[test]
+    fn notates_every_tile_field() {
+        for key in TILE_KEYS {
+            let mut doc = json!({
+                "data": {key: ["0m", "E"], "name": "1m"},
+                "seats": [{key: "9s"}],
+            });
+            notate_json(&mut doc, Notation::Tile136);
+            assert_eq!(doc["data"][key], json!(["16", "108"]), "{}", key);
+            assert_eq!(doc["seats"][0][key], "104", "{}", key);
+            assert_eq!(doc["data"]["name"], "1m", "{}", key);
+        }
+        // an object under a tile field only has its own tile fields rewritten
+        let mut doc = json!({"tile": {"tile": "1m", "count": "1m"}});
+        notate_json(&mut doc, Notation::Unicode);
+        assert_eq!(doc, json!({"tile": {"tile": "🀇", "count": "1m"}}));
+    }
+}
@@ -0,0 +1,219 @@
+use anyhow::Result;
+use std::{future::Future, pin::Pin};
+use tokio::{runtime::Handle, sync::oneshot};
+
+/// The proxy until the receiver fires, started again for every "start".
+pub type Serve = Box<dyn Future<Output = ()> + Send>;
+
+/// Show a tray icon with a menu to start and stop the proxy, see its status,
+/// open the folder of the CA certificate and quit. Blocks the calling thread,
+/// which must be the main thread on macOS, until quit.
+#[cfg(all(feature = "tray", any(windows, target_os = "macos")))]
+pub fn run(
+    runtime: Handle,
+    serve: impl Fn(oneshot::Receiver<()>) -> Pin<Serve> + 'static,
+) -> Result<()> {
+    imp::run(runtime, serve)
+}
+
+#[cfg(not(all(feature = "tray", any(windows, target_os = "macos"))))]
+pub fn run(
+    _runtime: Handle,
+    _serve: impl Fn(oneshot::Receiver<()>) -> Pin<Serve> + 'static,
+) -> Result<()> {
+    anyhow::bail!("Tray support is not compiled in, it needs the tray feature on Windows or macOS")
+}
+
+#[cfg(all(feature = "tray", any(windows, target_os = "macos")))]
+mod imp {
+    use super::Serve;
+    use crate::{tr, SETTINGS};
+    use anyhow::Result;
+    use std::{
+        pin::Pin,
+        time::{Duration, Instant},
+    };
+    use tao::event_loop::{ControlFlow, EventLoopBuilder};
+    use tokio::{runtime::Handle, sync::oneshot, task::JoinHandle};
+    use tracing::{error, info};
+    use tray_icon::{
+        menu::{Menu, MenuEvent, MenuItem, PredefinedMenuItem},
+        Icon, TrayIcon, TrayIconBuilder,
+    };
+
+    /// How often the status is refreshed, e.g. after the proxy failed to start.
+    const REFRESH: Duration = Duration::from_secs(1);
+    const ICON_SIZE: u32 = 32;
+
+    /// A filled circle, green while the proxy runs and grey otherwise.
+    fn icon(running: bool) -> Result<Icon> {
+        let color = match running {
+            true => [0x2e, 0xb8, 0x5c],
+            false => [0x80, 0x80, 0x80],
+        };
+        let center = (ICON_SIZE as f32 - 1.0) / 2.0;
+        let mut rgba = Vec::with_capacity((ICON_SIZE * ICON_SIZE * 4) as usize);
+        for y in 0..ICON_SIZE {
+            for x in 0..ICON_SIZE {
+                let distance = (x as f32 - center).hypot(y as f32 - center);
+                // a soft edge of one pixel
+                let alpha = (center - distance + 0.5).clamp(0.0, 1.0);
+                rgba.extend_from_slice(&color);
+                rgba.push((alpha * 255.0) as u8);
+            }
+        }
+        Ok(Icon::from_rgba(rgba, ICON_SIZE, ICON_SIZE)?)
+    }
+
+    fn open_folder() {
+        let dir = SETTINGS.config_dir();
+        let opener = match cfg!(windows) {
+            true => "explorer",
+            false => "open",
+        };
+        if let Err(e) = std::process::Command::new(opener).arg(dir).spawn() {
+            error!("Failed to open {}: {}", dir.display(), e);
+        }
+    }
+
+    struct Proxy {
+        stop: oneshot::Sender<()>,
+        task: JoinHandle<()>,
+    }
+
+    struct Menus {
+        status: MenuItem,
+        toggle: MenuItem,
+        folder: MenuItem,
+        quit: MenuItem,
+    }
+
+    impl Menus {
+        fn new() -> Result<(Self, Menu)> {
+            let menus = Menus {
+                status: MenuItem::new("", false, None),
+                toggle: MenuItem::new("", true, None),
+                folder: MenuItem::new(
+                    tr!(
+                        "打开证书文件夹",
+                        "証明書フォルダを開く",
+                        "Open certificate folder"
+                    ),
+                    true,
+                    None,
+                ),
+                quit: MenuItem::new(tr!("退出", "終了", "Quit"), true, None),
+            };
+            let menu = Menu::new();
+            menu.append_items(&[
+                &menus.status,
+                &PredefinedMenuItem::separator(),
+                &menus.toggle,
+                &menus.folder,
+                &PredefinedMenuItem::separator(),
+                &menus.quit,
+            ])?;
+            Ok((menus, menu))
+        }
+
+        fn show(&self, tray: &TrayIcon, running: bool) {
+            let status = match running {
+                true => tr!(
+                    "代理运行中：{}",
+                    "プロキシ動作中：{}",
+                    "Proxy running on {}",
+                    SETTINGS.proxy_addr
+                ),
+                false => tr!("代理已停止", "プロキシ停止中", "Proxy stopped"),
+            };
+            self.status.set_text(&status);
+            self.toggle.set_text(match running {
+                true => tr!("停止代理", "プロキシを停止", "Stop proxy"),
+                false => tr!("启动代理", "プロキシを開始", "Start proxy"),
+            });
+            let _ = tray.set_tooltip(Some(format!("MajsoulMax-rs\n{}", status)));
+            match icon(running) {
+                Ok(icon) => {
+                    let _ = tray.set_icon(Some(icon));
+                }
+                Err(e) => error!("Failed to draw tray icon: {:?}", e),
+            }
+        }
+    }
+
+    pub fn run(
+        runtime: Handle,
+        serve: impl Fn(oneshot::Receiver<()>) -> Pin<Serve> + 'static,
+    ) -> Result<()> {
+        let event_loop = EventLoopBuilder::new().build();
+        let (menus, menu) = Menus::new()?;
+        let start = move || {
+            let (stop, rx) = oneshot::channel();
+            info!(
+                "{}",
+                tr!("代理已启动", "プロキシを開始しました", "Proxy started")
+            );
+            Proxy {
+                stop,
+                task: runtime.spawn(serve(rx)),
+            }
+        };
+        let mut proxy = Some(start());
+        let mut menu = Some(menu);
+        let mut tray: Option<TrayIcon> = None;
+        let mut shown = None;
+        event_loop.run(move |_event, _, control_flow| {
+            *control_flow = ControlFlow::WaitUntil(Instant::now() + REFRESH);
+            // created once the loop runs, as macOS requires
+            if let Some(menu) = menu.take() {
+                let built = TrayIconBuilder::new()
+                    .with_menu(Box::new(menu))
+                    .with_tooltip("MajsoulMax-rs")
+                    .build();
+                match built {
+                    Ok(icon) => tray = Some(icon),
+                    Err(e) => {
+                        error!("Failed to create tray icon: {:?}", e);
+                        *control_flow = ControlFlow::Exit;
+                        return;
+                    }
+                }
+            }
+            let Some(tray) = &tray else {
+                return;
+            };
+            while let Ok(event) = MenuEvent::receiver().try_recv() {
+                if event.id == menus.toggle.id() {
+                    proxy = match proxy.take() {
+                        Some(running) => {
+                            let _ = running.stop.send(());
+                            info!(
+                                "{}",
+                                tr!("代理已停止", "プロキシを停止しました", "Proxy stopped")
+                            );
+                            None
+                        }
+                        None => Some(start()),
+                    };
+                } else if event.id == menus.folder.id() {
+                    open_folder();
+                } else if event.id == menus.quit.id() {
+                    if let Some(running) = proxy.take() {
+                        let _ = running.stop.send(());
+                    }
+                    *control_flow = ControlFlow::Exit;
+                    return;
+                }
+            }
+            // e.g. the port was taken
+            if proxy.as_ref().is_some_and(|p| p.task.is_finished()) {
+                proxy = None;
+            }
+            let running = proxy.is_some();
+            if shown != Some(running) {
+                menus.show(tray, running);
+                shown = Some(running);
+            }
+        })
+    }
+}
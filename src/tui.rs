@@ -0,0 +1,316 @@
+/// A full screen dashboard of the running proxy, replacing the console logs.
+#[cfg(feature = "tui")]
+pub use imp::Dashboard;
+
+#[cfg(not(feature = "tui"))]
+pub struct Dashboard(());
+
+#[cfg(not(feature = "tui"))]
+impl Dashboard {
+    pub fn open() -> anyhow::Result<Self> {
+        anyhow::bail!("TUI support is not compiled in, it needs the tui feature")
+    }
+
+    pub async fn run(self) {}
+}
+
+#[cfg(feature = "tui")]
+mod imp {
+    use crate::{
+        activity::{Decoded, ACTIVITY},
+        board,
+        sink::SinkHealth,
+        state::GAME_STATE,
+        tr, SETTINGS,
+    };
+    use anyhow::Result;
+    use chrono::{Local, TimeZone};
+    use ratatui::{
+        crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+        layout::{Constraint, Layout, Rect},
+        style::{Color, Style, Stylize},
+        text::{Line, Span},
+        widgets::{Block, Paragraph, Row, Sparkline, Table},
+        DefaultTerminal, Frame,
+    };
+    use std::{
+        collections::VecDeque,
+        time::{Duration, Instant},
+    };
+    use tokio::sync::oneshot;
+    use tracing::error;
+
+    /// Keys are polled this long between redraws.
+    const TICK: Duration = Duration::from_millis(250);
+    /// Seconds of throughput in the sparkline.
+    const HISTORY: usize = 120;
+    const LOG_LINES: u16 = 8;
+
+    pub struct Dashboard {
+        terminal: DefaultTerminal,
+    }
+
+    impl Dashboard {
+        /// Take over the terminal, failing when it is not one.
+        pub fn open() -> Result<Self> {
+            Ok(Dashboard {
+                terminal: ratatui::try_init()?,
+            })
+        }
+
+        /// Draw until `q`, `Esc` or Ctrl+C, or until dropped.
+        pub async fn run(self) {
+            let (quit, closed) = oneshot::channel();
+            let drawing = tokio::task::spawn_blocking(move || self.draw_loop(quit));
+            let _ = closed.await;
+            if let Ok(Err(e)) = drawing.await {
+                error!("Dashboard failed: {:?}", e);
+            }
+        }
+
+        fn draw_loop(mut self, quit: oneshot::Sender<()>) -> Result<()> {
+            let started = Instant::now();
+            let mut throughput = Throughput::default();
+            self.terminal.clear()?;
+            // also stops once the proxy stopped on a signal and dropped the receiver
+            while !quit.is_closed() {
+                throughput.sample();
+                self.terminal
+                    .draw(|frame| draw(frame, started, &throughput))?;
+                if event::poll(TICK)? {
+                    if let Event::Key(key) = event::read()? {
+                        let ctrl_c = key.modifiers.contains(KeyModifiers::CONTROL)
+                            && key.code == KeyCode::Char('c');
+                        if key.kind == KeyEventKind::Press
+                            && (ctrl_c || matches!(key.code, KeyCode::Char('q') | KeyCode::Esc))
+                        {
+                            let _ = quit.send(());
+                            break;
+                        }
+                    }
+                }
+            }
+            Ok(())
+        }
+    }
+
+    impl Drop for Dashboard {
+        fn drop(&mut self) {
+            ratatui::restore();
+        }
+    }
+
+    /// Frames per second over the last minutes.
+    #[derive(Default)]
+    struct Throughput {
+        rates: VecDeque<u64>,
+        last: Option<(Instant, u64)>,
+    }
+
+    impl Throughput {
+        fn sample(&mut self) {
+            let frames = ACTIVITY.frames();
+            match self.last {
+                Some((at, _)) if at.elapsed() < Duration::from_secs(1) => {}
+                Some((_, before)) => {
+                    if self.rates.len() == HISTORY {
+                        self.rates.pop_front();
+                    }
+                    self.rates.push_back(frames - before);
+                    self.last = Some((Instant::now(), frames));
+                }
+                None => self.last = Some((Instant::now(), frames)),
+            }
+        }
+
+        fn current(&self) -> u64 {
+            self.rates.back().copied().unwrap_or_default()
+        }
+    }
+
+    fn draw(frame: &mut Frame, started: Instant, throughput: &Throughput) {
+        let [header, body, logs, footer] = Layout::vertical([
+            Constraint::Length(3),
+            Constraint::Min(8),
+            Constraint::Length(LOG_LINES + 2),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        let [stats, spark] =
+            Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)])
+                .areas(header);
+        let [table, right] =
+            Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)])
+                .areas(body);
+        let sinks = ACTIVITY.sinks();
+        let [sink_area, recent] = Layout::vertical([
+            Constraint::Length(sinks.len() as u16 + 3),
+            Constraint::Min(3),
+        ])
+        .areas(right);
+
+        let uptime = started.elapsed().as_secs();
+        let queue = ACTIVITY.queue();
+        let summary = Line::from(vec![
+            Span::raw(tr!(
+                "代理 {}  ",
+                "プロキシ {}  ",
+                "Proxy {}  ",
+                SETTINGS.proxy_addr
+            )),
+            Span::raw(tr!("会话 ", "セッション ", "Sessions ")),
+            Span::raw(ACTIVITY.sessions().to_string()).bold(),
+            Span::raw(tr!("  消息 ", "  メッセージ ", "  Messages ")),
+            Span::raw(format!("{}/s", throughput.current())).bold(),
+            Span::raw(tr!("  队列 ", "  キュー ", "  Queue ")),
+            Span::raw(format!("{}/{}", queue.depth, queue.capacity)).bold(),
+            Span::raw(tr!("  解析失败 ", "  解析失敗 ", "  Parse errors ")),
+            Span::raw(ACTIVITY.errors().parse.values().sum::<u64>().to_string()).bold(),
+            Span::raw(tr!(
+                "  共 {}  运行 {:02}:{:02}:{:02}",
+                "  合計 {}  稼働 {:02}:{:02}:{:02}",
+                "  Total {}  Up {:02}:{:02}:{:02}",
+                ACTIVITY.frames(),
+                uptime / 3600,
+                uptime / 60 % 60,
+                uptime % 60
+            )),
+        ]);
+        let title = format!(" MajsoulMax-rs {} ", env!("CARGO_PKG_VERSION"));
+        frame.render_widget(
+            Paragraph::new(summary).block(Block::bordered().title(title)),
+            stats,
+        );
+        let rates: Vec<u64> = throughput.rates.iter().copied().collect();
+        // the newest rates, as many as fit
+        let shown = &rates[rates
+            .len()
+            .saturating_sub(spark.width.saturating_sub(2) as usize)..];
+        frame.render_widget(
+            Sparkline::default()
+                .block(Block::bordered().title(tr!(" 消息/秒 ", " メッセージ/秒 ", " Messages/s ")))
+                .data(shown)
+                .style(Style::new().fg(Color::Cyan)),
+            spark,
+        );
+
+        draw_board(frame, table);
+        draw_sinks(frame, sink_area, &sinks);
+        draw_recent(frame, recent);
+
+        let lines: Vec<Line> = ACTIVITY
+            .logs(LOG_LINES as usize)
+            .into_iter()
+            .map(Line::from)
+            .collect();
+        frame.render_widget(
+            Paragraph::new(lines).block(Block::bordered().title(tr!(" 日志 ", " ログ ", " Log "))),
+            logs,
+        );
+        frame.render_widget(
+            Paragraph::new(Line::from(vec![
+                Span::raw(tr!(" q 退出  ", " q 終了  ", " q quit  ")),
+                Span::raw(tr!(
+                    "本项目完全免费开源，如果您购买了此程序，请立即退款！",
+                    "本プロジェクトは完全無料のオープンソースです。購入した場合はすぐに返金を求めてください！",
+                    "This project is free and open source, if you paid for it, ask for a refund now!"
+                ))
+                .red(),
+            ])),
+            footer,
+        );
+    }
+
+    fn draw_board(frame: &mut Frame, area: Rect) {
+        // drawn on a blocking thread
+        let state = GAME_STATE.blocking_read();
+        let text = match state.players.is_empty() {
+            true => tr!("暂无对局", "対局なし", "No game"),
+            false => board::render(&state, SETTINGS.tile_notation),
+        };
+        drop(state);
+        frame.render_widget(
+            Paragraph::new(text).block(Block::bordered().title(tr!(" 牌局 ", " 卓 ", " Board "))),
+            area,
+        );
+    }
+
+    fn draw_sinks(frame: &mut Frame, area: Rect, sinks: &[SinkHealth]) {
+        let rows = sinks.iter().map(|sink| {
+            let status = match sink.up {
+                true => Span::raw(tr!("正常", "正常", "up")).green(),
+                false => Span::raw(tr!("断开", "切断", "down")).red(),
+            };
+            let last_success = sink
+                .last_success
+                .and_then(|secs| Local.timestamp_opt(secs as i64, 0).single())
+                .map_or("-".to_string(), |t| t.format("%H:%M:%S").to_string());
+            Row::new(vec![
+                Line::from(sink.sink.clone()),
+                Line::from(status),
+                Line::from(last_success),
+                Line::from(sink.consecutive_failures.to_string()),
+                Line::from(sink.dropped.to_string()),
+                Line::from(sink.last_error.clone().unwrap_or_default()),
+            ])
+        });
+        let widths = [
+            Constraint::Length(12),
+            Constraint::Length(4),
+            Constraint::Length(8),
+            Constraint::Length(4),
+            Constraint::Length(6),
+            Constraint::Min(10),
+        ];
+        let header = Row::new([
+            tr!("名称", "名前", "Name"),
+            tr!("状态", "状態", "State"),
+            tr!("上次成功", "最終成功", "Last OK"),
+            tr!("失败", "失敗", "Fails"),
+            tr!("丢弃", "破棄", "Drops"),
+            tr!("错误", "エラー", "Error"),
+        ])
+        .bold();
+        frame.render_widget(
+            Table::new(rows, widths)
+                .header(header)
+                .block(Block::bordered().title(tr!(" 输出 ", " 出力 ", " Sinks "))),
+            area,
+        );
+    }
+
+    fn draw_recent(frame: &mut Frame, area: Rect) {
+        let lines: Vec<Line> = ACTIVITY
+            .recent(area.height.saturating_sub(2) as usize)
+            .into_iter()
+            .map(
+                |Decoded {
+                     time,
+                     direction,
+                     method,
+                     spectated,
+                 }| {
+                    let mut spans = vec![
+                        Span::raw(time.format("%H:%M:%S ").to_string()).dark_gray(),
+                        Span::raw(format!("{} ", direction)),
+                        Span::raw(method),
+                    ];
+                    if spectated {
+                        spans.push(
+                            Span::raw(tr!(" (观战)", " (観戦)", " (spectating)")).dark_gray(),
+                        );
+                    }
+                    Line::from(spans)
+                },
+            )
+            .collect();
+        frame.render_widget(
+            Paragraph::new(lines).block(Block::bordered().title(tr!(
+                " 最近消息 ",
+                " 最近のメッセージ ",
+                " Recent messages "
+            ))),
+            area,
+        );
+    }
+}
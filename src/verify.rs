@@ -0,0 +1,139 @@
+use crate::{
+    format::strip_action, parser::i32_list, record::Record, report::Round, state::GameState,
+};
+use serde_json::Value as JsonValue;
+
+/// Scores after a hand result, `None` for an abortive draw which changes nothing.
+fn result_scores(name: &str, data: &JsonValue) -> Option<Vec<i32>> {
+    match name {
+        "Hule" => Some(i32_list(data, "scores")),
+        "NoTile" => {
+            // one entry per payment, e.g. each nagashi mangan
+            let last = data["scores"].as_array()?.last()?;
+            let old = i32_list(last, "old_scores");
+            let delta = i32_list(last, "delta_scores");
+            (old.len() == delta.len()).then(|| old.iter().zip(&delta).map(|(o, d)| o + d).collect())
+        }
+        _ => None,
+    }
+}
+
+/// Problems of a stored record: actions out of order, actions the table
+/// tracker rejects, scores that don't add up and a missing end.
+pub fn verify(record: &Record) -> Vec<String> {
+    let mut problems = vec![];
+    if record.head["uuid"].as_str().is_none_or(str::is_empty) {
+        problems.push("缺少uuid".to_string());
+    }
+    if record.actions.is_empty() {
+        problems.push("没有动作".to_string());
+        return problems;
+    }
+    let mut state = GameState {
+        spectated: true,
+        ..Default::default()
+    };
+    let round = |state: &GameState| Round {
+        round_wind: state.round_wind,
+        ju: state.ju,
+        honba: state.honba,
+    };
+    // points on the table, scores plus riichi sticks
+    let mut total = None;
+    let mut last_scores: Option<Vec<i32>> = None;
+    let mut in_hand = false;
+    for (i, action) in record.actions.iter().enumerate() {
+        let name = strip_action(&action.name);
+        let data = &action.data;
+        match name {
+            "NewRound" => {
+                if in_hand {
+                    problems.push(format!("第{}个动作：上一局没有结束就开始了新的一局", i + 1));
+                }
+                in_hand = true;
+                let scores = i32_list(data, "scores");
+                let sticks = data["liqibang"].as_i64().unwrap_or_default() as i32;
+                let sum = scores.iter().sum::<i32>() + sticks * 1000;
+                match total {
+                    None => total = Some(sum),
+                    Some(total) if total != sum => problems.push(format!(
+                        "第{}个动作：点数与供托合计{}，开局时为{}",
+                        i + 1,
+                        sum,
+                        total
+                    )),
+                    _ => (),
+                }
+                if let Some(last) = last_scores.take().filter(|s| *s != scores) {
+                    problems.push(format!(
+                        "第{}个动作：开局点数{:?}与上一局结算后的{:?}不符",
+                        i + 1,
+                        scores,
+                        last
+                    ));
+                }
+            }
+            _ if !in_hand => {
+                problems.push(format!("第{}个动作：{}不在任何一局中", i + 1, name));
+            }
+            "Hule" | "NoTile" | "LiuJu" => {
+                in_hand = false;
+                if name == "Hule" {
+                    let old = i32_list(data, "old_scores");
+                    let delta = i32_list(data, "delta_scores");
+                    let scores = i32_list(data, "scores");
+                    let sum: Vec<i32> = old.iter().zip(&delta).map(|(o, d)| o + d).collect();
+                    if old.len() != delta.len() || sum != scores {
+                        problems.push(format!(
+                            "第{}个动作（{}）：和了前点数加点数变化不等于和了后点数",
+                            i + 1,
+                            round(&state)
+                        ));
+                    }
+                }
+                last_scores = result_scores(name, data);
+            }
+            _ => (),
+        }
+        if let Err(e) = state.feed(name, data) {
+            problems.push(format!("第{}个动作（{}）无法应用：{}", i + 1, name, e));
+        }
+    }
+    if in_hand {
+        problems.push(format!("牌谱在{}中途截断", round(&state)));
+    }
+    let players = record.head["result"]["players"]
+        .as_array()
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+    if players.is_empty() {
+        problems.push("缺少终局结果，对局可能未完成".to_string());
+    } else if let Some(total) = total {
+        let finals: Vec<(usize, i32)> = players
+            .iter()
+            .filter_map(|p| {
+                let seat = p["seat"].as_u64()? as usize;
+                Some((seat, p["part_point_1"].as_i64()? as i32))
+            })
+            .collect();
+        let sum: i32 = finals.iter().map(|(_, p)| p).sum();
+        if sum != total {
+            problems.push(format!("终局点数合计{}，开局时为{}", sum, total));
+        }
+        // leftover riichi sticks go to the top
+        let top = finals.iter().max_by_key(|(_, p)| *p).map(|(s, _)| *s);
+        if let Some(last) = last_scores.filter(|_| !in_hand) {
+            for (seat, points) in &finals {
+                if Some(*seat) != top && last.get(*seat) != Some(points) {
+                    problems.push(format!(
+                        "座位{}终局点数{}与最后一局结算后的{:?}不符",
+                        seat,
+                        points,
+                        last.get(*seat)
+                    ));
+                }
+            }
+        }
+    }
+    problems
+}
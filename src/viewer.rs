@@ -0,0 +1,200 @@
+use crate::{
+    format::strip_action,
+    players::GameInfo,
+    record::Record,
+    state::{GameState, PlayerState, RoundResult, Wind},
+};
+use anyhow::Result;
+use serde::Serialize;
+use serde_json::json;
+use tracing::warn;
+
+/// The table after one action, as drawn by the page.
+#[derive(Serialize)]
+struct Step<'a> {
+    action: &'a str,
+    round_wind: Wind,
+    ju: u32,
+    honba: u32,
+    riichi_sticks: u32,
+    dora_indicators: &'a [String],
+    left_tiles: u32,
+    current: Option<u32>,
+    players: &'a [PlayerState],
+    result: &'a Option<RoundResult>,
+}
+
+/// A standalone HTML page stepping through `record` action by action.
+pub fn render(record: &Record) -> Result<String> {
+    let info = GameInfo::from_record(&record.head, 0);
+    let mut state = GameState {
+        spectated: true,
+        ..Default::default()
+    };
+    let mut steps = vec![];
+    for action in &record.actions {
+        let name = strip_action(&action.name);
+        if let Err(e) = state.feed(name, &action.data) {
+            warn!("Skipped {}: {:?}", action.name, e);
+            continue;
+        }
+        steps.push(serde_json::to_value(Step {
+            action: name,
+            round_wind: state.round_wind,
+            ju: state.ju,
+            honba: state.honba,
+            riichi_sticks: state.riichi_sticks,
+            dora_indicators: &state.dora_indicators,
+            left_tiles: state.left_tiles,
+            current: state.current,
+            players: &state.players,
+            result: &state.result,
+        })?);
+    }
+    let game = json!({
+        "uuid": info.game_uuid,
+        "names": info.names(),
+        "steps": steps,
+    });
+    // keep the JSON from closing the script element
+    let data = serde_json::to_string(&game)?.replace("</", "<\\/");
+    let title = info.game_uuid.unwrap_or_default();
+    Ok(TEMPLATE
+        .replace("{{title}}", &title)
+        .replace("{{data}}", &data))
+}
+
+const TEMPLATE: &str = r#"<!DOCTYPE html>
+<html lang="zh">
+<head>
+<meta charset="utf-8">
+<title>牌谱 {{title}}</title>
+<style>
+body { font-family: sans-serif; background: #1d5c3a; color: #eee; margin: 0; padding: 12px; }
+header, .controls { display: flex; gap: 16px; align-items: center; flex-wrap: wrap; margin-bottom: 8px; }
+.player { background: rgba(0, 0, 0, .25); border-radius: 6px; padding: 8px; margin-bottom: 8px; }
+.player.current { outline: 2px solid #ffd54f; }
+.name { font-weight: bold; margin-bottom: 4px; }
+.riichi { color: #ff8a80; }
+.row { display: flex; flex-wrap: wrap; gap: 2px; margin: 2px 0; }
+.river { display: grid; grid-template-columns: repeat(6, max-content); gap: 2px; margin-top: 4px; }
+.tile { display: inline-block; width: 26px; height: 36px; line-height: 36px; text-align: center;
+        background: #faf8f0; color: #222; border: 1px solid #555; border-radius: 3px; font-weight: bold; }
+.tile.m { color: #c62828; } .tile.p { color: #1e4ba0; } .tile.s { color: #1b7a3c; } .tile.red { background: #ffd6d6; }
+.tile.dim { opacity: .55; } .tile.mark { border: 2px solid #e53935; } .meld { margin-left: 10px; }
+.result { background: rgba(0, 0, 0, .4); border-radius: 6px; padding: 8px; white-space: pre-line; }
+input[type=range] { flex: 1; min-width: 200px; }
+</style>
+</head>
+<body>
+<header><span id="round"></span><span id="sticks"></span><span>宝牌指示 <span id="doras" class="row" style="display:inline-flex"></span></span><span id="left"></span></header>
+<div class="controls">
+<button id="prevRound">⏮</button><button id="prev">◀</button><button id="next">▶</button><button id="nextRound">⏭</button>
+<input id="slider" type="range" min="0" value="0"><span id="pos"></span><span id="action"></span>
+</div>
+<div id="players"></div>
+<div id="result" class="result" hidden></div>
+<script>
+const GAME = {{data}};
+const WINDS = ["东", "南", "西", "北"];
+const HONORS = ["", "东", "南", "西", "北", "白", "发", "中"];
+const WIND_INDEX = { east: 0, south: 1, west: 2, north: 3 };
+let step = 0;
+
+function tile(t, extra) {
+  const el = document.createElement("span");
+  const num = t[0], suit = t[1];
+  el.className = "tile " + (suit || "") + (num === "0" ? " red" : "") + (extra ? " " + extra : "");
+  el.textContent = suit === "z" ? HONORS[+num] : (num === "0" ? "5" : num) + (suit || "");
+  return el;
+}
+
+function name(seat) {
+  return GAME.names[seat] || "座位" + seat;
+}
+
+function show(i) {
+  step = Math.max(0, Math.min(GAME.steps.length - 1, i));
+  const s = GAME.steps[step];
+  if (!s) return;
+  document.getElementById("round").textContent =
+    WINDS[WIND_INDEX[s.round_wind]] + (s.ju + 1) + "局" + s.honba + "本场";
+  document.getElementById("sticks").textContent = "供托 " + s.riichi_sticks;
+  document.getElementById("left").textContent = "余 " + s.left_tiles;
+  const doras = document.getElementById("doras");
+  doras.replaceChildren(...s.dora_indicators.map(t => tile(t)));
+  document.getElementById("slider").value = step;
+  document.getElementById("pos").textContent = (step + 1) + " / " + GAME.steps.length;
+  document.getElementById("action").textContent = s.action;
+  const players = document.getElementById("players");
+  players.replaceChildren(...s.players.map(p => {
+    const div = document.createElement("div");
+    div.className = "player" + (s.current === p.seat ? " current" : "");
+    const head = document.createElement("div");
+    head.className = "name";
+    head.textContent = WINDS[WIND_INDEX[p.wind]] + " " + name(p.seat) + " " + p.score +
+      (p.nukidora ? " 拔北" + p.nukidora : "");
+    if (p.riichi) {
+      const r = document.createElement("span");
+      r.className = "riichi";
+      r.textContent = " 立直";
+      head.append(r);
+    }
+    const hand = document.createElement("div");
+    hand.className = "row";
+    hand.append(...p.hand.map(t => tile(t)));
+    for (const m of p.melds) {
+      const meld = document.createElement("span");
+      meld.className = "row meld";
+      meld.append(...m.tiles.map(t => tile(t)));
+      hand.append(meld);
+    }
+    const river = document.createElement("div");
+    river.className = "river";
+    river.append(...p.discards.map(d =>
+      tile(d.tile, [d.tsumogiri || d.called ? "dim" : "", d.riichi ? "mark" : ""].join(" "))));
+    div.append(head, hand, river);
+    return div;
+  }));
+  const result = document.getElementById("result");
+  result.hidden = !s.result;
+  if (s.result) result.textContent = describe(s.result);
+}
+
+function describe(r) {
+  if (r.type === "hule") {
+    return r.wins.map(w =>
+      name(w.seat) + (w.zimo ? " 自摸 " : " 荣和 " + name(w.from) + " ") + w.tile + "\n" +
+      w.yaku.map(y => y.name + " " + y.han).join("，") + "\n" +
+      (w.yakuman ? "役满 " : w.han + "番" + w.fu + "符 ") + w.points + "点").join("\n\n");
+  }
+  if (r.type === "noTile") {
+    return "流局，听牌：" + (r.tenpai.map(name).join("、") || "无");
+  }
+  return "途中流局";
+}
+
+function roundStart(from, dir) {
+  for (let i = from + dir; i >= 0 && i < GAME.steps.length; i += dir) {
+    if (GAME.steps[i].action === "NewRound") return i;
+  }
+  return dir > 0 ? GAME.steps.length - 1 : 0;
+}
+
+document.getElementById("slider").max = GAME.steps.length - 1;
+document.getElementById("slider").oninput = e => show(+e.target.value);
+document.getElementById("prev").onclick = () => show(step - 1);
+document.getElementById("next").onclick = () => show(step + 1);
+document.getElementById("prevRound").onclick = () => show(roundStart(step, -1));
+document.getElementById("nextRound").onclick = () => show(roundStart(step, 1));
+document.addEventListener("keydown", e => {
+  if (e.key === "ArrowLeft") show(step - 1);
+  if (e.key === "ArrowRight") show(step + 1);
+  if (e.key === "ArrowUp") show(roundStart(step, -1));
+  if (e.key === "ArrowDown") show(roundStart(step, 1));
+});
+show(0);
+</script>
+</body>
+</html>
+"#;
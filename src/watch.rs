@@ -0,0 +1,239 @@
+use crate::{
+    format::{live_actions, strip_action},
+    library::Library,
+    lobby::LobbyClient,
+    record::{fetch, live_head, Record, RecordAction},
+    tr,
+};
+use anyhow::{anyhow, Result};
+use serde_json::{json, Value as JsonValue};
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+    time::Duration,
+};
+use tokio::sync::mpsc;
+use tracing::{error, info, warn};
+
+/// Keeps the lobby connection from timing out between friends' games.
+const HEARTBEAT: Duration = Duration::from_secs(30);
+/// A spectated game without any action for this long is given up.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// Account and game uuid of an `AccountActiveState` in a game.
+fn playing(state: &JsonValue) -> Option<(u32, String)> {
+    let account_id = state["account_id"].as_u64()? as u32;
+    let uuid = state["playing"]["game_uuid"].as_str()?;
+    (!uuid.is_empty()).then(|| (account_id, uuid.to_string()))
+}
+
+fn push_actions(record: &mut Record, units: &[JsonValue]) {
+    match live_actions(units) {
+        Ok(actions) => record
+            .actions
+            .extend(actions.into_iter().map(|(name, data)| RecordAction {
+                name: format!("Record{}", strip_action(&name)),
+                data,
+            })),
+        Err(e) => warn!("Failed to decode spectated actions: {:?}", e),
+    }
+}
+
+/// Spectate one game on the observer server until it ends.
+async fn observe(location: &str, token: &str, uuid: &str) -> Result<Record> {
+    // a bare host is the game server, observers use its `/ob` path
+    let url = match location.contains("://") {
+        true => location.to_string(),
+        false => format!("wss://{}/ob", location),
+    };
+    let mut ob = LobbyClient::connect(&url).await?;
+    ob.call(".lq.FastTest.authObserve", json!({ "token": token }))
+        .await?;
+    let start = ob.call(".lq.FastTest.startObserve", json!({})).await?;
+    let head = &start["head"];
+    let start_time = head["start_time"]
+        .as_i64()
+        .unwrap_or_else(|| chrono::Utc::now().timestamp());
+    let mut record = Record {
+        head: live_head(uuid, start_time, head),
+        actions: vec![],
+    };
+    let passed = start["passed"]["actions"].as_array();
+    push_actions(&mut record, passed.map(Vec::as_slice).unwrap_or_default());
+    loop {
+        match tokio::time::timeout(IDLE_TIMEOUT, ob.next_notify()).await {
+            Ok(Ok((method, data))) => match method.as_str() {
+                ".lq.NotifyObserveData" => {
+                    push_actions(&mut record, std::slice::from_ref(&data["unit"]))
+                }
+                ".lq.NotifyGameEndResult" => {
+                    record.head["result"] = data["result"].clone();
+                    break;
+                }
+                _ => (),
+            },
+            // the observer server hangs up once the game is over
+            Ok(Err(_)) => break,
+            Err(_) => {
+                warn!("No action in game {} for a while, stopped watching", uuid);
+                break;
+            }
+        }
+    }
+    record.head["end_time"] = json!(chrono::Utc::now().timestamp());
+    Ok(record)
+}
+
+/// Store the record of a watched game, preferring the complete one of the
+/// lobby to the spectated one, which may lack the starting hands.
+async fn archive(
+    lobby: &mut LobbyClient,
+    library: &mut Library,
+    uuid: &str,
+    spectated: Option<Record>,
+) -> Result<()> {
+    let record = match fetch(lobby, uuid).await {
+        Ok(record) => record,
+        Err(e) => {
+            warn!(
+                "Failed to download record {}, keeping the spectated one: {:?}",
+                uuid, e
+            );
+            spectated.ok_or(anyhow!("Nothing of game {} was recorded", uuid))?
+        }
+    };
+    match library.add(&record)? {
+        Some(path) => info!(
+            "{}",
+            tr!(
+                "观战记录已保存到 {}",
+                "観戦記録を {} に保存しました",
+                "Spectated game saved to {}",
+                path.display()
+            )
+        ),
+        None => info!(
+            "{}",
+            tr!(
+                "对局 {} 已存在，未重复保存",
+                "対局 {} は既に保存されています",
+                "Game {} already saved",
+                uuid
+            )
+        ),
+    }
+    Ok(())
+}
+
+/// Watch friends and spectate each game one of them starts, archiving it to
+/// `dir` once it ends. `friends` are account ids, every friend when empty.
+///
+/// Everything goes through a lobby connection of its own, as the friend list
+/// of the web client does, so the game client may be closed meanwhile.
+pub async fn watch(lobby: &mut LobbyClient, dir: &Path, friends: &[u32]) -> Result<()> {
+    let mut library = Library::open(dir)?;
+    let list = lobby.call(".lq.Lobby.fetchFriendList", json!({})).await?;
+    let mut names = HashMap::new();
+    let mut states = vec![];
+    for friend in list["friends"].as_array().into_iter().flatten() {
+        let base = &friend["base"];
+        if let Some(id) = base["account_id"].as_u64() {
+            let name = base["nickname"].as_str().unwrap_or_default();
+            names.insert(id as u32, name.to_string());
+        }
+        states.push(friend["state"].clone());
+    }
+    let watched = match friends.is_empty() {
+        true => names.len(),
+        false => friends.len(),
+    };
+    info!(
+        "{}",
+        tr!(
+            "正在关注 {} 位好友的对局",
+            "{} 人のフレンドの対局を見守っています",
+            "Watching the games of {} friends",
+            watched
+        )
+    );
+
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let mut watching = HashSet::new();
+    let mut heartbeat = tokio::time::interval(HEARTBEAT);
+    loop {
+        for state in states.drain(..) {
+            let Some((account_id, uuid)) = playing(&state) else {
+                continue;
+            };
+            if (!friends.is_empty() && !friends.contains(&account_id))
+                || watching.contains(&uuid)
+                || !library.wants(&uuid, true)
+            {
+                continue;
+            }
+            let auth = match lobby
+                .call(
+                    ".lq.Lobby.createGameObserveAuth",
+                    json!({ "game_uuid": uuid }),
+                )
+                .await
+            {
+                Ok(auth) => auth,
+                // e.g. the room does not allow observers
+                Err(e) => {
+                    warn!("Failed to spectate game {}: {:?}", uuid, e);
+                    continue;
+                }
+            };
+            let name = names.get(&account_id).map_or("", String::as_str);
+            info!(
+                "{}",
+                tr!(
+                    "好友 {}（{}）开始对局 {}，开始观战",
+                    "フレンド {}（{}）が対局 {} を始めました。観戦を開始します",
+                    "Friend {} ({}) started game {}, spectating",
+                    name,
+                    account_id,
+                    uuid
+                )
+            );
+            watching.insert(uuid.clone());
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                let token = auth["token"].as_str().unwrap_or_default();
+                let location = auth["location"].as_str().unwrap_or_default();
+                let record = match observe(location, token, &uuid).await {
+                    Ok(record) => Some(record),
+                    Err(e) => {
+                        error!("Spectating game {} failed: {:?}", uuid, e);
+                        None
+                    }
+                };
+                let _ = tx.send((uuid, record));
+            });
+        }
+        tokio::select! {
+            notify = lobby.next_notify() => {
+                let (method, data) = notify?;
+                if method == ".lq.NotifyFriendStateChange" {
+                    states.push(data["active_state"].clone());
+                }
+            }
+            Some((uuid, record)) = rx.recv() => {
+                info!(
+                    "{}",
+                    tr!("对局 {} 已结束", "対局 {} が終了しました", "Game {} ended", uuid)
+                );
+                if let Err(e) = archive(lobby, &mut library, &uuid, record).await {
+                    error!("Failed to archive game {}: {:?}", uuid, e);
+                }
+                watching.remove(&uuid);
+            }
+            _ = heartbeat.tick() => {
+                lobby
+                    .call(".lq.Lobby.heatbeat", json!({ "no_operation_counter": 0 }))
+                    .await?;
+            }
+        }
+    }
+}
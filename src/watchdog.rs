@@ -0,0 +1,159 @@
+//! Stalled game servers: when the server side of a websocket sends nothing
+//! for half of `upstreamStallSecs`, the proxy pings the server, and when it
+//! stays silent for all of it, the websocket is closed for the client to
+//! reconnect instead of waiting on a dead connection.
+
+use hudsucker::{tokio_tungstenite::tungstenite::Message, WebSocketContext};
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    future::pending,
+    net::SocketAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use tokio::{
+    sync::{
+        broadcast,
+        mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+    },
+    time::sleep_until,
+};
+
+/// Payload of the pings sent by the watchdog, their pongs are not forwarded.
+const PROBE: &[u8] = b"majsoul_max_rs";
+
+static BUS: Lazy<broadcast::Sender<Stall>> = Lazy::new(|| broadcast::channel(16).0);
+
+/// Where to ask the client to server half of a websocket for a ping, by client.
+static PROBES: Lazy<Mutex<HashMap<SocketAddr, UnboundedSender<()>>>> = Lazy::new(Default::default);
+
+/// Data of `.helper.UpstreamStall`, emitted as a stalled websocket is closed.
+#[derive(Serialize, Debug, Clone)]
+pub struct Stall {
+    /// client end of the websocket
+    pub session: SocketAddr,
+    pub server: String,
+    pub silent_secs: u64,
+}
+
+pub fn subscribe() -> broadcast::Receiver<Stall> {
+    BUS.subscribe()
+}
+
+/// What [`Watch::next`] asks the websocket task to do.
+pub enum Alarm {
+    /// ping the server, on the client to server half
+    Probe,
+    /// close the websocket
+    Stalled(Stall),
+}
+
+/// The watchdog of one half of a websocket.
+pub enum Watch {
+    Off,
+    /// server to client, timing the server's silence
+    Upstream {
+        stall: Stall,
+        threshold: Duration,
+        last: Instant,
+        probed: bool,
+    },
+    /// client to server, sending the pings
+    Prober {
+        client: SocketAddr,
+        probes: UnboundedReceiver<()>,
+    },
+}
+
+impl Watch {
+    /// Watch a websocket, never stalling when `threshold_secs` is 0.
+    pub fn new(ctx: &WebSocketContext, threshold_secs: u64) -> Self {
+        if threshold_secs == 0 {
+            return Watch::Off;
+        }
+        match ctx {
+            WebSocketContext::ServerToClient { src, dst, .. } => Watch::Upstream {
+                stall: Stall {
+                    session: *dst,
+                    server: src.to_string(),
+                    silent_secs: 0,
+                },
+                threshold: Duration::from_secs(threshold_secs),
+                last: Instant::now(),
+                probed: false,
+            },
+            WebSocketContext::ClientToServer { src, .. } => {
+                let (tx, probes) = unbounded_channel();
+                if let Ok(mut all) = PROBES.lock() {
+                    all.insert(*src, tx);
+                }
+                Watch::Prober {
+                    client: *src,
+                    probes,
+                }
+            }
+        }
+    }
+
+    /// Note a message of this half, true for the pong to a probe, which is
+    /// not forwarded.
+    pub fn seen(&mut self, message: &Message) -> bool {
+        if let Watch::Upstream { last, probed, .. } = self {
+            *last = Instant::now();
+            *probed = false;
+        }
+        matches!(message, Message::Pong(payload) if payload == PROBE)
+    }
+
+    /// The ping sent to the server as a probe.
+    pub fn probe(&self) -> Message {
+        Message::Ping(PROBE.to_vec())
+    }
+
+    /// Wait for the next thing to do, forever when there is none.
+    pub async fn next(&mut self) -> Alarm {
+        match self {
+            Watch::Off => pending().await,
+            Watch::Upstream {
+                stall,
+                threshold,
+                last,
+                probed,
+            } => {
+                if !*probed {
+                    sleep_until((*last + *threshold / 2).into()).await;
+                    *probed = true;
+                    // the other half of the websocket talks to the server
+                    let probe = PROBES
+                        .lock()
+                        .ok()
+                        .and_then(|all| all.get(&stall.session).cloned());
+                    if let Some(probe) = probe {
+                        let _ = probe.send(());
+                    }
+                }
+                sleep_until((*last + *threshold).into()).await;
+                let mut stall = stall.clone();
+                stall.silent_secs = last.elapsed().as_secs();
+                let _ = BUS.send(stall.clone());
+                Alarm::Stalled(stall)
+            }
+            Watch::Prober { probes, .. } => match probes.recv().await {
+                Some(()) => Alarm::Probe,
+                None => pending().await,
+            },
+        }
+    }
+}
+
+impl Drop for Watch {
+    fn drop(&mut self) {
+        if let Watch::Prober { client, .. } = self {
+            if let Ok(mut all) = PROBES.lock() {
+                all.remove(client);
+            }
+        }
+    }
+}
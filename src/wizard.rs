@@ -0,0 +1,414 @@
+use crate::{
+    cert::{self, CERT_FILE},
+    settings::{Region, Settings},
+    tr, Command, ARG,
+};
+use anyhow::{anyhow, bail, Result};
+use serde_json::{json, Value};
+use std::{
+    io::{IsTerminal, Write},
+    net::{SocketAddr, TcpListener},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// Written when the config folder has none of its own.
+pub(crate) const TEMPLATE: &str = include_str!("../liqi_config/settings.json");
+const LIQI_DESC: &[u8] = include_bytes!("../liqi_config/liqi.desc");
+const LIQI_JSON: &str = include_str!("../liqi_config/liqi.json");
+const DEFAULT_PORT: u16 = 23410;
+
+/// Whether to set up before the settings are loaded: on `setup`, and when
+/// the proxy is run from a terminal without a settings.json.
+pub fn wanted() -> bool {
+    match &ARG.command {
+        Some(Command::Setup) => true,
+        None | Some(Command::Run) => {
+            !Settings::find_dir().join("settings.json").is_file() && std::io::stdin().is_terminal()
+        }
+        _ => false,
+    }
+}
+
+/// A line of input, `default` when left empty.
+fn ask(question: &str, default: &str) -> Result<String> {
+    let colon = tr!("：", "：", ": ");
+    match default.is_empty() {
+        true => print!("{}{}", question, colon),
+        false => print!("{} [{}]{}", question, default, colon),
+    }
+    std::io::stdout().flush()?;
+    let mut line = String::new();
+    if std::io::stdin().read_line(&mut line)? == 0 {
+        bail!(tr!("设置已取消", "設定を中止しました", "Setup cancelled"));
+    }
+    Ok(match line.trim() {
+        "" => default.to_string(),
+        answer => answer.to_string(),
+    })
+}
+
+fn confirm(question: &str, default: bool) -> Result<bool> {
+    let hint = if default { "Y/n" } else { "y/N" };
+    loop {
+        match ask(&format!("{} ({})", question, hint), "")?
+            .to_lowercase()
+            .as_str()
+        {
+            "" => return Ok(default),
+            "y" | "yes" => return Ok(true),
+            "n" | "no" => return Ok(false),
+            _ => println!(
+                "{}",
+                tr!(
+                    "请输入y或n",
+                    "y か n を入力してください",
+                    "Please enter y or n"
+                )
+            ),
+        }
+    }
+}
+
+/// Index of the chosen option, numbered from 1 on screen.
+fn choose(question: &str, options: &[String], default: usize) -> Result<usize> {
+    println!("{}", question);
+    for (i, option) in options.iter().enumerate() {
+        println!("  {}. {}", i + 1, option);
+    }
+    loop {
+        let answer = ask(
+            &tr!("请选择", "番号を選択", "Choose"),
+            &(default + 1).to_string(),
+        )?;
+        match answer.parse::<usize>() {
+            Ok(n) if (1..=options.len()).contains(&n) => return Ok(n - 1),
+            _ => println!(
+                "{}",
+                tr!(
+                    "请输入1到{}之间的数字",
+                    "1 から {} までの番号を入力してください",
+                    "Please enter a number from 1 to {}",
+                    options.len()
+                )
+            ),
+        }
+    }
+}
+
+fn step_ca(dir: &Path) -> Result<()> {
+    let path = dir.join(CERT_FILE);
+    if path.is_file() {
+        println!(
+            "{}",
+            tr!(
+                "已有CA证书 {}",
+                "CA証明書は既にあります：{}",
+                "Found the CA certificate {}",
+                path.display()
+            )
+        );
+    } else if confirm(
+        &tr!(
+            "生成自己的CA证书？（否则使用私钥公开的内置证书）",
+            "専用のCA証明書を生成しますか？（しない場合は秘密鍵が公開されている内蔵証明書を使います）",
+            "Generate a CA certificate of your own? (otherwise the built-in one, whose key is public, is used)"
+        ),
+        true,
+    )?
+    {
+        cert::generate(dir, false)?;
+    } else {
+        return Ok(());
+    }
+    if confirm(
+        &tr!(
+            "将CA证书导入当前用户的受信任根证书？",
+            "CA証明書を現在のユーザーの信頼されたルート証明書にインポートしますか？",
+            "Import the CA certificate into the trusted roots of the current user?"
+        ),
+        true,
+    )? {
+        if let Err(e) = cert::install(&path) {
+            println!(
+                "{}",
+                tr!(
+                    "导入失败：{:#}",
+                    "インポートに失敗しました：{:#}",
+                    "Import failed: {:#}",
+                    e
+                )
+            );
+            println!(
+                "{}",
+                tr!(
+                    "可稍后手动导入 {}",
+                    "後で手動で {} をインポートしてください",
+                    "You can import {} by hand later",
+                    path.display()
+                )
+            );
+        }
+    }
+    Ok(())
+}
+
+fn in_use(port: u16) -> String {
+    tr!(
+        "端口 {} 已被占用",
+        "ポート {} は使用中です",
+        "Port {} is in use",
+        port
+    )
+}
+
+fn free(port: u16) -> bool {
+    TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], port))).is_ok()
+}
+
+fn step_port(settings: &mut Value) -> Result<()> {
+    let current = settings["proxyAddr"]
+        .as_str()
+        .and_then(|addr| addr.parse::<SocketAddr>().ok());
+    let host = current.map_or("127.0.0.1".to_string(), |addr| addr.ip().to_string());
+    let preferred = current.map_or(DEFAULT_PORT, |addr| addr.port());
+    // suggest the next free one when it is taken, e.g. by another proxy
+    let suggested = (preferred..preferred.saturating_add(100))
+        .find(|port| free(*port))
+        .unwrap_or(preferred);
+    if suggested != preferred {
+        println!("{}", in_use(preferred));
+    }
+    let port = loop {
+        match ask(
+            &tr!("代理监听端口", "プロキシの待ち受けポート", "Proxy port"),
+            &suggested.to_string(),
+        )?
+        .parse::<u16>()
+        {
+            Ok(port) if port > 0 && !free(port) => println!("{}", in_use(port)),
+            Ok(port) if port > 0 => break port,
+            _ => println!(
+                "{}",
+                tr!(
+                    "请输入1到65535之间的端口",
+                    "1 から 65535 までのポートを入力してください",
+                    "Please enter a port from 1 to 65535"
+                )
+            ),
+        }
+    };
+    settings["proxyAddr"] = json!(format!("{}:{}", host, port));
+    // the admin API moves along when it would clash
+    if let Some(admin) = settings["adminAddr"]
+        .as_str()
+        .and_then(|addr| addr.parse::<SocketAddr>().ok())
+    {
+        if admin.port() == port || !free(admin.port()) {
+            let next = (port.saturating_add(1)..u16::MAX)
+                .find(|p| free(*p))
+                .unwrap_or(port.saturating_add(1));
+            settings["adminAddr"] = json!(format!("{}:{}", admin.ip(), next));
+        }
+    }
+    Ok(())
+}
+
+fn step_region(settings: &mut Value) -> Result<()> {
+    let regions = [Region::Cn, Region::Jp, Region::En];
+    let current = serde_json::from_value(settings["region"].clone()).unwrap_or_default();
+    let options = [
+        tr!("国服", "中国サーバー", "China"),
+        tr!("日服", "日本サーバー", "Japan"),
+        tr!("国际服", "国際サーバー", "International"),
+    ];
+    let default = regions.iter().position(|r| *r == current).unwrap_or(0);
+    let region = regions[choose(
+        &tr!("游戏服务器：", "ゲームサーバー：", "Game server:"),
+        &options,
+        default,
+    )?];
+    settings["region"] = serde_json::to_value(region)?;
+    Ok(())
+}
+
+/// Whether something answers at `url`, helpers reject a bare GET.
+pub(crate) async fn reachable(url: &str) -> bool {
+    let Ok(client) = reqwest::Client::builder()
+        .danger_accept_invalid_certs(true)
+        .timeout(Duration::from_secs(2))
+        .build()
+    else {
+        return false;
+    };
+    client.get(url).send().await.is_ok()
+}
+
+async fn step_sink(settings: &mut Value) -> Result<()> {
+    let api_url = settings["apiUrl"]
+        .as_str()
+        .unwrap_or("https://localhost:12121/")
+        .to_string();
+    let detected = reachable(&api_url).await;
+    match detected {
+        true => println!(
+            "{}",
+            tr!(
+                "检测到mahjong-helper正在运行：{}",
+                "mahjong-helper が動作しています：{}",
+                "Found mahjong-helper running at {}",
+                api_url
+            )
+        ),
+        false => println!(
+            "{}",
+            tr!(
+                "未检测到mahjong-helper（{}）",
+                "mahjong-helper が見つかりません（{}）",
+                "mahjong-helper is not running at {}",
+                api_url
+            )
+        ),
+    }
+    let options = [
+        format!("mahjong-helper（{}）", api_url),
+        tr!(
+            "接收mjai事件的HTTP地址，如mjai引擎",
+            "mjai イベントを受け取る HTTP アドレス（mjai エンジンなど）",
+            "An HTTP endpoint taking mjai events, e.g. an mjai engine"
+        ),
+        tr!("写入文件", "ファイルに書き込む", "A file"),
+        tr!(
+            "不输出，关闭helper",
+            "出力しない（helper をオフ）",
+            "Nowhere, turn the helper off"
+        ),
+    ];
+    let settings = settings
+        .as_object_mut()
+        .ok_or(anyhow!("settings.json is not an object"))?;
+    settings.insert("helperSwitch".into(), json!(1));
+    match choose(
+        &tr!(
+            "解析后的消息发送到：",
+            "解析したメッセージの送信先：",
+            "Send the parsed messages to:"
+        ),
+        &options,
+        0,
+    )? {
+        0 => {
+            settings.remove("sinks");
+        }
+        1 => {
+            let url = loop {
+                match ask(&tr!("地址", "アドレス", "Address"), "")?.as_str() {
+                    url if url.starts_with("http://") || url.starts_with("https://") => {
+                        break url.to_string()
+                    }
+                    _ => println!(
+                        "{}",
+                        tr!(
+                            "请输入http://或https://开头的地址",
+                            "http:// か https:// で始まるアドレスを入力してください",
+                            "Please enter an address starting with http:// or https://"
+                        )
+                    ),
+                }
+            };
+            settings.insert(
+                "sinks".into(),
+                json!([{ "name": "mjai", "type": "http", "url": url, "format": "mjai" }]),
+            );
+        }
+        2 => {
+            let path = ask(
+                &tr!("文件路径", "ファイルのパス", "File path"),
+                "messages.jsonl",
+            )?;
+            settings.insert(
+                "sinks".into(),
+                json!([{ "name": "file", "type": "file", "path": path }]),
+            );
+        }
+        _ => {
+            settings.insert("helperSwitch".into(), json!(0));
+        }
+    }
+    Ok(())
+}
+
+/// Ask for the CA, port, server and sink, then write settings.json and the
+/// bundled liqi files to the config folder.
+pub async fn run() -> Result<()> {
+    let found = Settings::find_dir();
+    let dir = match found.is_dir() {
+        true => found,
+        false => PathBuf::from(&ARG.config_dir),
+    };
+    std::fs::create_dir_all(&dir)?;
+    let path = dir.join("settings.json");
+    let mut settings: Value = match std::fs::read_to_string(&path) {
+        Ok(json) => serde_json::from_str(&json).map_err(|e| {
+            anyhow!(tr!(
+                "无法解析{}: {}",
+                "{} を解析できません: {}",
+                "Invalid {}: {}",
+                path.display(),
+                e
+            ))
+        })?,
+        Err(_) => serde_json::from_str(TEMPLATE)?,
+    };
+    println!(
+        "{}",
+        tr!(
+            "设置向导，配置目录：{}",
+            "設定ウィザード、設定フォルダ：{}",
+            "Setup, config folder: {}",
+            dir.display()
+        )
+    );
+    println!(
+        "{}",
+        tr!(
+            "直接回车使用方括号中的默认值，Ctrl+C退出",
+            "Enter だけで [] 内の既定値を使います。Ctrl+C で終了",
+            "Press Enter for the default in brackets, Ctrl+C to quit"
+        )
+    );
+    step_ca(&dir)?;
+    step_port(&mut settings)?;
+    step_region(&mut settings)?;
+    step_sink(&mut settings).await?;
+
+    for (name, bytes) in [
+        ("liqi.desc", LIQI_DESC),
+        ("liqi.json", LIQI_JSON.as_bytes()),
+    ] {
+        let file = dir.join(name);
+        if !file.exists() {
+            std::fs::write(&file, bytes)?;
+        }
+    }
+    std::fs::write(&path, serde_json::to_string_pretty(&settings)? + "\n")?;
+    println!(
+        "{}",
+        tr!(
+            "设置已写入 {}",
+            "設定を {} に書き込みました",
+            "Settings written to {}",
+            path.display()
+        )
+    );
+    println!(
+        "{}",
+        tr!(
+            "请将系统或Proxifier的HTTPS代理设为 {}，之后可运行`doctor`检查，或运行`setup`重新设置",
+            "システムまたは Proxifier の HTTPS プロキシを {} に設定してください。`doctor` で確認、`setup` で再設定できます",
+            "Set the HTTPS proxy of the system or Proxifier to {}, then run `doctor` to check, or `setup` to start over",
+            settings["proxyAddr"].as_str().unwrap_or_default()
+        )
+    );
+    Ok(())
+}
@@ -0,0 +1,464 @@
+use crate::{
+    state::{Meld, MeldKind},
+    tile::index34,
+};
+
+/// Round facts the yaku of a hand depend on.
+#[derive(Debug, Clone, Copy)]
+pub struct Context {
+    /// 34-index of the seat and round wind
+    pub seat_wind: usize,
+    pub round_wind: usize,
+    pub riichi: bool,
+    pub zimo: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Set {
+    /// lowest tile of a run
+    Run(usize),
+    Triplet(usize),
+}
+
+struct Parts {
+    pair: usize,
+    sets: Vec<Set>,
+}
+
+/// Yaku of a complete hand without dora, the decomposition with the most han
+/// wins and yakuman leave out the other yaku.
+///
+/// `counts` is the concealed part including `win`. Situational yaku like ippatsu,
+/// haitei or rinshan are not detected.
+pub fn detect(counts: &[u8; 34], melds: &[Meld], win: usize, ctx: Context) -> Vec<&'static str> {
+    let menzen = melds.iter().all(|m| m.kind == MeldKind::Ankan);
+    let open: Vec<Set> = melds.iter().filter_map(meld_set).collect();
+    let mut all = *counts;
+    for set in &open {
+        match *set {
+            Set::Run(i) => (i..i + 3).for_each(|t| all[t] += 1),
+            Set::Triplet(i) => all[i] += 3,
+        }
+    }
+    let ankan = melds.iter().filter(|m| m.kind == MeldKind::Ankan).count();
+    let kans = melds
+        .iter()
+        .filter(|m| !matches!(m.kind, MeldKind::Chi | MeldKind::Pon))
+        .count();
+    let mut common = vec![];
+    if ctx.riichi {
+        common.push("立直");
+    }
+    if menzen && ctx.zimo {
+        common.push("門前清自摸和");
+    }
+    if all.iter().enumerate().all(|(i, c)| *c == 0 || simple(i)) {
+        common.push("断幺九");
+    }
+    if let Some(flush) = flush(&all) {
+        common.push(flush);
+    }
+    match kans {
+        4 => common.push("四槓子"),
+        3 => common.push("三槓子"),
+        _ => {}
+    }
+    if all[..27].iter().all(|c| *c == 0) {
+        common.push("字一色");
+    }
+    if all
+        .iter()
+        .enumerate()
+        .all(|(i, c)| *c == 0 || i < 27 && i % 9 % 8 == 0)
+    {
+        common.push("清老頭");
+    }
+    if all
+        .iter()
+        .enumerate()
+        .all(|(i, c)| *c == 0 || [19, 20, 21, 23, 25, 32].contains(&i))
+    {
+        common.push("緑一色");
+    }
+    if menzen && melds.is_empty() && kokushi(counts) {
+        return vec!["国士無双"];
+    }
+    if melds.is_empty() && chuuren(counts) {
+        common.push("九蓮宝燈");
+    }
+    let mut best: Option<(u32, Vec<&'static str>)> = None;
+    let mut consider = |mut yaku: Vec<&'static str>| {
+        if yaku.iter().any(|y| yakuman(y)) {
+            yaku.retain(|y| yakuman(y));
+        }
+        let total = yaku.iter().map(|y| han(y, menzen)).sum();
+        if best.as_ref().is_none_or(|(most, _)| total > *most) {
+            best = Some((total, yaku));
+        }
+    };
+    if menzen && melds.is_empty() && counts.iter().filter(|c| **c == 2).count() == 7 {
+        let mut yaku = common.clone();
+        yaku.push("七対子");
+        if all.iter().enumerate().all(|(i, c)| *c == 0 || !simple(i)) {
+            yaku.push("混老頭");
+        }
+        consider(yaku);
+    }
+    for parts in decompose(counts) {
+        let mut yaku = common.clone();
+        let mut sets = parts.sets.clone();
+        sets.extend(open.iter().copied());
+        regular_yaku(&parts, &sets, menzen, ankan, win, ctx, &mut yaku);
+        consider(yaku);
+    }
+    best.map(|(_, yaku)| yaku).unwrap_or_default()
+}
+
+/// Han of a yaku, closed or open, 13 for yakuman.
+fn han(yaku: &str, menzen: bool) -> u32 {
+    let (closed, open) = match yaku {
+        "立直" | "門前清自摸和" | "平和" | "一盃口" => (1, 0),
+        "断幺九" | "役牌 白" | "役牌 發" | "役牌 中" | "役牌:自風牌" | "役牌:場風牌" => {
+            (1, 1)
+        }
+        "一気通貫" | "三色同順" | "混全帯幺九" => (2, 1),
+        "七対子" | "小三元" | "対々和" | "混老頭" | "三色同刻" | "三暗刻" | "三槓子" => {
+            (2, 2)
+        }
+        "二盃口" => (3, 0),
+        "純全帯幺九" | "混一色" => (3, 2),
+        "清一色" => (6, 5),
+        "国士無双" | "四暗刻" | "大三元" | "小四喜" | "大四喜" | "字一色" | "緑一色" | "清老頭"
+        | "九蓮宝燈" | "四槓子" => (13, 13),
+        // every name pushed here is listed above, see `every_yaku_has_han`
+        _ => unreachable!("Unknown yaku {}", yaku),
+    };
+    if menzen {
+        closed
+    } else {
+        open
+    }
+}
+
+fn yakuman(yaku: &str) -> bool {
+    han(yaku, true) == 13
+}
+
+fn regular_yaku(
+    parts: &Parts,
+    sets: &[Set],
+    menzen: bool,
+    ankan: usize,
+    win: usize,
+    ctx: Context,
+    yaku: &mut Vec<&'static str>,
+) {
+    let triplets: Vec<usize> = sets
+        .iter()
+        .filter_map(|s| match s {
+            Set::Triplet(i) => Some(*i),
+            _ => None,
+        })
+        .collect();
+    let runs: Vec<usize> = sets
+        .iter()
+        .filter_map(|s| match s {
+            Set::Run(i) => Some(*i),
+            _ => None,
+        })
+        .collect();
+    for (tile, name) in [(31, "役牌 白"), (32, "役牌 發"), (33, "役牌 中")] {
+        if triplets.contains(&tile) {
+            yaku.push(name);
+        }
+    }
+    if triplets.contains(&(27 + ctx.seat_wind)) {
+        yaku.push("役牌:自風牌");
+    }
+    if triplets.contains(&(27 + ctx.round_wind)) {
+        yaku.push("役牌:場風牌");
+    }
+    let dragons = |i: &usize| (31..34).contains(i);
+    match triplets.iter().filter(|i| dragons(i)).count() {
+        3 => yaku.push("大三元"),
+        2 if dragons(&parts.pair) => yaku.push("小三元"),
+        _ => {}
+    }
+    let winds = |i: &usize| (27..31).contains(i);
+    match triplets.iter().filter(|i| winds(i)).count() {
+        4 => yaku.push("大四喜"),
+        3 if winds(&parts.pair) => yaku.push("小四喜"),
+        _ => {}
+    }
+    // on ron the triplet the winning tile completes is open, unless it can
+    // complete a run instead
+    let mut concealed = ankan
+        + parts
+            .sets
+            .iter()
+            .filter(|s| matches!(s, Set::Triplet(_)))
+            .count();
+    let in_run = parts
+        .sets
+        .iter()
+        .any(|s| matches!(*s, Set::Run(i) if (i..i + 3).contains(&win)));
+    if !ctx.zimo && parts.sets.contains(&Set::Triplet(win)) && !in_run {
+        concealed -= 1;
+    }
+    match concealed {
+        4 => yaku.push("四暗刻"),
+        3 => yaku.push("三暗刻"),
+        _ => {}
+    }
+    if triplets.len() == 4 {
+        yaku.push("対々和");
+        if triplets.iter().chain([&parts.pair]).all(|i| !simple(*i)) {
+            yaku.push("混老頭");
+        }
+    }
+    let pair_yakuhai = dragons(&parts.pair)
+        || parts.pair == 27 + ctx.seat_wind
+        || parts.pair == 27 + ctx.round_wind;
+    // the winning tile must complete a two-sided wait
+    let ryanmen = parts.sets.iter().any(|s| match *s {
+        Set::Run(i) => win == i && i % 9 != 6 || win == i + 2 && i % 9 != 0,
+        _ => false,
+    });
+    if menzen && runs.len() == 4 && !pair_yakuhai && ryanmen {
+        yaku.push("平和");
+    }
+    if menzen {
+        let mut sorted = runs.clone();
+        sorted.sort();
+        let mut pairs = 0;
+        let mut i = 0;
+        while i + 1 < sorted.len() {
+            if sorted[i] == sorted[i + 1] {
+                pairs += 1;
+                i += 2;
+            } else {
+                i += 1;
+            }
+        }
+        match pairs {
+            2 => yaku.push("二盃口"),
+            1 => yaku.push("一盃口"),
+            _ => {}
+        }
+    }
+    for suit in 0..3 {
+        let base = suit * 9;
+        if [0, 3, 6].iter().all(|o| runs.contains(&(base + o))) {
+            yaku.push("一気通貫");
+        }
+    }
+    if runs
+        .iter()
+        .any(|r| r % 9 <= 6 && [0, 9, 18].iter().all(|o| runs.contains(&(r % 9 + o))))
+    {
+        yaku.push("三色同順");
+    }
+    if triplets
+        .iter()
+        .any(|t| *t < 27 && [0, 9, 18].iter().all(|o| triplets.contains(&(t % 9 + o))))
+    {
+        yaku.push("三色同刻");
+    }
+    let terminal_run = |r: &usize| r.is_multiple_of(9) || r % 9 == 6;
+    if !runs.is_empty()
+        && runs.iter().all(terminal_run)
+        && triplets.iter().chain([&parts.pair]).all(|i| !simple(*i))
+    {
+        let honors = triplets.iter().chain([&parts.pair]).any(|i| *i >= 27);
+        yaku.push(if honors {
+            "混全帯幺九"
+        } else {
+            "純全帯幺九"
+        });
+    }
+}
+
+/// Every split of a 3n+2 hand into a pair and sets.
+fn decompose(counts: &[u8; 34]) -> Vec<Parts> {
+    let mut out = vec![];
+    let mut c = *counts;
+    for pair in 0..34 {
+        if c[pair] >= 2 {
+            c[pair] -= 2;
+            let mut sets = vec![];
+            split(&mut c, 0, &mut sets, pair, &mut out);
+            c[pair] += 2;
+        }
+    }
+    out
+}
+
+fn split(c: &mut [u8; 34], mut i: usize, sets: &mut Vec<Set>, pair: usize, out: &mut Vec<Parts>) {
+    while i < 34 && c[i] == 0 {
+        i += 1;
+    }
+    if i == 34 {
+        out.push(Parts {
+            pair,
+            sets: sets.clone(),
+        });
+        return;
+    }
+    if c[i] >= 3 {
+        c[i] -= 3;
+        sets.push(Set::Triplet(i));
+        split(c, i, sets, pair, out);
+        sets.pop();
+        c[i] += 3;
+    }
+    if i < 27 && i % 9 <= 6 && c[i + 1] > 0 && c[i + 2] > 0 {
+        (i..i + 3).for_each(|t| c[t] -= 1);
+        sets.push(Set::Run(i));
+        split(c, i, sets, pair, out);
+        sets.pop();
+        (i..i + 3).for_each(|t| c[t] += 1);
+    }
+}
+
+fn meld_set(meld: &Meld) -> Option<Set> {
+    let mut tiles: Vec<usize> = meld.tiles.iter().filter_map(|t| index34(t).ok()).collect();
+    tiles.sort();
+    let first = *tiles.first()?;
+    Some(match meld.kind {
+        MeldKind::Chi => Set::Run(first),
+        _ => Set::Triplet(first),
+    })
+}
+
+fn simple(i: usize) -> bool {
+    i < 27 && (1..8).contains(&(i % 9))
+}
+
+fn flush(all: &[u8; 34]) -> Option<&'static str> {
+    let suits: Vec<usize> = (0..3)
+        .filter(|s| all[s * 9..s * 9 + 9].iter().any(|c| *c > 0))
+        .collect();
+    let honors = all[27..].iter().any(|c| *c > 0);
+    match (suits.len(), honors) {
+        (1, false) => Some("清一色"),
+        (1, true) => Some("混一色"),
+        _ => None,
+    }
+}
+
+fn chuuren(counts: &[u8; 34]) -> bool {
+    (0..3).any(|suit| {
+        let c = &counts[suit * 9..suit * 9 + 9];
+        c.iter().sum::<u8>() == 14 && c[0] >= 3 && c[8] >= 3 && c[1..8].iter().all(|n| *n > 0)
+    })
+}
+
+fn kokushi(counts: &[u8; 34]) -> bool {
+    const YAOCHU: [usize; 13] = [0, 8, 9, 17, 18, 26, 27, 28, 29, 30, 31, 32, 33];
+    YAOCHU.iter().all(|i| counts[*i] > 0) && YAOCHU.iter().map(|i| counts[*i]).sum::<u8>() == 14
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tile::{counts34, tiles};
+
+    #[test]
+    fn every_yaku_has_han() {
+        // the names `detect` pushes, the non-ASCII string literals before the tests
+        let src = include_str!("yaku.rs");
+        let code = &src[..src.find("#[cfg(test)]").unwrap()];
+        let names: Vec<&str> = code
+            .split('"')
+            .skip(1)
+            .step_by(2)
+            .filter(|s| !s.is_ascii())
+            .collect();
+        assert!(names.len() > 40);
+        for name in names {
+            han(name, true);
+            han(name, false);
+        }
+        assert_eq!(han("清一色", false), 5);
+        assert!(super::yakuman("九蓮宝燈"));
+        assert!(!super::yakuman("清一色"));
+    }
+
+    fn ron(text: &str, win: &str) -> Vec<&'static str> {
+        detect(
+            &counts34(&tiles(text)),
+            &[],
+            index34(win).unwrap(),
+            context(false),
+        )
+    }
+
+    fn tsumo(text: &str, win: &str) -> Vec<&'static str> {
+        detect(
+            &counts34(&tiles(text)),
+            &[],
+            index34(win).unwrap(),
+            context(true),
+        )
+    }
+
+    /// South seat in the east round, so only east and south are yakuhai.
+    fn context(zimo: bool) -> Context {
+        Context {
+            seat_wind: 1,
+            round_wind: 0,
+            riichi: false,
+            zimo,
+        }
+    }
+
+    #[test]
+    fn pinfu_needs_a_two_sided_wait() {
+        assert_eq!(ron("234m567p234678s99p", "2m"), vec!["平和"]);
+        assert_eq!(ron("234m567p234678s99p", "3m"), Vec::<&str>::new());
+        // 12m waiting on 3m is a penchan
+        assert_eq!(ron("123m567p234678s99p", "3m"), Vec::<&str>::new());
+        assert_eq!(ron("123m567p234678s99p", "1m"), vec!["平和"]);
+    }
+
+    #[test]
+    fn pinfu_excludes_a_yakuhai_pair() {
+        assert_eq!(ron("234m567p234678s22z", "2m"), Vec::<&str>::new());
+        assert_eq!(ron("234m567p234678s33z", "2m"), vec!["平和"]);
+    }
+
+    #[test]
+    fn ron_on_a_shanpon_opens_the_triplet() {
+        assert_eq!(ron("111m567m222p444s99s", "4s"), Vec::<&str>::new());
+        assert_eq!(
+            tsumo("111m567m222p444s99s", "4s"),
+            vec!["門前清自摸和", "三暗刻"]
+        );
+        let yaku = ron("111999m222p333s44s", "9m");
+        assert!(yaku.contains(&"三暗刻") && yaku.contains(&"対々和"));
+        assert!(!yaku.contains(&"四暗刻"));
+        assert_eq!(tsumo("111999m222p333s44s", "9m"), vec!["四暗刻"]);
+    }
+
+    #[test]
+    fn ron_completing_a_run_keeps_the_triplet_concealed() {
+        // 4s completes 345s as well as 444s
+        assert_eq!(ron("111m222p344445s99s", "4s"), vec!["三暗刻"]);
+    }
+
+    #[test]
+    fn ryanpeikou_beats_chiitoi() {
+        assert_eq!(ron("112233m445566p77s", "7s"), vec!["二盃口"]);
+        assert_eq!(ron("1144m2288p3366s55z", "5z"), vec!["七対子"]);
+    }
+
+    #[test]
+    fn yakuman() {
+        assert_eq!(ron("119m19p19s1234567z", "1m"), vec!["国士無双"]);
+        assert_eq!(ron("11123455678999m", "5m"), vec!["九蓮宝燈"]);
+        assert_eq!(
+            ron("111222333444z55z", "5z"),
+            vec!["字一色", "大四喜", "四暗刻"]
+        );
+    }
+}